@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS cold_manifests
+(
+    id         BIGSERIAL PRIMARY KEY,
+    object_key TEXT NOT NULL UNIQUE,
+    min_id     BIGINT NOT NULL,
+    max_id     BIGINT NOT NULL,
+    min_nonce  BIGINT NOT NULL,
+    max_nonce  BIGINT NOT NULL,
+    row_count  BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS cold_manifests_nonce_range ON cold_manifests (min_nonce, max_nonce);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS messages_created_at;
+ALTER TABLE messages DROP COLUMN IF EXISTS created_at;
@@ -0,0 +1,20 @@
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ;
+
+-- Backfill with the payload nonce where there is one, the best receive time estimate for existing rows.
+-- Nonces that are not numbers or not a time up to year 9999 are left to the NOW() fallback
+UPDATE messages
+SET created_at = CASE
+        WHEN jsonb_typeof(message->'nonce') = 'number'
+            AND (message->>'nonce')::numeric BETWEEN 0 AND 253402300799
+        THEN to_timestamp((message->>'nonce')::numeric::double precision)
+    END
+WHERE created_at IS NULL AND message ? 'nonce';
+
+UPDATE messages
+SET created_at = NOW()
+WHERE created_at IS NULL;
+
+ALTER TABLE messages ALTER COLUMN created_at SET DEFAULT NOW();
+ALTER TABLE messages ALTER COLUMN created_at SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS messages_created_at ON messages (created_at);
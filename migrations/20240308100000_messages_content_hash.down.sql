@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS messages_content_hash;
+ALTER TABLE messages DROP COLUMN IF EXISTS content_hash;
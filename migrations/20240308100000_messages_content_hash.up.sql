@@ -0,0 +1,14 @@
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS content_hash TEXT;
+
+UPDATE messages
+SET content_hash = encode(sha256(convert_to(message::text, 'UTF8')), 'hex')
+WHERE content_hash IS NULL;
+
+-- Keep only the first stored copy of messages that were received more than once
+DELETE FROM messages a
+USING messages b
+WHERE a.content_hash = b.content_hash AND a.id > b.id;
+
+ALTER TABLE messages ALTER COLUMN content_hash SET NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS messages_content_hash ON messages (content_hash);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS dead_letters
+(
+    id            BIGSERIAL PRIMARY KEY,
+    content_topic TEXT NOT NULL,
+    payload       BYTEA NOT NULL,
+    timestamp     BIGINT NOT NULL,
+    category      TEXT NOT NULL,
+    error         TEXT NOT NULL,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS dead_letters_category ON dead_letters (category, created_at);
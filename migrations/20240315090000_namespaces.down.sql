@@ -0,0 +1,20 @@
+DROP INDEX IF EXISTS cold_manifests_namespace_nonce_range;
+CREATE INDEX IF NOT EXISTS cold_manifests_nonce_range ON cold_manifests (min_nonce, max_nonce);
+
+DROP INDEX IF EXISTS dead_letters_namespace_category;
+CREATE INDEX IF NOT EXISTS dead_letters_category ON dead_letters (category, created_at);
+
+DROP INDEX IF EXISTS messages_namespace_created_at;
+CREATE INDEX IF NOT EXISTS messages_created_at ON messages (created_at);
+
+-- Copies of the same message from different namespaces collapse into the first stored one
+DELETE FROM messages a
+USING messages b
+WHERE a.content_hash = b.content_hash AND a.id > b.id;
+
+DROP INDEX IF EXISTS messages_namespace_content_hash;
+CREATE UNIQUE INDEX IF NOT EXISTS messages_content_hash ON messages (content_hash);
+
+ALTER TABLE cold_manifests DROP COLUMN IF EXISTS namespace;
+ALTER TABLE dead_letters DROP COLUMN IF EXISTS namespace;
+ALTER TABLE messages DROP COLUMN IF EXISTS namespace;
@@ -0,0 +1,17 @@
+-- Instances sharing one database keep their rows apart by namespace
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS namespace TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE dead_letters ADD COLUMN IF NOT EXISTS namespace TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE cold_manifests ADD COLUMN IF NOT EXISTS namespace TEXT NOT NULL DEFAULT 'default';
+
+-- Deduplication is per namespace, the same gossip can be stored by every instance
+DROP INDEX IF EXISTS messages_content_hash;
+CREATE UNIQUE INDEX IF NOT EXISTS messages_namespace_content_hash ON messages (namespace, content_hash);
+
+DROP INDEX IF EXISTS messages_created_at;
+CREATE INDEX IF NOT EXISTS messages_namespace_created_at ON messages (namespace, created_at);
+
+DROP INDEX IF EXISTS dead_letters_category;
+CREATE INDEX IF NOT EXISTS dead_letters_namespace_category ON dead_letters (namespace, category, created_at);
+
+DROP INDEX IF EXISTS cold_manifests_nonce_range;
+CREATE INDEX IF NOT EXISTS cold_manifests_namespace_nonce_range ON cold_manifests (namespace, min_nonce, max_nonce);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS outbox
+(
+    id           BIGSERIAL PRIMARY KEY,
+    namespace    TEXT NOT NULL DEFAULT 'default',
+    message_id   BIGINT NOT NULL,
+    payload      JSONB NOT NULL,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    published_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS outbox_unpublished ON outbox (namespace, id) WHERE published_at IS NULL;
+CREATE INDEX IF NOT EXISTS outbox_published_at ON outbox (namespace, published_at);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS export_cursors
+(
+    namespace  TEXT NOT NULL,
+    sink       TEXT NOT NULL,
+    last_id    BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (namespace, sink)
+);
@@ -0,0 +1,2 @@
+-- Existing rows already follow the first documented layout
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS schema_version INT NOT NULL DEFAULT 1;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS schema_metadata
+(
+    key        TEXT PRIMARY KEY,
+    value      TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,2 @@
+-- Rewritten POIs are kept in their canonical form
+DROP FUNCTION IF EXISTS normalize_poi(TEXT);
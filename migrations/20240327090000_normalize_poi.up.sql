@@ -0,0 +1,29 @@
+-- Canonical POI form, same rules as pipeline::validation::normalize_poi.
+-- Returns NULL for values that are not 32 bytes of hex
+CREATE OR REPLACE FUNCTION normalize_poi(poi TEXT) RETURNS TEXT
+LANGUAGE SQL IMMUTABLE STRICT AS $$
+    SELECT CASE
+        WHEN btrim(poi) ~* '^(0x)?[0-9a-f]{64}$'
+        THEN '0x' || lower(right(btrim(poi), 64))
+    END
+$$;
+
+DROP INDEX IF EXISTS messages_namespace_content_hash;
+
+-- Rewrite public POI messages stored before normalization at ingest
+UPDATE messages
+SET message = jsonb_set(message, '{payload,content}', to_jsonb(normalize_poi(message->'payload'->>'content')))
+WHERE message->'payload' ? 'block_number'
+  AND normalize_poi(message->'payload'->>'content') IS NOT NULL
+  AND normalize_poi(message->'payload'->>'content') <> message->'payload'->>'content';
+
+UPDATE messages
+SET content_hash = encode(sha256(convert_to(message::text, 'UTF8')), 'hex')
+WHERE content_hash <> encode(sha256(convert_to(message::text, 'UTF8')), 'hex');
+
+-- Copies that only differed in POI spelling are now duplicates
+DELETE FROM messages a
+USING messages b
+WHERE a.namespace = b.namespace AND a.content_hash = b.content_hash AND a.id > b.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS messages_namespace_content_hash ON messages (namespace, content_hash);
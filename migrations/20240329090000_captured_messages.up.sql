@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS captured_messages
+(
+    id            BIGSERIAL PRIMARY KEY,
+    namespace     TEXT NOT NULL DEFAULT 'default',
+    content_topic TEXT NOT NULL,
+    version       INT NOT NULL,
+    payload       BYTEA NOT NULL,
+    timestamp     BIGINT NOT NULL,
+    peer          TEXT,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS captured_messages_namespace ON captured_messages (namespace, id);
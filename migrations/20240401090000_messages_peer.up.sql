@@ -0,0 +1,2 @@
+-- Peer that delivered the message, when the Waku layer exposes it
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS peer TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS network_allocations;
+DROP TABLE IF EXISTS network_indexers;
@@ -0,0 +1,20 @@
+-- Snapshot of the network subgraph, shared by every namespace
+CREATE TABLE IF NOT EXISTS network_indexers
+(
+    id               TEXT PRIMARY KEY,
+    staked_tokens    NUMERIC NOT NULL,
+    allocated_tokens NUMERIC NOT NULL,
+    allocation_count INT NOT NULL,
+    synced_at        TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS network_allocations
+(
+    id               TEXT PRIMARY KEY,
+    indexer          TEXT NOT NULL,
+    deployment       TEXT NOT NULL,
+    allocated_tokens NUMERIC NOT NULL,
+    synced_at        TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS network_allocations_indexer ON network_allocations (indexer);
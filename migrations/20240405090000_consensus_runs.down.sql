@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS divergence_incidents;
+DROP TABLE IF EXISTS poi_consensus;
+DROP TABLE IF EXISTS consensus_runs;
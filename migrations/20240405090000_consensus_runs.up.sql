@@ -0,0 +1,35 @@
+-- Consensus results are versioned by run, so methodologies can be compared side by side
+CREATE TABLE IF NOT EXISTS consensus_runs
+(
+    id           BIGSERIAL PRIMARY KEY,
+    namespace    TEXT NOT NULL DEFAULT 'default',
+    methodology  TEXT NOT NULL,
+    from_ts      BIGINT NOT NULL,
+    to_ts        BIGINT NOT NULL,
+    status       TEXT NOT NULL DEFAULT 'running',
+    started_at   TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at  TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS poi_consensus
+(
+    run_id        BIGINT NOT NULL REFERENCES consensus_runs (id) ON DELETE CASCADE,
+    deployment    TEXT NOT NULL,
+    block_number  BIGINT NOT NULL,
+    consensus_poi TEXT NOT NULL,
+    support       DOUBLE PRECISION NOT NULL,
+    senders       INT NOT NULL,
+    PRIMARY KEY (run_id, deployment, block_number)
+);
+
+CREATE TABLE IF NOT EXISTS divergence_incidents
+(
+    run_id        BIGINT NOT NULL REFERENCES consensus_runs (id) ON DELETE CASCADE,
+    deployment    TEXT NOT NULL,
+    block_number  BIGINT NOT NULL,
+    graph_account TEXT NOT NULL,
+    poi           TEXT NOT NULL,
+    PRIMARY KEY (run_id, deployment, block_number, graph_account)
+);
+
+CREATE INDEX IF NOT EXISTS consensus_runs_namespace ON consensus_runs (namespace, id);
@@ -0,0 +1,14 @@
+-- API keys are stored as sha256 hashes, the plain key is only returned when created
+CREATE TABLE IF NOT EXISTS api_keys
+(
+    id          BIGSERIAL PRIMARY KEY,
+    namespace   TEXT NOT NULL DEFAULT 'default',
+    name        TEXT NOT NULL,
+    key_hash    TEXT NOT NULL UNIQUE,
+    role        TEXT NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at  TIMESTAMPTZ,
+    revoked_at  TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_namespace ON api_keys (namespace, id);
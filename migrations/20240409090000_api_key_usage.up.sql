@@ -0,0 +1,8 @@
+-- Running usage totals of each API key
+CREATE TABLE IF NOT EXISTS api_key_usage
+(
+    key_id        BIGINT PRIMARY KEY REFERENCES api_keys (id) ON DELETE CASCADE,
+    requests      BIGINT NOT NULL DEFAULT 0,
+    bytes         BIGINT NOT NULL DEFAULT 0,
+    last_used_at  TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,10 @@
+-- Operations allowed in query allowlist mode, keyed by the sha256 of the query text
+CREATE TABLE IF NOT EXISTS persisted_queries
+(
+    namespace   TEXT NOT NULL DEFAULT 'default',
+    hash        TEXT NOT NULL,
+    name        TEXT NOT NULL,
+    query       TEXT NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (namespace, hash)
+);
@@ -0,0 +1,14 @@
+-- Failed notifier deliveries waiting to be retried per channel
+CREATE TABLE IF NOT EXISTS notification_retries
+(
+    id              BIGSERIAL PRIMARY KEY,
+    namespace       TEXT NOT NULL DEFAULT 'default',
+    channel         TEXT NOT NULL,
+    content         TEXT NOT NULL,
+    attempts        INT NOT NULL DEFAULT 1,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    next_attempt_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS notification_retries_next_attempt_idx
+    ON notification_retries (namespace, next_attempt_at);
@@ -0,0 +1,10 @@
+-- Operator templates of notification messages, an empty channel applies to every channel
+CREATE TABLE IF NOT EXISTS notification_templates
+(
+    namespace   TEXT NOT NULL DEFAULT 'default',
+    alert       TEXT NOT NULL,
+    channel     TEXT NOT NULL DEFAULT '',
+    template    TEXT NOT NULL,
+    updated_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (namespace, alert, channel)
+);
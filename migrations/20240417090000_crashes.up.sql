@@ -0,0 +1,13 @@
+-- Panics of listener threads and tasks
+CREATE TABLE IF NOT EXISTS crashes
+(
+    id          BIGSERIAL PRIMARY KEY,
+    namespace   TEXT NOT NULL DEFAULT 'default',
+    thread      TEXT NOT NULL,
+    location    TEXT NOT NULL,
+    message     TEXT NOT NULL,
+    backtrace   TEXT NOT NULL,
+    occurred_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS crashes_namespace_idx ON crashes (namespace, occurred_at);
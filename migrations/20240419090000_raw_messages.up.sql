@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS raw_messages
+(
+    id            BIGSERIAL PRIMARY KEY,
+    namespace     TEXT NOT NULL DEFAULT 'default',
+    content_topic TEXT NOT NULL,
+    version       INT NOT NULL,
+    payload       BYTEA NOT NULL,
+    timestamp     BIGINT NOT NULL,
+    identifier    TEXT,
+    graph_account TEXT,
+    signature     TEXT,
+    peer          TEXT,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS raw_messages_namespace ON raw_messages (namespace, id);
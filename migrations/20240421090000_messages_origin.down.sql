@@ -0,0 +1,2 @@
+ALTER TABLE messages DROP COLUMN IF EXISTS unknown_bytes;
+ALTER TABLE messages DROP COLUMN IF EXISTS protocol_version;
@@ -0,0 +1,4 @@
+-- Graphcast protocol version of the content topic the message was received on
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS protocol_version INT;
+-- Payload bytes of fields this listener could not decode, null when not checked
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS unknown_bytes INT;
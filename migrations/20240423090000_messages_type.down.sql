@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS messages_namespace_type;
+ALTER TABLE messages DROP COLUMN IF EXISTS message_type;
@@ -0,0 +1,14 @@
+-- Name of the registered message type a message was decoded as
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS message_type TEXT;
+
+-- Earlier rows are told apart by the payload fields only their type carries
+UPDATE messages
+SET message_type = CASE
+    WHEN COALESCE(message->'payload', message) ? 'block_number' THEN 'public_poi'
+    WHEN COALESCE(message->'payload', message) ? 'migrate_block' THEN 'version_upgrade'
+    WHEN COALESCE(message->'payload', message) ? 'new_hash' THEN 'upgrade_intent'
+    WHEN COALESCE(message->'payload', message) ? 'content' THEN 'simple'
+END
+WHERE message_type IS NULL;
+
+CREATE INDEX IF NOT EXISTS messages_namespace_type ON messages (namespace, message_type, id);
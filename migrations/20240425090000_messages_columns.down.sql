@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS messages_namespace_content_topic;
+DROP INDEX IF EXISTS messages_namespace_identifier;
+DROP INDEX IF EXISTS messages_namespace_graph_account;
+DROP INDEX IF EXISTS messages_namespace_nonce;
+ALTER TABLE messages DROP COLUMN IF EXISTS content_topic;
+ALTER TABLE messages DROP COLUMN IF EXISTS identifier;
+ALTER TABLE messages DROP COLUMN IF EXISTS graph_account;
+ALTER TABLE messages DROP COLUMN IF EXISTS nonce;
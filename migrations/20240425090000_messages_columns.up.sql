@@ -0,0 +1,22 @@
+-- Envelope fields of stored messages, copied out of the jsonb so filters and aggregations
+-- can use indexes. Null when the stored message does not carry the field
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS nonce BIGINT;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS graph_account TEXT;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS identifier TEXT;
+-- Content topic the message was received on, null for messages stored by library users
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS content_topic TEXT;
+
+-- Nonces out of the bigint range are left null, as the insert path does
+UPDATE messages
+SET nonce = CASE
+        WHEN jsonb_typeof(message->'nonce') = 'number'
+            AND (message->>'nonce')::numeric BETWEEN 0 AND 9223372036854775807
+        THEN (message->>'nonce')::bigint
+    END,
+    graph_account = message->>'graph_account',
+    identifier = message->>'identifier';
+
+CREATE INDEX IF NOT EXISTS messages_namespace_nonce ON messages (namespace, nonce);
+CREATE INDEX IF NOT EXISTS messages_namespace_graph_account ON messages (namespace, graph_account, id);
+CREATE INDEX IF NOT EXISTS messages_namespace_identifier ON messages (namespace, identifier, id);
+CREATE INDEX IF NOT EXISTS messages_namespace_content_topic ON messages (namespace, content_topic, id);
@@ -0,0 +1,19 @@
+-- Messages under a retention hold are kept by pruning, max storage enforcement and cold
+-- tiering until the hold is released. A hold covers the messages matched when it was placed
+CREATE TABLE IF NOT EXISTS retention_holds
+(
+    id          BIGSERIAL PRIMARY KEY,
+    namespace   TEXT NOT NULL DEFAULT 'default',
+    reason      TEXT NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS held_messages
+(
+    hold_id     BIGINT NOT NULL REFERENCES retention_holds (id) ON DELETE CASCADE,
+    message_id  BIGINT NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    PRIMARY KEY (hold_id, message_id)
+);
+
+CREATE INDEX IF NOT EXISTS retention_holds_namespace ON retention_holds (namespace, id);
+CREATE INDEX IF NOT EXISTS held_messages_message ON held_messages (message_id);
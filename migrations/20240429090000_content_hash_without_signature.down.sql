@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS messages_namespace_content_hash;
+
+UPDATE messages
+SET content_hash = encode(sha256(convert_to(message::text, 'UTF8')), 'hex');
+
+CREATE UNIQUE INDEX IF NOT EXISTS messages_namespace_content_hash ON messages (namespace, content_hash);
+
+DROP FUNCTION IF EXISTS message_content_hash(JSONB);
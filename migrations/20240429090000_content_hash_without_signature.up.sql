@@ -0,0 +1,19 @@
+-- Content hash over the graph account, identifier, nonce and payload of a message, the
+-- signature left out so copies of one message signed differently count as duplicates
+CREATE OR REPLACE FUNCTION message_content_hash(message JSONB) RETURNS TEXT
+LANGUAGE SQL IMMUTABLE STRICT AS $$
+    SELECT encode(sha256(convert_to((message - 'signature')::text, 'UTF8')), 'hex')
+$$;
+
+DROP INDEX IF EXISTS messages_namespace_content_hash;
+
+UPDATE messages
+SET content_hash = message_content_hash(message)
+WHERE content_hash <> message_content_hash(message);
+
+-- Keep only the first stored copy of messages that only differed in their signature
+DELETE FROM messages a
+USING messages b
+WHERE a.namespace = b.namespace AND a.content_hash = b.content_hash AND a.id > b.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS messages_namespace_content_hash ON messages (namespace, content_hash);
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS stats_view_refreshes;
+DROP MATERIALIZED VIEW IF EXISTS consensus_summaries;
+DROP MATERIALIZED VIEW IF EXISTS topic_activity_hourly;
+DROP MATERIALIZED VIEW IF EXISTS indexer_activity_hourly;
@@ -0,0 +1,58 @@
+-- Precomputed rows of the heavy stats queries, refreshed by the listener every
+-- STATS_REFRESH_INTERVAL. Message counts are bucketed by receive hour so any window can be
+-- summed from the buckets, and unique indexes allow refreshing without blocking readers
+CREATE MATERIALIZED VIEW IF NOT EXISTS indexer_activity_hourly AS
+SELECT namespace, graph_account, identifier, date_trunc('hour', created_at) AS hour,
+       COUNT(*) AS message_count
+FROM messages
+WHERE graph_account IS NOT NULL AND identifier IS NOT NULL
+GROUP BY namespace, graph_account, identifier, date_trunc('hour', created_at);
+
+CREATE UNIQUE INDEX IF NOT EXISTS indexer_activity_hourly_key
+    ON indexer_activity_hourly (namespace, graph_account, identifier, hour);
+CREATE INDEX IF NOT EXISTS indexer_activity_hourly_namespace_hour
+    ON indexer_activity_hourly (namespace, hour);
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS topic_activity_hourly AS
+SELECT namespace, content_topic, graph_account, date_trunc('hour', created_at) AS hour,
+       COUNT(*) AS message_count, MAX(created_at) AS last_received_at
+FROM messages
+GROUP BY namespace, content_topic, graph_account, date_trunc('hour', created_at);
+
+CREATE UNIQUE INDEX IF NOT EXISTS topic_activity_hourly_key
+    ON topic_activity_hourly (namespace, content_topic, graph_account, hour);
+CREATE INDEX IF NOT EXISTS topic_activity_hourly_namespace_hour
+    ON topic_activity_hourly (namespace, hour);
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS consensus_summaries AS
+SELECT r.id AS run_id, r.namespace, r.methodology, r.from_ts, r.to_ts, r.status,
+       COALESCE(c.deployments, 0) AS deployments,
+       COALESCE(c.blocks, 0) AS blocks,
+       c.mean_support,
+       COALESCE(d.incidents, 0) AS incidents,
+       COALESCE(d.diverging_senders, 0) AS diverging_senders
+FROM consensus_runs r
+LEFT JOIN (
+    SELECT run_id, COUNT(DISTINCT deployment) AS deployments, COUNT(*) AS blocks,
+           AVG(support) AS mean_support
+    FROM poi_consensus
+    GROUP BY run_id
+) c ON c.run_id = r.id
+LEFT JOIN (
+    SELECT run_id, COUNT(*) AS incidents, COUNT(DISTINCT graph_account) AS diverging_senders
+    FROM divergence_incidents
+    GROUP BY run_id
+) d ON d.run_id = r.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS consensus_summaries_key ON consensus_summaries (run_id);
+CREATE INDEX IF NOT EXISTS consensus_summaries_namespace ON consensus_summaries (namespace, run_id);
+
+CREATE TABLE IF NOT EXISTS stats_view_refreshes
+(
+    view_name    TEXT PRIMARY KEY,
+    refreshed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO stats_view_refreshes ( view_name )
+VALUES ('indexer_activity_hourly'), ('topic_activity_hourly'), ('consensus_summaries')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,10 @@
+-- Latest nonce seen from each sender per identifier, so nonce ordering checks survive restarts
+CREATE TABLE IF NOT EXISTS sender_state
+(
+    namespace     TEXT NOT NULL DEFAULT 'default',
+    graph_account TEXT NOT NULL,
+    identifier    TEXT NOT NULL,
+    last_nonce    BIGINT NOT NULL,
+    updated_at    TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (namespace, graph_account, identifier)
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS messages_namespace_invalid_sender;
+ALTER TABLE messages DROP COLUMN IF EXISTS sender_valid;
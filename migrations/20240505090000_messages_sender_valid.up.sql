@@ -0,0 +1,5 @@
+-- Outcome of the ID_VALIDATION check of the message sender, null for messages stored
+-- without the check, such as imports or messages received while it was off
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS sender_valid BOOLEAN;
+
+CREATE INDEX IF NOT EXISTS messages_namespace_invalid_sender ON messages (namespace, id) WHERE sender_valid = false;
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS network_allocations_indexer_deployment;
+ALTER TABLE messages DROP COLUMN IF EXISTS sender_allocated_tokens;
+ALTER TABLE messages DROP COLUMN IF EXISTS sender_stake;
@@ -0,0 +1,7 @@
+-- Economic weight of the sender when the message was received, from the synced network
+-- snapshot: its staked tokens and its allocated tokens on the message's deployment, in GRT
+-- wei. Null for messages received before a sync or from accounts that are not indexers
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS sender_stake NUMERIC;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS sender_allocated_tokens NUMERIC;
+
+CREATE INDEX IF NOT EXISTS network_allocations_indexer_deployment ON network_allocations (indexer, deployment);
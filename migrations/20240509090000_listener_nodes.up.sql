@@ -0,0 +1,10 @@
+-- Waku identity of every listener that ran on a namespace, recorded at startup so operators
+-- can add the listeners as static peers
+CREATE TABLE IF NOT EXISTS listener_nodes
+(
+    namespace  TEXT NOT NULL DEFAULT 'default',
+    peer_id    TEXT NOT NULL,
+    multiaddrs TEXT[] NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (namespace, peer_id)
+);
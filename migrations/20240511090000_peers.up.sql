@@ -0,0 +1,16 @@
+-- Latest reachability check of every boot node, with the dial round trip
+CREATE TABLE IF NOT EXISTS peers
+(
+    namespace         TEXT NOT NULL DEFAULT 'default',
+    address           TEXT NOT NULL,
+    peer_id           TEXT,
+    critical          BOOLEAN NOT NULL DEFAULT false,
+    reachable         BOOLEAN NOT NULL,
+    connected         BOOLEAN NOT NULL,
+    dial_ms           BIGINT,
+    -- Failed checks since the node was last reachable
+    failures          INT NOT NULL DEFAULT 0,
+    checked_at        TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_reachable_at TIMESTAMPTZ,
+    PRIMARY KEY (namespace, address)
+);
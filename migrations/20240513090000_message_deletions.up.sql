@@ -0,0 +1,14 @@
+-- Bulk deletions of stored messages, deleted in batches with their progress counted here so
+-- an interrupted deletion can be resumed
+CREATE TABLE IF NOT EXISTS message_deletions
+(
+    id          BIGSERIAL PRIMARY KEY,
+    namespace   TEXT NOT NULL DEFAULT 'default',
+    -- Newest message id covered when the deletion started
+    max_id      BIGINT NOT NULL,
+    deleted     BIGINT NOT NULL DEFAULT 0,
+    status      TEXT NOT NULL DEFAULT 'running',
+    started_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS messages_namespace_message_time;
+ALTER TABLE messages DROP COLUMN IF EXISTS message_time;
@@ -0,0 +1,15 @@
+-- Time a message is dated by in activity and POI windows: its nonce when within NONCE_SKEW
+-- seconds of the receive time, otherwise the receive time. Computed at insert so the windows
+-- can use an index, existing rows are dated with the default skew of an hour
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS message_time BIGINT;
+
+UPDATE messages
+SET message_time = CASE
+        WHEN ABS(nonce - EXTRACT(EPOCH FROM created_at)::bigint) <= 3600 THEN nonce
+        ELSE EXTRACT(EPOCH FROM created_at)::bigint
+    END;
+
+ALTER TABLE messages ALTER COLUMN message_time SET DEFAULT EXTRACT(EPOCH FROM NOW())::bigint;
+ALTER TABLE messages ALTER COLUMN message_time SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS messages_namespace_message_time ON messages (namespace, message_time);
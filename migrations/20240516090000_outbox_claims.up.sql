@@ -0,0 +1,3 @@
+-- Relays claim outbox entries for a lease instead of holding row locks while publishing,
+-- entries whose lease ran out are claimed again
+ALTER TABLE outbox ADD COLUMN IF NOT EXISTS claimed_until TIMESTAMPTZ;
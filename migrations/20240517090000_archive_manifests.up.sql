@@ -0,0 +1,16 @@
+-- Objects of pruned messages written to ARCHIVE_URL, one per deleted batch
+CREATE TABLE IF NOT EXISTS archive_manifests
+(
+    id              BIGSERIAL PRIMARY KEY,
+    namespace       TEXT NOT NULL,
+    object_key      TEXT NOT NULL UNIQUE,
+    min_id          BIGINT NOT NULL,
+    max_id          BIGINT NOT NULL,
+    min_received_at BIGINT NOT NULL,
+    max_received_at BIGINT NOT NULL,
+    row_count       BIGINT NOT NULL,
+    bytes           BIGINT NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS archive_manifests_namespace_received_at ON archive_manifests (namespace, min_received_at, max_received_at);
@@ -0,0 +1,615 @@
+use anyhow::{anyhow, bail};
+use arrow::array::{Array, ArrayRef, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use async_graphql::OutputType;
+use bytes::Bytes;
+use chrono::Utc;
+use object_store::{parse_url_opts, path::Path, ObjectStore};
+use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter};
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, info, warn};
+use url::Url;
+
+pub mod pruned;
+
+use crate::{
+    db::resolver::{
+        commit_cold_manifest, content_hashes, list_cold_manifests, list_indexer_activity,
+        list_messages_before, list_prunable_messages, max_storage_threshold, ColdManifest,
+        IndexerActivity, IndexerStats,
+    },
+    metrics::COLD_STORED_MESSAGES,
+    server::model::GraphQLRow,
+};
+
+/// Object storage backing the cold tier, addressed by a URL such as
+/// `s3://bucket/prefix`, `gs://bucket/prefix` or `file:///var/lib/listener-radio`
+#[derive(Clone, Debug)]
+pub struct ColdStorage {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+}
+
+impl ColdStorage {
+    pub fn new(url: &str) -> Result<Self, anyhow::Error> {
+        let url = Url::parse(url)?;
+        // Credentials and regions are read from the usual AWS_* and GOOGLE_* environmental variables
+        let options = std::env::vars().map(|(k, v)| (k.to_ascii_lowercase(), v));
+        let (store, prefix) = parse_url_opts(&url, options)?;
+
+        Ok(ColdStorage {
+            store: Arc::from(store),
+            prefix,
+        })
+    }
+
+    pub async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), anyhow::Error> {
+        self.store
+            .put(&self.prefix.child(key), bytes.into())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), anyhow::Error> {
+        self.store.delete(&self.prefix.child(key)).await?;
+        Ok(())
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Bytes, anyhow::Error> {
+        let bytes = self
+            .store
+            .get(&self.prefix.child(key))
+            .await?
+            .bytes()
+            .await?;
+        Ok(bytes)
+    }
+}
+
+/// Cold tier objects are zstd compressed Parquet files with the row id, the message nonce and
+/// the json message. Objects written uncompressed by earlier versions read the same
+fn cold_schema() -> Arc<Schema> {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("nonce", DataType::Int64, false),
+        Field::new("message", DataType::Utf8, false),
+    ]))
+}
+
+pub fn encode_parquet(rows: &[(i64, i64, String)]) -> Result<Vec<u8>, anyhow::Error> {
+    let schema = cold_schema();
+    let ids = Int64Array::from_iter_values(rows.iter().map(|(id, _, _)| *id));
+    let nonces = Int64Array::from_iter_values(rows.iter().map(|(_, nonce, _)| *nonce));
+    let messages = StringArray::from_iter_values(rows.iter().map(|(_, _, message)| message));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(ids) as ArrayRef,
+            Arc::new(nonces) as ArrayRef,
+            Arc::new(messages) as ArrayRef,
+        ],
+    )?;
+
+    let mut buffer = Vec::new();
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let mut writer = ArrowWriter::try_new(&mut buffer, schema, Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(buffer)
+}
+
+pub fn decode_parquet(bytes: Bytes) -> Result<Vec<(i64, i64, String)>, anyhow::Error> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(bytes)?.build()?;
+    let mut rows = vec![];
+    for batch in reader {
+        let batch = batch?;
+        let ids = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| anyhow!("Cold storage object has an invalid id column"))?;
+        let nonces = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| anyhow!("Cold storage object has an invalid nonce column"))?;
+        let messages = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| anyhow!("Cold storage object has an invalid message column"))?;
+        for i in 0..batch.num_rows() {
+            rows.push((ids.value(i), nonces.value(i), messages.value(i).to_string()));
+        }
+    }
+
+    Ok(rows)
+}
+
+/// Read an object back and check that it holds exactly the rows of its manifest
+async fn verify_cold_object(
+    storage: &ColdStorage,
+    manifest: &ColdManifest,
+    ids: &[i64],
+) -> Result<(), anyhow::Error> {
+    let rows = decode_parquet(storage.get(&manifest.object_key).await?)?;
+    if rows.len() as i64 != manifest.row_count
+        || !rows.iter().map(|(id, _, _)| *id).eq(ids.iter().copied())
+    {
+        bail!(
+            "Cold storage object {} does not match its manifest",
+            manifest.object_key
+        );
+    }
+    Ok(())
+}
+
+/// Write rows to one cold tier object and drop them from Postgres once the object is
+/// recorded in the manifest table, after reading it back when `verify` is set. The object
+/// is removed again when it cannot be verified or recorded, so no object is left behind
+/// without a manifest
+/// Returns the number of messages moved
+async fn archive_rows(
+    pool: &PgPool,
+    namespace: &str,
+    storage: &ColdStorage,
+    rows: &[(i64, i64, String)],
+    verify: bool,
+) -> Result<i64, anyhow::Error> {
+    let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+        return Ok(0);
+    };
+
+    let manifest = ColdManifest {
+        object_key: format!("{}/messages-{}-{}.parquet", namespace, first.0, last.0),
+        min_id: first.0,
+        max_id: last.0,
+        min_nonce: rows
+            .iter()
+            .map(|(_, nonce, _)| *nonce)
+            .min()
+            .unwrap_or_default(),
+        max_nonce: rows
+            .iter()
+            .map(|(_, nonce, _)| *nonce)
+            .max()
+            .unwrap_or_default(),
+        row_count: rows.len() as i64,
+    };
+    // Only delete from Postgres once the object is durably written
+    storage
+        .put(&manifest.object_key, encode_parquet(rows)?)
+        .await?;
+    let ids = rows.iter().map(|(id, _, _)| *id).collect::<Vec<i64>>();
+    let committed = async {
+        if verify {
+            verify_cold_object(storage, &manifest, &ids).await?;
+        }
+        commit_cold_manifest(pool, namespace, &manifest, &ids).await
+    }
+    .await;
+    let moved = match committed {
+        Ok(moved) => moved,
+        Err(e) => {
+            if let Err(delete_err) = storage.delete(&manifest.object_key).await {
+                warn!(
+                    object_key = manifest.object_key.as_str(),
+                    err = delete_err.to_string(),
+                    "Failed to remove cold storage object without a manifest"
+                );
+            }
+            return Err(e);
+        }
+    };
+    debug!(
+        object_key = manifest.object_key.as_str(),
+        moved, "Archived batch to cold storage"
+    );
+    COLD_STORED_MESSAGES.inc_by(moved as u64);
+
+    Ok(moved)
+}
+
+/// Move messages received more than `age` minutes ago from Postgres into the cold tier in
+/// batches, each batch becomes one object recorded in the manifest table
+/// Returns the number of messages moved
+pub async fn tier_cold_messages(
+    pool: &PgPool,
+    namespace: &str,
+    storage: &ColdStorage,
+    age: i32,
+    batch_size: i64,
+    verify: bool,
+) -> Result<i64, anyhow::Error> {
+    let before = Utc::now().timestamp() - (age as i64 * 60);
+    let mut total_moved = 0i64;
+
+    loop {
+        let rows = list_messages_before(pool, namespace, before, batch_size).await?;
+        total_moved += archive_rows(pool, namespace, storage, &rows, verify).await?;
+
+        if (rows.len() as i64) < batch_size {
+            break;
+        }
+    }
+
+    Ok(total_moved)
+}
+
+/// Archive and delete in batches the messages pruning would delete, see
+/// [`list_prunable_messages`]. Every object is read back before its rows are deleted
+async fn archive_prunable_messages(
+    pool: &PgPool,
+    namespace: &str,
+    storage: &ColdStorage,
+    before: Option<i64>,
+    max_id: Option<i64>,
+    batch_size: i64,
+) -> Result<i64, anyhow::Error> {
+    let mut total_moved = 0i64;
+
+    loop {
+        let rows = list_prunable_messages(pool, namespace, before, max_id, batch_size).await?;
+        total_moved += archive_rows(pool, namespace, storage, &rows, true).await?;
+
+        if (rows.len() as i64) < batch_size {
+            break;
+        }
+    }
+
+    Ok(total_moved)
+}
+
+/// Prune messages received more than `retention` minutes ago like
+/// [`crate::db::resolver::prune_old_messages`], archiving them to the cold tier first
+/// Returns the number of messages pruned
+pub async fn archive_expired_messages(
+    pool: &PgPool,
+    namespace: &str,
+    storage: &ColdStorage,
+    retention: i32,
+    batch_size: i64,
+) -> Result<i64, anyhow::Error> {
+    let before = Utc::now().timestamp() - (retention as i64 * 60);
+    archive_prunable_messages(pool, namespace, storage, Some(before), None, batch_size).await
+}
+
+/// Keep the `max_storage` newest messages like [`crate::db::resolver::retain_max_storage`],
+/// archiving the pruned ones to the cold tier first
+/// Returns the number of messages pruned
+pub async fn archive_excess_messages(
+    pool: &PgPool,
+    namespace: &str,
+    storage: &ColdStorage,
+    max_storage: usize,
+    batch_size: i64,
+) -> Result<i64, anyhow::Error> {
+    let Some(threshold_id) = max_storage_threshold(pool, namespace, max_storage).await? else {
+        return Ok(0);
+    };
+    archive_prunable_messages(
+        pool,
+        namespace,
+        storage,
+        None,
+        Some(threshold_id),
+        batch_size,
+    )
+    .await
+}
+
+/// Read messages with nonces within `[from, to]` back from the cold tier, only
+/// fetching the objects whose manifest overlaps the requested range. Reading stops once
+/// `limit` rows are read, so a caller passing one over its cap learns the range is too
+/// large without downloading all of it
+pub async fn cold_messages<T>(
+    pool: &PgPool,
+    namespace: &str,
+    storage: &ColdStorage,
+    from: i64,
+    to: i64,
+    limit: Option<usize>,
+) -> Result<Vec<GraphQLRow<T>>, anyhow::Error>
+where
+    T: Clone + Serialize + DeserializeOwned + OutputType,
+{
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut rows = vec![];
+    for manifest in list_cold_manifests(pool, namespace, from, to).await? {
+        if rows.len() >= limit {
+            break;
+        }
+        let bytes = storage.get(&manifest.object_key).await?;
+        let (ids, messages): (Vec<i64>, Vec<String>) = decode_parquet(bytes)?
+            .into_iter()
+            .filter(|(_, nonce, _)| *nonce >= from && *nonce <= to)
+            .take(limit - rows.len())
+            .map(|(id, _, message)| (id, message))
+            .unzip();
+        // Cold objects only keep the message text, hashed again the way inserts hash it
+        let hashes = content_hashes(pool, &messages).await?;
+        for ((id, content_hash), message) in ids.into_iter().zip(hashes).zip(messages) {
+            rows.push(GraphQLRow::new(
+                id,
+                content_hash,
+                serde_json::from_str::<T>(&message)?,
+            ));
+        }
+    }
+
+    Ok(rows)
+}
+
+/// Messages by indexer and identifier moved to the cold tier with nonces within
+/// `(from, to]`, the cold counterpart of [`list_indexer_activity`]
+pub async fn cold_indexer_activity(
+    pool: &PgPool,
+    namespace: &str,
+    storage: &ColdStorage,
+    indexers: Option<&[String]>,
+    from: i64,
+    to: i64,
+) -> Result<Vec<IndexerActivity>, anyhow::Error> {
+    let mut counts: BTreeMap<(String, String), i64> = BTreeMap::new();
+    for manifest in list_cold_manifests(pool, namespace, from + 1, to).await? {
+        let bytes = storage.get(&manifest.object_key).await?;
+        for (_, nonce, message) in decode_parquet(bytes)? {
+            if nonce <= from || nonce > to {
+                continue;
+            }
+            let message: serde_json::Value = serde_json::from_str(&message)?;
+            let field = |name| message.get(name).and_then(serde_json::Value::as_str);
+            let (Some(graph_account), Some(identifier)) =
+                (field("graph_account"), field("identifier"))
+            else {
+                continue;
+            };
+            if indexers.is_some_and(|indexers| !indexers.iter().any(|i| i == graph_account)) {
+                continue;
+            }
+            *counts
+                .entry((graph_account.to_string(), identifier.to_string()))
+                .or_default() += 1;
+        }
+    }
+
+    Ok(counts
+        .into_iter()
+        .map(
+            |((graph_account, identifier), message_count)| IndexerActivity {
+                graph_account,
+                identifier,
+                message_count,
+            },
+        )
+        .collect())
+}
+
+/// Stats of the indexers over activity of several sources, by graph account
+pub fn merge_indexer_stats(
+    activity: impl IntoIterator<Item = IndexerActivity>,
+) -> Vec<IndexerStats> {
+    let mut indexers: BTreeMap<String, (i64, BTreeSet<String>)> = BTreeMap::new();
+    for row in activity {
+        let (message_count, subgraphs) = indexers.entry(row.graph_account).or_default();
+        *message_count += row.message_count;
+        subgraphs.insert(row.identifier);
+    }
+    indexers
+        .into_iter()
+        .map(|(graph_account, (message_count, subgraphs))| IndexerStats {
+            graph_account,
+            message_count,
+            subgraphs_count: subgraphs.len() as i64,
+        })
+        .collect()
+}
+
+/// Indexer stats over `(from, to]`, the window possibly predating hot retention. Postgres
+/// and the objects of the cold tier overlapping the window are read, objects are only
+/// fetched once messages of the window were moved to the cold tier
+pub async fn indexer_stats_as_of(
+    pool: &PgPool,
+    namespace: &str,
+    storage: Option<&ColdStorage>,
+    indexers: Option<&[String]>,
+    from: i64,
+    to: i64,
+) -> Result<Vec<IndexerStats>, anyhow::Error> {
+    let mut activity = list_indexer_activity(pool, namespace, indexers, from, to).await?;
+    if let Some(storage) = storage {
+        activity.extend(cold_indexer_activity(pool, namespace, storage, indexers, from, to).await?);
+    }
+    Ok(merge_indexer_stats(activity))
+}
+
+/// Background job periodically moving aged messages into the cold tier, reading every
+/// object back before deleting its rows when `verify` is set
+pub async fn run_cold_storage_job(
+    db: PgPool,
+    namespace: String,
+    storage: ColdStorage,
+    age: i32,
+    verify: bool,
+    running: Arc<AtomicBool>,
+) {
+    let mut tiering_interval = interval(Duration::from_secs(3600));
+    let batch_size = 1000;
+
+    while running.load(Ordering::SeqCst) {
+        tiering_interval.tick().await;
+        match tier_cold_messages(&db, &namespace, &storage, age, batch_size, verify).await {
+            Ok(num_moved) => info!(num_moved, "Moved aged messages to cold storage"),
+            Err(e) => warn!(
+                err = tracing::field::debug(e),
+                "Failed to move messages to cold storage"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::resolver::{add_message, count_messages};
+    use crate::message_types::PublicPoiMessage;
+
+    const TEST_NAMESPACE: &str = "default";
+
+    fn poi_message(nonce: u64) -> PublicPoiMessage {
+        PublicPoiMessage {
+            identifier: "QmTamam".to_string(),
+            content: "0xpoi".to_string(),
+            nonce,
+            network: "goerli".to_string(),
+            block_number: nonce,
+            block_hash: "hash".to_string(),
+            graph_account: "0xb4b4".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let rows = vec![
+            (1, 1707328500, r#"{"nonce":1707328500}"#.to_string()),
+            (7, 1707328600, "{}".to_string()),
+        ];
+        let bytes = encode_parquet(&rows).unwrap();
+        assert_eq!(decode_parquet(Bytes::from(bytes)).unwrap(), rows);
+        assert!(decode_parquet(Bytes::from_static(b"not parquet")).is_err());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_tier_cold_messages(pool: PgPool) {
+        let storage = ColdStorage::new("memory:///").unwrap();
+        let now = Utc::now().timestamp() as u64;
+        // A nonce claiming an old message does not tier a message received just now, and
+        // a nonce in the future does not keep an old message hot
+        add_message(&pool, TEST_NAMESPACE, poi_message(now - 86400))
+            .await
+            .unwrap();
+        let future = add_message(&pool, TEST_NAMESPACE, poi_message(now + 86400))
+            .await
+            .unwrap()
+            .unwrap();
+        sqlx::query("UPDATE messages SET created_at = NOW() - INTERVAL '2 hours' WHERE id = $1")
+            .bind(future)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let moved = tier_cold_messages(&pool, TEST_NAMESPACE, &storage, 60, 10, true)
+            .await
+            .unwrap();
+        assert_eq!(moved, 1);
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 1);
+
+        let key = format!("{}/messages-{}-{}.parquet", TEST_NAMESPACE, future, future);
+        let rows = decode_parquet(storage.get(&key).await.unwrap()).unwrap();
+        assert_eq!(
+            rows.iter()
+                .map(|(id, nonce, _)| (*id, *nonce))
+                .collect::<Vec<_>>(),
+            vec![(future, (now + 86400) as i64)]
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_cold_messages_limit(pool: PgPool) {
+        let storage = ColdStorage::new("memory:///").unwrap();
+        let mut ids = vec![];
+        for nonce in [1707328500, 1707328501, 1707328502] {
+            ids.push(
+                add_message(&pool, TEST_NAMESPACE, poi_message(nonce))
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+        sqlx::query("UPDATE messages SET created_at = NOW() - INTERVAL '2 hours'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        // One object per message
+        tier_cold_messages(&pool, TEST_NAMESPACE, &storage, 60, 1, true)
+            .await
+            .unwrap();
+
+        let rows = cold_messages::<PublicPoiMessage>(
+            &pool,
+            TEST_NAMESPACE,
+            &storage,
+            1707328500,
+            1707328502,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(rows.len(), 3);
+
+        // Objects past the limit are not fetched
+        let last = ids[2];
+        storage
+            .delete(&format!(
+                "{}/messages-{}-{}.parquet",
+                TEST_NAMESPACE, last, last
+            ))
+            .await
+            .unwrap();
+        let rows = cold_messages::<PublicPoiMessage>(
+            &pool,
+            TEST_NAMESPACE,
+            &storage,
+            1707328500,
+            1707328502,
+            Some(2),
+        )
+        .await
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_archive_rows_removes_unrecorded_object(pool: PgPool) {
+        let storage = ColdStorage::new("memory:///").unwrap();
+        let id = add_message(&pool, TEST_NAMESPACE, poi_message(1707328500))
+            .await
+            .unwrap()
+            .unwrap();
+        let key = format!("{}/messages-{}-{}.parquet", TEST_NAMESPACE, id, id);
+        // A manifest already holding the key makes recording the new one fail
+        sqlx::query(
+            "INSERT INTO cold_manifests (namespace, object_key, min_id, max_id, min_nonce, max_nonce, row_count) \
+             VALUES ($1, $2, 0, 0, 0, 0, 0)",
+        )
+        .bind(TEST_NAMESPACE)
+        .bind(&key)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let before = Utc::now().timestamp() + 60;
+        let rows = list_messages_before(&pool, TEST_NAMESPACE, before, 10)
+            .await
+            .unwrap();
+        assert!(archive_rows(&pool, TEST_NAMESPACE, &storage, &rows, false)
+            .await
+            .is_err());
+        assert!(storage.get(&key).await.is_err());
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 1);
+    }
+}
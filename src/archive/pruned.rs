@@ -0,0 +1,99 @@
+//! Archive of the messages pruning deletes, enabled by ARCHIVE_URL. Every batch pruned by
+//! RETENTION, MAX_STORAGE or an on demand prune is written as one gzip compressed JSONL
+//! object and recorded in the `archive_manifests` table in the transaction deleting it
+use anyhow::bail;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::io::{BufRead, BufReader, Write};
+
+use crate::{
+    archive::ColdStorage,
+    db::resolver::{ArchiveManifest, PrunedRow},
+};
+
+/// Object storage receiving pruned messages, addressed like the cold tier by a URL such as
+/// `s3://bucket/prefix`, `gs://bucket/prefix` or `file:///var/lib/listener-radio/archive`
+#[derive(Clone, Debug)]
+pub struct ArchiveSink {
+    storage: ColdStorage,
+}
+
+impl ArchiveSink {
+    pub fn new(url: &str) -> Result<Self, anyhow::Error> {
+        Ok(ArchiveSink {
+            storage: ColdStorage::new(url)?,
+        })
+    }
+
+    /// Write rows ordered by id as one object, returning the manifest to record for it
+    pub async fn write(
+        &self,
+        namespace: &str,
+        rows: &[PrunedRow],
+    ) -> Result<ArchiveManifest, anyhow::Error> {
+        let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+            bail!("No rows to archive");
+        };
+        let bytes = encode_jsonl_gz(rows)?;
+        let received_at = rows.iter().map(|row| row.received_at);
+        let manifest = ArchiveManifest {
+            object_key: format!("{}/pruned-{}-{}.jsonl.gz", namespace, first.id, last.id),
+            min_id: first.id,
+            max_id: last.id,
+            min_received_at: received_at.clone().min().unwrap_or_default(),
+            max_received_at: received_at.max().unwrap_or_default(),
+            row_count: rows.len() as i64,
+            bytes: bytes.len() as i64,
+        };
+        self.storage.put(&manifest.object_key, bytes).await?;
+        Ok(manifest)
+    }
+
+    /// Rows of an archived object
+    pub async fn read(&self, object_key: &str) -> Result<Vec<PrunedRow>, anyhow::Error> {
+        decode_jsonl_gz(&self.storage.get(object_key).await?)
+    }
+}
+
+/// One json object per row and line, gzip compressed
+pub fn encode_jsonl_gz(rows: &[PrunedRow]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for row in rows {
+        serde_json::to_writer(&mut encoder, row)?;
+        encoder.write_all(b"\n")?;
+    }
+    Ok(encoder.finish()?)
+}
+
+pub fn decode_jsonl_gz(bytes: &[u8]) -> Result<Vec<PrunedRow>, anyhow::Error> {
+    BufReader::new(GzDecoder::new(bytes))
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_jsonl_gz_round_trip() {
+        let rows = vec![
+            PrunedRow {
+                id: 1,
+                content_hash: Some("abc".to_string()),
+                received_at: 1707328500,
+                message: json!({"nonce": 1707328500}),
+            },
+            PrunedRow {
+                id: 7,
+                content_hash: None,
+                received_at: 1707328600,
+                message: json!({}),
+            },
+        ];
+        let bytes = encode_jsonl_gz(&rows).unwrap();
+        assert_eq!(decode_jsonl_gz(&bytes).unwrap(), rows);
+        assert!(decode_jsonl_gz(b"not gzip").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use derive_getters::Getters;
 use ethers::signers::WalletError;
 use graphcast_sdk::{
@@ -9,12 +9,30 @@ use graphcast_sdk::{
         message_typing::IdentityValidation, GraphcastAgentConfig, GraphcastAgentError,
     },
     graphql::QueryError,
-    init_tracing, wallet_address, GraphcastNetworkName, LogFormat,
+    wallet_address, GraphcastNetworkName, LogFormat,
 };
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{info, warn};
 
-#[derive(clap::ValueEnum, Clone, Debug, Serialize, Deserialize, Default)]
+use crate::consensus::ConsensusStrategy;
+use crate::export::ipc::ExportFormat;
+use crate::logging::{init_tracing, set_recent_logs_capacity, waku::capture_waku_logs};
+use crate::operator::discovery::discover_nodes;
+use crate::pipeline::validation::canonical_deployment;
+use crate::server::{auth::Role, limits::Cidr};
+
+#[derive(
+    clap::ValueEnum,
+    async_graphql::Enum,
+    Clone,
+    Copy,
+    Debug,
+    Serialize,
+    Deserialize,
+    Default,
+    PartialEq,
+    Eq,
+)]
 pub enum CoverageLevel {
     Minimal,
     #[default]
@@ -22,6 +40,81 @@ pub enum CoverageLevel {
     Comprehensive,
 }
 
+/// Preset of the API server behavior
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub enum ServerProfile {
+    /// Every query and mutation, no limits
+    #[default]
+    Default,
+    /// Read-only API for untrusted clients: mutations are rejected, list queries are capped at
+    /// PUBLIC_PAGE_LIMIT rows, query complexity and depth are limited and every client address
+    /// is rate limited
+    Public,
+}
+
+/// Handling of messages with a nonce older than the latest one of their sender
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub enum NonceOrdering {
+    /// Nonces are not tracked
+    Off,
+    /// Out of order messages are counted and stored
+    #[default]
+    Flag,
+    /// Out of order messages are moved to the dead letters
+    Reject,
+}
+
+/// Level of the monitoring summary logged every update interval
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub enum SummaryLevel {
+    /// The summary is not logged
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Values of the deployment label of per-deployment metrics
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub enum DeploymentLabels {
+    /// One series per deployment
+    #[default]
+    Deployment,
+    /// One series per message type, deployments are not told apart
+    MessageType,
+    /// The METRIC_TOP_DEPLOYMENTS busiest deployments of the last day, the rest as `other`
+    Top,
+    /// METRIC_LABEL_BUCKETS buckets of hashed deployments
+    Hashed,
+}
+
+/// Payload sanity checks applied before messages are stored
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PayloadCheck {
+    /// Block numbers of a sender's POIs never decrease for a deployment
+    BlockNumber,
+    /// POIs are 0x prefixed 32 byte hex strings
+    PoiFormat,
+    /// Identifiers are IPFS hashes (Qm...) or hex ids (0x...)
+    IdentifierFormat,
+    /// Networks are within NETWORK_ALLOWLIST, when one is set
+    Network,
+}
+
+impl PayloadCheck {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PayloadCheck::BlockNumber => "block_number",
+            PayloadCheck::PoiFormat => "poi_format",
+            PayloadCheck::IdentifierFormat => "identifier_format",
+            PayloadCheck::Network => "network",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Parser, Serialize, Deserialize, Getters, Default)]
 #[clap(
     name = "listener-radio",
@@ -43,6 +136,14 @@ pub struct Config {
         help = "Enable filter subscriptions based on topic generation"
     )]
     pub filter_protocol: Option<bool>,
+    #[clap(
+        long,
+        value_name = "FILTER_CHECK_INTERVAL",
+        env = "FILTER_CHECK_INTERVAL",
+        default_value_t = 60,
+        help = "Interval in seconds between health checks of the filter subscriptions when FILTER_PROTOCOL is enabled"
+    )]
+    pub filter_check_interval: u64,
     #[clap(
         long,
         value_name = "INDEXER_ADDRESS",
@@ -84,6 +185,20 @@ pub struct Config {
         default_value = "https://gateway.testnet.thegraph.com/network"
     )]
     pub network_subgraph: String,
+    #[clap(
+        long,
+        value_name = "NETWORK_SYNC_INTERVAL",
+        env = "NETWORK_SYNC_INTERVAL",
+        help = "Interval in seconds between snapshots of indexer stake and allocations from NETWORK_SUBGRAPH, no sync when unset"
+    )]
+    pub network_sync_interval: Option<u64>,
+    #[clap(
+        long,
+        value_name = "STATS_REFRESH_INTERVAL",
+        env = "STATS_REFRESH_INTERVAL",
+        help = "Interval in seconds between refreshes of the materialized stats views, the views are not refreshed when unset"
+    )]
+    pub stats_refresh_interval: Option<u64>,
     #[clap(
         long,
         value_name = "GRAPHCAST_NETWORK",
@@ -92,11 +207,28 @@ pub struct Config {
         help = "Supported Graphcast networks: mainnet, testnet"
     )]
     pub graphcast_network: GraphcastNetworkName,
+    #[clap(
+        long,
+        value_name = "PUBSUB_TOPIC",
+        env = "PUBSUB_TOPIC",
+        value_parser = Config::parse_pubsub_topic,
+        help = "Waku pubsub topic to join instead of the one of GRAPHCAST_NETWORK, as a Graphcast namespace or a full /waku/2/graphcast-v0-<namespace>/proto topic, for private test deployments"
+    )]
+    pub pubsub_topic: Option<String>,
+    #[clap(
+        long,
+        value_name = "[KEY]",
+        value_delimiter = ',',
+        env = "DECRYPTION_KEYS",
+        help = "Comma separated 32 byte hex keys decrypting symmetrically encrypted payloads, as <content topic>=<key> for one content topic or a bare key tried on every topic"
+    )]
+    pub decryption_keys: Vec<String>,
     #[clap(
         long,
         value_name = "[TOPIC]",
         value_delimiter = ',',
         env = "TOPICS",
+        value_parser = Config::parse_topic,
         help = "Comma separated static list of content topics to subscribe to (Static list to include)"
     )]
     pub topics: Vec<String>,
@@ -130,6 +262,12 @@ pub struct Config {
         help = "Advertised address to be connected among the Waku peers"
     )]
     pub waku_addr: Option<String>,
+    #[clap(
+        long,
+        env = "ANNOUNCE_NODE",
+        help = "Send the Waku peer id and addresses of this listener to the notifiers on startup, for other operators to add it as a static peer"
+    )]
+    pub announce_node: bool,
     #[clap(
         long,
         value_name = "NODE_ADDRESSES",
@@ -137,6 +275,37 @@ pub struct Config {
         env = "BOOT_NODE_ADDRESSES"
     )]
     pub boot_node_addresses: Vec<String>,
+    #[clap(
+        long,
+        value_name = "[NODE_ADDRESS]",
+        value_delimiter = ',',
+        env = "STORE_NODES",
+        help = "Comma separated multiaddrs of Waku store nodes queried on startup for the messages sent while the listener was down, tried in order"
+    )]
+    pub store_nodes: Vec<String>,
+    #[clap(
+        long,
+        value_name = "[NODE_ADDRESS]",
+        value_delimiter = ',',
+        env = "CRITICAL_BOOT_NODES",
+        help = "Comma separated boot node multiaddrs to alert on when they fail several reachability checks in a row"
+    )]
+    pub critical_boot_nodes: Vec<String>,
+    #[clap(
+        long,
+        value_name = "PEER_CHECK_INTERVAL",
+        env = "PEER_CHECK_INTERVAL",
+        help = "Interval in seconds between reachability checks of the boot nodes, no check when unset"
+    )]
+    pub peer_check_interval: Option<u64>,
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        env = "BACKFILL_WINDOW",
+        help = "Longest downtime replayed from STORE_NODES on startup, in seconds, 0 turns the backfill off",
+        default_value_t = 3600
+    )]
+    pub backfill_window: u64,
     #[clap(
         long,
         value_name = "WAKU_LOG_LEVEL",
@@ -144,6 +313,12 @@ pub struct Config {
         env = "WAKU_LOG_LEVEL"
     )]
     pub waku_log_level: Option<String>,
+    #[clap(
+        long,
+        env = "WAKU_LOG_CAPTURE",
+        help = "Parse the Waku node's log lines from stdout into tracing events with the `waku` target, filtered by RUST_LOG like the listener's own logs"
+    )]
+    pub waku_log_capture: bool,
     #[clap(
         long,
         value_name = "DISCV5_ENRS",
@@ -158,6 +333,22 @@ pub struct Config {
         env = "DISCV5_PORT"
     )]
     pub discv5_port: Option<u16>,
+    #[clap(
+        long,
+        value_name = "[URL]",
+        value_delimiter = ',',
+        env = "DNS_DISCOVERY_URLS",
+        help = "Comma separated Waku DNS discovery URLs (enrtree://...) to read boot nodes from, next to BOOT_NODE_ADDRESSES and DISCV5_ENRS"
+    )]
+    pub dns_discovery_urls: Vec<String>,
+    #[clap(
+        long,
+        value_name = "DNS_DISCOVERY_INTERVAL",
+        env = "DNS_DISCOVERY_INTERVAL",
+        default_value_t = 600,
+        help = "Seconds between resolutions of DNS_DISCOVERY_URLS, nodes newly listed are dialed"
+    )]
+    pub dns_discovery_interval: u64,
     #[clap(
         long,
         value_name = "LOG_LEVEL",
@@ -194,6 +385,28 @@ pub struct Config {
         env = "TELEGRAM_CHAT_ID"
     )]
     pub telegram_chat_id: Option<i64>,
+    #[clap(
+        long,
+        value_name = "WEBHOOK_URL",
+        help = "Generic webhook URL to post alerts to as structured JSON",
+        env = "WEBHOOK_URL"
+    )]
+    pub webhook_url: Option<String>,
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        env = "NOTIFICATION_RETRY_MAX_AGE",
+        default_value = "86400",
+        help = "Seconds failed notifications are retried with exponential backoff before they are dropped, 0 disables retries"
+    )]
+    pub notification_retry_max_age: u64,
+    #[clap(
+        long,
+        value_name = "FILE",
+        env = "NOTIFICATION_TEMPLATES",
+        help = "JSON file of notification templates keyed by alert, or alert.channel for a single channel, used unless a template is set through the API"
+    )]
+    pub notification_templates: Option<String>,
     #[clap(
         long,
         value_name = "METRICS_HOST",
@@ -224,6 +437,150 @@ pub struct Config {
         env = "SERVER_PORT"
     )]
     pub server_port: Option<u16>,
+    #[clap(
+        long,
+        value_name = "URL",
+        env = "PUBLIC_API_URL",
+        help = "Base URL the API is reachable at for alert recipients (e.g. https://listener.example.com), used for links in notifications. Defaults to SERVER_HOST and SERVER_PORT"
+    )]
+    pub public_api_url: Option<String>,
+    #[clap(
+        long,
+        value_name = "SERVER_PROFILE",
+        value_enum,
+        env = "SERVER_PROFILE",
+        default_value = "default",
+        help = "API server profile: default, or public for a read-only, rate limited API with capped page sizes and query complexity"
+    )]
+    pub server_profile: ServerProfile,
+    #[clap(
+        long,
+        value_name = "PUBLIC_PAGE_LIMIT",
+        env = "PUBLIC_PAGE_LIMIT",
+        default_value_t = 100,
+        help = "Most rows a list query returns with the public server profile"
+    )]
+    pub public_page_limit: i64,
+    #[clap(
+        long,
+        value_name = "PUBLIC_QUERY_COMPLEXITY",
+        env = "PUBLIC_QUERY_COMPLEXITY",
+        default_value_t = 500,
+        help = "Highest GraphQL query complexity accepted with the public server profile"
+    )]
+    pub public_query_complexity: usize,
+    #[clap(
+        long,
+        value_name = "PUBLIC_QUERY_DEPTH",
+        env = "PUBLIC_QUERY_DEPTH",
+        default_value_t = 10,
+        help = "Deepest GraphQL query accepted with the public server profile"
+    )]
+    pub public_query_depth: usize,
+    #[clap(
+        long,
+        value_name = "PUBLIC_RATE_LIMIT",
+        env = "PUBLIC_RATE_LIMIT",
+        default_value_t = 60,
+        help = "Requests per minute accepted from a client address with the public server profile"
+    )]
+    pub public_rate_limit: u32,
+    #[clap(
+        long,
+        value_name = "RATE_LIMIT",
+        env = "RATE_LIMIT",
+        help = "Requests per minute accepted from a client address with any server profile, unlimited when unset. The public profile applies the lower of this and PUBLIC_RATE_LIMIT"
+    )]
+    pub rate_limit: Option<u32>,
+    #[clap(
+        long,
+        value_name = "MAX_QUERY_COMPLEXITY",
+        env = "MAX_QUERY_COMPLEXITY",
+        help = "Highest GraphQL query complexity accepted with any server profile, unlimited when unset"
+    )]
+    pub max_query_complexity: Option<usize>,
+    #[clap(
+        long,
+        value_name = "MAX_QUERY_DEPTH",
+        env = "MAX_QUERY_DEPTH",
+        help = "Deepest GraphQL query accepted with any server profile, unlimited when unset"
+    )]
+    pub max_query_depth: Option<usize>,
+    #[clap(
+        long,
+        value_name = "TOKEN=ROLE",
+        value_delimiter = ',',
+        env = "API_TOKENS",
+        value_parser = Config::parse_api_token,
+        help = "Comma separated static API tokens with their role (viewer, analyst or admin), sent as bearer tokens next to the API keys created through the API"
+    )]
+    pub api_tokens: Vec<(String, Role)>,
+    #[clap(
+        long,
+        env = "API_AUTH_REQUIRED",
+        help = "Reject requests without a known token even before any API token or key is set up, instead of letting them read. The first admin token comes from API_TOKENS either way, unless API_OPEN is set"
+    )]
+    pub api_auth_required: bool,
+    #[clap(
+        long,
+        env = "API_OPEN",
+        conflicts_with = "api_auth_required",
+        help = "Treat every request as admin, mutations included, until an API token or key is set up, to create the first admin key through the API"
+    )]
+    pub api_open: bool,
+    #[clap(
+        long,
+        value_name = "MAX_REQUEST_BYTES",
+        env = "MAX_REQUEST_BYTES",
+        default_value_t = 1_048_576,
+        help = "Largest GraphQL request body accepted, in bytes"
+    )]
+    pub max_request_bytes: usize,
+    #[clap(
+        long,
+        value_name = "MAX_RESULT_ROWS",
+        env = "MAX_RESULT_ROWS",
+        default_value_t = 10_000,
+        help = "Most rows a list query returns, larger results are rejected with an error advising pagination. 0 disables the limit"
+    )]
+    pub max_result_rows: i64,
+    #[clap(
+        long,
+        value_name = "EXPORT_CONCURRENCY",
+        env = "EXPORT_CONCURRENCY",
+        default_value_t = 2,
+        help = "Exports streaming from /api/v1/export at once, each holds a database connection until it ends"
+    )]
+    pub export_concurrency: usize,
+    #[clap(
+        long,
+        value_name = "EXPORT_IDLE_TIMEOUT",
+        env = "EXPORT_IDLE_TIMEOUT",
+        default_value_t = 60,
+        help = "Seconds an export waits for its client to read before it is aborted and its connection released. 0 waits indefinitely"
+    )]
+    pub export_idle_timeout: u64,
+    #[clap(
+        long,
+        value_name = "[CIDR]",
+        value_delimiter = ',',
+        env = "ADMIN_ALLOWLIST",
+        help = "Comma separated networks (e.g. 10.0.0.0/8) allowed to send mutations and admin queries regardless of the API token, any address when empty"
+    )]
+    pub admin_allowlist: Vec<Cidr>,
+    #[clap(
+        long,
+        env = "QUERY_ALLOWLIST",
+        help = "Only execute registered (persisted) GraphQL operations, except for requests with the admin role"
+    )]
+    pub query_allowlist: bool,
+    #[clap(
+        long,
+        value_name = "DIR",
+        env = "PERSISTED_QUERIES_DIR",
+        help = "Directory of .graphql documents registered as persisted queries at startup, named after their file"
+    )]
+    pub persisted_queries_dir: Option<String>,
     #[clap(
         long,
         value_name = "LOG_FORMAT",
@@ -233,6 +590,90 @@ pub struct Config {
         default_value = "pretty"
     )]
     pub log_format: LogFormat,
+    #[clap(
+        long,
+        value_name = "COUNT",
+        env = "RECENT_LOGS",
+        default_value_t = 200,
+        help = "Number of recent warnings and errors kept in memory for the recentLogs query, 0 keeps none"
+    )]
+    pub recent_logs: usize,
+    #[clap(
+        long,
+        value_name = "SUMMARY_LOG_LEVEL",
+        value_enum,
+        env = "SUMMARY_LOG_LEVEL",
+        default_value = "info",
+        help = "Level of the monitoring summary event logged every update interval: off, error, warn, info, debug or trace"
+    )]
+    pub summary_log_level: SummaryLevel,
+    #[clap(
+        long,
+        value_name = "SUMMARY_WEBHOOK",
+        env = "SUMMARY_WEBHOOK",
+        help = "Endpoint receiving the monitoring summary as a JSON POST every update interval"
+    )]
+    pub summary_webhook: Option<String>,
+    #[clap(
+        long,
+        value_name = "HEARTBEAT_FILE",
+        env = "HEARTBEAT_FILE",
+        help = "File the operator loop rewrites about every 15 seconds while it runs, for liveness probes checking its modification time"
+    )]
+    pub heartbeat_file: Option<String>,
+    #[clap(
+        long,
+        value_name = "SHUTDOWN_TIMEOUT",
+        env = "SHUTDOWN_TIMEOUT",
+        default_value_t = 20,
+        help = "Seconds to store the messages received before a SIGINT or SIGTERM and finish open API requests, anything left after is dropped"
+    )]
+    pub shutdown_timeout: u64,
+    #[clap(
+        long,
+        value_name = "HEALTH_MIN_PEERS",
+        env = "HEALTH_MIN_PEERS",
+        default_value_t = 1,
+        help = "Gossip peers below which /health reports the listener degraded"
+    )]
+    pub health_min_peers: u64,
+    #[clap(
+        long,
+        value_name = "HEALTH_MAX_MESSAGE_AGE",
+        env = "HEALTH_MAX_MESSAGE_AGE",
+        default_value_t = 600,
+        help = "Seconds without receiving any message after which /health reports the listener degraded"
+    )]
+    pub health_max_message_age: u64,
+    #[clap(
+        long,
+        value_name = "FILE",
+        env = "CRASH_FILE",
+        help = "File panics are appended to with their location and backtrace, in addition to the crashes table"
+    )]
+    pub crash_file: Option<String>,
+    #[clap(
+        long,
+        value_name = "URL",
+        env = "HANDOFF_FROM",
+        help = "API URL of the listener this instance replaces. Once connected to peers, it asks that instance to drain and takes over the ingest lock"
+    )]
+    pub handoff_from: Option<String>,
+    #[clap(
+        long,
+        value_name = "TOKEN",
+        env = "HANDOFF_TOKEN",
+        help = "Admin API token of the instance given by HANDOFF_FROM"
+    )]
+    pub handoff_token: Option<String>,
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        env = "HANDOFF_TIMEOUT",
+        default_value_t = 300,
+        help = "Longest wait for peers and the ingest lock during a handoff, after which this instance ingests alongside the previous one"
+    )]
+    pub handoff_timeout: u64,
     // This is only used when filtering for specific radios
     #[clap(
         long,
@@ -266,6 +707,373 @@ pub struct Config {
         default_value_t = 1440
     )]
     pub retention: i32,
+    #[clap(
+        long,
+        value_name = "NONCE_SKEW",
+        env = "NONCE_SKEW",
+        default_value_t = 3600,
+        help = "Largest difference in seconds between a message nonce and its receive time for the nonce to date the message in activity and POI windows, the receive time is used otherwise"
+    )]
+    pub nonce_skew: i64,
+    #[clap(
+        long,
+        value_name = "PROCESSING_TIMEOUT",
+        env = "PROCESSING_TIMEOUT",
+        default_value_t = 1000,
+        help = "Time budget in milliseconds for processing a received message, slower messages are moved to dead letters"
+    )]
+    pub processing_timeout: u64,
+    #[clap(
+        long,
+        value_name = "INSERT_BATCH_SIZE",
+        env = "INSERT_BATCH_SIZE",
+        default_value_t = 1,
+        help = "Number of received messages stored with a single insert, 1 stores every message on its own"
+    )]
+    pub insert_batch_size: usize,
+    #[clap(
+        long,
+        value_name = "INSERT_BATCH_INTERVAL",
+        env = "INSERT_BATCH_INTERVAL",
+        default_value_t = 200,
+        help = "Longest time in milliseconds a received message waits for its insert batch to fill"
+    )]
+    pub insert_batch_interval: u64,
+    #[clap(
+        long,
+        value_name = "PROCESSING_WORKERS",
+        env = "PROCESSING_WORKERS",
+        default_value_t = 1,
+        help = "Number of tasks processing received messages concurrently, with more than one messages may be stored out of order"
+    )]
+    pub processing_workers: usize,
+    #[clap(
+        long,
+        value_name = "COLD_STORAGE_URL",
+        env = "COLD_STORAGE_URL",
+        help = "Object storage URL for the cold tier (s3://bucket/prefix, gs://bucket/prefix or file:///path), tiering is off when unset"
+    )]
+    pub cold_storage_url: Option<String>,
+    #[clap(
+        long,
+        value_name = "COLD_STORAGE_AGE",
+        env = "COLD_STORAGE_AGE",
+        default_value_t = 720,
+        help = "Age in minutes after which messages are moved from Postgres to the cold tier, should be smaller than RETENTION"
+    )]
+    pub cold_storage_age: i32,
+    #[clap(
+        long,
+        env = "ARCHIVE_BEFORE_PRUNE",
+        help = "Only delete messages once they are archived to the cold tier and the written object was read back, for RETENTION, MAX_STORAGE and cold tiering alike. Needs COLD_STORAGE_URL"
+    )]
+    pub archive_before_prune: bool,
+    #[clap(
+        long,
+        value_name = "ARCHIVE_URL",
+        env = "ARCHIVE_URL",
+        conflicts_with = "archive_before_prune",
+        help = "Object storage URL (s3://bucket/prefix, gs://bucket/prefix or file:///path) that messages deleted by RETENTION, MAX_STORAGE and on demand prunes are written to as gzip compressed JSONL first, pruning deletes them for good when unset"
+    )]
+    pub archive_url: Option<String>,
+    #[clap(
+        long,
+        value_name = "COVERAGE_WINDOW",
+        env = "COVERAGE_WINDOW",
+        default_value_t = 60,
+        help = "Window in minutes within which a deployment needs a POI message to count as covered"
+    )]
+    pub coverage_window: u64,
+    #[clap(
+        long,
+        value_name = "STATS_TOP_INDEXERS",
+        env = "STATS_TOP_INDEXERS",
+        default_value_t = 20,
+        help = "Number of most active indexers exported as labeled Prometheus gauges, 0 to disable"
+    )]
+    pub stats_top_indexers: usize,
+    #[clap(
+        long,
+        value_name = "METRIC_DEPLOYMENT_LABELS",
+        value_enum,
+        env = "METRIC_DEPLOYMENT_LABELS",
+        default_value = "deployment",
+        help = "Deployment label of per-deployment metrics: deployment, message-type, top (busiest deployments, the rest as other) or hashed (a fixed number of buckets)"
+    )]
+    pub metric_deployment_labels: DeploymentLabels,
+    #[clap(
+        long,
+        value_name = "METRIC_TOP_DEPLOYMENTS",
+        env = "METRIC_TOP_DEPLOYMENTS",
+        default_value_t = 50,
+        help = "Deployments labeled on their own with METRIC_DEPLOYMENT_LABELS=top, by messages over the last day"
+    )]
+    pub metric_top_deployments: usize,
+    #[clap(
+        long,
+        value_name = "METRIC_LABEL_BUCKETS",
+        env = "METRIC_LABEL_BUCKETS",
+        default_value_t = 32,
+        help = "Buckets deployments are hashed into with METRIC_DEPLOYMENT_LABELS=hashed"
+    )]
+    pub metric_label_buckets: u64,
+    #[clap(
+        long,
+        value_name = "TOPIC_SILENCE_THRESHOLD",
+        env = "TOPIC_SILENCE_THRESHOLD",
+        default_value_t = 60,
+        help = "Minutes without messages after which a subscribed topic is reported as silent"
+    )]
+    pub topic_silence_threshold: u64,
+    #[clap(
+        long,
+        value_name = "TOPIC=MINUTES",
+        value_delimiter = ',',
+        env = "TOPIC_SILENCE_THRESHOLDS",
+        value_parser = Config::parse_topic_threshold,
+        help = "Comma separated silence thresholds of specific topics, overriding TOPIC_SILENCE_THRESHOLD"
+    )]
+    pub topic_silence_thresholds: Vec<(String, u64)>,
+    #[clap(
+        long,
+        value_name = "MAX_MESSAGE_RATE",
+        env = "MAX_MESSAGE_RATE",
+        help = "Alert when more messages than this are received per minute for BUDGET_SUSTAIN_MINUTES"
+    )]
+    pub max_message_rate: Option<u64>,
+    #[clap(
+        long,
+        value_name = "MAX_INGEST_BANDWIDTH",
+        env = "MAX_INGEST_BANDWIDTH",
+        help = "Alert when more payload bytes than this are received per minute for BUDGET_SUSTAIN_MINUTES"
+    )]
+    pub max_ingest_bandwidth: Option<u64>,
+    #[clap(
+        long,
+        value_name = "BUDGET_SUSTAIN_MINUTES",
+        env = "BUDGET_SUSTAIN_MINUTES",
+        default_value_t = 5,
+        help = "Consecutive minutes over (or back under) the ingest budget before alerting"
+    )]
+    pub budget_sustain_minutes: u32,
+    #[clap(
+        long,
+        value_name = "NO_PEER_BACKOFF",
+        env = "NO_PEER_BACKOFF",
+        default_value_t = 10,
+        help = "Seconds to wait before checking for peers again once the listener has none, doubled with every check still without peers"
+    )]
+    pub no_peer_backoff: u64,
+    #[clap(
+        long,
+        value_name = "NO_PEER_BACKOFF_MAX",
+        env = "NO_PEER_BACKOFF_MAX",
+        default_value_t = 300,
+        help = "Longest wait in seconds between checks for peers during an outage"
+    )]
+    pub no_peer_backoff_max: u64,
+    #[clap(
+        long,
+        value_name = "NO_PEER_ALERT_AFTER",
+        env = "NO_PEER_ALERT_AFTER",
+        help = "Seconds without any peer before alerting, no alert is sent when unset"
+    )]
+    pub no_peer_alert_after: Option<u64>,
+    #[clap(
+        long,
+        value_name = "BUDGET_SAMPLE_RATE",
+        env = "BUDGET_SAMPLE_RATE",
+        help = "Fraction of messages to keep while over the ingest budget, no sampling when unset"
+    )]
+    pub budget_sample_rate: Option<f64>,
+    #[clap(
+        long,
+        value_name = "CONSENSUS_STRATEGY",
+        value_enum,
+        env = "CONSENSUS_STRATEGY",
+        default_value = "sender-count",
+        help = "POI consensus strategy of divergence alerts and of queries that do not pick one: sender-count or stake-weighted (needs NETWORK_SYNC_INTERVAL)"
+    )]
+    pub consensus_strategy: ConsensusStrategy,
+    #[clap(
+        long,
+        value_name = "DIVERGENCE_CHECK_INTERVAL",
+        env = "DIVERGENCE_CHECK_INTERVAL",
+        help = "Interval in seconds between POI divergence checks over the messages of the previous interval, alerting the notifiers, no checks when unset"
+    )]
+    pub divergence_check_interval: Option<u64>,
+    #[clap(
+        long,
+        value_name = "INSTANCE_NAMESPACE",
+        env = "INSTANCE_NAMESPACE",
+        default_value = "default",
+        help = "Namespace of the stored rows, lets several listeners (e.g. mainnet and testnet) share one database"
+    )]
+    pub instance_namespace: String,
+    #[clap(
+        long,
+        value_name = "[CHECK]",
+        value_enum,
+        value_delimiter = ',',
+        env = "PAYLOAD_CHECKS",
+        default_value = "block-number,poi-format,identifier-format,network",
+        help = "Comma separated payload sanity checks, messages failing a check are quarantined in the dead letters"
+    )]
+    pub payload_checks: Vec<PayloadCheck>,
+    #[clap(
+        long,
+        value_name = "NONCE_ORDERING",
+        value_enum,
+        env = "NONCE_ORDERING",
+        default_value = "flag",
+        help = "Messages with a nonce older than the latest of their sender for the identifier: off, flag to count them as invalid nonce_order messages, or reject to move them to the dead letters"
+    )]
+    pub nonce_ordering: NonceOrdering,
+    #[clap(
+        long,
+        value_name = "[NETWORK]",
+        value_delimiter = ',',
+        env = "NETWORK_ALLOWLIST",
+        help = "Comma separated networks accepted in POI messages, any network is accepted when empty"
+    )]
+    pub network_allowlist: Vec<String>,
+    #[clap(
+        long,
+        value_name = "[TYPE]",
+        value_delimiter = ',',
+        env = "MESSAGE_TYPES",
+        help = "Comma separated message types to decode and store (e.g. public_poi,upgrade_intent,simple), messages of the other known types are dropped. Every type is stored when empty"
+    )]
+    pub message_types: Vec<String>,
+    #[clap(
+        long,
+        value_name = "FILE",
+        env = "PROTO_DESCRIPTORS",
+        help = "Protobuf descriptor set (protoc --include_imports --descriptor_set_out) of payload types without a built-in decoder, matching messages are stored as json"
+    )]
+    pub proto_descriptors: Option<String>,
+    #[clap(
+        long,
+        value_name = "[MESSAGE]",
+        value_delimiter = ',',
+        env = "PROTO_MESSAGE_TYPES",
+        help = "Comma separated fully qualified messages of PROTO_DESCRIPTORS tried as payloads, every message of the set when empty"
+    )]
+    pub proto_message_types: Vec<String>,
+    #[clap(
+        long,
+        value_name = "NOTIFY_CHANNEL",
+        env = "NOTIFY_CHANNEL",
+        help = "Postgres channel receiving a pg_notify with the row id and summary fields of every new message, disabled when unset"
+    )]
+    pub notify_channel: Option<String>,
+    #[clap(
+        long,
+        value_name = "OUTBOX_WEBHOOK",
+        env = "OUTBOX_WEBHOOK",
+        help = "Webhook receiving every stored message through the transactional outbox, the outbox is disabled when unset"
+    )]
+    pub outbox_webhook: Option<String>,
+    #[clap(
+        long,
+        value_name = "OUTBOX_RETENTION",
+        env = "OUTBOX_RETENTION",
+        default_value_t = 1440,
+        help = "Minutes published outbox entries are kept for inspection before they are pruned"
+    )]
+    pub outbox_retention: i32,
+    #[clap(
+        long,
+        value_name = "BIGQUERY_TABLE",
+        env = "BIGQUERY_TABLE",
+        help = "BigQuery table (project.dataset.table) receiving new messages, the export is disabled when unset"
+    )]
+    pub bigquery_table: Option<String>,
+    #[clap(
+        long,
+        value_name = "BIGQUERY_CREDENTIALS",
+        env = "GOOGLE_APPLICATION_CREDENTIALS",
+        help = "Path to the service account key used for the BigQuery export, required with BIGQUERY_TABLE"
+    )]
+    pub bigquery_credentials: Option<String>,
+    #[clap(
+        long,
+        value_name = "BIGQUERY_EXPORT_INTERVAL",
+        env = "BIGQUERY_EXPORT_INTERVAL",
+        default_value_t = 300,
+        help = "Seconds between BigQuery exports"
+    )]
+    pub bigquery_export_interval: u64,
+    #[clap(
+        long,
+        value_name = "BIGQUERY_SAFETY_WINDOW",
+        env = "BIGQUERY_SAFETY_WINDOW",
+        default_value_t = 60,
+        help = "Seconds a message has to be stored before the BigQuery export picks it up, so messages committed after newer ones are not skipped"
+    )]
+    pub bigquery_safety_window: u64,
+    #[clap(
+        long,
+        env = "SKIP_MIGRATIONS",
+        conflicts_with = "migrate_only",
+        help = "Start without running database migrations, for deployments that manage the schema out-of-band"
+    )]
+    pub skip_migrations: bool,
+    #[clap(
+        long,
+        env = "MIGRATE_ONLY",
+        help = "Run pending database migrations and exit, with exit code 1 if the database is unreachable and 2 if a migration fails"
+    )]
+    pub migrate_only: bool,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// One-off tasks run instead of listening
+#[derive(Clone, Debug, clap::Subcommand, Serialize, Deserialize)]
+pub enum Command {
+    /// Write the messages stored under INSTANCE_NAMESPACE to a file and exit
+    Export(ExportArgs),
+    /// Import the messages of a subgraph-radio state file into INSTANCE_NAMESPACE and exit
+    Import(ImportArgs),
+}
+
+#[derive(Clone, Debug, clap::Args, Serialize, Deserialize)]
+pub struct ExportArgs {
+    #[clap(value_name = "PATH", help = "File the messages are written to")]
+    pub path: String,
+    #[clap(
+        long,
+        value_name = "EXPORT_FORMAT",
+        value_enum,
+        env = "EXPORT_FORMAT",
+        default_value = "ndjson",
+        help = "File format of the export: ndjson, csv, parquet or arrow"
+    )]
+    pub format: ExportFormat,
+    #[clap(
+        long,
+        value_name = "EXPORT_FROM",
+        env = "EXPORT_FROM",
+        help = "Only export messages received at or after this unix timestamp"
+    )]
+    pub from: Option<i64>,
+    #[clap(
+        long,
+        value_name = "EXPORT_TO",
+        env = "EXPORT_TO",
+        help = "Only export messages received before this unix timestamp"
+    )]
+    pub to: Option<i64>,
+}
+
+#[derive(Clone, Debug, clap::Args, Serialize, Deserialize)]
+pub struct ImportArgs {
+    #[clap(
+        value_name = "PATH",
+        help = "State file of subgraph-radio, its PERSISTENCE_FILE_PATH"
+    )]
+    pub path: String,
 }
 
 impl Config {
@@ -274,8 +1082,14 @@ impl Config {
         // TODO: load config file before parse (maybe add new level of subcommands)
         let config = Config::parse();
         std::env::set_var("RUST_LOG", config.log_level.clone());
+        set_recent_logs_capacity(config.recent_logs);
+        // Before tracing, so the listener's logs bypass the captured stdout
+        let waku_log_capture = config.waku_log_capture.then(capture_waku_logs);
         // Enables tracing under RUST_LOG variable
-        init_tracing(config.log_format.to_string()).expect("Could not set up global default subscriber for logger, check environmental variable `RUST_LOG` or the CLI input `log-level`");
+        init_tracing(&config.log_format.to_string()).expect("Could not set up global default subscriber for logger, check environmental variable `RUST_LOG` or the CLI input `log-level`");
+        if let Some(Err(e)) = waku_log_capture {
+            warn!(err = e.to_string(), "Could not capture the Waku node logs");
+        }
         config
     }
 
@@ -288,6 +1102,61 @@ impl Config {
         Ok(String::from(value))
     }
 
+    /// Deployment hashes given as bytes32 hex subscribe to the same topic as their CIDv0 form
+    fn parse_topic(value: &str) -> Result<String, String> {
+        Ok(canonical_deployment(value).unwrap_or_else(|| value.to_string()))
+    }
+
+    /// Graphcast namespace of a pubsub topic, the SDK derives the topic from it
+    fn parse_pubsub_topic(value: &str) -> Result<String, String> {
+        let namespace = match value.strip_prefix('/') {
+            Some(topic) => topic
+                .strip_prefix("waku/2/graphcast-v0-")
+                .and_then(|rest| rest.strip_suffix("/proto"))
+                .ok_or_else(|| {
+                    format!(
+                        "Expected a /waku/2/graphcast-v0-<namespace>/proto topic, got {}",
+                        value
+                    )
+                })?,
+            None => value,
+        };
+        if namespace.is_empty() || namespace.contains('/') {
+            return Err(format!("Invalid Graphcast namespace {}", namespace));
+        }
+        Ok(namespace.to_string())
+    }
+
+    /// Graphcast namespace of the pubsub topic joined, PUBSUB_TOPIC or GRAPHCAST_NETWORK
+    pub fn pubsub_namespace(&self) -> String {
+        self.pubsub_topic
+            .clone()
+            .unwrap_or_else(|| self.graphcast_network.to_string())
+    }
+
+    /// Waku pubsub topic joined
+    pub fn pubsub_topic_name(&self) -> String {
+        format!("/waku/2/graphcast-v0-{}/proto", self.pubsub_namespace())
+    }
+
+    fn parse_topic_threshold(value: &str) -> Result<(String, u64), String> {
+        let (topic, minutes) = value
+            .split_once('=')
+            .ok_or_else(|| format!("Expected TOPIC=MINUTES, got {}", value))?;
+        let minutes = minutes
+            .parse()
+            .map_err(|e| format!("Invalid threshold for topic {}: {}", topic, e))?;
+        Ok((Config::parse_topic(topic)?, minutes))
+    }
+
+    fn parse_api_token(value: &str) -> Result<(String, Role), String> {
+        let (token, role) = value
+            .rsplit_once('=')
+            .ok_or_else(|| "Expected TOKEN=ROLE".to_string())?;
+        let role = Role::from_str(role, true)?;
+        Ok((token.to_string(), role))
+    }
+
     /// Private key takes precedence over mnemonic
     pub fn wallet_input(&self) -> Result<&String, ConfigError> {
         match (&self.private_key, &self.mnemonic) {
@@ -305,6 +1174,17 @@ impl Config {
         let wallet_key = self.wallet_input().unwrap().to_string();
         let topics = self.topics.clone();
 
+        // Boot nodes from the ENR trees join the static ones
+        let discovered = discover_nodes(&self.dns_discovery_urls).await;
+        let mut boot_node_addresses = self.boot_node_addresses.clone();
+        boot_node_addresses.extend(discovered.addresses.iter().map(ToString::to_string));
+        let mut discv5_enrs = self.discv5_enrs.clone();
+        if !discovered.enrs.is_empty() {
+            discv5_enrs
+                .get_or_insert_with(Vec::new)
+                .extend(discovered.enrs);
+        }
+
         GraphcastAgentConfig::new(
             wallet_key,
             self.indexer_address.clone().unwrap_or("none".to_string()),
@@ -313,17 +1193,17 @@ impl Config {
             self.network_subgraph.clone(),
             self.id_validation.clone(),
             None,
-            Some(self.boot_node_addresses.clone()),
-            Some(self.graphcast_network.to_string()),
+            Some(boot_node_addresses),
+            Some(self.pubsub_namespace()),
             Some(topics),
             self.waku_node_key.clone(),
             self.waku_host.clone(),
             self.waku_port.clone(),
             self.waku_addr.clone(),
             self.filter_protocol,
-            self.discv5_enrs.clone(),
+            discv5_enrs.clone(),
             self.discv5_port,
-            self.discv5_enrs().clone().unwrap_or_default(),
+            discv5_enrs.unwrap_or_default(),
             Some(cf_nameserver().to_string()),
         )
         .await
@@ -351,3 +1231,62 @@ pub enum ConfigError {
     #[error("Unknown error: {0}")]
     Other(anyhow::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pubsub_topic() {
+        assert_eq!(
+            Config::parse_pubsub_topic("/waku/2/graphcast-v0-devnet/proto"),
+            Ok("devnet".to_string())
+        );
+        assert_eq!(
+            Config::parse_pubsub_topic("devnet"),
+            Ok("devnet".to_string())
+        );
+        assert!(Config::parse_pubsub_topic("/waku/2/default-waku/proto").is_err());
+        assert!(Config::parse_pubsub_topic("/waku/2/graphcast-v0-/proto").is_err());
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let config = Config::try_parse_from([
+            "listener-radio",
+            "--database-url",
+            "postgres://localhost/listener",
+            "export",
+            "messages.csv",
+            "--format",
+            "csv",
+            "--from",
+            "1707328517",
+        ])
+        .unwrap();
+        let Some(Command::Export(args)) = config.command else {
+            panic!("Expected the export command, got {:?}", config.command);
+        };
+        assert_eq!(args.path, "messages.csv");
+        assert_eq!(args.format, ExportFormat::Csv);
+        assert_eq!(args.from, Some(1707328517));
+        assert_eq!(args.to, None);
+
+        let config =
+            Config::try_parse_from(["listener-radio", "export", "messages.ndjson"]).unwrap();
+        assert!(
+            matches!(config.command, Some(Command::Export(args)) if args.format == ExportFormat::Ndjson)
+        );
+        assert!(
+            Config::try_parse_from(["listener-radio", "export", "out", "--format", "xlsx"])
+                .is_err()
+        );
+
+        let config = Config::try_parse_from(["listener-radio", "import", "state.json"]).unwrap();
+        assert!(matches!(config.command, Some(Command::Import(args)) if args.path == "state.json"));
+        assert!(Config::try_parse_from(["listener-radio"])
+            .unwrap()
+            .command
+            .is_none());
+    }
+}
@@ -0,0 +1,382 @@
+//! POI consensus over stored messages. Runs are versioned by methodology so results of
+//! different algorithms over the same history can be compared
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{info, warn};
+
+use crate::db::resolver::{
+    add_consensus_results, create_consensus_run, finish_consensus_run, list_poi_votes,
+    ConsensusResult, PoiVote,
+};
+use crate::{
+    operator::{
+        notifier::Notifier,
+        templates::{Alert, AlertKind},
+    },
+    server::links::ApiLinks,
+    ListenerError,
+};
+
+/// Blocks written per transaction while recomputing
+const RESULT_BATCH: usize = 1000;
+
+#[derive(
+    clap::ValueEnum,
+    async_graphql::Enum,
+    Clone,
+    Copy,
+    Debug,
+    Serialize,
+    Deserialize,
+    Default,
+    PartialEq,
+    Eq,
+)]
+pub enum ConsensusStrategy {
+    /// Every sender has one vote
+    #[default]
+    SenderCount,
+    /// Senders vote with their stake from the synced network snapshot, senders without
+    /// synced stake carry no weight
+    StakeWeighted,
+}
+
+impl ConsensusStrategy {
+    /// Name and version recorded with each run, bump the version when the algorithm changes
+    pub fn methodology(&self) -> &'static str {
+        match self {
+            ConsensusStrategy::SenderCount => "sender_count/v1",
+            ConsensusStrategy::StakeWeighted => "stake_weighted/v1",
+        }
+    }
+
+    fn weight(&self, vote: &PoiVote) -> f64 {
+        match self {
+            ConsensusStrategy::SenderCount => 1.0,
+            ConsensusStrategy::StakeWeighted => vote.stake.unwrap_or_default(),
+        }
+    }
+}
+
+/// POI with the most weight and its share of the total weight, None without any weight.
+/// Ties go to the lexicographically smallest POI so reruns are deterministic
+pub fn majority<'a>(votes: impl IntoIterator<Item = (&'a str, f64)>) -> Option<(String, f64)> {
+    let mut weights: HashMap<&str, f64> = HashMap::new();
+    for (poi, weight) in votes {
+        *weights.entry(poi).or_default() += weight;
+    }
+    let total: f64 = weights.values().sum();
+    if total <= 0.0 {
+        return None;
+    }
+    weights
+        .into_iter()
+        .max_by(|(a_poi, a), (b_poi, b)| a.total_cmp(b).then_with(|| b_poi.cmp(a_poi)))
+        .map(|(poi, weight)| (poi.to_string(), weight / total))
+}
+
+/// Consensus of one deployment and block, and the votes that diverge from it
+pub fn block_consensus(
+    strategy: ConsensusStrategy,
+    votes: &[PoiVote],
+) -> Option<(ConsensusResult, Vec<PoiVote>)> {
+    let first = votes.first()?;
+    let (consensus_poi, support) =
+        majority(votes.iter().map(|v| (v.poi.as_str(), strategy.weight(v))))?;
+    let divergences = votes
+        .iter()
+        .filter(|v| v.poi != consensus_poi)
+        .cloned()
+        .collect();
+    let result = ConsensusResult {
+        deployment: first.deployment.clone(),
+        block_number: first.block_number,
+        consensus_poi,
+        support,
+        senders: votes.len() as i32,
+    };
+    Some((result, divergences))
+}
+
+/// Outcome of a consensus run
+#[derive(Debug, Default)]
+struct RunSummary {
+    blocks: usize,
+    divergences: usize,
+    diverging_indexers: HashSet<String>,
+    diverging_deployments: HashSet<String>,
+}
+
+/// Recompute consensus and divergences into an existing run
+async fn compute_run(
+    pool: &PgPool,
+    namespace: &str,
+    run_id: i64,
+    strategy: ConsensusStrategy,
+    from_timestamp: i64,
+    to_timestamp: i64,
+) -> Result<RunSummary, ListenerError> {
+    let votes = list_poi_votes(pool, namespace, from_timestamp, to_timestamp).await?;
+    let mut results = vec![];
+    let mut divergences = vec![];
+    let mut summary = RunSummary::default();
+    // Votes are ordered by deployment and block
+    for group in
+        votes.chunk_by(|a, b| a.deployment == b.deployment && a.block_number == b.block_number)
+    {
+        if let Some((result, diverging)) = block_consensus(strategy, group) {
+            for vote in &diverging {
+                summary
+                    .diverging_indexers
+                    .insert(vote.graph_account.clone());
+                summary
+                    .diverging_deployments
+                    .insert(vote.deployment.clone());
+            }
+            summary.divergences += diverging.len();
+            results.push(result);
+            divergences.extend(diverging);
+        }
+        if results.len() >= RESULT_BATCH {
+            summary.blocks += results.len();
+            add_consensus_results(pool, run_id, &results, &divergences).await?;
+            results.clear();
+            divergences.clear();
+        }
+    }
+    summary.blocks += results.len();
+    add_consensus_results(pool, run_id, &results, &divergences).await?;
+    Ok(summary)
+}
+
+/// Record a new run for messages sent between `from_timestamp` and `to_timestamp`
+/// Returns the run id, results are filled in by [`recompute_consensus`]
+pub async fn start_consensus_run(
+    pool: &PgPool,
+    namespace: &str,
+    strategy: ConsensusStrategy,
+    from_timestamp: i64,
+    to_timestamp: i64,
+) -> Result<i64, ListenerError> {
+    create_consensus_run(
+        pool,
+        namespace,
+        strategy.methodology(),
+        from_timestamp,
+        to_timestamp,
+    )
+    .await
+}
+
+/// Fill a run with the consensus of every deployment and block in its range, marking it
+/// finished or failed. Earlier runs are kept untouched
+pub async fn recompute_consensus(
+    pool: PgPool,
+    namespace: String,
+    run_id: i64,
+    strategy: ConsensusStrategy,
+    from_timestamp: i64,
+    to_timestamp: i64,
+) {
+    run_consensus(
+        &pool,
+        &namespace,
+        run_id,
+        strategy,
+        from_timestamp,
+        to_timestamp,
+    )
+    .await;
+}
+
+async fn run_consensus(
+    pool: &PgPool,
+    namespace: &str,
+    run_id: i64,
+    strategy: ConsensusStrategy,
+    from_timestamp: i64,
+    to_timestamp: i64,
+) -> Option<RunSummary> {
+    let result = compute_run(
+        pool,
+        namespace,
+        run_id,
+        strategy,
+        from_timestamp,
+        to_timestamp,
+    )
+    .await;
+    let status = match &result {
+        Ok(summary) => {
+            info!(
+                run_id,
+                blocks = summary.blocks,
+                divergences = summary.divergences,
+                methodology = strategy.methodology(),
+                "Recomputed POI consensus"
+            );
+            "finished"
+        }
+        Err(e) => {
+            warn!(
+                run_id,
+                err = tracing::field::debug(e),
+                "Failed to recompute POI consensus"
+            );
+            "failed"
+        }
+    };
+    if let Err(e) = finish_consensus_run(pool, run_id, status).await {
+        warn!(
+            run_id,
+            err = tracing::field::debug(&e),
+            "Failed to update consensus run"
+        );
+    }
+    result.ok()
+}
+
+/// Every `check_interval`, compute the consensus of the messages sent during the previous
+/// interval with `strategy` and alert the notifiers about senders diverging from it
+pub async fn run_divergence_alerts(
+    db: PgPool,
+    namespace: String,
+    strategy: ConsensusStrategy,
+    check_interval: Duration,
+    notifier: Notifier,
+    links: Option<ApiLinks>,
+    running: Arc<AtomicBool>,
+) {
+    let mut check_interval = interval(check_interval);
+    // The first tick completes immediately, there is no previous interval to check yet
+    check_interval.tick().await;
+    let mut from_timestamp = Utc::now().timestamp();
+
+    while running.load(Ordering::SeqCst) {
+        check_interval.tick().await;
+        let to_timestamp = Utc::now().timestamp();
+        let run_id = match start_consensus_run(
+            &db,
+            &namespace,
+            strategy,
+            from_timestamp,
+            to_timestamp,
+        )
+        .await
+        {
+            Ok(id) => id,
+            Err(e) => {
+                warn!(
+                    err = tracing::field::debug(e),
+                    "Failed to start divergence check"
+                );
+                continue;
+            }
+        };
+        let summary = run_consensus(
+            &db,
+            &namespace,
+            run_id,
+            strategy,
+            from_timestamp,
+            to_timestamp,
+        )
+        .await;
+        let window_start = from_timestamp;
+        from_timestamp = to_timestamp;
+        let Some(summary) = summary else {
+            continue;
+        };
+        if summary.divergences > 0 {
+            let mut indexers: Vec<String> = summary.diverging_indexers.into_iter().collect();
+            let mut deployments: Vec<String> = summary.diverging_deployments.into_iter().collect();
+            indexers.sort();
+            deployments.sort();
+            let minutes_ago = (Utc::now().timestamp() - window_start).max(0) as u64 / 60 + 1;
+            let mut alert = Alert::new(AlertKind::Divergence);
+            if let Some(links) = &links {
+                alert = alert
+                    .link("Divergence incidents", links.divergence_incidents(run_id))
+                    .link(
+                        "Diverging indexer stats",
+                        links.indexer_stats(&indexers, minutes_ago),
+                    );
+            }
+            let alert = alert
+                .metric("indexers", indexers.len() as i64)
+                .metric("deployments", deployments.len() as i64)
+                .metric("divergences", summary.divergences as i64)
+                .entities("indexers", indexers)
+                .entities("deployments", deployments)
+                .with("methodology", strategy.methodology())
+                .with("run_id", run_id)
+                .with("from", window_start)
+                .with("to", to_timestamp);
+            notifier.clone().notify(alert).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(graph_account: &str, poi: &str) -> PoiVote {
+        PoiVote {
+            deployment: "QmTamam".to_string(),
+            block_number: 1,
+            graph_account: graph_account.to_string(),
+            poi: poi.to_string(),
+            stake: None,
+        }
+    }
+
+    #[test]
+    fn test_majority() {
+        assert_eq!(majority(vec![]), None);
+        assert_eq!(
+            majority(vec![("0xb", 1.0), ("0xa", 1.0), ("0xb", 2.0)]),
+            Some(("0xb".to_string(), 0.75))
+        );
+        // Ties resolve to the smallest POI
+        assert_eq!(
+            majority(vec![("0xb", 1.0), ("0xa", 1.0)]),
+            Some(("0xa".to_string(), 0.5))
+        );
+    }
+
+    #[test]
+    fn test_block_consensus() {
+        let votes = vec![vote("0x1", "0xa"), vote("0x2", "0xa"), vote("0x3", "0xb")];
+        let (result, divergences) =
+            block_consensus(ConsensusStrategy::SenderCount, &votes).unwrap();
+        assert_eq!(result.consensus_poi, "0xa");
+        assert_eq!(result.senders, 3);
+        assert!((result.support - 2.0 / 3.0).abs() < f64::EPSILON);
+        assert_eq!(divergences, vec![vote("0x3", "0xb")]);
+    }
+
+    #[test]
+    fn test_stake_weighted_consensus() {
+        let mut whale = vote("0x3", "0xb");
+        whale.stake = Some(1000.0);
+        let mut votes = vec![vote("0x1", "0xa"), vote("0x2", "0xa"), whale];
+        let (result, divergences) =
+            block_consensus(ConsensusStrategy::StakeWeighted, &votes).unwrap();
+        assert_eq!(result.consensus_poi, "0xb");
+        assert_eq!(result.support, 1.0);
+        assert_eq!(divergences.len(), 2);
+
+        // Without synced stake there is no consensus
+        votes.pop();
+        assert!(block_consensus(ConsensusStrategy::StakeWeighted, &votes).is_none());
+    }
+}
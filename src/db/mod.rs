@@ -1 +1,149 @@
+use sqlx::{migrate::MigrateError, postgres::PgPoolOptions, PgPool};
+use std::time::Duration;
+use tracing::info;
+
 pub mod resolver;
+pub mod views;
+
+/// Version of the data schema this build reads and writes. Bump it with every migration,
+/// along with [`DATA_SCHEMA_MIGRATION`], so older builds refuse to write against it
+pub const DATA_SCHEMA_VERSION: i32 = 5;
+
+/// Latest migration of [`DATA_SCHEMA_VERSION`], checked against the bundled migrations by
+/// the tests so a new migration cannot ship without a version bump
+const DATA_SCHEMA_MIGRATION: i64 = 20240517090000;
+
+const DATA_SCHEMA_VERSION_KEY: &str = "data_schema_version";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error("Database schema version {found} is newer than version {supported} supported by this build, refusing to write")]
+    TooNew { found: i32, supported: i32 },
+    #[error("Database schema version {found:?} is older than version {supported} required by this build, run the migrations")]
+    TooOld { found: Option<i32>, supported: i32 },
+    #[error("Could not run database migrations: {0}")]
+    Migration(#[from] MigrateError),
+    #[error("Could not read schema metadata: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+pub async fn connect(database_url: &str) -> Result<PgPool, sqlx::Error> {
+    PgPoolOptions::new()
+        .max_connections(50)
+        .acquire_timeout(Duration::from_secs(3))
+        .connect(database_url)
+        .await
+}
+
+/// Apply the migrations bundled with the binary that have not been run yet and record the
+/// data schema version they bring the database to
+pub async fn migrate(pool: &PgPool) -> Result<(), SchemaError> {
+    if let Some(found) = schema_version(pool).await? {
+        if found > DATA_SCHEMA_VERSION {
+            return Err(SchemaError::TooNew {
+                found,
+                supported: DATA_SCHEMA_VERSION,
+            });
+        }
+    }
+
+    sqlx::migrate!().run(pool).await?;
+
+    sqlx::query(
+        r#"
+INSERT INTO schema_metadata ( key, value, updated_at )
+VALUES ( $1, $2, NOW() )
+ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = NOW()
+        "#,
+    )
+    .bind(DATA_SCHEMA_VERSION_KEY)
+    .bind(DATA_SCHEMA_VERSION.to_string())
+    .execute(pool)
+    .await?;
+    info!(
+        version = DATA_SCHEMA_VERSION,
+        migration = DATA_SCHEMA_MIGRATION,
+        "Database schema is up to date"
+    );
+
+    Ok(())
+}
+
+/// Verify that a database migrated out-of-band matches the data schema of this build
+pub async fn check_schema_version(pool: &PgPool) -> Result<i32, SchemaError> {
+    match schema_version(pool).await? {
+        Some(found) if found > DATA_SCHEMA_VERSION => Err(SchemaError::TooNew {
+            found,
+            supported: DATA_SCHEMA_VERSION,
+        }),
+        Some(found) if found == DATA_SCHEMA_VERSION => Ok(found),
+        found => Err(SchemaError::TooOld {
+            found,
+            supported: DATA_SCHEMA_VERSION,
+        }),
+    }
+}
+
+/// Round trip to the database, for health checks
+pub async fn ping(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+/// Data schema version recorded in the database, None before the metadata table exists
+pub async fn schema_version(pool: &PgPool) -> Result<Option<i32>, sqlx::Error> {
+    let exists = sqlx::query_scalar::<_, bool>("SELECT to_regclass('schema_metadata') IS NOT NULL")
+        .fetch_one(pool)
+        .await?;
+    if !exists {
+        return Ok(None);
+    }
+
+    let version =
+        sqlx::query_scalar::<_, String>("SELECT value FROM schema_metadata WHERE key = $1")
+            .bind(DATA_SCHEMA_VERSION_KEY)
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(version.and_then(|v| v.parse().ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_version_covers_migrations() {
+        let latest = sqlx::migrate!()
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| migration.version)
+            .max();
+        assert_eq!(
+            latest,
+            Some(DATA_SCHEMA_MIGRATION),
+            "Bump DATA_SCHEMA_VERSION and DATA_SCHEMA_MIGRATION with the new migration"
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_migrate_records_schema_version(pool: PgPool) {
+        assert_eq!(schema_version(&pool).await.unwrap(), None);
+        migrate(&pool).await.unwrap();
+        assert_eq!(
+            check_schema_version(&pool).await.unwrap(),
+            DATA_SCHEMA_VERSION
+        );
+
+        sqlx::query("UPDATE schema_metadata SET value = $1 WHERE key = $2")
+            .bind((DATA_SCHEMA_VERSION + 1).to_string())
+            .bind(DATA_SCHEMA_VERSION_KEY)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(matches!(
+            migrate(&pool).await,
+            Err(SchemaError::TooNew { .. })
+        ));
+    }
+}
@@ -1,526 +1,5867 @@
 use async_graphql::{OutputType, SimpleObject};
 use chrono::Utc;
-use serde::{de::DeserializeOwned, Serialize};
-use sqlx::{postgres::PgQueryResult, types::Json, FromRow, PgPool, Row as SqliteRow};
+use prost::Message;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::{
+    postgres::PgQueryResult, types::Json, FromRow, PgExecutor, PgPool, Postgres, Row as SqliteRow,
+    Transaction,
+};
 use std::ops::Deref;
-use tracing::trace;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::Instant;
+use tracing::{info, trace, warn};
 
-use crate::server::model::GraphQLRow;
+use crate::{
+    archive::pruned::ArchiveSink,
+    message_types::{GraphcastEnvelope, MESSAGE_SCHEMA_VERSION},
+    metrics::ARCHIVED_MESSAGES,
+    operator::crash::CrashReport,
+    pipeline::MessageOrigin,
+    server::model::GraphQLRow,
+    ListenerError,
+};
 
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct Row<T: Clone + Serialize + DeserializeOwned + OutputType> {
     id: i64,
+    content_hash: String,
     message: Json<T>,
 }
 
-#[allow(dead_code)]
-#[derive(Clone, Debug)]
-pub struct MessageID {
-    id: i64,
-}
-
 #[allow(dead_code)]
 #[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+#[graphql(complex)]
 pub struct IndexerStats {
-    graph_account: String,
-    message_count: i64,
-    subgraphs_count: i64,
+    pub graph_account: String,
+    pub message_count: i64,
+    pub subgraphs_count: i64,
+}
+
+/// A batch of messages moved to the cold tier, stored as one object
+#[derive(FromRow, Debug, Clone)]
+pub struct ColdManifest {
+    pub object_key: String,
+    pub min_id: i64,
+    pub max_id: i64,
+    pub min_nonce: i64,
+    pub max_nonce: i64,
+    pub row_count: i64,
+}
+
+/// A batch of pruned messages written to ARCHIVE_URL, stored as one object
+#[derive(FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveManifest {
+    pub object_key: String,
+    pub min_id: i64,
+    pub max_id: i64,
+    pub min_received_at: i64,
+    pub max_received_at: i64,
+    pub row_count: i64,
+    pub bytes: i64,
+}
+
+/// A pruned message as written to ARCHIVE_URL, with its receive time in unix seconds
+#[derive(FromRow, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrunedRow {
+    pub id: i64,
+    pub content_hash: Option<String>,
+    pub received_at: i64,
+    pub message: serde_json::Value,
+}
+
+/// Largest difference in seconds between a message's nonce and its receive time for the
+/// nonce to date the message. Stored messages keep the result in `message_time`, the payload
+/// nonce when within the skew and the receive time otherwise. A missing or skewed nonce would
+/// otherwise hide an active indexer or count a stale one
+static NONCE_SKEW: AtomicI64 = AtomicI64::new(3600);
+
+pub fn set_nonce_skew(seconds: i64) {
+    NONCE_SKEW.store(seconds, Ordering::SeqCst);
+}
+
+pub fn nonce_skew() -> i64 {
+    NONCE_SKEW.load(Ordering::SeqCst)
+}
+
+/// Advisory lock key shared by the bulk deletes (pruning, cold tiering and API deletes)
+/// so concurrent maintenance operations are serialized instead of deadlocking on rows
+const MAINTENANCE_LOCK: i64 = 0x6c69_7374_656e_6572;
+
+/// Take the maintenance lock for the duration of the transaction
+async fn lock_maintenance(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(MAINTENANCE_LOCK)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Read-only transaction reading every query from the snapshot taken by its first one, so a
+/// multi-query read such as an export stays consistent while pruning deletes rows meanwhile
+pub async fn begin_snapshot(pool: &PgPool) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+/// Debug mode that logs `EXPLAIN ANALYZE` plans and timings for the heavy stats queries,
+/// toggled at runtime through the admin API
+static EXPLAIN_QUERIES: AtomicBool = AtomicBool::new(false);
+
+pub fn set_explain_queries(enabled: bool) {
+    EXPLAIN_QUERIES.store(enabled, Ordering::SeqCst);
+}
+
+pub fn explain_queries() -> bool {
+    EXPLAIN_QUERIES.load(Ordering::SeqCst)
 }
 
 // Define graphql type for the Row in Messages
 impl<T: Clone + Serialize + DeserializeOwned + OutputType> Row<T> {
     pub fn get_graphql_row(&self) -> GraphQLRow<T> {
-        GraphQLRow::new(self.get_id(), self.get_message())
+        GraphQLRow::new(self.get_id(), self.content_hash.clone(), self.get_message())
     }
 
     pub fn get_id(&self) -> i64 {
         self.id
     }
 
+    pub fn get_content_hash(&self) -> &str {
+        &self.content_hash
+    }
+
     pub fn get_message(&self) -> T {
         self.message.clone().deref().clone()
     }
 }
 
-pub async fn add_message<T>(pool: &PgPool, message: T) -> anyhow::Result<i64>
+/// Store a message, idempotent over the message content: the hash of the canonical jsonb
+/// text is unique within a namespace so a message replayed from the store protocol or relayed
+/// twice is skipped
+/// Returns the new row id, or None if the message was already stored
+pub async fn add_message<'e, T, E>(
+    executor: E,
+    namespace: &str,
+    message: T,
+) -> Result<Option<i64>, ListenerError>
 where
-    T: Clone + Serialize + DeserializeOwned + OutputType,
+    T: Serialize + Send,
+    E: PgExecutor<'e>,
+{
+    add_message_from(executor, namespace, message, MessageOrigin::default()).await
+}
+
+/// Store a message along with the peer and topic it was received from. The envelope fields
+/// are copied into their own columns, nonces out of the bigint range are left out. The
+/// sender's stake and allocation on the deployment are looked up in the network snapshot.
+/// Copies differing only in their signature are skipped as duplicates
+pub async fn add_message_from<'e, T, E>(
+    executor: E,
+    namespace: &str,
+    message: T,
+    origin: MessageOrigin<'_>,
+) -> Result<Option<i64>, ListenerError>
+where
+    T: Serialize + Send,
+    E: PgExecutor<'e>,
 {
-    let rec = sqlx::query!(
+    let message = serde_json::to_value(message)
+        .map_err(|e| ListenerError::Decode(format!("Message does not serialize: {}", e)))?;
+    let id = sqlx::query_scalar::<_, i64>(
         r#"
-INSERT INTO messages ( message )
-VALUES ( $1 )
+INSERT INTO messages ( namespace, message, content_hash, schema_version, peer, protocol_version, unknown_bytes, message_type,
+                       nonce, graph_account, identifier, content_topic, sender_valid, sender_stake, sender_allocated_tokens,
+                       message_time )
+VALUES ( $1, $2, message_content_hash($2), $3, $4, $5, $6, $7, $8, $9, $10, $11, $12,
+         (SELECT staked_tokens FROM network_indexers WHERE id = lower($9)),
+         (SELECT SUM(allocated_tokens) FROM network_allocations WHERE indexer = lower($9) AND deployment = $10),
+         CASE WHEN ABS($8 - EXTRACT(EPOCH FROM NOW())::bigint) <= $13 THEN $8
+              ELSE EXTRACT(EPOCH FROM NOW())::bigint END )
+ON CONFLICT (namespace, content_hash) DO NOTHING
 RETURNING id
         "#,
-        Json(message) as _
     )
-    .fetch_one(pool)
+    .bind(namespace)
+    .bind(&message)
+    .bind(MESSAGE_SCHEMA_VERSION)
+    .bind(origin.peer)
+    .bind(origin.protocol_version)
+    .bind(origin.unknown_bytes)
+    .bind(origin.message_type)
+    .bind(message.get("nonce").and_then(serde_json::Value::as_i64))
+    .bind(message.get("graph_account").and_then(serde_json::Value::as_str))
+    .bind(message.get("identifier").and_then(serde_json::Value::as_str))
+    .bind(origin.content_topic)
+    .bind(origin.sender_valid)
+    .bind(nonce_skew())
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(id)
+}
+
+/// Store several messages with a single insert, deduplicated like [`add_message_from`]
+/// against stored messages and within the batch. Returns the new row id of each message in
+/// order, None for the ones already stored
+pub async fn add_messages_from<'e, E: PgExecutor<'e>>(
+    executor: E,
+    namespace: &str,
+    messages: &[(serde_json::Value, MessageOrigin<'_>)],
+) -> Result<Vec<Option<i64>>, ListenerError> {
+    let field = |name: &str| -> Vec<Option<String>> {
+        messages
+            .iter()
+            .map(|(message, _)| {
+                message
+                    .get(name)
+                    .and_then(serde_json::Value::as_str)
+                    .map(str::to_string)
+            })
+            .collect()
+    };
+    let ids = sqlx::query_scalar::<_, Option<i64>>(
+        r#"
+WITH batch AS (
+    SELECT *, message_content_hash(message) AS content_hash
+    FROM UNNEST($3::jsonb[], $4::text[], $5::int[], $6::int[], $7::text[], $8::bigint[], $9::text[], $10::text[], $11::text[], $12::boolean[])
+        WITH ORDINALITY AS b ( message, peer, protocol_version, unknown_bytes, message_type, nonce, graph_account, identifier, content_topic, sender_valid, ord )
+), firsts AS (
+    SELECT DISTINCT ON (content_hash) * FROM batch ORDER BY content_hash, ord
+), inserted AS (
+    INSERT INTO messages ( namespace, message, content_hash, schema_version, peer, protocol_version, unknown_bytes, message_type,
+                           nonce, graph_account, identifier, content_topic, sender_valid, sender_stake, sender_allocated_tokens,
+                           message_time )
+    SELECT $1, message, content_hash, $2, peer, protocol_version, unknown_bytes, message_type,
+           nonce, graph_account, identifier, content_topic, sender_valid,
+           (SELECT staked_tokens FROM network_indexers WHERE id = lower(f.graph_account)),
+           (SELECT SUM(allocated_tokens) FROM network_allocations
+            WHERE indexer = lower(f.graph_account) AND deployment = f.identifier),
+           CASE WHEN ABS(nonce - EXTRACT(EPOCH FROM NOW())::bigint) <= $13 THEN nonce
+                ELSE EXTRACT(EPOCH FROM NOW())::bigint END
+    FROM firsts f
+    ORDER BY ord
+    ON CONFLICT (namespace, content_hash) DO NOTHING
+    RETURNING id, content_hash
+)
+SELECT i.id
+FROM batch b
+LEFT JOIN firsts f ON f.ord = b.ord
+LEFT JOIN inserted i ON i.content_hash = f.content_hash
+ORDER BY b.ord
+        "#,
+    )
+    .bind(namespace)
+    .bind(MESSAGE_SCHEMA_VERSION)
+    .bind(
+        messages
+            .iter()
+            .map(|(message, _)| message.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        messages
+            .iter()
+            .map(|(_, origin)| origin.peer)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        messages
+            .iter()
+            .map(|(_, origin)| origin.protocol_version)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        messages
+            .iter()
+            .map(|(_, origin)| origin.unknown_bytes)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        messages
+            .iter()
+            .map(|(_, origin)| origin.message_type)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        messages
+            .iter()
+            .map(|(message, _)| message.get("nonce").and_then(serde_json::Value::as_i64))
+            .collect::<Vec<_>>(),
+    )
+    .bind(field("graph_account"))
+    .bind(field("identifier"))
+    .bind(
+        messages
+            .iter()
+            .map(|(_, origin)| origin.content_topic)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        messages
+            .iter()
+            .map(|(_, origin)| origin.sender_valid)
+            .collect::<Vec<_>>(),
+    )
+    .bind(nonce_skew())
+    .fetch_all(executor)
+    .await?;
+
+    Ok(ids)
+}
+
+/// Store a message imported from another store, deduplicated like [`add_message_from`]. It
+/// is dated by its nonce rather than the import time, so retention treats it like a message
+/// received back then
+pub async fn import_message<'e, E: PgExecutor<'e>>(
+    executor: E,
+    namespace: &str,
+    message: &serde_json::Value,
+    message_type: &str,
+) -> Result<Option<i64>, ListenerError> {
+    let nonce = message.get("nonce").and_then(serde_json::Value::as_i64);
+    let id = sqlx::query_scalar::<_, i64>(
+        r#"
+INSERT INTO messages ( namespace, message, content_hash, schema_version, message_type,
+                       nonce, graph_account, identifier, created_at, message_time )
+VALUES ( $1, $2, message_content_hash($2), $3, $4,
+         $5, $6, $7, COALESCE(to_timestamp($5), NOW()), COALESCE($5, EXTRACT(EPOCH FROM NOW())::bigint) )
+ON CONFLICT (namespace, content_hash) DO NOTHING
+RETURNING id
+        "#,
+    )
+    .bind(namespace)
+    .bind(message)
+    .bind(MESSAGE_SCHEMA_VERSION)
+    .bind(message_type)
+    .bind(nonce)
+    .bind(
+        message
+            .get("graph_account")
+            .and_then(serde_json::Value::as_str),
+    )
+    .bind(
+        message
+            .get("identifier")
+            .and_then(serde_json::Value::as_str),
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(id)
+}
+
+/// Copy a stored message into the outbox, meant to run in the inserting transaction so an
+/// entry exists for every committed message
+pub async fn add_outbox_entry<'e, E: PgExecutor<'e>>(
+    executor: E,
+    message_id: i64,
+) -> Result<(), ListenerError> {
+    sqlx::query(
+        r#"
+INSERT INTO outbox ( namespace, message_id, payload )
+SELECT namespace, id, message
+FROM messages
+WHERE id = $1
+        "#,
+    )
+    .bind(message_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Entry of the transactional outbox, relayed to the configured sink
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub namespace: String,
+    pub message_id: i64,
+    pub payload: serde_json::Value,
+}
+
+/// Claim the oldest unpublished outbox entries for `lease` seconds, in order. Entries
+/// claimed by another relay are skipped until their lease runs out
+pub async fn claim_outbox_entries(
+    pool: &PgPool,
+    namespace: &str,
+    limit: i64,
+    lease: i64,
+) -> Result<Vec<OutboxEntry>, ListenerError> {
+    let entries = sqlx::query_as::<_, OutboxEntry>(
+        r#"
+WITH claimed AS (
+    UPDATE outbox SET claimed_until = NOW() + make_interval(secs => $3)
+    WHERE id IN (
+        SELECT id FROM outbox
+        WHERE namespace = $1 AND published_at IS NULL
+            AND (claimed_until IS NULL OR claimed_until < NOW())
+        ORDER BY id ASC
+        LIMIT $2
+        FOR UPDATE SKIP LOCKED
+    )
+    RETURNING id, namespace, message_id, payload
+)
+SELECT id, namespace, message_id, payload FROM claimed ORDER BY id ASC
+        "#,
+    )
+    .bind(namespace)
+    .bind(limit)
+    .bind(lease as f64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(entries)
+}
+
+pub async fn mark_outbox_published(pool: &PgPool, ids: &[i64]) -> Result<(), ListenerError> {
+    sqlx::query("UPDATE outbox SET published_at = NOW(), claimed_until = NULL WHERE id = ANY($1)")
+        .bind(ids)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Give up the claim on outbox entries that were not published, so the next relay picks
+/// them up without waiting for the lease
+pub async fn release_outbox_entries(pool: &PgPool, ids: &[i64]) -> Result<(), ListenerError> {
+    sqlx::query(
+        "UPDATE outbox SET claimed_until = NULL WHERE id = ANY($1) AND published_at IS NULL",
+    )
+    .bind(ids)
+    .execute(pool)
     .await?;
 
-    Ok(rec.id)
+    Ok(())
+}
+
+/// Drop outbox entries published more than `retention` minutes ago
+pub async fn prune_outbox(
+    pool: &PgPool,
+    namespace: &str,
+    retention: i32,
+) -> Result<i64, ListenerError> {
+    let deleted = sqlx::query(
+        r#"
+DELETE FROM outbox
+WHERE namespace = $1 AND published_at < NOW() - make_interval(mins => $2)
+        "#,
+    )
+    .bind(namespace)
+    .bind(retention)
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted as i64)
+}
+
+/// Notify listeners of `channel` about a newly stored message, the payload is the summary
+/// json extended with the row id. Within a transaction, the notification is only delivered
+/// once it commits
+pub async fn notify_message<'e, E: PgExecutor<'e>>(
+    executor: E,
+    channel: &str,
+    id: i64,
+    mut summary: serde_json::Value,
+) -> Result<(), ListenerError> {
+    summary["id"] = id.into();
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(channel)
+        .bind(summary.to_string())
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Take the session advisory lock held by the instance ingesting into `namespace`, without
+/// waiting. The lock belongs to the connection of `executor` until released or closed
+pub async fn try_ingest_lock<'e, E: PgExecutor<'e>>(
+    executor: E,
+    namespace: &str,
+) -> Result<bool, ListenerError> {
+    let locked =
+        sqlx::query_scalar::<_, bool>("SELECT pg_try_advisory_lock(hashtext('ingest:' || $1))")
+            .bind(namespace)
+            .fetch_one(executor)
+            .await?;
+
+    Ok(locked)
+}
+
+pub async fn release_ingest_lock<'e, E: PgExecutor<'e>>(
+    executor: E,
+    namespace: &str,
+) -> Result<bool, ListenerError> {
+    let released =
+        sqlx::query_scalar::<_, bool>("SELECT pg_advisory_unlock(hashtext('ingest:' || $1))")
+            .bind(namespace)
+            .fetch_one(executor)
+            .await?;
+
+    Ok(released)
+}
+
+fn into_row<T>((id, content_hash, message): (i64, String, Json<T>)) -> Row<T>
+where
+    T: Clone + Serialize + DeserializeOwned + OutputType,
+{
+    Row {
+        id,
+        content_hash,
+        message,
+    }
 }
 
-pub async fn list_messages<T>(pool: &PgPool) -> Result<Vec<Row<T>>, anyhow::Error>
+/// Stored messages in insertion order, the first `limit` when set. Pages start after the row
+/// id `after` when set, then skip `offset` rows
+pub async fn list_messages<T>(
+    pool: &PgPool,
+    namespace: &str,
+    after: Option<i64>,
+    offset: i64,
+    limit: Option<i64>,
+) -> Result<Vec<Row<T>>, anyhow::Error>
 where
-    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin,
+    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin + 'static,
 {
-    let rows = sqlx::query_as!(
-        Row,
+    let rows = sqlx::query_as::<_, (i64, String, Json<T>)>(
         r#"
-SELECT id, message as "message: Json<T>"
+SELECT id, content_hash, message
 FROM messages
+WHERE namespace = $1 AND ($2::bigint IS NULL OR id > $2)
 ORDER BY id
-        "#
+OFFSET $3
+LIMIT $4
+        "#,
     )
+    .bind(namespace)
+    .bind(after)
+    .bind(offset)
+    .bind(limit)
     .fetch_all(pool)
     .await
     .map_err(|e| {
         trace!("Database resolver connection error: {:#?}", e);
         e
-    })?;
+    })?
+    .into_iter()
+    .map(into_row)
+    .collect();
 
     Ok(rows)
 }
 
-pub async fn count_messages(pool: &PgPool) -> anyhow::Result<i64> {
-    let result = sqlx::query!(
+/// Receive time of the newest stored message in unix seconds, None for an empty namespace
+pub async fn latest_message_time(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<Option<i64>, ListenerError> {
+    let latest = sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT EXTRACT(EPOCH FROM MAX(created_at))::bigint FROM messages WHERE namespace = $1",
+    )
+    .bind(namespace)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(latest)
+}
+
+pub async fn count_messages(pool: &PgPool, namespace: &str) -> Result<i64, ListenerError> {
+    let count = sqlx::query_scalar::<_, i64>(
         r#"
-        SELECT COUNT(*) as "count!: i64"
+        SELECT COUNT(*)
         FROM messages
-        "#
+        WHERE namespace = $1
+        "#,
     )
+    .bind(namespace)
     .fetch_one(pool)
     .await
     .map_err(|e| {
         trace!("Database query error: {:#?}", e);
-        anyhow::Error::new(e)
+        ListenerError::Storage(e)
     })?;
 
-    Ok(result.count)
+    Ok(count)
+}
+
+/// Number of distinct subgraph deployments messages were received about since `from_timestamp`
+pub async fn count_distinct_subgraphs(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+) -> Result<i64, ListenerError> {
+    let query = "SELECT COUNT(DISTINCT identifier) FROM messages WHERE message_time > $1 AND namespace = $2";
+    let count = sqlx::query_scalar::<_, i64>(query)
+        .bind(from_timestamp)
+        .bind(namespace)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(count)
+}
+
+/// Number of distinct deployments with at least one public POI message since `from_timestamp`
+pub async fn count_covered_deployments(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+) -> Result<i64, ListenerError> {
+    let query = "SELECT COUNT(DISTINCT identifier) FROM messages \
+         WHERE message_time > $1 AND namespace = $2 \
+         AND (message->'payload' ? 'block_number' OR message ? 'block_number')";
+    let count = sqlx::query_scalar::<_, i64>(query)
+        .bind(from_timestamp)
+        .bind(namespace)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(count)
+}
+
+/// Identifiers with the most messages since `from_timestamp`, busiest first
+pub async fn busiest_identifiers(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+    limit: i64,
+) -> Result<Vec<String>, ListenerError> {
+    let query = "SELECT identifier FROM messages \
+         WHERE message_time > $1 AND namespace = $2 AND identifier IS NOT NULL \
+         GROUP BY identifier ORDER BY COUNT(*) DESC, identifier LIMIT $3";
+    let identifiers = sqlx::query_scalar::<_, String>(query)
+        .bind(from_timestamp)
+        .bind(namespace)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(identifiers)
 }
 
-pub async fn list_rows<T>(pool: &PgPool) -> Result<Vec<GraphQLRow<T>>, anyhow::Error>
+pub async fn list_rows<T>(
+    pool: &PgPool,
+    namespace: &str,
+    after: Option<i64>,
+    offset: i64,
+    limit: Option<i64>,
+) -> Result<Vec<GraphQLRow<T>>, anyhow::Error>
 where
-    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin,
+    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin + 'static,
 {
-    let rows = sqlx::query_as!(
-        Row,
-        r#"
-SELECT id, message as "message: Json<T>"
-FROM messages
-ORDER BY id
-        "#
-    )
-    .fetch_all(pool)
-    .await?
-    .iter()
-    .map(|r| r.get_graphql_row())
-    .collect::<Vec<GraphQLRow<T>>>();
+    let rows = list_messages(pool, namespace, after, offset, limit)
+        .await?
+        .iter()
+        .map(|r| r.get_graphql_row())
+        .collect::<Vec<GraphQLRow<T>>>();
+
+    Ok(rows)
+}
+
+/// Conditions on stored messages, unset fields match every message
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageFilter {
+    pub graph_account: Option<String>,
+    pub identifier: Option<String>,
+    pub network: Option<String>,
+    pub nonce_gte: Option<i64>,
+    pub nonce_lte: Option<i64>,
+    /// Name of a registered message type, such as `public_poi`
+    pub message_type: Option<String>,
+    pub content_topic: Option<String>,
+    /// Outcome of the sender identity check, messages stored without it match neither
+    pub sender_valid: Option<bool>,
+    /// Received at or after, in unix seconds
+    pub received_from: Option<i64>,
+    /// Received before, in unix seconds
+    pub received_to: Option<i64>,
+}
+
+/// Payloads stored before the [`crate::message_types::StoredMessage`] layout are read from
+/// the top level
+const MESSAGE_FILTER: &str = "namespace = $1 \
+    AND ($2::text IS NULL OR graph_account = $2) \
+    AND ($3::text IS NULL OR identifier = $3) \
+    AND ($4::text IS NULL OR COALESCE(message->'payload'->>'network', message->>'network') = $4) \
+    AND ($5::bigint IS NULL OR nonce >= $5) \
+    AND ($6::bigint IS NULL OR nonce <= $6) \
+    AND ($7::text IS NULL OR message_type = $7) \
+    AND ($8::text IS NULL OR content_topic = $8) \
+    AND ($9::boolean IS NULL OR sender_valid = $9) \
+    AND ($10::bigint IS NULL OR created_at >= to_timestamp($10)) \
+    AND ($11::bigint IS NULL OR created_at < to_timestamp($11))";
+
+/// Messages matching `filter` in insertion order, paginated like [`list_messages`]
+pub async fn list_filtered_messages<T>(
+    pool: &PgPool,
+    namespace: &str,
+    filter: &MessageFilter,
+    after: Option<i64>,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<Row<T>>, ListenerError>
+where
+    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin + 'static,
+{
+    let query = format!(
+        "SELECT id, content_hash, message FROM messages \
+         WHERE {} AND ($12::bigint IS NULL OR id > $12) \
+         ORDER BY id OFFSET $13 LIMIT $14",
+        MESSAGE_FILTER
+    );
+    let rows = sqlx::query_as::<_, (i64, String, Json<T>)>(&query)
+        .bind(namespace)
+        .bind(&filter.graph_account)
+        .bind(&filter.identifier)
+        .bind(&filter.network)
+        .bind(filter.nonce_gte)
+        .bind(filter.nonce_lte)
+        .bind(&filter.message_type)
+        .bind(&filter.content_topic)
+        .bind(filter.sender_valid)
+        .bind(filter.received_from)
+        .bind(filter.received_to)
+        .bind(after)
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(into_row)
+        .collect();
 
     Ok(rows)
 }
 
-pub async fn message_by_id<T>(pool: &PgPool, id: i64) -> Result<Row<T>, anyhow::Error>
+pub async fn count_filtered_messages(
+    pool: &PgPool,
+    namespace: &str,
+    filter: &MessageFilter,
+) -> Result<i64, ListenerError> {
+    let query = format!("SELECT COUNT(*) FROM messages WHERE {}", MESSAGE_FILTER);
+    let count = sqlx::query_scalar::<_, i64>(&query)
+        .bind(namespace)
+        .bind(&filter.graph_account)
+        .bind(&filter.identifier)
+        .bind(&filter.network)
+        .bind(filter.nonce_gte)
+        .bind(filter.nonce_lte)
+        .bind(&filter.message_type)
+        .bind(&filter.content_topic)
+        .bind(filter.sender_valid)
+        .bind(filter.received_from)
+        .bind(filter.received_to)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(count)
+}
+
+pub async fn message_by_id<T>(
+    pool: &PgPool,
+    namespace: &str,
+    id: i64,
+) -> Result<Row<T>, anyhow::Error>
 where
-    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin,
+    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin + 'static,
 {
-    let row = sqlx::query_as!(
-        Row,
+    let row = sqlx::query_as::<_, (i64, String, Json<T>)>(
         r#"
-SELECT id, message as "message: Json<T>"
+SELECT id, content_hash, message
 FROM messages
-WHERE id = $1
+WHERE namespace = $1 AND id = $2
         "#,
-        id
     )
+    .bind(namespace)
+    .bind(id)
     .fetch_one(pool)
     .await?;
 
-    Ok(row)
+    Ok(into_row(row))
 }
 
-pub async fn delete_message_by_id<T>(pool: &PgPool, id: i64) -> Result<Row<T>, anyhow::Error>
+/// Message by its content hash, which unlike the row id is the same on every instance and
+/// survives exports and re-imports
+pub async fn message_by_hash<T>(
+    pool: &PgPool,
+    namespace: &str,
+    content_hash: &str,
+) -> Result<Row<T>, anyhow::Error>
 where
-    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin,
+    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin + 'static,
 {
-    let row = sqlx::query_as!(
-        Row,
+    let row = sqlx::query_as::<_, (i64, String, Json<T>)>(
         r#"
-DELETE
+SELECT id, content_hash, message
 FROM messages
-WHERE id = $1
-RETURNING id, message as "message: Json<T>"
+WHERE namespace = $1 AND content_hash = $2
         "#,
-        id
     )
+    .bind(namespace)
+    .bind(content_hash)
     .fetch_one(pool)
     .await?;
 
-    Ok(row)
+    Ok(into_row(row))
 }
 
-pub async fn delete_message_all<T>(pool: &PgPool) -> Result<Vec<Row<T>>, anyhow::Error>
+pub async fn delete_message_by_id<T>(
+    pool: &PgPool,
+    namespace: &str,
+    id: i64,
+) -> Result<Row<T>, anyhow::Error>
 where
-    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin,
+    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin + 'static,
 {
-    let rows = sqlx::query_as!(
-        Row,
+    let row = sqlx::query_as::<_, (i64, String, Json<T>)>(
         r#"
 DELETE
 FROM messages
-RETURNING id, message as "message: Json<T>"
-        "#
+WHERE namespace = $1 AND id = $2
+RETURNING id, content_hash, message
+        "#,
     )
-    .fetch_all(pool)
+    .bind(namespace)
+    .bind(id)
+    .fetch_one(pool)
     .await?;
 
-    Ok(rows)
+    Ok(into_row(row))
 }
 
-/// Function to automatically prune older messages and keep the `max_storage` newest messages
-/// We prune from the smallest id by the automcatic ascending behavior
-/// Return the number of messages deleted
-pub async fn retain_max_storage(pool: &PgPool, max_storage: usize) -> Result<i64, anyhow::Error> {
-    // find out the IDs of the top `max_storage` newest messages.
-    let top_ids: Vec<i64> = sqlx::query_as!(
-        MessageID,
+/// Content hashes of stored message texts, in order, for messages read back from outside the
+/// table such as the cold tier
+pub async fn content_hashes(
+    pool: &PgPool,
+    messages: &[String],
+) -> Result<Vec<String>, anyhow::Error> {
+    let hashes = sqlx::query_scalar::<_, String>(
         r#"
-SELECT id
-FROM messages
-ORDER BY id DESC
-LIMIT $1
+SELECT message_content_hash(m::jsonb)
+FROM unnest($1::text[]) WITH ORDINALITY AS t(m, ord)
+ORDER BY ord
         "#,
-        max_storage as i64
     )
+    .bind(messages)
     .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|row| row.id)
-    .collect::<Vec<i64>>();
+    .await?;
 
-    trace!(top_ids = tracing::field::debug(&top_ids), "IDs to keep");
+    Ok(hashes)
+}
 
-    // Then, delete all messages except those with the above IDs.
-    let deleted_ids = sqlx::query!(
+pub async fn delete_message_by_hash<T>(
+    pool: &PgPool,
+    namespace: &str,
+    content_hash: &str,
+) -> Result<Row<T>, anyhow::Error>
+where
+    T: Clone + Serialize + DeserializeOwned + OutputType + std::marker::Unpin + 'static,
+{
+    let row = sqlx::query_as::<_, (i64, String, Json<T>)>(
         r#"
 DELETE
 FROM messages
-WHERE id NOT IN (SELECT unnest($1::int8[]))
-RETURNING id
+WHERE namespace = $1 AND content_hash = $2
+RETURNING id, content_hash, message
         "#,
-        &top_ids
     )
-    .fetch_all(pool)
-    .await?
-    .len();
+    .bind(namespace)
+    .bind(content_hash)
+    .fetch_one(pool)
+    .await?;
 
-    Ok(deleted_ids.try_into().unwrap())
+    Ok(into_row(row))
 }
 
-/// Function to delete messages older than `retention` minutes in batches
-/// Returns the total number of messages deleted
-/// Arguments:
-/// - `pool`: &PgPool - A reference to the PostgreSQL connection pool
-/// - `retention`: i32 - The retention time in minutes
-/// - `batch_size`: i64 - The number of messages to delete in each batch
-pub async fn prune_old_messages(
-    pool: &PgPool,
-    retention: i32,
-    batch_size: i64,
-) -> Result<i64, anyhow::Error> {
-    let cutoff_nonce = Utc::now().timestamp() - (retention as i64 * 60);
-    let mut total_deleted = 0i64;
+/// Deletion of every message stored up to `max_id`, run in batches. The id is the
+/// continuation token of an interrupted deletion
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone, PartialEq, Eq)]
+#[graphql(complex)]
+pub struct MessageDeletion {
+    pub id: i64,
+    /// Newest message id covered, messages stored after the deletion started are kept
+    pub max_id: i64,
+    /// Messages deleted so far
+    pub deleted: i64,
+    /// running, finished or failed. Deletions interrupted by a shutdown stay running
+    pub status: String,
+    pub started_at: i64,
+    pub updated_at: i64,
+    pub finished_at: Option<i64>,
+}
 
-    loop {
-        let delete_query = sqlx::query(
-            r#"
-            WITH deleted AS (
-                SELECT id
-                FROM messages
-                WHERE (message->>'nonce')::bigint < $1
-                ORDER BY id ASC
-                LIMIT $2
-                FOR UPDATE SKIP LOCKED
-            )
-            DELETE FROM messages
-            WHERE id IN (SELECT id FROM deleted)
-            RETURNING id
-            "#,
-        )
-        .bind(cutoff_nonce)
-        .bind(batch_size);
+const MESSAGE_DELETION_COLUMNS: &str = "id, max_id, deleted, status, \
+    EXTRACT(EPOCH FROM started_at)::bigint AS started_at, \
+    EXTRACT(EPOCH FROM updated_at)::bigint AS updated_at, \
+    EXTRACT(EPOCH FROM finished_at)::bigint AS finished_at";
 
-        let result: PgQueryResult = delete_query.execute(pool).await?;
-        let deleted_count = result.rows_affected() as i64;
+/// Record a deletion of every message currently stored in the namespace
+pub async fn create_message_deletion(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<MessageDeletion, ListenerError> {
+    let query = format!(
+        "INSERT INTO message_deletions ( namespace, max_id ) \
+         SELECT $1, COALESCE(MAX(id), 0) FROM messages WHERE namespace = $1 \
+         RETURNING {}",
+        MESSAGE_DELETION_COLUMNS
+    );
+    let deletion = sqlx::query_as::<_, MessageDeletion>(&query)
+        .bind(namespace)
+        .fetch_one(pool)
+        .await?;
 
-        total_deleted += deleted_count;
+    Ok(deletion)
+}
 
-        // Break the loop if we deleted fewer rows than the batch size, indicating we've processed all eligible messages.
-        if deleted_count < batch_size {
-            break;
-        }
-    }
+pub async fn message_deletion(
+    pool: &PgPool,
+    namespace: &str,
+    id: i64,
+) -> Result<Option<MessageDeletion>, ListenerError> {
+    let query = format!(
+        "SELECT {} FROM message_deletions WHERE namespace = $1 AND id = $2",
+        MESSAGE_DELETION_COLUMNS
+    );
+    let deletion = sqlx::query_as::<_, MessageDeletion>(&query)
+        .bind(namespace)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
 
-    Ok(total_deleted)
+    Ok(deletion)
 }
 
-pub async fn list_active_indexers(
+/// Delete the `batch_size` oldest messages left to a deletion, counting them in the same
+/// transaction so its progress matches the table after an interruption
+/// Returns the number of messages deleted, below `batch_size` once nothing is left
+pub async fn delete_message_batch(
     pool: &PgPool,
-    indexers: Option<Vec<String>>,
-    from_timestamp: i64,
-) -> Result<Vec<String>, anyhow::Error> {
-    let mut query = String::from("SELECT DISTINCT message->>'graph_account' as graph_account FROM messages WHERE (CAST(message->>'nonce' AS BIGINT)) > $1");
-
-    // Dynamically add placeholders for indexers if provided.
-    if let Some(ref idxs) = indexers {
-        let placeholders = idxs
-            .iter()
-            .enumerate()
-            .map(|(i, _)| format!("${}", i + 2))
-            .collect::<Vec<_>>()
-            .join(",");
-        query.push_str(&format!(
-            " AND (message->>'graph_account') IN ({})",
-            placeholders
-        ));
-    }
+    namespace: &str,
+    deletion: &MessageDeletion,
+    batch_size: i64,
+) -> Result<i64, ListenerError> {
+    let mut tx = pool.begin().await?;
+    lock_maintenance(&mut tx).await?;
 
-    let mut query = sqlx::query(&query).bind(from_timestamp);
+    let deleted = sqlx::query(
+        r#"
+DELETE FROM messages
+WHERE id IN (
+    SELECT id
+    FROM messages
+    WHERE namespace = $1 AND id <= $2
+    ORDER BY id
+    LIMIT $3
+)
+        "#,
+    )
+    .bind(namespace)
+    .bind(deletion.max_id)
+    .bind(batch_size)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected() as i64;
+    sqlx::query(
+        "UPDATE message_deletions SET deleted = deleted + $2, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(deletion.id)
+    .bind(deleted)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(deleted)
+}
+
+pub async fn finish_message_deletion(
+    pool: &PgPool,
+    id: i64,
+    status: &str,
+) -> Result<(), ListenerError> {
+    sqlx::query(
+        "UPDATE message_deletions SET status = $2, updated_at = NOW(), finished_at = NOW() \
+         WHERE id = $1",
+    )
+    .bind(id)
+    .bind(status)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Messages a deletion has left to delete
+pub async fn count_deletion_remaining(
+    pool: &PgPool,
+    namespace: &str,
+    max_id: i64,
+) -> Result<i64, ListenerError> {
+    let count = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM messages WHERE namespace = $1 AND id <= $2",
+    )
+    .bind(namespace)
+    .bind(max_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+/// Messages under a retention hold, which pruning and cold tiering skip
+const NOT_HELD: &str =
+    "NOT EXISTS (SELECT 1 FROM held_messages h WHERE h.message_id = messages.id)";
+
+/// Delete the next batch of at most `batch_size` unheld messages matching `condition`, in
+/// which `$2` is `bound`, after writing them to `archive`. The rows stay locked while the
+/// object is written and its manifest is recorded with the delete, so a failed write deletes
+/// nothing
+/// Returns the number of messages deleted
+async fn archive_prune_batch(
+    tx: &mut Transaction<'_, Postgres>,
+    namespace: &str,
+    archive: &ArchiveSink,
+    condition: &str,
+    bound: i64,
+    batch_size: i64,
+) -> Result<i64, anyhow::Error> {
+    let query = format!(
+        "SELECT id, content_hash, EXTRACT(EPOCH FROM created_at)::bigint AS received_at, message \
+         FROM messages WHERE namespace = $1 AND {} AND {} \
+         ORDER BY id ASC LIMIT $3 FOR UPDATE SKIP LOCKED",
+        condition, NOT_HELD
+    );
+    let rows = sqlx::query_as::<_, PrunedRow>(&query)
+        .bind(namespace)
+        .bind(bound)
+        .bind(batch_size)
+        .fetch_all(&mut **tx)
+        .await?;
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let manifest = archive.write(namespace, &rows).await?;
+    sqlx::query(
+        r#"
+INSERT INTO archive_manifests ( namespace, object_key, min_id, max_id, min_received_at, max_received_at, row_count, bytes )
+VALUES ( $1, $2, $3, $4, $5, $6, $7, $8 )
+        "#,
+    )
+    .bind(namespace)
+    .bind(&manifest.object_key)
+    .bind(manifest.min_id)
+    .bind(manifest.max_id)
+    .bind(manifest.min_received_at)
+    .bind(manifest.max_received_at)
+    .bind(manifest.row_count)
+    .bind(manifest.bytes)
+    .execute(&mut **tx)
+    .await?;
+
+    let ids = rows.iter().map(|row| row.id).collect::<Vec<i64>>();
+    let deleted = sqlx::query("DELETE FROM messages WHERE namespace = $1 AND id = ANY($2)")
+        .bind(namespace)
+        .bind(&ids)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected();
+    ARCHIVED_MESSAGES.inc_by(deleted);
+
+    Ok(deleted as i64)
+}
+
+/// List the objects written to ARCHIVE_URL, oldest first
+pub async fn list_archive_manifests(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<Vec<ArchiveManifest>, ListenerError> {
+    let manifests = sqlx::query_as::<_, ArchiveManifest>(
+        r#"
+SELECT object_key, min_id, max_id, min_received_at, max_received_at, row_count, bytes
+FROM archive_manifests
+WHERE namespace = $1
+ORDER BY min_id ASC
+        "#,
+    )
+    .bind(namespace)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(manifests)
+}
+
+/// Function to automatically prune older messages and keep the `max_storage` newest messages
+/// We prune from the smallest id by the automcatic ascending behavior: everything at or below
+/// the newest id outside of the kept window is deleted in batches of `batch_size`. Held
+/// messages are kept on top of the `max_storage` newest, and every batch is written to
+/// `archive` first when set
+/// Return the number of messages deleted
+pub async fn retain_max_storage(
+    pool: &PgPool,
+    namespace: &str,
+    max_storage: usize,
+    batch_size: i64,
+    archive: Option<&ArchiveSink>,
+) -> Result<i64, anyhow::Error> {
+    let Some(threshold_id) = max_storage_threshold(pool, namespace, max_storage).await? else {
+        return Ok(0);
+    };
+    trace!(threshold_id, "Pruning messages at or below id");
+
+    let mut total_deleted = 0i64;
+    loop {
+        let mut tx = pool.begin().await?;
+        lock_maintenance(&mut tx).await?;
+
+        let query = format!(
+            r#"
+            WITH deleted AS (
+                SELECT id
+                FROM messages
+                WHERE namespace = $1 AND id <= $2 AND {}
+                ORDER BY id ASC
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            DELETE FROM messages
+            WHERE id IN (SELECT id FROM deleted)
+            "#,
+            NOT_HELD
+        );
+        let deleted_count = match archive {
+            Some(archive) => {
+                archive_prune_batch(
+                    &mut tx,
+                    namespace,
+                    archive,
+                    "id <= $2",
+                    threshold_id,
+                    batch_size,
+                )
+                .await?
+            }
+            None => sqlx::query(&query)
+                .bind(namespace)
+                .bind(threshold_id)
+                .bind(batch_size)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected() as i64,
+        };
+
+        tx.commit().await?;
+        total_deleted += deleted_count;
+
+        if deleted_count < batch_size {
+            break;
+        }
+    }
+
+    Ok(total_deleted)
+}
+
+/// Newest id that falls outside of the `max_storage` newest messages, None when everything fits
+pub async fn max_storage_threshold(
+    pool: &PgPool,
+    namespace: &str,
+    max_storage: usize,
+) -> Result<Option<i64>, ListenerError> {
+    let threshold_id = sqlx::query_scalar::<_, i64>(
+        r#"
+SELECT id
+FROM messages
+WHERE namespace = $1
+ORDER BY id DESC
+OFFSET $2
+LIMIT 1
+        "#,
+    )
+    .bind(namespace)
+    .bind(max_storage as i64)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(threshold_id)
+}
+
+/// Oldest unheld messages that pruning would delete, received before `before` (unix seconds)
+/// and at or below `max_id`, for archival ahead of the delete. Returned as (id, nonce,
+/// message) like [`list_messages_before`], with the receive time for messages without a nonce
+pub async fn list_prunable_messages(
+    pool: &PgPool,
+    namespace: &str,
+    before: Option<i64>,
+    max_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<(i64, i64, String)>, anyhow::Error> {
+    let query = format!(
+        "SELECT id, COALESCE(nonce, EXTRACT(EPOCH FROM created_at)::bigint) AS nonce, \
+             message::text AS message FROM messages \
+         WHERE namespace = $1 AND {} \
+             AND ($2::bigint IS NULL OR created_at < to_timestamp($2)) \
+             AND ($3::bigint IS NULL OR id <= $3) \
+         ORDER BY id ASC LIMIT $4",
+        NOT_HELD
+    );
+    let rows = sqlx::query_as::<_, (i64, i64, String)>(&query)
+        .bind(namespace)
+        .bind(before)
+        .bind(max_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows)
+}
+
+/// Function to delete messages received more than `retention` minutes ago in batches
+/// Uses the stored receive time so message types without a nonce are pruned as well, held
+/// messages are kept
+/// Returns the total number of messages deleted
+/// Arguments:
+/// - `pool`: &PgPool - A reference to the PostgreSQL connection pool
+/// - `namespace`: &str - The namespace of the listener instance
+/// - `retention`: i32 - The retention time in minutes
+/// - `batch_size`: i64 - The number of messages to delete in each batch
+/// - `archive`: Option<&ArchiveSink> - Where each batch is written before its deletion
+pub async fn prune_old_messages(
+    pool: &PgPool,
+    namespace: &str,
+    retention: i32,
+    batch_size: i64,
+    archive: Option<&ArchiveSink>,
+) -> Result<i64, anyhow::Error> {
+    let cutoff_timestamp = Utc::now().timestamp() - (retention as i64 * 60);
+    let mut total_deleted = 0i64;
+
+    let query = format!(
+        r#"
+            WITH deleted AS (
+                SELECT id
+                FROM messages
+                WHERE namespace = $1 AND created_at < to_timestamp($2) AND {}
+                ORDER BY id ASC
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            DELETE FROM messages
+            WHERE id IN (SELECT id FROM deleted)
+            RETURNING id
+            "#,
+        NOT_HELD
+    );
+
+    loop {
+        let delete_query = sqlx::query(&query)
+            .bind(namespace)
+            .bind(cutoff_timestamp)
+            .bind(batch_size);
+
+        let mut tx = pool.begin().await?;
+        lock_maintenance(&mut tx).await?;
+        let deleted_count = match archive {
+            Some(archive) => {
+                archive_prune_batch(
+                    &mut tx,
+                    namespace,
+                    archive,
+                    "created_at < to_timestamp($2)",
+                    cutoff_timestamp,
+                    batch_size,
+                )
+                .await?
+            }
+            None => {
+                let result: PgQueryResult = delete_query.execute(&mut *tx).await?;
+                result.rows_affected() as i64
+            }
+        };
+        tx.commit().await?;
+
+        total_deleted += deleted_count;
+
+        // Break the loop if we deleted fewer rows than the batch size, indicating we've processed all eligible messages.
+        if deleted_count < batch_size {
+            break;
+        }
+    }
+
+    Ok(total_deleted)
+}
+
+/// Record a message that could not be processed, keeping the raw Waku payload and
+/// envelope so it can be inspected or reprocessed later
+pub async fn add_dead_letter(
+    pool: &PgPool,
+    namespace: &str,
+    content_topic: &str,
+    payload: &[u8],
+    timestamp: i64,
+    category: &str,
+    error: &str,
+) -> Result<i64, ListenerError> {
+    let id = sqlx::query_scalar::<_, i64>(
+        r#"
+INSERT INTO dead_letters ( namespace, content_topic, payload, timestamp, category, error )
+VALUES ( $1, $2, $3, $4, $5, $6 )
+RETURNING id
+        "#,
+    )
+    .bind(namespace)
+    .bind(content_topic)
+    .bind(payload)
+    .bind(timestamp)
+    .bind(category)
+    .bind(error)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Messages delivered by a peer, unattributed messages are grouped under a null peer
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct PeerShare {
+    peer: Option<String>,
+    message_count: i64,
+    /// Fraction of all messages received in the window
+    share: f64,
+}
+
+/// Delivery share of every peer for messages received since `from_timestamp`
+pub async fn peer_delivery_share(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+) -> Result<Vec<PeerShare>, ListenerError> {
+    let rows = sqlx::query_as::<_, PeerShare>(
+        r#"
+SELECT peer, COUNT(*) AS message_count,
+       COUNT(*)::float8 / SUM(COUNT(*)) OVER () AS share
+FROM messages
+WHERE created_at > to_timestamp($1) AND namespace = $2
+GROUP BY peer
+ORDER BY message_count DESC
+        "#,
+    )
+    .bind(from_timestamp)
+    .bind(namespace)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Messages received of a registered type
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct MessageTypeStats {
+    /// Null for messages stored without a type, such as the ones of library users storing
+    /// messages directly
+    pub message_type: Option<String>,
+    pub message_count: i64,
+    pub sender_count: i64,
+    /// Unix timestamp of the latest message
+    pub last_received_at: i64,
+}
+
+/// Message and sender counts by message type since `from_timestamp`
+pub async fn message_type_stats(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+) -> Result<Vec<MessageTypeStats>, ListenerError> {
+    let rows = sqlx::query_as::<_, MessageTypeStats>(
+        r#"
+SELECT message_type, COUNT(*) AS message_count,
+       COUNT(DISTINCT graph_account) AS sender_count,
+       EXTRACT(EPOCH FROM MAX(created_at))::bigint AS last_received_at
+FROM messages
+WHERE created_at > to_timestamp($1) AND namespace = $2
+GROUP BY message_type
+ORDER BY message_count DESC
+        "#,
+    )
+    .bind(from_timestamp)
+    .bind(namespace)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Messages received on a content topic about one identifier
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct TopicIdentifierStats {
+    /// Null for messages stored without their content topic
+    pub content_topic: Option<String>,
+    pub identifier: Option<String>,
+    pub message_count: i64,
+    pub sender_count: i64,
+    /// Unix timestamp of the earliest message in the window
+    pub first_seen_at: i64,
+    /// Unix timestamp of the latest message
+    pub last_seen_at: i64,
+    /// Summed stake of the senders when their messages were received, in GRT wei, null when
+    /// none had synced stake
+    pub sender_stake: Option<String>,
+    /// Summed allocations of the senders on the identifier, in GRT wei
+    pub sender_allocated_tokens: Option<String>,
+}
+
+/// Message and sender counts by content topic and identifier since `from_timestamp`, busiest
+/// first, optionally on one content topic. Each sender's stake is counted once, at its latest
+/// message
+pub async fn topic_identifier_stats(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+    content_topic: Option<&str>,
+    limit: i64,
+) -> Result<Vec<TopicIdentifierStats>, ListenerError> {
+    let rows = sqlx::query_as::<_, TopicIdentifierStats>(
+        r#"
+SELECT content_topic, identifier, SUM(message_count)::bigint AS message_count,
+       COUNT(graph_account) AS sender_count,
+       EXTRACT(EPOCH FROM MIN(first_seen))::bigint AS first_seen_at,
+       EXTRACT(EPOCH FROM MAX(last_seen))::bigint AS last_seen_at,
+       SUM(sender_stake)::text AS sender_stake,
+       SUM(sender_allocated_tokens)::text AS sender_allocated_tokens
+FROM (
+    SELECT content_topic, identifier, lower(graph_account) AS graph_account,
+           COUNT(*) AS message_count, MIN(created_at) AS first_seen, MAX(created_at) AS last_seen,
+           (array_agg(sender_stake ORDER BY id DESC))[1] AS sender_stake,
+           (array_agg(sender_allocated_tokens ORDER BY id DESC))[1] AS sender_allocated_tokens
+    FROM messages
+    WHERE namespace = $1 AND created_at > to_timestamp($2)
+      AND ($3::text IS NULL OR content_topic = $3)
+    GROUP BY content_topic, identifier, lower(graph_account)
+) senders
+GROUP BY content_topic, identifier
+ORDER BY message_count DESC, content_topic, identifier
+LIMIT $4
+        "#,
+    )
+    .bind(namespace)
+    .bind(from_timestamp)
+    .bind(content_topic)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Payloads of the latest messages of `message_type`, newest first, optionally about one
+/// identifier
+pub async fn list_payloads<T>(
+    pool: &PgPool,
+    namespace: &str,
+    message_type: &str,
+    identifier: Option<&str>,
+    limit: i64,
+) -> Result<Vec<T>, ListenerError>
+where
+    T: DeserializeOwned + Send + Unpin + 'static,
+{
+    let rows = sqlx::query_scalar::<_, Json<T>>(
+        r#"
+SELECT message->'payload'
+FROM messages
+WHERE namespace = $1 AND message_type = $2 AND ($3::text IS NULL OR identifier = $3)
+ORDER BY id DESC
+LIMIT $4
+        "#,
+    )
+    .bind(namespace)
+    .bind(message_type)
+    .bind(identifier)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|Json(payload)| payload).collect())
+}
+
+/// Messages received on a Graphcast protocol version, with the ones carrying fields this
+/// listener could not decode
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct ProtocolCompatibility {
+    /// Null for messages stored without a content topic, such as requeued dead letters
+    pub protocol_version: Option<i32>,
+    pub message_count: i64,
+    /// Messages with undecoded payload fields, usually sent by a newer SDK
+    pub undecoded_count: i64,
+    /// Senders of the messages with undecoded fields
+    pub undecoded_senders: Vec<String>,
+    /// Unix timestamp of the latest message with undecoded fields
+    pub last_undecoded_at: Option<i64>,
+}
+
+/// Compatibility of the messages received since `from_timestamp` by protocol version
+pub async fn protocol_compatibility(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+) -> Result<Vec<ProtocolCompatibility>, ListenerError> {
+    let rows = sqlx::query_as::<_, ProtocolCompatibility>(
+        r#"
+SELECT protocol_version, COUNT(*) AS message_count,
+       COUNT(*) FILTER (WHERE unknown_bytes > 0) AS undecoded_count,
+       COALESCE(array_agg(DISTINCT graph_account)
+           FILTER (WHERE unknown_bytes > 0 AND graph_account IS NOT NULL), '{}') AS undecoded_senders,
+       EXTRACT(EPOCH FROM MAX(created_at) FILTER (WHERE unknown_bytes > 0))::bigint AS last_undecoded_at
+FROM messages
+WHERE created_at > to_timestamp($1) AND namespace = $2
+GROUP BY protocol_version
+ORDER BY protocol_version DESC NULLS LAST
+        "#,
+    )
+    .bind(from_timestamp)
+    .bind(namespace)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Indexer of the synced network subgraph snapshot, token amounts are GRT wei as decimal strings
+#[derive(FromRow, Debug, Clone, PartialEq)]
+pub struct NetworkIndexer {
+    pub id: String,
+    pub staked_tokens: String,
+    pub allocated_tokens: String,
+    pub allocation_count: i32,
+}
+
+/// Active allocation of the synced network subgraph snapshot
+#[derive(FromRow, Debug, Clone, PartialEq)]
+pub struct NetworkAllocation {
+    pub id: String,
+    pub indexer: String,
+    pub deployment: String,
+    pub allocated_tokens: String,
+}
+
+/// Replace the network subgraph snapshot in one transaction, readers never see a partial sync
+pub async fn replace_network_snapshot(
+    pool: &PgPool,
+    indexers: &[NetworkIndexer],
+    allocations: &[NetworkAllocation],
+) -> Result<(), ListenerError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM network_indexers")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM network_allocations")
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+INSERT INTO network_indexers ( id, staked_tokens, allocated_tokens, allocation_count )
+SELECT id, staked::numeric, allocated::numeric, count
+FROM UNNEST($1::text[], $2::text[], $3::text[], $4::int[]) AS t(id, staked, allocated, count)
+        "#,
+    )
+    .bind(
+        indexers
+            .iter()
+            .map(|i| i.id.to_lowercase())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        indexers
+            .iter()
+            .map(|i| i.staked_tokens.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        indexers
+            .iter()
+            .map(|i| i.allocated_tokens.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        indexers
+            .iter()
+            .map(|i| i.allocation_count)
+            .collect::<Vec<_>>(),
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+INSERT INTO network_allocations ( id, indexer, deployment, allocated_tokens )
+SELECT id, indexer, deployment, allocated::numeric
+FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[]) AS t(id, indexer, deployment, allocated)
+        "#,
+    )
+    .bind(allocations.iter().map(|a| a.id.clone()).collect::<Vec<_>>())
+    .bind(
+        allocations
+            .iter()
+            .map(|a| a.indexer.to_lowercase())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        allocations
+            .iter()
+            .map(|a| a.deployment.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        allocations
+            .iter()
+            .map(|a| a.allocated_tokens.clone())
+            .collect::<Vec<_>>(),
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Synced network subgraph entry of an indexer, None if unknown or never synced
+pub async fn network_indexer(
+    pool: &PgPool,
+    id: &str,
+) -> Result<Option<NetworkIndexer>, ListenerError> {
+    let indexer = sqlx::query_as::<_, NetworkIndexer>(
+        r#"
+SELECT id, staked_tokens::text AS staked_tokens, allocated_tokens::text AS allocated_tokens,
+       allocation_count
+FROM network_indexers
+WHERE id = lower($1)
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(indexer)
+}
+
+/// Deployments the indexer has active allocations on in the synced snapshot
+pub async fn list_allocated_deployments(
+    pool: &PgPool,
+    indexer: &str,
+) -> Result<Vec<String>, ListenerError> {
+    let deployments = sqlx::query_scalar::<_, String>(
+        "SELECT DISTINCT deployment FROM network_allocations WHERE indexer = lower($1) ORDER BY deployment",
+    )
+    .bind(indexer)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(deployments)
+}
+
+/// Allocated deployments of the indexer without a message from it since `from_timestamp`
+pub async fn list_coverage_gaps(
+    pool: &PgPool,
+    namespace: &str,
+    indexer: &str,
+    from_timestamp: i64,
+) -> Result<Vec<String>, ListenerError> {
+    let query = "SELECT DISTINCT a.deployment FROM network_allocations a \
+         WHERE a.indexer = lower($3) AND NOT EXISTS ( \
+             SELECT 1 FROM messages \
+             WHERE message_time > $1 AND namespace = $2 \
+             AND lower(graph_account) = lower($3) \
+             AND identifier = a.deployment \
+         ) ORDER BY a.deployment";
+    let deployments = sqlx::query_scalar::<_, String>(query)
+        .bind(from_timestamp)
+        .bind(namespace)
+        .bind(indexer)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(deployments)
+}
+
+/// Public POI fields, read from the payload of the stored layout and from the top level of
+/// rows stored as bare payloads
+const POI_CONTENT: &str = "COALESCE(message->'payload'->>'content', message->>'content')";
+const POI_BLOCK_NUMBER: &str =
+    "COALESCE(message->'payload'->>'block_number', message->>'block_number')::bigint";
+const POI_BLOCK_HASH: &str = "COALESCE(message->'payload'->>'block_hash', message->>'block_hash')";
+const POI_NETWORK: &str = "COALESCE(message->'payload'->>'network', message->>'network')";
+
+/// POI an indexer sent for a deployment and block
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct PoiSubmission {
+    pub message_id: i64,
+    pub graph_account: String,
+    pub poi: String,
+    pub block_hash: Option<String>,
+    pub network: Option<String>,
+    pub nonce: Option<i64>,
+    /// Receive time in unix seconds
+    pub received_at: i64,
+    /// Stake of the indexer at receipt, in GRT wei
+    pub sender_stake: Option<String>,
+    /// Allocation of the indexer on the deployment at receipt, in GRT wei
+    pub sender_allocated_tokens: Option<String>,
+}
+
+/// Latest POI the indexer sent for the deployment at `block_number`
+pub async fn poi_submission(
+    pool: &PgPool,
+    namespace: &str,
+    deployment: &str,
+    block_number: i64,
+    indexer: &str,
+) -> Result<Option<PoiSubmission>, ListenerError> {
+    let query = format!(
+        "SELECT id AS message_id, graph_account, {} AS poi, \
+         {} AS block_hash, {} AS network, nonce, \
+         EXTRACT(EPOCH FROM created_at)::bigint AS received_at, \
+         sender_stake::text AS sender_stake, \
+         sender_allocated_tokens::text AS sender_allocated_tokens \
+         FROM messages \
+         WHERE namespace = $1 AND identifier = $2 AND {} = $3 \
+         AND lower(graph_account) = lower($4) AND {} IS NOT NULL \
+         ORDER BY id DESC LIMIT 1",
+        POI_CONTENT, POI_BLOCK_HASH, POI_NETWORK, POI_BLOCK_NUMBER, POI_CONTENT
+    );
+    let submission = sqlx::query_as::<_, PoiSubmission>(&query)
+        .bind(namespace)
+        .bind(deployment)
+        .bind(block_number)
+        .bind(indexer)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(submission)
+}
+
+/// Latest POI of a sender for a deployment and block
+#[derive(FromRow, Debug, Clone, PartialEq)]
+pub struct PoiVote {
+    pub deployment: String,
+    pub block_number: i64,
+    pub graph_account: String,
+    pub poi: String,
+    /// Staked GRT wei of the sender when the message was received, or in the current network
+    /// snapshot for messages stored before it was synced. None when not synced
+    pub stake: Option<f64>,
+}
+
+/// Votes of the messages matching `filter`, the latest per sender for each deployment and
+/// block, ordered by deployment, block and sender
+fn poi_votes_query(filter: &str) -> String {
+    format!(
+        "SELECT votes.deployment, votes.block_number, votes.graph_account, votes.poi, \
+             COALESCE(votes.sender_stake, n.staked_tokens)::float8 AS stake FROM ( \
+             SELECT DISTINCT ON (identifier, {block}, lower(graph_account)) \
+                 identifier AS deployment, {block} AS block_number, \
+                 lower(graph_account) AS graph_account, {poi} AS poi, sender_stake \
+             FROM messages \
+             WHERE namespace = $1 AND {filter} AND {block} IS NOT NULL AND {poi} IS NOT NULL \
+             ORDER BY identifier, {block}, lower(graph_account), id DESC \
+         ) votes \
+         LEFT JOIN network_indexers n ON n.id = votes.graph_account \
+         ORDER BY votes.deployment, votes.block_number, votes.graph_account",
+        block = POI_BLOCK_NUMBER,
+        poi = POI_CONTENT,
+        filter = filter,
+    )
+}
+
+/// POI votes of messages sent between `from_timestamp` and `to_timestamp`
+pub async fn list_poi_votes(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+    to_timestamp: i64,
+) -> Result<Vec<PoiVote>, ListenerError> {
+    let filter = "message_time >= $2 AND message_time < $3";
+    let votes = sqlx::query_as::<_, PoiVote>(&poi_votes_query(filter))
+        .bind(namespace)
+        .bind(from_timestamp)
+        .bind(to_timestamp)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(votes)
+}
+
+/// POI votes for a deployment at `block_number`, regardless of when they were sent
+pub async fn list_block_votes(
+    pool: &PgPool,
+    namespace: &str,
+    deployment: &str,
+    block_number: i64,
+) -> Result<Vec<PoiVote>, ListenerError> {
+    let filter = format!("identifier = $2 AND {} = $3", POI_BLOCK_NUMBER);
+    let votes = sqlx::query_as::<_, PoiVote>(&poi_votes_query(&filter))
+        .bind(namespace)
+        .bind(deployment)
+        .bind(block_number)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(votes)
+}
+
+/// Consensus recomputation over a time range with one methodology
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct ConsensusRun {
+    pub id: i64,
+    pub methodology: String,
+    pub from_ts: i64,
+    pub to_ts: i64,
+    /// running, finished or failed
+    pub status: String,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+/// Consensus POI of a deployment at a block, `support` is the share of the vote weight
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsensusResult {
+    pub deployment: String,
+    pub block_number: i64,
+    pub consensus_poi: String,
+    pub support: f64,
+    pub senders: i32,
+}
+
+/// Sender whose POI differs from the consensus of a run
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct DivergenceIncident {
+    pub deployment: String,
+    pub block_number: i64,
+    pub graph_account: String,
+    pub poi: String,
+    pub consensus_poi: String,
+}
+
+pub async fn create_consensus_run(
+    pool: &PgPool,
+    namespace: &str,
+    methodology: &str,
+    from_timestamp: i64,
+    to_timestamp: i64,
+) -> Result<i64, ListenerError> {
+    let id = sqlx::query_scalar::<_, i64>(
+        r#"
+INSERT INTO consensus_runs ( namespace, methodology, from_ts, to_ts )
+VALUES ( $1, $2, $3, $4 )
+RETURNING id
+        "#,
+    )
+    .bind(namespace)
+    .bind(methodology)
+    .bind(from_timestamp)
+    .bind(to_timestamp)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(id)
+}
+
+pub async fn finish_consensus_run(
+    pool: &PgPool,
+    run_id: i64,
+    status: &str,
+) -> Result<(), ListenerError> {
+    sqlx::query("UPDATE consensus_runs SET status = $2, finished_at = NOW() WHERE id = $1")
+        .bind(run_id)
+        .bind(status)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Store the consensus and divergences of a batch of blocks for a run
+pub async fn add_consensus_results(
+    pool: &PgPool,
+    run_id: i64,
+    results: &[ConsensusResult],
+    divergences: &[PoiVote],
+) -> Result<(), ListenerError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+INSERT INTO poi_consensus ( run_id, deployment, block_number, consensus_poi, support, senders )
+SELECT $1, * FROM UNNEST($2::text[], $3::bigint[], $4::text[], $5::float8[], $6::int[])
+ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(run_id)
+    .bind(
+        results
+            .iter()
+            .map(|r| r.deployment.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(results.iter().map(|r| r.block_number).collect::<Vec<_>>())
+    .bind(
+        results
+            .iter()
+            .map(|r| r.consensus_poi.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(results.iter().map(|r| r.support).collect::<Vec<_>>())
+    .bind(results.iter().map(|r| r.senders).collect::<Vec<_>>())
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+INSERT INTO divergence_incidents ( run_id, deployment, block_number, graph_account, poi )
+SELECT $1, * FROM UNNEST($2::text[], $3::bigint[], $4::text[], $5::text[])
+ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(run_id)
+    .bind(
+        divergences
+            .iter()
+            .map(|d| d.deployment.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        divergences
+            .iter()
+            .map(|d| d.block_number)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        divergences
+            .iter()
+            .map(|d| d.graph_account.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        divergences
+            .iter()
+            .map(|d| d.poi.clone())
+            .collect::<Vec<_>>(),
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+const CONSENSUS_RUN_COLUMNS: &str = "id, methodology, from_ts, to_ts, status, \
+    EXTRACT(EPOCH FROM started_at)::bigint AS started_at, \
+    EXTRACT(EPOCH FROM finished_at)::bigint AS finished_at";
+
+/// Latest consensus runs, newest first
+pub async fn list_consensus_runs(
+    pool: &PgPool,
+    namespace: &str,
+    limit: i64,
+) -> Result<Vec<ConsensusRun>, ListenerError> {
+    let query = format!(
+        "SELECT {} FROM consensus_runs WHERE namespace = $1 ORDER BY id DESC LIMIT $2",
+        CONSENSUS_RUN_COLUMNS
+    );
+    let runs = sqlx::query_as::<_, ConsensusRun>(&query)
+        .bind(namespace)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(runs)
+}
+
+/// Divergence incidents of a run, optionally for one deployment
+pub async fn list_divergence_incidents(
+    pool: &PgPool,
+    namespace: &str,
+    run_id: i64,
+    deployment: Option<&str>,
+    limit: i64,
+) -> Result<Vec<DivergenceIncident>, ListenerError> {
+    let incidents = sqlx::query_as::<_, DivergenceIncident>(
+        r#"
+SELECT d.deployment, d.block_number, d.graph_account, d.poi, c.consensus_poi
+FROM divergence_incidents d
+JOIN consensus_runs r ON r.id = d.run_id
+JOIN poi_consensus c
+    ON c.run_id = d.run_id AND c.deployment = d.deployment AND c.block_number = d.block_number
+WHERE r.namespace = $1 AND d.run_id = $2 AND ($3::text IS NULL OR d.deployment = $3)
+ORDER BY d.deployment, d.block_number DESC, d.graph_account
+LIMIT $4
+        "#,
+    )
+    .bind(namespace)
+    .bind(run_id)
+    .bind(deployment)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(incidents)
+}
+
+/// Set of messages kept by pruning and cold tiering until released
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct RetentionHold {
+    pub id: i64,
+    pub reason: String,
+    /// Held messages still stored
+    pub message_count: i64,
+    pub created_at: i64,
+}
+
+const RETENTION_HOLD_COLUMNS: &str = "r.id, r.reason, \
+    (SELECT COUNT(*) FROM held_messages h WHERE h.hold_id = r.id) AS message_count, \
+    EXTRACT(EPOCH FROM r.created_at)::bigint AS created_at";
+
+async fn insert_retention_hold(
+    tx: &mut Transaction<'_, Postgres>,
+    namespace: &str,
+    reason: &str,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "INSERT INTO retention_holds ( namespace, reason ) VALUES ( $1, $2 ) RETURNING id",
+    )
+    .bind(namespace)
+    .bind(reason)
+    .fetch_one(&mut **tx)
+    .await
+}
+
+async fn retention_hold<'e, E: PgExecutor<'e>>(
+    executor: E,
+    namespace: &str,
+    id: i64,
+) -> Result<RetentionHold, sqlx::Error> {
+    let query = format!(
+        "SELECT {} FROM retention_holds r WHERE r.namespace = $1 AND r.id = $2",
+        RETENTION_HOLD_COLUMNS
+    );
+    sqlx::query_as::<_, RetentionHold>(&query)
+        .bind(namespace)
+        .bind(id)
+        .fetch_one(executor)
+        .await
+}
+
+/// Hold the messages with the given ids and, when `filter` is set, every message currently
+/// matching it. Messages stored later are not covered
+pub async fn hold_messages(
+    pool: &PgPool,
+    namespace: &str,
+    reason: &str,
+    ids: &[i64],
+    filter: Option<&MessageFilter>,
+) -> Result<RetentionHold, ListenerError> {
+    let mut tx = pool.begin().await?;
+    let hold_id = insert_retention_hold(&mut tx, namespace, reason).await?;
+
+    sqlx::query(
+        r#"
+INSERT INTO held_messages ( hold_id, message_id )
+SELECT $3, id FROM messages WHERE namespace = $1 AND id = ANY($2)
+        "#,
+    )
+    .bind(namespace)
+    .bind(ids)
+    .bind(hold_id)
+    .execute(&mut *tx)
+    .await?;
+
+    if let Some(filter) = filter {
+        let query = format!(
+            "INSERT INTO held_messages ( hold_id, message_id ) \
+             SELECT $12, id FROM messages WHERE {} \
+             ON CONFLICT DO NOTHING",
+            MESSAGE_FILTER
+        );
+        sqlx::query(&query)
+            .bind(namespace)
+            .bind(&filter.graph_account)
+            .bind(&filter.identifier)
+            .bind(&filter.network)
+            .bind(filter.nonce_gte)
+            .bind(filter.nonce_lte)
+            .bind(&filter.message_type)
+            .bind(&filter.content_topic)
+            .bind(filter.sender_valid)
+            .bind(filter.received_from)
+            .bind(filter.received_to)
+            .bind(hold_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let hold = retention_hold(&mut *tx, namespace, hold_id).await?;
+    tx.commit().await?;
+    Ok(hold)
+}
+
+/// Hold the evidence of the divergence incidents of a consensus run: every POI message for
+/// the deployments and blocks that diverged, from all senders
+pub async fn hold_divergence_evidence(
+    pool: &PgPool,
+    namespace: &str,
+    run_id: i64,
+    reason: &str,
+) -> Result<RetentionHold, ListenerError> {
+    let mut tx = pool.begin().await?;
+    let hold_id = insert_retention_hold(&mut tx, namespace, reason).await?;
+
+    let query = format!(
+        "INSERT INTO held_messages ( hold_id, message_id ) \
+         SELECT DISTINCT $3, messages.id FROM messages \
+         JOIN divergence_incidents d \
+             ON d.deployment = messages.identifier AND d.block_number = {} \
+         JOIN consensus_runs r ON r.id = d.run_id \
+         WHERE messages.namespace = $1 AND r.namespace = $1 AND d.run_id = $2",
+        POI_BLOCK_NUMBER
+    );
+    sqlx::query(&query)
+        .bind(namespace)
+        .bind(run_id)
+        .bind(hold_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let hold = retention_hold(&mut *tx, namespace, hold_id).await?;
+    tx.commit().await?;
+    Ok(hold)
+}
+
+/// Release a hold, returns whether it existed. Its messages become prunable again unless
+/// another hold covers them
+pub async fn release_retention_hold(
+    pool: &PgPool,
+    namespace: &str,
+    id: i64,
+) -> Result<bool, ListenerError> {
+    let released = sqlx::query("DELETE FROM retention_holds WHERE namespace = $1 AND id = $2")
+        .bind(namespace)
+        .bind(id)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(released > 0)
+}
+
+pub async fn list_retention_holds(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<Vec<RetentionHold>, ListenerError> {
+    let query = format!(
+        "SELECT {} FROM retention_holds r WHERE r.namespace = $1 ORDER BY r.id",
+        RETENTION_HOLD_COLUMNS
+    );
+    let holds = sqlx::query_as::<_, RetentionHold>(&query)
+        .bind(namespace)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(holds)
+}
+
+/// API key without its secret
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub role: String,
+    /// Unix timestamps
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+const API_KEY_COLUMNS: &str = "id, name, role, \
+    EXTRACT(EPOCH FROM created_at)::bigint AS created_at, \
+    EXTRACT(EPOCH FROM expires_at)::bigint AS expires_at, \
+    EXTRACT(EPOCH FROM revoked_at)::bigint AS revoked_at";
+
+/// Hash of a plain key as stored in `api_keys`
+const API_KEY_HASH: &str = "encode(sha256(convert_to($2, 'UTF8')), 'hex')";
+
+/// Create a key with `role`, valid until the `expires_at` unix timestamp when set.
+/// Returns the key and its secret, which is not stored
+pub async fn create_api_key(
+    pool: &PgPool,
+    namespace: &str,
+    name: &str,
+    role: &str,
+    expires_at: Option<i64>,
+) -> Result<(ApiKey, String), ListenerError> {
+    // Secret made of two random v4 uuids generated by the database
+    let secret = sqlx::query_scalar::<_, String>(
+        "SELECT 'lr_' || replace(gen_random_uuid()::text, '-', '') \
+         || replace(gen_random_uuid()::text, '-', '')",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let query = format!(
+        "INSERT INTO api_keys ( namespace, key_hash, name, role, expires_at ) \
+         VALUES ( $1, {}, $3, $4, to_timestamp($5) ) \
+         RETURNING {}",
+        API_KEY_HASH, API_KEY_COLUMNS
+    );
+    let key = sqlx::query_as::<_, ApiKey>(&query)
+        .bind(namespace)
+        .bind(&secret)
+        .bind(name)
+        .bind(role)
+        .bind(expires_at)
+        .fetch_one(pool)
+        .await?;
+
+    Ok((key, secret))
+}
+
+/// Revoke a key, returns whether an active key was revoked
+pub async fn revoke_api_key(
+    pool: &PgPool,
+    namespace: &str,
+    id: i64,
+) -> Result<bool, ListenerError> {
+    let revoked = sqlx::query(
+        "UPDATE api_keys SET revoked_at = NOW() \
+         WHERE namespace = $1 AND id = $2 AND revoked_at IS NULL",
+    )
+    .bind(namespace)
+    .bind(id)
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(revoked > 0)
+}
+
+pub async fn list_api_keys(pool: &PgPool, namespace: &str) -> Result<Vec<ApiKey>, ListenerError> {
+    let query = format!(
+        "SELECT {} FROM api_keys WHERE namespace = $1 ORDER BY id",
+        API_KEY_COLUMNS
+    );
+    let keys = sqlx::query_as::<_, ApiKey>(&query)
+        .bind(namespace)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(keys)
+}
+
+/// Id and role of an unexpired and unrevoked key, None for unknown keys
+pub async fn api_key_role(
+    pool: &PgPool,
+    namespace: &str,
+    secret: &str,
+) -> Result<Option<(i64, String)>, ListenerError> {
+    let query = format!(
+        "SELECT id, role FROM api_keys \
+         WHERE namespace = $1 AND key_hash = {} AND revoked_at IS NULL \
+         AND (expires_at IS NULL OR expires_at > NOW())",
+        API_KEY_HASH
+    );
+    let role = sqlx::query_as::<_, (i64, String)>(&query)
+        .bind(namespace)
+        .bind(secret)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(role)
+}
+
+/// Count a request of the key and the bytes returned to it
+pub async fn record_api_key_usage(
+    pool: &PgPool,
+    key_id: i64,
+    bytes: i64,
+) -> Result<(), ListenerError> {
+    sqlx::query(
+        r#"
+INSERT INTO api_key_usage ( key_id, requests, bytes, last_used_at )
+VALUES ( $1, 1, $2, NOW() )
+ON CONFLICT (key_id) DO UPDATE
+SET requests = api_key_usage.requests + 1,
+    bytes = api_key_usage.bytes + EXCLUDED.bytes,
+    last_used_at = EXCLUDED.last_used_at
+        "#,
+    )
+    .bind(key_id)
+    .bind(bytes)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Usage totals of an API key
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct ApiKeyUsage {
+    pub key_id: i64,
+    pub name: String,
+    pub role: String,
+    pub requests: i64,
+    /// Bytes of the GraphQL responses returned
+    pub bytes: i64,
+    /// Unix timestamp, null when never used
+    pub last_used_at: Option<i64>,
+}
+
+/// Usage of every key, heaviest consumers by bytes first
+pub async fn list_api_key_usage(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<Vec<ApiKeyUsage>, ListenerError> {
+    let usage = sqlx::query_as::<_, ApiKeyUsage>(
+        r#"
+SELECT k.id AS key_id, k.name, k.role,
+       COALESCE(u.requests, 0) AS requests, COALESCE(u.bytes, 0) AS bytes,
+       EXTRACT(EPOCH FROM u.last_used_at)::bigint AS last_used_at
+FROM api_keys k
+LEFT JOIN api_key_usage u ON u.key_id = k.id
+WHERE k.namespace = $1
+ORDER BY bytes DESC, requests DESC, k.id
+        "#,
+    )
+    .bind(namespace)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(usage)
+}
+
+/// Whether any key was created and not revoked, the API requires a key once one exists
+pub async fn has_api_keys(pool: &PgPool, namespace: &str) -> Result<bool, ListenerError> {
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM api_keys WHERE namespace = $1 AND revoked_at IS NULL)",
+    )
+    .bind(namespace)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists)
+}
+
+/// Operation allowed in query allowlist mode
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct PersistedQuery {
+    /// Hex sha256 of the query text, as sent in the `persistedQuery` request extension
+    pub hash: String,
+    pub name: String,
+    pub query: String,
+    pub created_at: i64,
+}
+
+/// Hash of a query text as stored in `persisted_queries`
+const QUERY_HASH: &str = "encode(sha256(convert_to($2, 'UTF8')), 'hex')";
+
+/// Register a query under `name`, replacing the name of an already registered query
+pub async fn upsert_persisted_query(
+    pool: &PgPool,
+    namespace: &str,
+    name: &str,
+    query: &str,
+) -> Result<PersistedQuery, ListenerError> {
+    let statement = format!(
+        "INSERT INTO persisted_queries ( namespace, hash, query, name ) \
+         VALUES ( $1, {}, $2, $3 ) \
+         ON CONFLICT (namespace, hash) DO UPDATE SET name = EXCLUDED.name \
+         RETURNING hash, name, query, EXTRACT(EPOCH FROM created_at)::bigint AS created_at",
+        QUERY_HASH
+    );
+    let persisted = sqlx::query_as::<_, PersistedQuery>(&statement)
+        .bind(namespace)
+        .bind(query)
+        .bind(name)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(persisted)
+}
+
+/// Text of the registered query with `hash`
+pub async fn persisted_query(
+    pool: &PgPool,
+    namespace: &str,
+    hash: &str,
+) -> Result<Option<String>, ListenerError> {
+    let query = sqlx::query_scalar::<_, String>(
+        "SELECT query FROM persisted_queries WHERE namespace = $1 AND hash = $2",
+    )
+    .bind(namespace)
+    .bind(hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(query)
+}
+
+/// Whether the exact query text is registered
+pub async fn is_persisted_query(
+    pool: &PgPool,
+    namespace: &str,
+    query: &str,
+) -> Result<bool, ListenerError> {
+    let statement = format!(
+        "SELECT EXISTS (SELECT 1 FROM persisted_queries WHERE namespace = $1 AND hash = {})",
+        QUERY_HASH
+    );
+    let registered = sqlx::query_scalar::<_, bool>(&statement)
+        .bind(namespace)
+        .bind(query)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(registered)
+}
+
+pub async fn list_persisted_queries(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<Vec<PersistedQuery>, ListenerError> {
+    let queries = sqlx::query_as::<_, PersistedQuery>(
+        r#"
+SELECT hash, name, query, EXTRACT(EPOCH FROM created_at)::bigint AS created_at
+FROM persisted_queries
+WHERE namespace = $1
+ORDER BY name, hash
+        "#,
+    )
+    .bind(namespace)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(queries)
+}
+
+pub async fn delete_persisted_query(
+    pool: &PgPool,
+    namespace: &str,
+    hash: &str,
+) -> Result<bool, ListenerError> {
+    let deleted = sqlx::query("DELETE FROM persisted_queries WHERE namespace = $1 AND hash = $2")
+        .bind(namespace)
+        .bind(hash)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted > 0)
+}
+
+/// Failed notifier delivery waiting for its next attempt
+#[derive(FromRow, Debug, Clone, PartialEq)]
+pub struct QueuedNotification {
+    pub id: i64,
+    pub channel: String,
+    pub content: String,
+    pub attempts: i32,
+}
+
+/// Queue a failed delivery to `channel` for another attempt at `next_attempt_at`
+pub async fn enqueue_notification(
+    pool: &PgPool,
+    namespace: &str,
+    channel: &str,
+    content: &str,
+    next_attempt_at: i64,
+) -> Result<i64, ListenerError> {
+    let id = sqlx::query_scalar::<_, i64>(
+        r#"
+INSERT INTO notification_retries ( namespace, channel, content, next_attempt_at )
+VALUES ( $1, $2, $3, to_timestamp($4) )
+RETURNING id
+        "#,
+    )
+    .bind(namespace)
+    .bind(channel)
+    .bind(content)
+    .bind(next_attempt_at)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Queued deliveries due at `now`, oldest first
+pub async fn due_notifications(
+    pool: &PgPool,
+    namespace: &str,
+    now: i64,
+    limit: i64,
+) -> Result<Vec<QueuedNotification>, ListenerError> {
+    let notifications = sqlx::query_as::<_, QueuedNotification>(
+        r#"
+SELECT id, channel, content, attempts
+FROM notification_retries
+WHERE namespace = $1 AND next_attempt_at <= to_timestamp($2)
+ORDER BY created_at, id
+LIMIT $3
+        "#,
+    )
+    .bind(namespace)
+    .bind(now)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(notifications)
+}
+
+/// Count another failed attempt and schedule the next one
+pub async fn reschedule_notification(
+    pool: &PgPool,
+    id: i64,
+    next_attempt_at: i64,
+) -> Result<(), ListenerError> {
+    sqlx::query(
+        "UPDATE notification_retries SET attempts = attempts + 1, next_attempt_at = to_timestamp($2) WHERE id = $1",
+    )
+    .bind(id)
+    .bind(next_attempt_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_notification(pool: &PgPool, id: i64) -> Result<(), ListenerError> {
+    sqlx::query("DELETE FROM notification_retries WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Drop queued deliveries first failed before `before`, returns how many were dropped
+pub async fn expire_notifications(
+    pool: &PgPool,
+    namespace: &str,
+    before: i64,
+) -> Result<u64, ListenerError> {
+    let expired = sqlx::query(
+        "DELETE FROM notification_retries WHERE namespace = $1 AND created_at < to_timestamp($2)",
+    )
+    .bind(namespace)
+    .bind(before)
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(expired)
+}
+
+/// Notification template set by an operator
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone, PartialEq)]
+pub struct NotificationTemplate {
+    pub alert: String,
+    /// Null when the template applies to every channel
+    pub channel: Option<String>,
+    pub template: String,
+}
+
+/// Template of `alert` for `channel`, falling back to the template of every channel
+pub async fn notification_template(
+    pool: &PgPool,
+    namespace: &str,
+    alert: &str,
+    channel: &str,
+) -> Result<Option<String>, ListenerError> {
+    let template = sqlx::query_scalar::<_, String>(
+        r#"
+SELECT template
+FROM notification_templates
+WHERE namespace = $1 AND alert = $2 AND channel IN ($3, '')
+ORDER BY channel DESC
+LIMIT 1
+        "#,
+    )
+    .bind(namespace)
+    .bind(alert)
+    .bind(channel)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(template)
+}
+
+pub async fn set_notification_template(
+    pool: &PgPool,
+    namespace: &str,
+    alert: &str,
+    channel: Option<&str>,
+    template: &str,
+) -> Result<(), ListenerError> {
+    sqlx::query(
+        r#"
+INSERT INTO notification_templates ( namespace, alert, channel, template )
+VALUES ( $1, $2, $3, $4 )
+ON CONFLICT (namespace, alert, channel)
+DO UPDATE SET template = EXCLUDED.template, updated_at = NOW()
+        "#,
+    )
+    .bind(namespace)
+    .bind(alert)
+    .bind(channel.unwrap_or_default())
+    .bind(template)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Remove a template, returns whether it was set
+pub async fn delete_notification_template(
+    pool: &PgPool,
+    namespace: &str,
+    alert: &str,
+    channel: Option<&str>,
+) -> Result<bool, ListenerError> {
+    let deleted = sqlx::query(
+        "DELETE FROM notification_templates WHERE namespace = $1 AND alert = $2 AND channel = $3",
+    )
+    .bind(namespace)
+    .bind(alert)
+    .bind(channel.unwrap_or_default())
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted > 0)
+}
+
+pub async fn list_notification_templates(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<Vec<NotificationTemplate>, ListenerError> {
+    let templates = sqlx::query_as::<_, NotificationTemplate>(
+        r#"
+SELECT alert, NULLIF(channel, '') AS channel, template
+FROM notification_templates
+WHERE namespace = $1
+ORDER BY alert, channel NULLS FIRST
+        "#,
+    )
+    .bind(namespace)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(templates)
+}
+
+/// Panic recorded by the crash reporter
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct Crash {
+    pub id: i64,
+    pub thread: String,
+    pub location: String,
+    pub message: String,
+    pub backtrace: String,
+    pub occurred_at: i64,
+}
+
+pub async fn add_crash(
+    pool: &PgPool,
+    namespace: &str,
+    report: &CrashReport,
+) -> Result<i64, ListenerError> {
+    let id = sqlx::query_scalar::<_, i64>(
+        r#"
+INSERT INTO crashes ( namespace, thread, location, message, backtrace, occurred_at )
+VALUES ( $1, $2, $3, $4, $5, to_timestamp($6) )
+RETURNING id
+        "#,
+    )
+    .bind(namespace)
+    .bind(&report.thread)
+    .bind(&report.location)
+    .bind(&report.message)
+    .bind(&report.backtrace)
+    .bind(report.timestamp)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Most recent crashes first
+pub async fn list_crashes(
+    pool: &PgPool,
+    namespace: &str,
+    limit: i64,
+) -> Result<Vec<Crash>, ListenerError> {
+    let crashes = sqlx::query_as::<_, Crash>(
+        r#"
+SELECT id, thread, location, message, backtrace, EXTRACT(EPOCH FROM occurred_at)::bigint AS occurred_at
+FROM crashes
+WHERE namespace = $1
+ORDER BY occurred_at DESC, id DESC
+LIMIT $2
+        "#,
+    )
+    .bind(namespace)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(crashes)
+}
+
+/// Outcome of a reachability check of a boot node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCheck {
+    pub address: String,
+    pub peer_id: Option<String>,
+    pub critical: bool,
+    pub reachable: bool,
+    /// Whether the node is among the connected peers after the dial
+    pub connected: bool,
+    pub dial_ms: Option<i64>,
+}
+
+/// Latest check of a boot node
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerStatus {
+    pub address: String,
+    pub peer_id: Option<String>,
+    pub critical: bool,
+    pub reachable: bool,
+    pub connected: bool,
+    pub dial_ms: Option<i64>,
+    /// Failed checks since the node was last reachable
+    pub failures: i32,
+    pub checked_at: i64,
+    pub last_reachable_at: Option<i64>,
+}
+
+/// Record a check, counting consecutive failures. Returns the failures after this check
+pub async fn record_peer_check(
+    pool: &PgPool,
+    namespace: &str,
+    check: &PeerCheck,
+) -> Result<i32, ListenerError> {
+    let failures = sqlx::query_scalar::<_, i32>(
+        r#"
+INSERT INTO peers ( namespace, address, peer_id, critical, reachable, connected, dial_ms, failures, last_reachable_at )
+VALUES ( $1, $2, $3, $4, $5, $6, $7, CASE WHEN $5 THEN 0 ELSE 1 END, CASE WHEN $5 THEN NOW() END )
+ON CONFLICT (namespace, address) DO UPDATE SET
+    peer_id = COALESCE(EXCLUDED.peer_id, peers.peer_id),
+    critical = EXCLUDED.critical,
+    reachable = EXCLUDED.reachable,
+    connected = EXCLUDED.connected,
+    dial_ms = EXCLUDED.dial_ms,
+    failures = CASE WHEN EXCLUDED.reachable THEN 0 ELSE peers.failures + 1 END,
+    checked_at = NOW(),
+    last_reachable_at = COALESCE(EXCLUDED.last_reachable_at, peers.last_reachable_at)
+RETURNING failures
+        "#,
+    )
+    .bind(namespace)
+    .bind(&check.address)
+    .bind(&check.peer_id)
+    .bind(check.critical)
+    .bind(check.reachable)
+    .bind(check.connected)
+    .bind(check.dial_ms)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(failures)
+}
+
+/// Latest check of every boot node, unreachable ones first
+pub async fn list_peer_statuses(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<Vec<PeerStatus>, ListenerError> {
+    let peers = sqlx::query_as::<_, PeerStatus>(
+        r#"
+SELECT address, peer_id, critical, reachable, connected, dial_ms, failures,
+       EXTRACT(EPOCH FROM checked_at)::bigint AS checked_at,
+       EXTRACT(EPOCH FROM last_reachable_at)::bigint AS last_reachable_at
+FROM peers
+WHERE namespace = $1
+ORDER BY reachable, critical DESC, address
+        "#,
+    )
+    .bind(namespace)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(peers)
+}
+
+/// Waku identity of a listener node
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ListenerNode {
+    pub peer_id: String,
+    /// Dialable addresses, each ending with the peer id
+    pub multiaddrs: Vec<String>,
+    /// Unix timestamp of the last startup
+    pub started_at: i64,
+}
+
+/// Record the node of a starting listener, replacing the addresses of its previous run
+pub async fn upsert_listener_node(
+    pool: &PgPool,
+    namespace: &str,
+    node: &ListenerNode,
+) -> Result<(), ListenerError> {
+    sqlx::query(
+        r#"
+INSERT INTO listener_nodes ( namespace, peer_id, multiaddrs, started_at )
+VALUES ( $1, $2, $3, to_timestamp($4) )
+ON CONFLICT (namespace, peer_id)
+DO UPDATE SET multiaddrs = EXCLUDED.multiaddrs, started_at = EXCLUDED.started_at
+        "#,
+    )
+    .bind(namespace)
+    .bind(&node.peer_id)
+    .bind(&node.multiaddrs)
+    .bind(node.started_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Nodes of the listeners that ran on the namespace, most recently started first
+pub async fn list_listener_nodes(
+    pool: &PgPool,
+    namespace: &str,
+    limit: i64,
+) -> Result<Vec<ListenerNode>, ListenerError> {
+    let nodes = sqlx::query_as::<_, ListenerNode>(
+        r#"
+SELECT peer_id, multiaddrs, EXTRACT(EPOCH FROM started_at)::bigint AS started_at
+FROM listener_nodes
+WHERE namespace = $1
+ORDER BY started_at DESC
+LIMIT $2
+        "#,
+    )
+    .bind(namespace)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(nodes)
+}
+
+/// Raw Waku message recorded by the debug capture
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct CapturedMessage {
+    id: i64,
+    content_topic: String,
+    version: i32,
+    /// Hex encoded payload
+    payload: String,
+    timestamp: i64,
+    /// Delivering peer, when the Waku layer exposes it
+    peer: Option<String>,
+    /// Capture time in unix seconds
+    created_at: i64,
+}
+
+pub async fn add_captured_message(
+    pool: &PgPool,
+    namespace: &str,
+    content_topic: &str,
+    version: i32,
+    payload: &[u8],
+    timestamp: i64,
+    peer: Option<&str>,
+) -> Result<i64, ListenerError> {
+    let id = sqlx::query_scalar::<_, i64>(
+        r#"
+INSERT INTO captured_messages ( namespace, content_topic, version, payload, timestamp, peer )
+VALUES ( $1, $2, $3, $4, $5, $6 )
+RETURNING id
+        "#,
+    )
+    .bind(namespace)
+    .bind(content_topic)
+    .bind(version)
+    .bind(payload)
+    .bind(timestamp)
+    .bind(peer)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Latest captured messages, newest first
+pub async fn list_captured_messages(
+    pool: &PgPool,
+    namespace: &str,
+    limit: i64,
+) -> Result<Vec<CapturedMessage>, ListenerError> {
+    let rows = sqlx::query_as::<_, CapturedMessage>(
+        r#"
+SELECT id, content_topic, version, encode(payload, 'hex') AS payload, timestamp, peer,
+       EXTRACT(EPOCH FROM created_at)::bigint AS created_at
+FROM captured_messages
+WHERE namespace = $1
+ORDER BY id DESC
+LIMIT $2
+        "#,
+    )
+    .bind(namespace)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn delete_captured_messages(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<i64, ListenerError> {
+    let result = sqlx::query("DELETE FROM captured_messages WHERE namespace = $1")
+        .bind(namespace)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() as i64)
+}
+
+/// Waku message of a type the listener cannot decode, kept so no broadcast is lost
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct RawMessage {
+    id: i64,
+    content_topic: String,
+    version: i32,
+    /// Hex encoded payload
+    payload: String,
+    timestamp: i64,
+    /// Envelope fields, null when the payload is not a Graphcast message
+    identifier: Option<String>,
+    graph_account: Option<String>,
+    signature: Option<String>,
+    peer: Option<String>,
+    /// Receive time in unix seconds
+    created_at: i64,
+}
+
+pub async fn add_raw_message(
+    pool: &PgPool,
+    namespace: &str,
+    content_topic: &str,
+    version: i32,
+    payload: &[u8],
+    timestamp: i64,
+    peer: Option<&str>,
+) -> Result<i64, ListenerError> {
+    let envelope = GraphcastEnvelope::decode(payload).ok();
+    let id = sqlx::query_scalar::<_, i64>(
+        r#"
+INSERT INTO raw_messages ( namespace, content_topic, version, payload, timestamp, identifier, graph_account, signature, peer )
+VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9 )
+RETURNING id
+        "#,
+    )
+    .bind(namespace)
+    .bind(content_topic)
+    .bind(version)
+    .bind(payload)
+    .bind(timestamp)
+    .bind(envelope.as_ref().map(|e| e.identifier.as_str()))
+    .bind(envelope.as_ref().map(|e| e.graph_account.as_str()))
+    .bind(envelope.as_ref().map(|e| e.signature.as_str()))
+    .bind(peer)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Latest raw messages, newest first, optionally of one content topic
+pub async fn list_raw_messages(
+    pool: &PgPool,
+    namespace: &str,
+    content_topic: Option<&str>,
+    limit: i64,
+) -> Result<Vec<RawMessage>, ListenerError> {
+    let rows = sqlx::query_as::<_, RawMessage>(
+        r#"
+SELECT id, content_topic, version, encode(payload, 'hex') AS payload, timestamp, identifier,
+       graph_account, signature, peer, EXTRACT(EPOCH FROM created_at)::bigint AS created_at
+FROM raw_messages
+WHERE namespace = $1 AND ($2::text IS NULL OR content_topic = $2)
+ORDER BY id DESC
+LIMIT $3
+        "#,
+    )
+    .bind(namespace)
+    .bind(content_topic)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Payload no registered type could decode
+#[derive(FromRow, Debug, Clone, PartialEq)]
+pub struct UndecodedPayload {
+    pub content_topic: String,
+    pub payload: Vec<u8>,
+    /// Sender from the Graphcast envelope, when the payload has one
+    pub graph_account: Option<String>,
+    /// Receive time in unix seconds
+    pub created_at: i64,
+}
+
+/// Newest undecodable payloads received since `from_timestamp`, from the raw messages and the
+/// decode failures quarantined in the dead letters before raw messages were kept
+pub async fn list_undecoded_payloads(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+    limit: i64,
+) -> Result<Vec<UndecodedPayload>, ListenerError> {
+    let rows = sqlx::query_as::<_, UndecodedPayload>(
+        r#"
+SELECT content_topic, payload, graph_account, EXTRACT(EPOCH FROM created_at)::bigint AS created_at
+FROM raw_messages
+WHERE namespace = $1 AND created_at > to_timestamp($2)
+UNION ALL
+SELECT content_topic, payload, NULL, EXTRACT(EPOCH FROM created_at)::bigint
+FROM dead_letters
+WHERE namespace = $1 AND category = 'decode' AND created_at > to_timestamp($2)
+ORDER BY created_at DESC
+LIMIT $3
+        "#,
+    )
+    .bind(namespace)
+    .bind(from_timestamp)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Drop raw messages received more than `retention` minutes ago
+pub async fn prune_raw_messages(
+    pool: &PgPool,
+    namespace: &str,
+    retention: i32,
+) -> Result<i64, ListenerError> {
+    let deleted = sqlx::query(
+        r#"
+DELETE FROM raw_messages
+WHERE namespace = $1 AND created_at < NOW() - make_interval(mins => $2)
+        "#,
+    )
+    .bind(namespace)
+    .bind(retention)
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted as i64)
+}
+
+/// Message that could not be processed, kept for inspection and requeueing
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct DeadLetter {
+    id: i64,
+    content_topic: String,
+    /// Hex encoded payload
+    payload: String,
+    timestamp: i64,
+    category: String,
+    error: String,
+    /// Time the message was dead lettered in unix seconds
+    created_at: i64,
+}
+
+/// Selection of dead letters, unset filters match every entry
+#[derive(Clone, Debug, Default)]
+pub struct DeadLetterFilter {
+    pub ids: Option<Vec<i64>>,
+    pub category: Option<String>,
+    /// Unix timestamps bounding `created_at`
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+const DEAD_LETTER_FILTER: &str = "namespace = $1 \
+    AND ($2::bigint[] IS NULL OR id = ANY($2)) \
+    AND ($3::text IS NULL OR category = $3) \
+    AND ($4::bigint IS NULL OR created_at >= to_timestamp($4)) \
+    AND ($5::bigint IS NULL OR created_at < to_timestamp($5))";
+
+/// Latest dead letters matching the filter, newest first
+pub async fn list_dead_letters(
+    pool: &PgPool,
+    namespace: &str,
+    filter: &DeadLetterFilter,
+    limit: i64,
+) -> Result<Vec<DeadLetter>, ListenerError> {
+    let query = format!(
+        "SELECT id, content_topic, encode(payload, 'hex') AS payload, timestamp, category, error, \
+         EXTRACT(EPOCH FROM created_at)::bigint AS created_at \
+         FROM dead_letters WHERE {} ORDER BY id DESC LIMIT $6",
+        DEAD_LETTER_FILTER
+    );
+    let rows = sqlx::query_as::<_, DeadLetter>(&query)
+        .bind(namespace)
+        .bind(&filter.ids)
+        .bind(&filter.category)
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows)
+}
+
+/// Raw payloads of the oldest dead letters matching the filter, as (id, payload)
+pub async fn dead_letter_payloads(
+    pool: &PgPool,
+    namespace: &str,
+    filter: &DeadLetterFilter,
+    limit: i64,
+) -> Result<Vec<(i64, Vec<u8>)>, ListenerError> {
+    let query = format!(
+        "SELECT id, payload FROM dead_letters WHERE {} ORDER BY id ASC LIMIT $6",
+        DEAD_LETTER_FILTER
+    );
+    let rows = sqlx::query_as::<_, (i64, Vec<u8>)>(&query)
+        .bind(namespace)
+        .bind(&filter.ids)
+        .bind(&filter.category)
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows)
+}
+
+/// Record the outcome of a failed requeue on the dead letter
+pub async fn update_dead_letter(
+    pool: &PgPool,
+    id: i64,
+    category: &str,
+    error: &str,
+) -> Result<(), ListenerError> {
+    sqlx::query("UPDATE dead_letters SET category = $2, error = $3 WHERE id = $1")
+        .bind(id)
+        .bind(category)
+        .bind(error)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_dead_letters(
+    pool: &PgPool,
+    namespace: &str,
+    filter: &DeadLetterFilter,
+) -> Result<i64, ListenerError> {
+    let query = format!("DELETE FROM dead_letters WHERE {}", DEAD_LETTER_FILTER);
+    let result = sqlx::query(&query)
+        .bind(namespace)
+        .bind(&filter.ids)
+        .bind(&filter.category)
+        .bind(filter.from)
+        .bind(filter.to)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() as i64)
+}
+
+/// Fetch the oldest messages received before `before` (unix seconds) as raw json,
+/// returned as (id, nonce, message) for archival into the cold tier, with the receive time
+/// for messages without a nonce. The sender controlled nonce is not trusted for age, held
+/// messages stay in the messages table
+pub async fn list_messages_before(
+    pool: &PgPool,
+    namespace: &str,
+    before: i64,
+    limit: i64,
+) -> Result<Vec<(i64, i64, String)>, anyhow::Error> {
+    let query = format!(
+        "SELECT id, COALESCE(nonce, EXTRACT(EPOCH FROM created_at)::bigint) AS nonce, \
+             message::text AS message FROM messages \
+         WHERE namespace = $1 AND created_at < to_timestamp($2) AND {} \
+         ORDER BY id ASC LIMIT $3",
+        NOT_HELD
+    );
+    let rows = sqlx::query_as::<_, (i64, i64, String)>(&query)
+        .bind(namespace)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows)
+}
+
+/// Stored message as raw json with the version of its layout, for exporters
+#[derive(FromRow, Debug, Clone)]
+pub struct ExportedMessage {
+    pub id: i64,
+    /// Receive time in unix seconds
+    pub created_at: i64,
+    pub schema_version: i32,
+    pub message: String,
+}
+
+/// Fetch messages stored after `after_id` in id order, up to the first one received at or
+/// after `settled_before`. Ids are taken before the inserting transaction commits, so a
+/// message can show up after messages with higher ids. Holding back the recent ones keeps a
+/// cursor on the id from moving past messages that were not committed yet
+pub async fn list_messages_after(
+    pool: &PgPool,
+    namespace: &str,
+    after_id: i64,
+    settled_before: i64,
+    limit: i64,
+) -> Result<Vec<ExportedMessage>, anyhow::Error> {
+    let rows = sqlx::query_as::<_, ExportedMessage>(
+        r#"
+SELECT id, EXTRACT(EPOCH FROM created_at)::bigint AS created_at, schema_version, message::text AS message
+FROM messages
+WHERE namespace = $1 AND id > $2
+    AND id < COALESCE(
+        (SELECT MIN(id) FROM messages
+         WHERE namespace = $1 AND id > $2 AND created_at >= to_timestamp($3)),
+        9223372036854775807
+    )
+ORDER BY id ASC
+LIMIT $4
+        "#,
+    )
+    .bind(namespace)
+    .bind(after_id)
+    .bind(settled_before)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Stored message with its indexed columns, for analytical exports
+#[derive(FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct MessageRecord {
+    pub id: i64,
+    pub content_hash: String,
+    /// Receive time in unix seconds
+    pub created_at: Option<i64>,
+    pub message_type: Option<String>,
+    pub content_topic: Option<String>,
+    pub graph_account: Option<String>,
+    pub identifier: Option<String>,
+    pub nonce: Option<i64>,
+    pub message: String,
+}
+
+/// Messages matching `filter` stored after `after_id`, in id order
+pub async fn list_message_records<'e, E: PgExecutor<'e>>(
+    executor: E,
+    namespace: &str,
+    filter: &MessageFilter,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<MessageRecord>, ListenerError> {
+    let query = format!(
+        "SELECT id, content_hash, EXTRACT(EPOCH FROM created_at)::bigint AS created_at, \
+         message_type, content_topic, graph_account, identifier, nonce, \
+         message::text AS message FROM messages WHERE {} AND id > $12 ORDER BY id LIMIT $13",
+        MESSAGE_FILTER
+    );
+    let rows = sqlx::query_as::<_, MessageRecord>(&query)
+        .bind(namespace)
+        .bind(&filter.graph_account)
+        .bind(&filter.identifier)
+        .bind(&filter.network)
+        .bind(filter.nonce_gte)
+        .bind(filter.nonce_lte)
+        .bind(&filter.message_type)
+        .bind(&filter.content_topic)
+        .bind(filter.sender_valid)
+        .bind(filter.received_from)
+        .bind(filter.received_to)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(executor)
+        .await?;
+
+    Ok(rows)
+}
+
+/// Last message id delivered to an export sink, 0 when the sink never exported
+pub async fn export_cursor(
+    pool: &PgPool,
+    namespace: &str,
+    sink: &str,
+) -> Result<i64, anyhow::Error> {
+    let last_id = sqlx::query_scalar::<_, i64>(
+        "SELECT last_id FROM export_cursors WHERE namespace = $1 AND sink = $2",
+    )
+    .bind(namespace)
+    .bind(sink)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(last_id.unwrap_or_default())
+}
+
+pub async fn set_export_cursor(
+    pool: &PgPool,
+    namespace: &str,
+    sink: &str,
+    last_id: i64,
+) -> Result<(), anyhow::Error> {
+    sqlx::query(
+        r#"
+INSERT INTO export_cursors ( namespace, sink, last_id, updated_at )
+VALUES ( $1, $2, $3, NOW() )
+ON CONFLICT (namespace, sink) DO UPDATE SET last_id = EXCLUDED.last_id, updated_at = NOW()
+        "#,
+    )
+    .bind(namespace)
+    .bind(sink)
+    .bind(last_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Record a cold tier manifest and drop the archived rows from the messages table
+/// in a single transaction, returns the number of messages removed
+pub async fn commit_cold_manifest(
+    pool: &PgPool,
+    namespace: &str,
+    manifest: &ColdManifest,
+    ids: &[i64],
+) -> Result<i64, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+    lock_maintenance(&mut tx).await?;
+
+    sqlx::query(
+        r#"
+INSERT INTO cold_manifests ( namespace, object_key, min_id, max_id, min_nonce, max_nonce, row_count )
+VALUES ( $1, $2, $3, $4, $5, $6, $7 )
+        "#,
+    )
+    .bind(namespace)
+    .bind(&manifest.object_key)
+    .bind(manifest.min_id)
+    .bind(manifest.max_id)
+    .bind(manifest.min_nonce)
+    .bind(manifest.max_nonce)
+    .bind(manifest.row_count)
+    .execute(&mut *tx)
+    .await?;
+
+    let deleted = sqlx::query("DELETE FROM messages WHERE namespace = $1 AND id = ANY($2)")
+        .bind(namespace)
+        .bind(ids)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(deleted as i64)
+}
+
+/// List the cold tier manifests covering any nonce within `[from, to]`
+pub async fn list_cold_manifests(
+    pool: &PgPool,
+    namespace: &str,
+    from: i64,
+    to: i64,
+) -> Result<Vec<ColdManifest>, anyhow::Error> {
+    let manifests = sqlx::query_as::<_, ColdManifest>(
+        r#"
+SELECT object_key, min_id, max_id, min_nonce, max_nonce, row_count
+FROM cold_manifests
+WHERE namespace = $1 AND max_nonce >= $2 AND min_nonce <= $3
+ORDER BY min_id
+        "#,
+    )
+    .bind(namespace)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(manifests)
+}
+
+/// Latest nonce of a sender for an identifier
+#[derive(FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct SenderNonce {
+    pub graph_account: String,
+    pub identifier: String,
+    pub last_nonce: i64,
+}
+
+pub async fn list_sender_nonces(
+    pool: &PgPool,
+    namespace: &str,
+) -> Result<Vec<SenderNonce>, ListenerError> {
+    let rows = sqlx::query_as::<_, SenderNonce>(
+        "SELECT graph_account, identifier, last_nonce FROM sender_state WHERE namespace = $1",
+    )
+    .bind(namespace)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Record the latest nonces of senders, stored nonces never go back
+pub async fn upsert_sender_nonces(
+    pool: &PgPool,
+    namespace: &str,
+    nonces: &[SenderNonce],
+) -> Result<u64, ListenerError> {
+    let result = sqlx::query(
+        r#"
+INSERT INTO sender_state ( namespace, graph_account, identifier, last_nonce )
+SELECT $1, graph_account, identifier, last_nonce
+FROM UNNEST($2::text[], $3::text[], $4::bigint[]) AS n ( graph_account, identifier, last_nonce )
+ON CONFLICT (namespace, graph_account, identifier) DO UPDATE
+SET last_nonce = GREATEST(sender_state.last_nonce, EXCLUDED.last_nonce), updated_at = NOW()
+        "#,
+    )
+    .bind(namespace)
+    .bind(
+        nonces
+            .iter()
+            .map(|n| n.graph_account.as_str())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        nonces
+            .iter()
+            .map(|n| n.identifier.as_str())
+            .collect::<Vec<_>>(),
+    )
+    .bind(nonces.iter().map(|n| n.last_nonce).collect::<Vec<_>>())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Messages of an indexer about one identifier, the unit stats over several sources are
+/// merged from since subgraph counts cannot be summed
+#[derive(FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct IndexerActivity {
+    pub graph_account: String,
+    pub identifier: String,
+    pub message_count: i64,
+}
+
+/// Messages by indexer and identifier sent within `(from_timestamp, to_timestamp]`
+pub async fn list_indexer_activity(
+    pool: &PgPool,
+    namespace: &str,
+    indexers: Option<&[String]>,
+    from_timestamp: i64,
+    to_timestamp: i64,
+) -> Result<Vec<IndexerActivity>, ListenerError> {
+    let query = r#"
+SELECT graph_account, identifier, COUNT(*) AS message_count
+FROM messages
+WHERE namespace = $1 AND message_time > $2 AND message_time <= $3
+  AND graph_account IS NOT NULL AND identifier IS NOT NULL
+  AND ($4::text[] IS NULL OR graph_account = ANY($4))
+GROUP BY graph_account, identifier
+        "#;
+    let rows = sqlx::query_as::<_, IndexerActivity>(query)
+        .bind(namespace)
+        .bind(from_timestamp)
+        .bind(to_timestamp)
+        .bind(indexers)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows)
+}
+
+/// Log the query plan of a stats query, binding the same timestamp, namespace and indexer arguments
+async fn log_query_plan(
+    pool: &PgPool,
+    name: &str,
+    query: &str,
+    from_timestamp: i64,
+    namespace: &str,
+    indexers: &Option<Vec<String>>,
+) {
+    let explain = format!("EXPLAIN (ANALYZE, BUFFERS) {}", query);
+    let mut explain_query = sqlx::query_scalar::<_, String>(&explain)
+        .bind(from_timestamp)
+        .bind(namespace);
+    if let Some(idxs) = indexers {
+        for account in idxs {
+            explain_query = explain_query.bind(account.clone());
+        }
+    }
+
+    match explain_query.fetch_all(pool).await {
+        Ok(plan) => info!(query = name, plan = plan.join("\n"), "Query plan"),
+        Err(e) => warn!(
+            query = name,
+            err = tracing::field::debug(e),
+            "Failed to explain query"
+        ),
+    }
+}
+
+pub async fn list_active_indexers(
+    pool: &PgPool,
+    namespace: &str,
+    indexers: Option<Vec<String>>,
+    from_timestamp: i64,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut query = String::from(
+        "SELECT DISTINCT graph_account FROM messages WHERE message_time > $1 AND namespace = $2",
+    );
+
+    // Dynamically add placeholders for indexers if provided.
+    if let Some(ref idxs) = indexers {
+        let placeholders = idxs
+            .iter()
+            .enumerate()
+            .map(|(i, _)| format!("${}", i + 3))
+            .collect::<Vec<_>>()
+            .join(",");
+        query.push_str(&format!(" AND graph_account IN ({})", placeholders));
+    }
+
+    if explain_queries() {
+        log_query_plan(
+            pool,
+            "list_active_indexers",
+            &query,
+            from_timestamp,
+            namespace,
+            &indexers,
+        )
+        .await;
+    }
+
+    let mut query = sqlx::query(&query).bind(from_timestamp).bind(namespace);
+
+    // Bind indexers to the query if provided.
+    if let Some(indexers) = indexers {
+        for account in indexers {
+            query = query.bind(account);
+        }
+    }
+
+    let started = Instant::now();
+    let rows = query
+        .fetch_all(pool)
+        .await
+        .map_err(anyhow::Error::new)?
+        .iter()
+        .map(|row| row.get::<String, _>("graph_account"))
+        .collect();
+
+    if explain_queries() {
+        info!(
+            query = "list_active_indexers",
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Query timing"
+        );
+    }
+
+    Ok(rows)
+}
+
+pub async fn get_indexer_stats(
+    pool: &PgPool,
+    namespace: &str,
+    indexers: Option<Vec<String>>,
+    from_timestamp: i64,
+) -> Result<Vec<IndexerStats>, anyhow::Error> {
+    let mut query = String::from(
+        "
+        SELECT 
+            graph_account, 
+            COUNT(*) as message_count, 
+            COUNT(DISTINCT identifier) as subgraphs_count -- Updated field name
+        FROM messages 
+        WHERE message_time > $1 AND namespace = $2",
+    );
+
+    if let Some(ref idxs) = indexers {
+        let placeholders = idxs
+            .iter()
+            .enumerate()
+            .map(|(i, _)| format!("${}", i + 3))
+            .collect::<Vec<_>>()
+            .join(",");
+        query.push_str(&format!(" AND graph_account IN ({})", placeholders));
+    }
+
+    query.push_str(" GROUP BY graph_account");
+
+    if explain_queries() {
+        log_query_plan(
+            pool,
+            "get_indexer_stats",
+            &query,
+            from_timestamp,
+            namespace,
+            &indexers,
+        )
+        .await;
+    }
+
+    let mut dynamic_query = sqlx::query_as::<_, IndexerStats>(&query)
+        .bind(from_timestamp)
+        .bind(namespace);
+
+    if let Some(indexers) = indexers {
+        for account in indexers {
+            dynamic_query = dynamic_query.bind(account);
+        }
+    }
+
+    let started = Instant::now();
+    let stats = dynamic_query
+        .fetch_all(pool)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    if explain_queries() {
+        info!(
+            query = "get_indexer_stats",
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Query timing"
+        );
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message_types::{
+        PublicPoiMessage, SimpleMessage, StoredMessage, VersionUpgradeMessage,
+    };
+
+    use super::*;
+    use sqlx::PgPool;
+    use std::sync::atomic::AtomicUsize;
+
+    const TEST_NAMESPACE: &str = "default";
+
+    async fn insert_test_data(pool: &PgPool, entries: Vec<(i64, &str, &str)>) {
+        for (nonce, graph_account, identifier) in entries {
+            let message = PublicPoiMessage {
+                identifier: identifier.to_string(),
+                content: "0xText".to_string(),
+                nonce: nonce.try_into().unwrap(),
+                network: "testnet".to_string(),
+                block_number: 1,
+                block_hash: "hash".to_string(),
+                graph_account: graph_account.to_string(),
+            };
+
+            add_message(pool, TEST_NAMESPACE, message)
+                .await
+                .expect("Failed to insert test data");
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_active_indexers_without_indexers(pool: PgPool) {
+        insert_test_data(
+            &pool,
+            vec![(
+                1707328517,
+                "0xb4b4570df6f7fe320f10fdfb702dba7e35244550",
+                "QmTamam",
+            )],
+        )
+        .await;
+
+        let from_timestamp = 1707328516;
+        let indexers = None;
+        let result = list_active_indexers(&pool, TEST_NAMESPACE, indexers, from_timestamp)
+            .await
+            .expect("Function should complete successfully");
+
+        assert!(
+            result.contains(&"0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string()),
+            "Result should contain the expected graph_account"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_active_indexers_with_specific_indexers(pool: PgPool) {
+        insert_test_data(
+            &pool,
+            vec![(
+                1707328517,
+                "0xb4b4570df6f7fe320f10fdfb702dba7e35244550",
+                "QmTamam",
+            )],
+        )
+        .await;
+
+        let from_timestamp = 1707328516;
+        let indexers = Some(vec![
+            "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
+            "nonexistent_indexer".to_string(),
+        ]);
+        let result = list_active_indexers(&pool, TEST_NAMESPACE, indexers, from_timestamp)
+            .await
+            .expect("Function should complete successfully");
+
+        assert_eq!(
+            result.len(),
+            1,
+            "Should only match records for existing indexers"
+        );
+        assert!(
+            result.contains(&"0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string()),
+            "Result should contain the expected graph_account"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_active_indexers_no_matching_records(pool: PgPool) {
+        let from_timestamp = 9999999999;
+        let indexers = None;
+        let result = list_active_indexers(&pool, TEST_NAMESPACE, indexers, from_timestamp)
+            .await
+            .expect("Function should complete successfully");
+
+        assert!(
+            result.is_empty(),
+            "Result should be empty when no records match criteria"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_active_indexers_edge_cases(pool: PgPool) {
+        let specific_nonce = Utc::now().timestamp();
+        insert_test_data(
+            &pool,
+            vec![(
+                specific_nonce,
+                "0xb4b4570df6f7fe320f10fdfb702dba7e35244550",
+                "QmTamam",
+            )],
+        )
+        .await;
+
+        let from_timestamp = specific_nonce;
+        let indexers = None;
+        let result = list_active_indexers(&pool, TEST_NAMESPACE, indexers, from_timestamp)
+            .await
+            .expect("Function should complete successfully");
+
+        assert!(
+            result.is_empty(),
+            "Result should be empty when from_timestamp exactly matches nonce"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_active_indexers_with_partial_matching_indexers(pool: PgPool) {
+        insert_test_data(
+            &pool,
+            vec![
+                (
+                    1707328517,
+                    "0xb4b4570df6f7fe320f10fdfb702dba7e35244550",
+                    "QmTamam",
+                ),
+                (1707328518, "some_other_account", "QmTamam"),
+            ],
+        )
+        .await;
+
+        let from_timestamp = 1707328516;
+        let indexers = Some(vec![
+            "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
+            "partial_match_indexer".to_string(),
+        ]);
+        let result = list_active_indexers(&pool, TEST_NAMESPACE, indexers, from_timestamp)
+            .await
+            .expect("Function should complete successfully");
+
+        assert_eq!(
+            result.len(),
+            1,
+            "Should only match records for existing indexers"
+        );
+        assert!(
+            result.contains(&"0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string()),
+            "Result should contain the expected graph_account"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_active_indexers_with_nonexistent_indexers(pool: PgPool) {
+        let from_timestamp = 1707328516;
+        let indexers = Some(vec![
+            "nonexistent_indexer_1".to_string(),
+            "nonexistent_indexer_2".to_string(),
+        ]);
+        let result = list_active_indexers(&pool, TEST_NAMESPACE, indexers, from_timestamp)
+            .await
+            .expect("Function should complete successfully");
+
+        assert!(
+            result.is_empty(),
+            "Result should be empty for non-existent indexers"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_active_indexers_skewed_nonce(pool: PgPool) {
+        // A nonce far in the past falls back to the receive time
+        insert_test_data(
+            &pool,
+            vec![(1, "0xb4b4570df6f7fe320f10fdfb702dba7e35244550", "QmTamam")],
+        )
+        .await;
+
+        let from_timestamp = Utc::now().timestamp() - 600;
+        let result = list_active_indexers(&pool, TEST_NAMESPACE, None, from_timestamp)
+            .await
+            .expect("Function should complete successfully");
+
+        assert_eq!(
+            result,
+            vec!["0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string()],
+            "Recently received message should count as active despite its nonce"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_prune_old_messages_skewed_nonce(pool: PgPool) {
+        insert_test_data(
+            &pool,
+            vec![
+                (1, "0xb4b4570df6f7fe320f10fdfb702dba7e35244550", "QmTamam"),
+                (
+                    Utc::now().timestamp(),
+                    "0xb4b4570df6f7fe320f10fdfb702dba7e35244551",
+                    "QmTamam",
+                ),
+            ],
+        )
+        .await;
+
+        let pruned = prune_old_messages(&pool, TEST_NAMESPACE, 60, 1000, None)
+            .await
+            .expect("Function should complete successfully");
+
+        assert_eq!(pruned, 0, "Freshly received messages should not be pruned");
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 2);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_message_time(pool: PgPool) {
+        let now = Utc::now().timestamp();
+        let recent = add_message(&pool, TEST_NAMESPACE, poi_message((now - 600) as u64))
+            .await
+            .unwrap()
+            .unwrap();
+        let skewed = add_message(&pool, TEST_NAMESPACE, poi_message((now - 7200) as u64))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let message_time = |id: i64| {
+            sqlx::query_scalar::<_, i64>("SELECT message_time FROM messages WHERE id = $1")
+                .bind(id)
+                .fetch_one(&pool)
+        };
+        // Nonces within the skew date the message, others fall back to the receive time
+        assert_eq!(message_time(recent).await.unwrap(), now - 600);
+        assert!(message_time(skewed).await.unwrap() >= now);
+    }
+
+    // Keeps simple test messages unique so they are not deduplicated
+    static SIMPLE_MESSAGES: AtomicUsize = AtomicUsize::new(0);
+
+    async fn insert_simple_message(pool: &PgPool, minutes_ago: i32) {
+        let message = SimpleMessage {
+            identifier: "ping".to_string(),
+            content: format!("pong {}", SIMPLE_MESSAGES.fetch_add(1, Ordering::SeqCst)),
+        };
+        let origin = MessageOrigin {
+            message_type: Some("simple"),
+            ..Default::default()
+        };
+        let id = add_message_from(pool, TEST_NAMESPACE, message, origin)
+            .await
+            .expect("Failed to insert test data")
+            .expect("Test data should be unique");
+        sqlx::query(
+            "UPDATE messages SET created_at = NOW() - make_interval(mins => $1) WHERE id = $2",
+        )
+        .bind(minutes_ago)
+        .bind(id)
+        .execute(pool)
+        .await
+        .expect("Failed to backdate test data");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_prune_old_messages_without_nonce(pool: PgPool) {
+        insert_simple_message(&pool, 120).await;
+        insert_simple_message(&pool, 0).await;
+
+        let pruned = prune_old_messages(&pool, TEST_NAMESPACE, 60, 1000, None)
+            .await
+            .expect("Function should complete successfully");
+
+        assert_eq!(
+            pruned, 1,
+            "Only the nonce-less message past retention should be pruned"
+        );
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_prune_old_messages_in_batches(pool: PgPool) {
+        for _ in 0..5 {
+            insert_simple_message(&pool, 120).await;
+        }
+        insert_test_data(
+            &pool,
+            vec![(
+                Utc::now().timestamp(),
+                "0xb4b4570df6f7fe320f10fdfb702dba7e35244550",
+                "QmTamam",
+            )],
+        )
+        .await;
+
+        let pruned = prune_old_messages(&pool, TEST_NAMESPACE, 60, 2, None)
+            .await
+            .expect("Function should complete successfully");
+
+        assert_eq!(
+            pruned, 5,
+            "All expired messages should be pruned across batches"
+        );
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_prune_old_messages_archived(pool: PgPool) {
+        for _ in 0..3 {
+            insert_simple_message(&pool, 120).await;
+        }
+        insert_simple_message(&pool, 0).await;
+        let archive = ArchiveSink::new("memory:///").unwrap();
+
+        let pruned = prune_old_messages(&pool, TEST_NAMESPACE, 60, 2, Some(&archive))
+            .await
+            .expect("Function should complete successfully");
+
+        assert_eq!(pruned, 3);
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 1);
+        let manifests = list_archive_manifests(&pool, TEST_NAMESPACE).await.unwrap();
+        assert_eq!(
+            manifests.iter().map(|m| m.row_count).collect::<Vec<_>>(),
+            vec![2, 1],
+            "Every batch should be one object"
+        );
+        let mut archived = vec![];
+        for manifest in &manifests {
+            let rows = archive.read(&manifest.object_key).await.unwrap();
+            assert_eq!(rows.first().map(|row| row.id), Some(manifest.min_id));
+            assert_eq!(rows.last().map(|row| row.id), Some(manifest.max_id));
+            archived.extend(rows);
+        }
+        assert_eq!(archived.len(), 3);
+        assert!(archived.iter().all(|row| row.content_hash.is_some()
+            && row.received_at < Utc::now().timestamp() - 3600
+            && row.message.is_object()));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_prune_old_messages_archive_failure(pool: PgPool) {
+        insert_simple_message(&pool, 120).await;
+        // Objects cannot be created below a file
+        let archive = ArchiveSink::new("file:///dev/null/archive").unwrap();
+
+        assert!(
+            prune_old_messages(&pool, TEST_NAMESPACE, 60, 1000, Some(&archive))
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            count_messages(&pool, TEST_NAMESPACE).await.unwrap(),
+            1,
+            "Messages should only be deleted once archived"
+        );
+        assert!(list_archive_manifests(&pool, TEST_NAMESPACE)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_messages_pages(pool: PgPool) {
+        for _ in 0..5 {
+            insert_simple_message(&pool, 0).await;
+        }
+        let ids =
+            |rows: Vec<Row<SimpleMessage>>| rows.iter().map(|r| r.get_id()).collect::<Vec<i64>>();
+
+        let first = list_messages(&pool, TEST_NAMESPACE, None, 0, Some(2))
+            .await
+            .unwrap();
+        let first = ids(first);
+        assert_eq!(first.len(), 2);
+
+        let next = list_messages(&pool, TEST_NAMESPACE, first.last().copied(), 0, Some(2))
+            .await
+            .unwrap();
+        let next = ids(next);
+        assert!(next.iter().all(|id| id > &first[1]));
+        assert_eq!(next.len(), 2);
+
+        let offset = list_messages(&pool, TEST_NAMESPACE, None, 2, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(ids(offset), next);
+
+        let last =
+            list_messages::<SimpleMessage>(&pool, TEST_NAMESPACE, next.last().copied(), 0, Some(2))
+                .await
+                .unwrap();
+        assert_eq!(last.len(), 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_filtered_messages(pool: PgPool) {
+        let origin = MessageOrigin {
+            message_type: Some("public_poi"),
+            ..Default::default()
+        };
+        for nonce in [1707328500, 1707328517, 1707328530] {
+            add_message_from(&pool, TEST_NAMESPACE, poi_message(nonce), origin)
+                .await
+                .unwrap();
+        }
+        insert_simple_message(&pool, 0).await;
+
+        let filter = MessageFilter {
+            graph_account: Some("0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string()),
+            nonce_gte: Some(1707328510),
+            message_type: Some("public_poi".to_string()),
+            ..Default::default()
+        };
+        let rows =
+            list_filtered_messages::<PublicPoiMessage>(&pool, TEST_NAMESPACE, &filter, None, 0, 10)
+                .await
+                .unwrap();
+        assert_eq!(
+            rows.iter()
+                .map(|r| r.get_message().nonce)
+                .collect::<Vec<u64>>(),
+            vec![1707328517, 1707328530]
+        );
+        assert_eq!(
+            count_filtered_messages(&pool, TEST_NAMESPACE, &filter)
+                .await
+                .unwrap(),
+            2
+        );
+
+        let simple = MessageFilter {
+            message_type: Some("simple".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_filtered_messages(&pool, TEST_NAMESPACE, &simple)
+                .await
+                .unwrap(),
+            1
+        );
+        let network = MessageFilter {
+            network: Some("mainnet".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_filtered_messages(&pool, TEST_NAMESPACE, &network)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let now = Utc::now().timestamp();
+        let received = |received_from, received_to| MessageFilter {
+            received_from,
+            received_to,
+            ..Default::default()
+        };
+        for (range, expected) in [
+            (received(Some(now - 60), None), 4),
+            (received(None, Some(now - 60)), 0),
+            (received(Some(now + 60), Some(now + 120)), 0),
+        ] {
+            assert_eq!(
+                count_filtered_messages(&pool, TEST_NAMESPACE, &range)
+                    .await
+                    .unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_sender_valid_filter(pool: PgPool) {
+        for (nonce, sender_valid) in [(1707328500, Some(true)), (1707328517, Some(false))] {
+            let origin = MessageOrigin {
+                sender_valid,
+                ..Default::default()
+            };
+            add_message_from(&pool, TEST_NAMESPACE, poi_message(nonce), origin)
+                .await
+                .unwrap();
+        }
+        let rows: Vec<(serde_json::Value, MessageOrigin)> = vec![(
+            serde_json::to_value(poi_message(1707328530)).unwrap(),
+            MessageOrigin::default(),
+        )];
+        add_messages_from(&pool, TEST_NAMESPACE, &rows)
+            .await
+            .unwrap();
+
+        let count = |sender_valid| {
+            let filter = MessageFilter {
+                sender_valid,
+                ..Default::default()
+            };
+            let pool = pool.clone();
+            async move {
+                count_filtered_messages(&pool, TEST_NAMESPACE, &filter)
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(count(Some(true)).await, 1);
+        assert_eq!(count(Some(false)).await, 1);
+        assert_eq!(count(None).await, 3);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_message_type_stats(pool: PgPool) {
+        let upgrade = VersionUpgradeMessage {
+            identifier: "QmWecB2".to_string(),
+            new_hash: "QmNewB2".to_string(),
+            subgraph_id: "0xsubgraph".to_string(),
+            nonce: 1707328517,
+            network: "mainnet".to_string(),
+            migrate_block: 19000000,
+            graph_account: "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
+        };
+        let stored = StoredMessage {
+            identifier: upgrade.identifier.clone(),
+            nonce: upgrade.nonce,
+            graph_account: upgrade.graph_account.clone(),
+            signature: "0xsig".to_string(),
+            payload: upgrade.clone(),
+        };
+        let origin = MessageOrigin {
+            message_type: Some("version_upgrade"),
+            ..Default::default()
+        };
+        add_message_from(&pool, TEST_NAMESPACE, stored, origin)
+            .await
+            .unwrap();
+        insert_simple_message(&pool, 0).await;
+        insert_simple_message(&pool, 0).await;
+
+        let from_timestamp = Utc::now().timestamp() - 60;
+        let stats = message_type_stats(&pool, TEST_NAMESPACE, from_timestamp)
+            .await
+            .unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].message_type.as_deref(), Some("simple"));
+        assert_eq!(stats[0].message_count, 2);
+        assert_eq!(stats[1].message_type.as_deref(), Some("version_upgrade"));
+        assert_eq!(stats[1].sender_count, 1);
+
+        let upgrades = list_payloads::<VersionUpgradeMessage>(
+            &pool,
+            TEST_NAMESPACE,
+            "version_upgrade",
+            Some("QmWecB2"),
+            10,
+        )
+        .await
+        .unwrap();
+        assert_eq!(upgrades, vec![upgrade]);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_retain_max_storage(pool: PgPool) {
+        for _ in 0..7 {
+            insert_simple_message(&pool, 0).await;
+        }
+
+        let pruned = retain_max_storage(&pool, TEST_NAMESPACE, 3, 2, None)
+            .await
+            .expect("Function should complete successfully");
+        assert_eq!(
+            pruned, 4,
+            "Should delete everything but the 3 newest messages"
+        );
+
+        let remaining = list_messages::<SimpleMessage>(&pool, TEST_NAMESPACE, None, 0, None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get_id())
+            .collect::<Vec<i64>>();
+        assert_eq!(remaining.len(), 3);
+        assert!(
+            remaining.iter().all(|id| *id > 4),
+            "Newest messages should be kept"
+        );
+
+        let pruned = retain_max_storage(&pool, TEST_NAMESPACE, 3, 2, None)
+            .await
+            .expect("Function should complete successfully");
+        assert_eq!(pruned, 0, "Nothing to prune within the storage limit");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_retention_holds(pool: PgPool) {
+        for _ in 0..3 {
+            insert_simple_message(&pool, 120).await;
+        }
+        let ids = list_messages::<SimpleMessage>(&pool, TEST_NAMESPACE, None, 0, None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get_id())
+            .collect::<Vec<i64>>();
+
+        let hold = hold_messages(&pool, TEST_NAMESPACE, "investigation", &ids[..1], None)
+            .await
+            .unwrap();
+        assert_eq!(hold.message_count, 1);
+        let filter = MessageFilter {
+            message_type: Some("simple".to_string()),
+            ..Default::default()
+        };
+        let other = hold_messages(&pool, "other", "unrelated", &[], Some(&filter))
+            .await
+            .unwrap();
+        assert_eq!(
+            other.message_count, 0,
+            "Holds are scoped to their namespace"
+        );
+
+        let pruned = prune_old_messages(&pool, TEST_NAMESPACE, 60, 1000, None)
+            .await
+            .unwrap();
+        assert_eq!(pruned, 2, "Held messages should not be pruned");
+        let pruned = retain_max_storage(&pool, TEST_NAMESPACE, 0, 1000, None)
+            .await
+            .unwrap();
+        assert_eq!(pruned, 0, "Held messages are kept over the storage limit");
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 1);
+
+        let holds = list_retention_holds(&pool, TEST_NAMESPACE).await.unwrap();
+        assert_eq!(holds.len(), 1);
+        assert_eq!(holds[0].reason, "investigation");
+        assert!(release_retention_hold(&pool, TEST_NAMESPACE, hold.id)
+            .await
+            .unwrap());
+        assert!(!release_retention_hold(&pool, TEST_NAMESPACE, hold.id)
+            .await
+            .unwrap());
+
+        let pruned = prune_old_messages(&pool, TEST_NAMESPACE, 60, 1000, None)
+            .await
+            .unwrap();
+        assert_eq!(pruned, 1, "Released messages should be prunable again");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_prunable_messages(pool: PgPool) {
+        for minutes_ago in [120, 120, 120, 0] {
+            insert_simple_message(&pool, minutes_ago).await;
+        }
+        let ids = list_messages::<SimpleMessage>(&pool, TEST_NAMESPACE, None, 0, None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get_id())
+            .collect::<Vec<i64>>();
+        hold_messages(&pool, TEST_NAMESPACE, "investigation", &ids[..1], None)
+            .await
+            .unwrap();
+
+        let before = Utc::now().timestamp() - 3600;
+        let prunable = list_prunable_messages(&pool, TEST_NAMESPACE, Some(before), None, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            prunable.iter().map(|(id, _, _)| *id).collect::<Vec<i64>>(),
+            ids[1..3],
+            "Only unheld messages past retention are prunable"
+        );
+        assert!(
+            prunable.iter().all(|(_, nonce, _)| *nonce < before),
+            "Messages without a nonce are dated by their receive time"
+        );
+
+        let threshold = max_storage_threshold(&pool, TEST_NAMESPACE, 2)
+            .await
+            .unwrap();
+        assert_eq!(threshold, Some(ids[1]));
+        let prunable = list_prunable_messages(&pool, TEST_NAMESPACE, None, threshold, 10)
+            .await
+            .unwrap();
+        assert_eq!(prunable.len(), 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_concurrent_prune_and_insert(pool: PgPool) {
+        for _ in 0..50 {
+            insert_simple_message(&pool, 120).await;
+        }
+
+        let prunes = (0..2)
+            .map(|_| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    prune_old_messages(&pool, TEST_NAMESPACE, 60, 5, None)
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        let inserts = (0..20)
+            .map(|_| {
+                let pool = pool.clone();
+                tokio::spawn(async move { insert_simple_message(&pool, 0).await })
+            })
+            .collect::<Vec<_>>();
+
+        let mut total_pruned = 0;
+        for prune in prunes {
+            total_pruned += prune.await.unwrap();
+        }
+        for insert in inserts {
+            insert.await.unwrap();
+        }
+
+        assert_eq!(
+            total_pruned, 50,
+            "Each expired message is counted exactly once"
+        );
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 20);
+    }
+
+    /// Run a bulk deletion to the end in batches of 5, returning the number of deleted messages
+    async fn delete_all(pool: &PgPool, namespace: &str) -> i64 {
+        let deletion = create_message_deletion(pool, namespace).await.unwrap();
+        loop {
+            let deleted = delete_message_batch(pool, namespace, &deletion, 5)
+                .await
+                .unwrap();
+            if deleted < 5 {
+                break;
+            }
+        }
+        message_deletion(pool, namespace, deletion.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .deleted
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_message_deletion(pool: PgPool) {
+        for _ in 0..12 {
+            insert_simple_message(&pool, 0).await;
+        }
+        let deletion = create_message_deletion(&pool, TEST_NAMESPACE)
+            .await
+            .unwrap();
+        assert_eq!(deletion.status, "running");
+        // Stored after the deletion started
+        insert_simple_message(&pool, 0).await;
+
+        assert_eq!(
+            delete_message_batch(&pool, TEST_NAMESPACE, &deletion, 5)
+                .await
+                .unwrap(),
+            5
+        );
+        let progress = message_deletion(&pool, TEST_NAMESPACE, deletion.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(progress.deleted, 5);
+        assert_eq!(
+            count_deletion_remaining(&pool, TEST_NAMESPACE, deletion.max_id)
+                .await
+                .unwrap(),
+            7
+        );
+        assert!(message_deletion(&pool, "mainnet", deletion.id)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Resuming from the recorded deletion
+        while delete_message_batch(&pool, TEST_NAMESPACE, &progress, 5)
+            .await
+            .unwrap()
+            == 5
+        {}
+        finish_message_deletion(&pool, deletion.id, "finished")
+            .await
+            .unwrap();
+        let finished = message_deletion(&pool, TEST_NAMESPACE, deletion.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(finished.deleted, 12);
+        assert!(finished.finished_at.is_some());
+        assert_eq!(
+            count_messages(&pool, TEST_NAMESPACE).await.unwrap(),
+            1,
+            "Messages stored after the deletion started are kept"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_concurrent_prune_and_delete_all(pool: PgPool) {
+        for _ in 0..30 {
+            insert_simple_message(&pool, 120).await;
+        }
+
+        let prune_pool = pool.clone();
+        let prune = tokio::spawn(async move {
+            prune_old_messages(&prune_pool, TEST_NAMESPACE, 60, 5, None)
+                .await
+                .unwrap()
+        });
+        let retain_pool = pool.clone();
+        let retain = tokio::spawn(async move {
+            retain_max_storage(&retain_pool, TEST_NAMESPACE, 10, 5, None)
+                .await
+                .unwrap()
+        });
+        let deleted = delete_all(&pool, TEST_NAMESPACE).await;
+
+        let total = prune.await.unwrap() + retain.await.unwrap() + deleted;
+        assert_eq!(
+            total, 30,
+            "Every message is deleted by exactly one operation"
+        );
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 0);
+    }
+
+    fn poi_message(nonce: u64) -> PublicPoiMessage {
+        PublicPoiMessage {
+            identifier: "QmTamam".to_string(),
+            content: format!("0x{:064x}", nonce),
+            nonce,
+            network: "testnet".to_string(),
+            block_number: nonce,
+            block_hash: "hash".to_string(),
+            graph_account: "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_topic_identifier_stats(pool: PgPool) {
+        let topic = "/graphcast/0/testnet/proto";
+        for nonce in [1707328517, 1707328518] {
+            let origin = MessageOrigin {
+                content_topic: Some(topic),
+                ..Default::default()
+            };
+            add_message_from(&pool, TEST_NAMESPACE, poi_message(nonce), origin)
+                .await
+                .unwrap();
+        }
+        let mut other = poi_message(1707328519);
+        other.identifier = "QmOther".to_string();
+        add_message(&pool, TEST_NAMESPACE, other).await.unwrap();
+
+        let from_timestamp = Utc::now().timestamp() - 60;
+        let stats = topic_identifier_stats(&pool, TEST_NAMESPACE, from_timestamp, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].content_topic.as_deref(), Some(topic));
+        assert_eq!(stats[0].identifier.as_deref(), Some("QmTamam"));
+        assert_eq!(stats[0].message_count, 2);
+        assert_eq!(stats[0].sender_count, 1);
+        assert!(stats[0].first_seen_at <= stats[0].last_seen_at);
+        assert_eq!(stats[1].content_topic, None);
+
+        let on_topic =
+            topic_identifier_stats(&pool, TEST_NAMESPACE, from_timestamp, Some(topic), 10)
+                .await
+                .unwrap();
+        assert_eq!(on_topic.len(), 1);
+        let limited = topic_identifier_stats(&pool, TEST_NAMESPACE, from_timestamp, None, 1)
+            .await
+            .unwrap();
+        assert_eq!(limited.len(), 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_message_columns(pool: PgPool) {
+        let origin = MessageOrigin {
+            content_topic: Some("/graphcast/0/testnet/proto"),
+            ..Default::default()
+        };
+        let id = add_message_from(&pool, TEST_NAMESPACE, poi_message(1707328517), origin)
+            .await
+            .unwrap()
+            .unwrap();
+        let overflow = add_message(&pool, TEST_NAMESPACE, poi_message(u64::MAX))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let columns = sqlx::query_as::<_, (Option<i64>, String, String, Option<String>)>(
+            "SELECT nonce, graph_account, identifier, content_topic FROM messages WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            columns,
+            (
+                Some(1707328517),
+                "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
+                "QmTamam".to_string(),
+                Some("/graphcast/0/testnet/proto".to_string()),
+            )
+        );
+        let nonce =
+            sqlx::query_scalar::<_, Option<i64>>("SELECT nonce FROM messages WHERE id = $1")
+                .bind(overflow)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(nonce, None, "Nonces out of the bigint range are not copied");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_add_message_is_idempotent(pool: PgPool) {
+        let first = add_message(&pool, TEST_NAMESPACE, poi_message(1707328517))
+            .await
+            .unwrap();
+        let replayed = add_message(&pool, TEST_NAMESPACE, poi_message(1707328517))
+            .await
+            .unwrap();
+
+        assert!(first.is_some(), "First copy should be stored");
+        assert!(replayed.is_none(), "Replayed copy should be skipped");
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_add_messages_batch(pool: PgPool) {
+        add_message(&pool, TEST_NAMESPACE, poi_message(1707328517))
+            .await
+            .unwrap();
+        let origin = MessageOrigin {
+            message_type: Some("public_poi"),
+            ..Default::default()
+        };
+        let batch: Vec<(serde_json::Value, MessageOrigin)> =
+            [1707328517, 1707328518, 1707328518, 1707328519]
+                .into_iter()
+                .map(|nonce| (serde_json::to_value(poi_message(nonce)).unwrap(), origin))
+                .collect();
+
+        let ids = add_messages_from(&pool, TEST_NAMESPACE, &batch)
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 4);
+        assert!(ids[0].is_none(), "Stored message should be skipped");
+        assert!(ids[2].is_none(), "Repeated message should be skipped");
+        assert!(
+            ids[1].unwrap() < ids[3].unwrap(),
+            "Ids follow the batch order"
+        );
 
-    // Bind indexers to the query if provided.
-    if let Some(indexers) = indexers {
-        for account in indexers {
-            query = query.bind(account);
+        let filter = MessageFilter {
+            nonce_gte: Some(1707328518),
+            message_type: Some("public_poi".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_filtered_messages(&pool, TEST_NAMESPACE, &filter)
+                .await
+                .unwrap(),
+            2
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_duplicate_signatures(pool: PgPool) {
+        let signed = |signature: &str| {
+            serde_json::json!({
+                "identifier": "QmTamam",
+                "nonce": 1707328517,
+                "graph_account": "0xb4b4",
+                "signature": signature,
+                "payload": poi_message(1707328517),
+            })
+        };
+        assert!(add_message(&pool, TEST_NAMESPACE, signed("0x01"))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(
+            add_message(&pool, TEST_NAMESPACE, signed("0x02"))
+                .await
+                .unwrap()
+                .is_none(),
+            "Copy with another signature should be skipped"
+        );
+
+        let batch = vec![
+            (signed("0x03"), MessageOrigin::default()),
+            (
+                serde_json::to_value(poi_message(1707328518)).unwrap(),
+                MessageOrigin::default(),
+            ),
+        ];
+        let ids = add_messages_from(&pool, TEST_NAMESPACE, &batch)
+            .await
+            .unwrap();
+        assert!(ids[0].is_none());
+        assert!(ids[1].is_some());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_import_message(pool: PgPool) {
+        let message = serde_json::to_value(poi_message(1707328517)).unwrap();
+        let id = import_message(&pool, TEST_NAMESPACE, &message, "public_poi")
+            .await
+            .unwrap()
+            .expect("Message should be imported");
+        let created_at: i64 = sqlx::query_scalar(
+            "SELECT EXTRACT(EPOCH FROM created_at)::bigint FROM messages WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            created_at, 1707328517,
+            "Imported message is dated by its nonce"
+        );
+
+        // Importing again, or receiving the same message, does not store a second copy
+        assert!(
+            import_message(&pool, TEST_NAMESPACE, &message, "public_poi")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(add_message(&pool, TEST_NAMESPACE, poi_message(1707328517))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_replay_overlapping_windows(pool: PgPool) {
+        // Live relay window
+        for nonce in 1707328500..1707328510 {
+            add_message(&pool, TEST_NAMESPACE, poi_message(nonce))
+                .await
+                .unwrap();
+        }
+
+        // Store protocol backfill overlapping the second half of the live window
+        let mut duplicates = 0;
+        for nonce in 1707328505..1707328515 {
+            if add_message(&pool, TEST_NAMESPACE, poi_message(nonce))
+                .await
+                .unwrap()
+                .is_none()
+            {
+                duplicates += 1;
+            }
+        }
+
+        assert_eq!(duplicates, 5, "Overlapping messages should be detected");
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 15);
+
+        // Replaying both windows again concurrently stores nothing new
+        let replays = (1707328500..1707328515)
+            .map(|nonce| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    add_message(&pool, TEST_NAMESPACE, poi_message(nonce))
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        for replay in replays {
+            assert!(replay.await.unwrap().is_none());
         }
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 15);
     }
 
-    let rows = query
-        .fetch_all(pool)
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_notify_message(pool: PgPool) {
+        let mut listener = sqlx::postgres::PgListener::connect_with(&pool)
+            .await
+            .unwrap();
+        listener.listen("listener_radio_messages").await.unwrap();
+
+        let id = add_message(&pool, TEST_NAMESPACE, poi_message(1707328517))
+            .await
+            .unwrap()
+            .unwrap();
+        notify_message(
+            &pool,
+            "listener_radio_messages",
+            id,
+            serde_json::json!({ "identifier": "QmTamam" }),
+        )
         .await
-        .map_err(anyhow::Error::new)?
-        .iter()
-        .map(|row| row.get::<String, _>("graph_account"))
-        .collect();
+        .unwrap();
+
+        let notification = listener.recv().await.unwrap();
+        let payload: serde_json::Value = serde_json::from_str(notification.payload()).unwrap();
+        assert_eq!(payload["id"], id);
+        assert_eq!(payload["identifier"], "QmTamam");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_outbox_entries_are_claimed_once(pool: PgPool) {
+        for nonce in 1707328500..1707328505 {
+            let mut tx = pool.begin().await.unwrap();
+            let id = add_message(&mut *tx, TEST_NAMESPACE, poi_message(nonce))
+                .await
+                .unwrap()
+                .unwrap();
+            add_outbox_entry(&mut *tx, id).await.unwrap();
+            tx.commit().await.unwrap();
+        }
+
+        let claimed = claim_outbox_entries(&pool, TEST_NAMESPACE, 3, 60)
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 3);
+        assert_eq!(claimed[0].payload["nonce"], 1707328500);
+
+        // A concurrent relay only sees the entries that are not claimed
+        let rest = claim_outbox_entries(&pool, TEST_NAMESPACE, 10, 60)
+            .await
+            .unwrap();
+        assert_eq!(rest.len(), 2);
+        let rest_ids = rest.iter().map(|e| e.id).collect::<Vec<i64>>();
+        release_outbox_entries(&pool, &rest_ids).await.unwrap();
+
+        let ids = claimed.iter().map(|e| e.id).collect::<Vec<i64>>();
+        mark_outbox_published(&pool, &ids).await.unwrap();
+
+        let pending = claim_outbox_entries(&pool, TEST_NAMESPACE, 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            pending.len(),
+            2,
+            "Published entries should not be relayed again"
+        );
+
+        // Claims expire after their lease
+        sqlx::query("SELECT pg_sleep(0.01)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            claim_outbox_entries(&pool, TEST_NAMESPACE, 10, 60)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_export_cursor(pool: PgPool) {
+        for nonce in 1707328500..1707328505 {
+            add_message(&pool, TEST_NAMESPACE, poi_message(nonce))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            export_cursor(&pool, TEST_NAMESPACE, "bigquery")
+                .await
+                .unwrap(),
+            0
+        );
+
+        let settled_before = Utc::now().timestamp() + 1;
+        let batch = list_messages_after(&pool, TEST_NAMESPACE, 0, settled_before, 3)
+            .await
+            .unwrap();
+        assert_eq!(batch.len(), 3);
+        assert!(batch
+            .iter()
+            .all(|m| m.schema_version == MESSAGE_SCHEMA_VERSION));
+        set_export_cursor(&pool, TEST_NAMESPACE, "bigquery", batch[2].id)
+            .await
+            .unwrap();
+
+        let cursor = export_cursor(&pool, TEST_NAMESPACE, "bigquery")
+            .await
+            .unwrap();
+        let rest = list_messages_after(&pool, TEST_NAMESPACE, cursor, settled_before, 3)
+            .await
+            .unwrap();
+        assert_eq!(
+            rest.len(),
+            2,
+            "Exported messages should not be listed again"
+        );
+        assert!(rest.iter().all(|m| m.id > cursor));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_list_messages_after_holds_back_recent(pool: PgPool) {
+        insert_simple_message(&pool, 120).await;
+        insert_simple_message(&pool, 0).await;
+        // Imported messages are backdated, they wait for the recent message before them
+        insert_simple_message(&pool, 120).await;
+
+        let settled_before = Utc::now().timestamp() - 60;
+        let batch = list_messages_after(&pool, TEST_NAMESPACE, 0, settled_before, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            batch.len(),
+            1,
+            "Only messages before the recent one are settled"
+        );
+
+        let batch = list_messages_after(&pool, TEST_NAMESPACE, batch[0].id, settled_before, 10)
+            .await
+            .unwrap();
+        assert!(batch.is_empty());
+
+        let all = list_messages_after(&pool, TEST_NAMESPACE, 0, Utc::now().timestamp() + 1, 10)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 3);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_message_records(pool: PgPool) {
+        let origin = MessageOrigin {
+            message_type: Some("public_poi"),
+            ..Default::default()
+        };
+        for nonce in 1707328500..1707328505 {
+            add_message_from(&pool, TEST_NAMESPACE, poi_message(nonce), origin)
+                .await
+                .unwrap();
+        }
+        let filter = MessageFilter {
+            nonce_gte: Some(1707328501),
+            ..Default::default()
+        };
+
+        let page = list_message_records(&pool, TEST_NAMESPACE, &filter, 0, 3)
+            .await
+            .unwrap();
+        assert_eq!(
+            page.iter().map(|r| r.nonce).collect::<Vec<_>>(),
+            vec![Some(1707328501), Some(1707328502), Some(1707328503)]
+        );
+        assert_eq!(page[0].message_type.as_deref(), Some("public_poi"));
+        assert_eq!(page[0].identifier.as_deref(), Some("QmTamam"));
+        assert!(page[0].created_at.is_some());
+
+        let rest = list_message_records(&pool, TEST_NAMESPACE, &filter, page[2].id, 3)
+            .await
+            .unwrap();
+        assert_eq!(rest.len(), 1);
+
+        let mut snapshot = begin_snapshot(&pool).await.unwrap();
+        let first = list_message_records(&mut *snapshot, TEST_NAMESPACE, &filter, 0, 1)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM messages")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let rest = list_message_records(&mut *snapshot, TEST_NAMESPACE, &filter, first[0].id, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            rest.len(),
+            3,
+            "Rows deleted during a snapshot read stay in it"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_namespaces_are_isolated(pool: PgPool) {
+        // The same message is stored once per namespace
+        add_message(&pool, "mainnet", poi_message(1707328517))
+            .await
+            .unwrap()
+            .expect("Message should be stored in mainnet");
+        add_message(&pool, "testnet", poi_message(1707328517))
+            .await
+            .unwrap()
+            .expect("Message should be stored in testnet");
+        for offset in 0..3 {
+            add_message(
+                &pool,
+                "testnet",
+                poi_message(Utc::now().timestamp() as u64 + offset),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(delete_all(&pool, "testnet").await, 4);
+        assert_eq!(count_messages(&pool, "testnet").await.unwrap(), 0);
+        assert_eq!(
+            count_messages(&pool, "mainnet").await.unwrap(),
+            1,
+            "Deleting a namespace should leave other instances untouched"
+        );
+
+        let active = list_active_indexers(&pool, "testnet", None, 1707328516)
+            .await
+            .unwrap();
+        assert!(active.is_empty());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_message_by_hash(pool: PgPool) {
+        add_message(&pool, "mainnet", poi_message(1707328516))
+            .await
+            .unwrap();
+        for namespace in ["mainnet", "testnet"] {
+            add_message(&pool, namespace, poi_message(1707328517))
+                .await
+                .unwrap();
+        }
+        let mainnet = list_messages::<PublicPoiMessage>(&pool, "mainnet", None, 0, None)
+            .await
+            .unwrap();
+        let testnet = list_messages::<PublicPoiMessage>(&pool, "testnet", None, 0, None)
+            .await
+            .unwrap();
+        assert_ne!(mainnet[1].get_id(), testnet[0].get_id());
+        assert_eq!(
+            mainnet[1].get_content_hash(),
+            testnet[0].get_content_hash(),
+            "Copies of a message share their hash across row ids"
+        );
+
+        let hash = testnet[0].get_content_hash();
+        let row = message_by_hash::<PublicPoiMessage>(&pool, "mainnet", hash)
+            .await
+            .unwrap();
+        assert_eq!(row.get_id(), mainnet[1].get_id());
+        assert!(
+            message_by_hash::<PublicPoiMessage>(&pool, "mainnet", "unknown")
+                .await
+                .is_err()
+        );
+
+        let deleted = delete_message_by_hash::<PublicPoiMessage>(&pool, "testnet", hash)
+            .await
+            .unwrap();
+        assert_eq!(deleted.get_id(), testnet[0].get_id());
+        assert_eq!(count_messages(&pool, "testnet").await.unwrap(), 0);
+        assert_eq!(count_messages(&pool, "mainnet").await.unwrap(), 2);
+
+        let records = list_message_records(&pool, "mainnet", &MessageFilter::default(), 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(records[1].content_hash, hash);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_count_covered_deployments(pool: PgPool) {
+        let now = Utc::now().timestamp() as u64;
+        for (offset, identifier) in ["QmTamam", "QmTamam", "QmOther"].iter().enumerate() {
+            let mut message = poi_message(now + offset as u64);
+            message.identifier = identifier.to_string();
+            add_message(&pool, TEST_NAMESPACE, message).await.unwrap();
+        }
+        // Simple messages do not count towards coverage
+        insert_simple_message(&pool, 0).await;
+
+        let covered = count_covered_deployments(&pool, TEST_NAMESPACE, now as i64 - 60)
+            .await
+            .unwrap();
+        assert_eq!(covered, 2);
+        let subgraphs = count_distinct_subgraphs(&pool, TEST_NAMESPACE, now as i64 - 60)
+            .await
+            .unwrap();
+        assert_eq!(
+            subgraphs, 3,
+            "Simple messages count as a subgraph of their own"
+        );
+
+        let covered = count_covered_deployments(&pool, TEST_NAMESPACE, now as i64 + 60)
+            .await
+            .unwrap();
+        assert_eq!(covered, 0);
+
+        let busiest = busiest_identifiers(&pool, TEST_NAMESPACE, now as i64 - 60, 1)
+            .await
+            .unwrap();
+        assert_eq!(busiest, vec!["QmTamam".to_string()]);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_protocol_compatibility(pool: PgPool) {
+        for (nonce, unknown_bytes) in [(1, Some(0)), (2, Some(12)), (3, None)] {
+            let origin = MessageOrigin {
+                protocol_version: Some(0),
+                unknown_bytes,
+                ..Default::default()
+            };
+            add_message_from(&pool, TEST_NAMESPACE, poi_message(nonce), origin)
+                .await
+                .unwrap();
+        }
+        add_message(&pool, TEST_NAMESPACE, poi_message(4))
+            .await
+            .unwrap();
+
+        let report = protocol_compatibility(&pool, TEST_NAMESPACE, Utc::now().timestamp() - 60)
+            .await
+            .unwrap();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].protocol_version, Some(0));
+        assert_eq!(report[0].message_count, 3);
+        assert_eq!(report[0].undecoded_count, 1);
+        assert_eq!(report[0].undecoded_senders.len(), 1);
+        assert!(report[0].last_undecoded_at.is_some());
+        assert_eq!(report[1].protocol_version, None);
+        assert_eq!(report[1].undecoded_count, 0);
+        assert!(report[1].undecoded_senders.is_empty());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_peer_delivery_share(pool: PgPool) {
+        let peers = [
+            Some("16Uiu2HAmBoot"),
+            Some("16Uiu2HAmBoot"),
+            Some("16Uiu2HAmRelay"),
+            None,
+        ];
+        for (nonce, peer) in peers.into_iter().enumerate() {
+            let origin = MessageOrigin {
+                peer,
+                ..Default::default()
+            };
+            add_message_from(&pool, TEST_NAMESPACE, poi_message(nonce as u64), origin)
+                .await
+                .unwrap();
+        }
+
+        let shares = peer_delivery_share(&pool, TEST_NAMESPACE, Utc::now().timestamp() - 60)
+            .await
+            .unwrap();
+        assert_eq!(shares.len(), 3);
+        assert_eq!(shares[0].peer.as_deref(), Some("16Uiu2HAmBoot"));
+        assert_eq!(shares[0].message_count, 2);
+        assert!((shares[0].share - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_dead_letter_filters(pool: PgPool) {
+        let topic = "/graphcast/0/listener-radio/QmTamam/proto";
+        let validation =
+            add_dead_letter(&pool, TEST_NAMESPACE, topic, b"\x01", 0, "validation", "x")
+                .await
+                .unwrap();
+        add_dead_letter(&pool, TEST_NAMESPACE, topic, b"\x02", 0, "timeout", "y")
+            .await
+            .unwrap();
+        add_dead_letter(&pool, "other", topic, b"\x03", 0, "timeout", "z")
+            .await
+            .unwrap();
+
+        let all = list_dead_letters(&pool, TEST_NAMESPACE, &DeadLetterFilter::default(), 10)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 2);
+
+        let timeouts = DeadLetterFilter {
+            category: Some("timeout".to_string()),
+            ..Default::default()
+        };
+        let payloads = dead_letter_payloads(&pool, TEST_NAMESPACE, &timeouts, 10)
+            .await
+            .unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].1, vec![2]);
+
+        let future = DeadLetterFilter {
+            from: Some(Utc::now().timestamp() + 60),
+            ..Default::default()
+        };
+        assert!(list_dead_letters(&pool, TEST_NAMESPACE, &future, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let by_id = DeadLetterFilter {
+            ids: Some(vec![validation]),
+            ..Default::default()
+        };
+        assert_eq!(
+            delete_dead_letters(&pool, TEST_NAMESPACE, &by_id)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            delete_dead_letters(&pool, TEST_NAMESPACE, &DeadLetterFilter::default())
+                .await
+                .unwrap(),
+            1,
+            "Other namespaces keep their dead letters"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_network_snapshot(pool: PgPool) {
+        let indexer = NetworkIndexer {
+            id: "0xB4B4570DF6F7FE320F10FDFB702DBA7E35244550".to_string(),
+            staked_tokens: "100000000000000000000000".to_string(),
+            allocated_tokens: "50000000000000000000000".to_string(),
+            allocation_count: 1,
+        };
+        let allocation = NetworkAllocation {
+            id: "0x01".to_string(),
+            indexer: indexer.id.clone(),
+            deployment: "QmTamam".to_string(),
+            allocated_tokens: indexer.allocated_tokens.clone(),
+        };
+        replace_network_snapshot(&pool, std::slice::from_ref(&indexer), &[allocation])
+            .await
+            .unwrap();
+        // A second sync replaces the first one
+        replace_network_snapshot(&pool, std::slice::from_ref(&indexer), &[])
+            .await
+            .unwrap();
+
+        let synced = network_indexer(&pool, "0xb4b4570df6f7fe320f10fdfb702dba7e35244550")
+            .await
+            .unwrap()
+            .expect("Indexer should be synced");
+        assert_eq!(synced.staked_tokens, indexer.staked_tokens);
+        assert_eq!(synced.allocation_count, 1);
+        assert!(network_indexer(&pool, "0x00").await.unwrap().is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_sender_stake(pool: PgPool) {
+        let account = "0xB4B4570DF6F7FE320F10FDFB702DBA7E35244550";
+        add_message(&pool, TEST_NAMESPACE, poi_message(1707328517))
+            .await
+            .unwrap();
+        let indexer = NetworkIndexer {
+            id: account.to_string(),
+            staked_tokens: "1000".to_string(),
+            allocated_tokens: "300".to_string(),
+            allocation_count: 2,
+        };
+        let allocations = ["QmTamam", "QmTamam", "QmOther"]
+            .iter()
+            .enumerate()
+            .map(|(i, deployment)| NetworkAllocation {
+                id: format!("0x0{}", i),
+                indexer: account.to_string(),
+                deployment: deployment.to_string(),
+                allocated_tokens: "100".to_string(),
+            })
+            .collect::<Vec<_>>();
+        replace_network_snapshot(&pool, &[indexer], &allocations)
+            .await
+            .unwrap();
+        add_message(&pool, TEST_NAMESPACE, poi_message(1707328518))
+            .await
+            .unwrap();
+        let batch = vec![(
+            serde_json::to_value(poi_message(1707328519)).unwrap(),
+            MessageOrigin::default(),
+        )];
+        add_messages_from(&pool, TEST_NAMESPACE, &batch)
+            .await
+            .unwrap();
+
+        let before = poi_submission(&pool, TEST_NAMESPACE, "QmTamam", 1707328517, account)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(before.sender_stake, None, "Received before the first sync");
+        for block in [1707328518, 1707328519] {
+            let submission = poi_submission(&pool, TEST_NAMESPACE, "QmTamam", block, account)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(submission.sender_stake.as_deref(), Some("1000"));
+            assert_eq!(submission.sender_allocated_tokens.as_deref(), Some("200"));
+        }
+
+        let stats = topic_identifier_stats(&pool, TEST_NAMESPACE, 0, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].message_count, 3);
+        assert_eq!(
+            stats[0].sender_stake.as_deref(),
+            Some("1000"),
+            "A sender's stake is counted once"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_peer_checks(pool: PgPool) {
+        let mut check = PeerCheck {
+            address: "/dns4/boot.example.org/tcp/31900/p2p/16Uiu2HAmBoot".to_string(),
+            peer_id: Some("16Uiu2HAmBoot".to_string()),
+            critical: true,
+            reachable: true,
+            connected: true,
+            dial_ms: Some(42),
+        };
+        assert_eq!(
+            record_peer_check(&pool, TEST_NAMESPACE, &check)
+                .await
+                .unwrap(),
+            0
+        );
+        check.reachable = false;
+        check.connected = false;
+        check.dial_ms = None;
+        for expected in [1, 2] {
+            assert_eq!(
+                record_peer_check(&pool, TEST_NAMESPACE, &check)
+                    .await
+                    .unwrap(),
+                expected
+            );
+        }
+
+        let peers = list_peer_statuses(&pool, TEST_NAMESPACE).await.unwrap();
+        assert_eq!(peers.len(), 1);
+        assert!(!peers[0].reachable);
+        assert_eq!(peers[0].failures, 2);
+        assert!(
+            peers[0].last_reachable_at.is_some(),
+            "The last success is kept"
+        );
+
+        check.reachable = true;
+        assert_eq!(
+            record_peer_check(&pool, TEST_NAMESPACE, &check)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_listener_nodes(pool: PgPool) {
+        let mut node = ListenerNode {
+            peer_id: "16Uiu2HAmListener".to_string(),
+            multiaddrs: vec!["/ip4/10.0.0.1/tcp/60000/p2p/16Uiu2HAmListener".to_string()],
+            started_at: 1707328517,
+        };
+        upsert_listener_node(&pool, TEST_NAMESPACE, &node)
+            .await
+            .unwrap();
+        node.multiaddrs = vec!["/ip4/10.0.0.2/tcp/60000/p2p/16Uiu2HAmListener".to_string()];
+        node.started_at += 60;
+        upsert_listener_node(&pool, TEST_NAMESPACE, &node)
+            .await
+            .unwrap();
+
+        let nodes = list_listener_nodes(&pool, TEST_NAMESPACE, 10)
+            .await
+            .unwrap();
+        assert_eq!(nodes, vec![node], "A restart replaces the previous record");
+        assert!(list_listener_nodes(&pool, "other", 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_poi_submission(pool: PgPool) {
+        let account = "0xb4b4570df6f7fe320f10fdfb702dba7e35244550";
+        add_message(&pool, TEST_NAMESPACE, poi_message(1707328517))
+            .await
+            .unwrap();
+
+        let submission = poi_submission(&pool, TEST_NAMESPACE, "QmTamam", 1707328517, account)
+            .await
+            .unwrap()
+            .expect("Submission should be found");
+        assert_eq!(submission.poi, format!("0x{:064x}", 1707328517));
+        assert_eq!(submission.nonce, Some(1707328517));
 
-    Ok(rows)
-}
+        assert!(poi_submission(&pool, TEST_NAMESPACE, "QmTamam", 1, account)
+            .await
+            .unwrap()
+            .is_none());
+    }
 
-pub async fn get_indexer_stats(
-    pool: &PgPool,
-    indexers: Option<Vec<String>>,
-    from_timestamp: i64,
-) -> Result<Vec<IndexerStats>, anyhow::Error> {
-    let base_query = "
-        SELECT 
-            message->>'graph_account' as graph_account, 
-            COUNT(*) as message_count, 
-            COUNT(DISTINCT message->>'identifier') as subgraphs_count -- Updated field name
-        FROM messages 
-        WHERE (CAST(message->>'nonce' AS BIGINT)) > $1";
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_consensus_run(pool: PgPool) {
+        let nonce = Utc::now().timestamp() as u64;
+        for (account, content) in [("0x01", "0xaa"), ("0x02", "0xaa"), ("0x03", "0xbb")] {
+            let mut msg = poi_message(nonce);
+            msg.graph_account = account.to_string();
+            msg.content = content.to_string();
+            add_message(&pool, TEST_NAMESPACE, msg).await.unwrap();
+        }
 
-    let mut query = String::from(base_query);
+        let indexer = NetworkIndexer {
+            id: "0x01".to_string(),
+            staked_tokens: "1000".to_string(),
+            allocated_tokens: "0".to_string(),
+            allocation_count: 0,
+        };
+        replace_network_snapshot(&pool, &[indexer], &[])
+            .await
+            .unwrap();
 
-    if let Some(ref idxs) = indexers {
-        let placeholders = idxs
-            .iter()
-            .enumerate()
-            .map(|(i, _)| format!("${}", i + 2))
-            .collect::<Vec<_>>()
-            .join(",");
-        query.push_str(&format!(
-            " AND (message->>'graph_account') IN ({})",
-            placeholders
-        ));
-    }
+        let votes = list_poi_votes(&pool, TEST_NAMESPACE, nonce as i64 - 60, nonce as i64 + 60)
+            .await
+            .unwrap();
+        assert_eq!(votes.len(), 3);
+        assert_eq!(votes[0].stake, Some(1000.0));
+        assert_eq!(votes[1].stake, None);
+        assert_eq!(
+            list_block_votes(&pool, TEST_NAMESPACE, "QmTamam", nonce as i64)
+                .await
+                .unwrap(),
+            votes
+        );
+        assert!(list_poi_votes(&pool, TEST_NAMESPACE, 0, 60)
+            .await
+            .unwrap()
+            .is_empty());
 
-    query.push_str(" GROUP BY graph_account");
+        let run_id = create_consensus_run(&pool, TEST_NAMESPACE, "test/v1", 0, nonce as i64)
+            .await
+            .unwrap();
+        let result = ConsensusResult {
+            deployment: "QmTamam".to_string(),
+            block_number: nonce as i64,
+            consensus_poi: "0xaa".to_string(),
+            support: 2.0 / 3.0,
+            senders: 3,
+        };
+        let divergences: Vec<PoiVote> = votes.into_iter().filter(|v| v.poi == "0xbb").collect();
+        add_consensus_results(&pool, run_id, &[result], &divergences)
+            .await
+            .unwrap();
+        finish_consensus_run(&pool, run_id, "finished")
+            .await
+            .unwrap();
+
+        let incidents = list_divergence_incidents(&pool, TEST_NAMESPACE, run_id, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].graph_account, "0x03");
+        assert_eq!(incidents[0].consensus_poi, "0xaa");
 
-    let mut dynamic_query = sqlx::query_as::<_, IndexerStats>(&query).bind(from_timestamp);
+        let hold = hold_divergence_evidence(&pool, TEST_NAMESPACE, run_id, "divergence")
+            .await
+            .unwrap();
+        assert_eq!(
+            hold.message_count, 3,
+            "Every POI of the diverging block is evidence"
+        );
 
-    if let Some(indexers) = indexers {
-        for account in indexers {
-            dynamic_query = dynamic_query.bind(account);
-        }
+        let runs = list_consensus_runs(&pool, TEST_NAMESPACE, 10)
+            .await
+            .unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].status, "finished");
+        assert!(runs[0].finished_at.is_some());
     }
 
-    let stats = dynamic_query
-        .fetch_all(pool)
-        .await
-        .map_err(anyhow::Error::new)?;
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_api_keys(pool: PgPool) {
+        assert!(!has_api_keys(&pool, TEST_NAMESPACE).await.unwrap());
+        let (key, secret) = create_api_key(&pool, TEST_NAMESPACE, "ops", "admin", None)
+            .await
+            .unwrap();
+        let (_, expired) = create_api_key(&pool, TEST_NAMESPACE, "old", "viewer", Some(1))
+            .await
+            .unwrap();
+        assert!(has_api_keys(&pool, TEST_NAMESPACE).await.unwrap());
+        assert!(secret.starts_with("lr_"));
 
-    Ok(stats)
-}
+        assert_eq!(
+            api_key_role(&pool, TEST_NAMESPACE, &secret).await.unwrap(),
+            Some((key.id, "admin".to_string()))
+        );
 
-#[cfg(test)]
-mod tests {
-    use crate::message_types::PublicPoiMessage;
+        record_api_key_usage(&pool, key.id, 100).await.unwrap();
+        record_api_key_usage(&pool, key.id, 50).await.unwrap();
+        let usage = list_api_key_usage(&pool, TEST_NAMESPACE).await.unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(
+            (usage[0].key_id, usage[0].requests, usage[0].bytes),
+            (key.id, 2, 150)
+        );
+        assert!(usage[0].last_used_at.is_some());
+        assert_eq!(usage[1].requests, 0);
+        assert_eq!(
+            api_key_role(&pool, TEST_NAMESPACE, &expired).await.unwrap(),
+            None
+        );
+        assert_eq!(api_key_role(&pool, "other", &secret).await.unwrap(), None);
 
-    use super::*;
-    use sqlx::PgPool;
+        assert!(revoke_api_key(&pool, TEST_NAMESPACE, key.id).await.unwrap());
+        assert!(!revoke_api_key(&pool, TEST_NAMESPACE, key.id).await.unwrap());
+        assert_eq!(
+            api_key_role(&pool, TEST_NAMESPACE, &secret).await.unwrap(),
+            None
+        );
+        assert_eq!(list_api_keys(&pool, TEST_NAMESPACE).await.unwrap().len(), 2);
+    }
 
-    async fn insert_test_data(pool: &PgPool, entries: Vec<(i64, &str, &str)>) {
-        for (nonce, graph_account, identifier) in entries {
-            let message = PublicPoiMessage {
-                identifier: identifier.to_string(),
-                content: "0xText".to_string(),
-                nonce: nonce.try_into().unwrap(),
-                network: "testnet".to_string(),
-                block_number: 1,
-                block_hash: "hash".to_string(),
-                graph_account: graph_account.to_string(),
-            };
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_notification_retries(pool: PgPool) {
+        let now = Utc::now().timestamp();
+        let slack = enqueue_notification(&pool, TEST_NAMESPACE, "slack", "Divergence", now)
+            .await
+            .unwrap();
+        enqueue_notification(&pool, TEST_NAMESPACE, "discord", "Divergence", now + 60)
+            .await
+            .unwrap();
+
+        let due = due_notifications(&pool, TEST_NAMESPACE, now, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            due,
+            vec![QueuedNotification {
+                id: slack,
+                channel: "slack".to_string(),
+                content: "Divergence".to_string(),
+                attempts: 1,
+            }]
+        );
+        assert!(due_notifications(&pool, "other", now, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        reschedule_notification(&pool, slack, now + 120)
+            .await
+            .unwrap();
+        let due = due_notifications(&pool, TEST_NAMESPACE, now + 120, 10)
+            .await
+            .unwrap();
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].attempts, 2);
 
-            add_message(pool, message)
+        delete_notification(&pool, slack).await.unwrap();
+        assert_eq!(
+            expire_notifications(&pool, TEST_NAMESPACE, now + 3600)
                 .await
-                .expect("Failed to insert test data");
-        }
+                .unwrap(),
+            1
+        );
+        assert!(due_notifications(&pool, TEST_NAMESPACE, now + 3600, 10)
+            .await
+            .unwrap()
+            .is_empty());
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn test_list_active_indexers_without_indexers(pool: PgPool) {
-        insert_test_data(
+    async fn test_notification_templates(pool: PgPool) {
+        assert_eq!(
+            notification_template(&pool, TEST_NAMESPACE, "divergence", "slack")
+                .await
+                .unwrap(),
+            None
+        );
+        set_notification_template(&pool, TEST_NAMESPACE, "divergence", None, "{{run_id}}")
+            .await
+            .unwrap();
+        set_notification_template(
             &pool,
-            vec![(
-                1707328517,
-                "0xb4b4570df6f7fe320f10fdfb702dba7e35244550",
-                "QmTamam",
-            )],
+            TEST_NAMESPACE,
+            "divergence",
+            Some("slack"),
+            ":rotating_light: {{run_id}}",
         )
-        .await;
+        .await
+        .unwrap();
 
-        let from_timestamp = 1707328516;
-        let indexers = None;
-        let result = list_active_indexers(&pool, indexers, from_timestamp)
+        // Channel templates take precedence over the template of every channel
+        assert_eq!(
+            notification_template(&pool, TEST_NAMESPACE, "divergence", "slack")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some(":rotating_light: {{run_id}}")
+        );
+        assert_eq!(
+            notification_template(&pool, TEST_NAMESPACE, "divergence", "discord")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("{{run_id}}")
+        );
+        let templates = list_notification_templates(&pool, TEST_NAMESPACE)
             .await
-            .expect("Function should complete successfully");
+            .unwrap();
+        assert_eq!(templates.len(), 2);
+        assert_eq!(templates[0].channel, None);
 
         assert!(
-            result.contains(&"0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string()),
-            "Result should contain the expected graph_account"
+            delete_notification_template(&pool, TEST_NAMESPACE, "divergence", Some("slack"))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            notification_template(&pool, TEST_NAMESPACE, "divergence", "slack")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("{{run_id}}")
         );
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn test_list_active_indexers_with_specific_indexers(pool: PgPool) {
-        insert_test_data(
+    async fn test_crashes(pool: PgPool) {
+        let report = CrashReport {
+            timestamp: 1712000000,
+            thread: "message-processor".to_string(),
+            location: "src/operator/mod.rs:42:5".to_string(),
+            message: "index out of bounds".to_string(),
+            backtrace: String::new(),
+        };
+        let first = add_crash(&pool, TEST_NAMESPACE, &report).await.unwrap();
+        let second = add_crash(
             &pool,
-            vec![(
-                1707328517,
-                "0xb4b4570df6f7fe320f10fdfb702dba7e35244550",
-                "QmTamam",
-            )],
+            TEST_NAMESPACE,
+            &CrashReport {
+                timestamp: 1712000060,
+                ..report.clone()
+            },
         )
-        .await;
+        .await
+        .unwrap();
 
-        let from_timestamp = 1707328516;
-        let indexers = Some(vec![
-            "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
-            "nonexistent_indexer".to_string(),
-        ]);
-        let result = list_active_indexers(&pool, indexers, from_timestamp)
+        let crashes = list_crashes(&pool, TEST_NAMESPACE, 10).await.unwrap();
+        assert_eq!(
+            crashes.iter().map(|c| c.id).collect::<Vec<_>>(),
+            vec![second, first]
+        );
+        assert_eq!(crashes[1].occurred_at, 1712000000);
+        assert_eq!(crashes[1].location, report.location);
+        assert!(list_crashes(&pool, "other", 10).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_persisted_queries(pool: PgPool) {
+        let query = "{ healthCheck }";
+        let persisted = upsert_persisted_query(&pool, TEST_NAMESPACE, "health", query)
             .await
-            .expect("Function should complete successfully");
+            .unwrap();
+        // Same hash as persisted query clients compute over the query text
+        assert_eq!(
+            persisted.hash,
+            "b9b5bf9f5c2866a5bdfdd8a7ef0c483dda5618d5c5cfce7101a5e06d1a27b068"
+        );
+        assert!(is_persisted_query(&pool, TEST_NAMESPACE, query)
+            .await
+            .unwrap());
+        assert!(
+            !is_persisted_query(&pool, TEST_NAMESPACE, "{ rows { id } }")
+                .await
+                .unwrap()
+        );
+        assert!(!is_persisted_query(&pool, "other", query).await.unwrap());
 
+        // Registering again only renames
+        upsert_persisted_query(&pool, TEST_NAMESPACE, "health_check", query)
+            .await
+            .unwrap();
+        let queries = list_persisted_queries(&pool, TEST_NAMESPACE).await.unwrap();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].name, "health_check");
         assert_eq!(
-            result.len(),
-            1,
-            "Should only match records for existing indexers"
+            persisted_query(&pool, TEST_NAMESPACE, &persisted.hash)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some(query)
         );
+
         assert!(
-            result.contains(&"0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string()),
-            "Result should contain the expected graph_account"
+            delete_persisted_query(&pool, TEST_NAMESPACE, &persisted.hash)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !delete_persisted_query(&pool, TEST_NAMESPACE, &persisted.hash)
+                .await
+                .unwrap()
         );
+        assert!(persisted_query(&pool, TEST_NAMESPACE, &persisted.hash)
+            .await
+            .unwrap()
+            .is_none());
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn test_list_active_indexers_no_matching_records(pool: PgPool) {
-        let from_timestamp = 9999999999;
-        let indexers = None;
-        let result = list_active_indexers(&pool, indexers, from_timestamp)
+    async fn test_coverage_gaps(pool: PgPool) {
+        let account = "0xb4b4570df6f7fe320f10fdfb702dba7e35244550";
+        let allocations: Vec<NetworkAllocation> = ["QmTamam", "QmSilent"]
+            .iter()
+            .enumerate()
+            .map(|(i, deployment)| NetworkAllocation {
+                id: format!("0x0{}", i),
+                indexer: account.to_string(),
+                deployment: deployment.to_string(),
+                allocated_tokens: "1".to_string(),
+            })
+            .collect();
+        replace_network_snapshot(&pool, &[], &allocations)
             .await
-            .expect("Function should complete successfully");
+            .unwrap();
+        // poi_message is sent by the same account about QmTamam
+        let now = Utc::now().timestamp();
+        add_message(&pool, TEST_NAMESPACE, poi_message(now as u64))
+            .await
+            .unwrap();
 
-        assert!(
-            result.is_empty(),
-            "Result should be empty when no records match criteria"
+        assert_eq!(
+            list_allocated_deployments(&pool, account).await.unwrap(),
+            vec!["QmSilent", "QmTamam"]
+        );
+        assert_eq!(
+            list_coverage_gaps(&pool, TEST_NAMESPACE, account, now - 60)
+                .await
+                .unwrap(),
+            vec!["QmSilent"]
         );
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn test_list_active_indexers_edge_cases(pool: PgPool) {
-        let specific_nonce = Utc::now().timestamp();
-        insert_test_data(
-            &pool,
-            vec![(
-                specific_nonce,
-                "0xb4b4570df6f7fe320f10fdfb702dba7e35244550",
-                "QmTamam",
-            )],
-        )
-        .await;
+    async fn test_captured_messages(pool: PgPool) {
+        for payload in [b"\x01\x02".as_slice(), b"not a graphcast message"] {
+            add_captured_message(
+                &pool,
+                TEST_NAMESPACE,
+                "/graphcast/0/listener-radio/QmTamam/proto",
+                0,
+                payload,
+                1707328517,
+                None,
+            )
+            .await
+            .unwrap();
+        }
 
-        let from_timestamp = specific_nonce;
-        let indexers = None;
-        let result = list_active_indexers(&pool, indexers, from_timestamp)
+        let captured = list_captured_messages(&pool, TEST_NAMESPACE, 10)
             .await
-            .expect("Function should complete successfully");
+            .unwrap();
+        assert_eq!(captured.len(), 2);
+        assert_eq!(captured[1].payload, "0102");
+        assert!(list_captured_messages(&pool, "other", 10)
+            .await
+            .unwrap()
+            .is_empty());
 
-        assert!(
-            result.is_empty(),
-            "Result should be empty when from_timestamp exactly matches nonce"
-        );
+        let deleted = delete_captured_messages(&pool, TEST_NAMESPACE)
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn test_list_active_indexers_with_partial_matching_indexers(pool: PgPool) {
-        insert_test_data(
+    async fn test_raw_messages(pool: PgPool) {
+        let envelope = GraphcastEnvelope {
+            identifier: "QmTamam".to_string(),
+            nonce: 1707328517,
+            graph_account: "0xe9a1cabd57700b17945fd81feefba82340d9568f".to_string(),
+            signature: "0x2f6d".to_string(),
+        };
+        let topic = "/graphcast/0/listener-radio/QmTamam/proto";
+        add_raw_message(
             &pool,
-            vec![
-                (
-                    1707328517,
-                    "0xb4b4570df6f7fe320f10fdfb702dba7e35244550",
-                    "QmTamam",
-                ),
-                (1707328518, "some_other_account", "QmTamam"),
-            ],
+            TEST_NAMESPACE,
+            topic,
+            0,
+            &envelope.encode_to_vec(),
+            1707328517,
+            Some("16Uiu2HAm"),
         )
-        .await;
+        .await
+        .unwrap();
+        add_raw_message(
+            &pool,
+            TEST_NAMESPACE,
+            "other",
+            0,
+            b"\xff\xff",
+            1707328518,
+            None,
+        )
+        .await
+        .unwrap();
 
-        let from_timestamp = 1707328516;
-        let indexers = Some(vec![
-            "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
-            "partial_match_indexer".to_string(),
-        ]);
-        let result = list_active_indexers(&pool, indexers, from_timestamp)
+        let raw = list_raw_messages(&pool, TEST_NAMESPACE, None, 10)
             .await
-            .expect("Function should complete successfully");
+            .unwrap();
+        assert_eq!(raw.len(), 2);
+        assert_eq!(raw[0].payload, "ffff");
+        assert_eq!(raw[0].signature, None);
+        assert_eq!(raw[1].signature.as_deref(), Some("0x2f6d"));
+        assert_eq!(
+            raw[1].graph_account.as_deref(),
+            Some(envelope.graph_account.as_str())
+        );
+
+        let raw = list_raw_messages(&pool, TEST_NAMESPACE, Some(topic), 10)
+            .await
+            .unwrap();
+        assert_eq!(raw.len(), 1);
+        assert_eq!(raw[0].peer.as_deref(), Some("16Uiu2HAm"));
+
+        let undecoded = list_undecoded_payloads(&pool, TEST_NAMESPACE, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(undecoded.len(), 2);
+        assert!(undecoded
+            .iter()
+            .any(|p| p.payload == envelope.encode_to_vec()
+                && p.graph_account.as_deref() == Some(envelope.graph_account.as_str())));
 
         assert_eq!(
-            result.len(),
-            1,
-            "Should only match records for existing indexers"
+            prune_raw_messages(&pool, TEST_NAMESPACE, 60).await.unwrap(),
+            0
         );
-        assert!(
-            result.contains(&"0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string()),
-            "Result should contain the expected graph_account"
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_ingest_lock(pool: PgPool) {
+        let mut old = pool.acquire().await.unwrap();
+        let mut new = pool.acquire().await.unwrap();
+        assert!(try_ingest_lock(&mut *old, TEST_NAMESPACE).await.unwrap());
+        assert!(!try_ingest_lock(&mut *new, TEST_NAMESPACE).await.unwrap());
+        assert!(try_ingest_lock(&mut *new, "other").await.unwrap());
+
+        assert!(release_ingest_lock(&mut *old, TEST_NAMESPACE)
+            .await
+            .unwrap());
+        assert!(try_ingest_lock(&mut *new, TEST_NAMESPACE).await.unwrap());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_sender_nonces(pool: PgPool) {
+        let nonce = |last_nonce| SenderNonce {
+            graph_account: "0xb4b4".to_string(),
+            identifier: "QmTamam".to_string(),
+            last_nonce,
+        };
+        upsert_sender_nonces(&pool, TEST_NAMESPACE, &[nonce(1707328518)])
+            .await
+            .unwrap();
+        upsert_sender_nonces(&pool, TEST_NAMESPACE, &[nonce(1707328517)])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            list_sender_nonces(&pool, TEST_NAMESPACE).await.unwrap(),
+            vec![nonce(1707328518)]
         );
+        assert!(list_sender_nonces(&pool, "testnet")
+            .await
+            .unwrap()
+            .is_empty());
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn test_list_active_indexers_with_nonexistent_indexers(pool: PgPool) {
-        let from_timestamp = 1707328516;
-        let indexers = Some(vec![
-            "nonexistent_indexer_1".to_string(),
-            "nonexistent_indexer_2".to_string(),
-        ]);
-        let result = list_active_indexers(&pool, indexers, from_timestamp)
+    async fn test_list_indexer_activity(pool: PgPool) {
+        // Imported messages are dated by their nonce
+        for nonce in [1707328517, 1707328518, 1707328600] {
+            let message = serde_json::to_value(poi_message(nonce)).unwrap();
+            import_message(&pool, TEST_NAMESPACE, &message, "public_poi")
+                .await
+                .unwrap();
+        }
+        let activity = list_indexer_activity(&pool, TEST_NAMESPACE, None, 1707328516, 1707328518)
             .await
-            .expect("Function should complete successfully");
+            .unwrap();
+        assert_eq!(
+            activity,
+            vec![IndexerActivity {
+                graph_account: "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
+                identifier: "QmTamam".to_string(),
+                message_count: 2,
+            }]
+        );
 
+        let other = ["0x0000".to_string()];
         assert!(
-            result.is_empty(),
-            "Result should be empty for non-existent indexers"
+            list_indexer_activity(&pool, TEST_NAMESPACE, Some(&other), 0, i64::MAX)
+                .await
+                .unwrap()
+                .is_empty()
         );
     }
 
@@ -545,7 +5886,7 @@ mod tests {
 
         let from_timestamp = 1707328516;
         let indexers = None;
-        let result = get_indexer_stats(&pool, indexers, from_timestamp)
+        let result = get_indexer_stats(&pool, TEST_NAMESPACE, indexers, from_timestamp)
             .await
             .expect("Function should complete successfully");
 
@@ -569,7 +5910,7 @@ mod tests {
         let indexers = Some(vec![
             "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string()
         ]);
-        let result = get_indexer_stats(&pool, indexers, from_timestamp)
+        let result = get_indexer_stats(&pool, TEST_NAMESPACE, indexers, from_timestamp)
             .await
             .expect("Function should complete successfully");
 
@@ -611,7 +5952,7 @@ mod tests {
             "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
             "0xb4b4570df6f7fe320f10fdfb702dba7e35244551".to_string(),
         ]);
-        let result = get_indexer_stats(&pool, indexers, from_timestamp)
+        let result = get_indexer_stats(&pool, TEST_NAMESPACE, indexers, from_timestamp)
             .await
             .expect("Function should complete successfully");
 
@@ -628,7 +5969,7 @@ mod tests {
         // Assuming a very high timestamp to ensure no records match
         let from_timestamp = Utc::now().timestamp() + 10000;
         let indexers = None;
-        let result = get_indexer_stats(&pool, indexers, from_timestamp)
+        let result = get_indexer_stats(&pool, TEST_NAMESPACE, indexers, from_timestamp)
             .await
             .expect("Function should complete successfully");
 
@@ -671,7 +6012,7 @@ mod tests {
             "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
             "0xb4b4570df6f7fe320f10fdfb702dba7e35244551".to_string(),
         ]);
-        let result = get_indexer_stats(&pool, indexers, from_timestamp)
+        let result = get_indexer_stats(&pool, TEST_NAMESPACE, indexers, from_timestamp)
             .await
             .expect("Function should complete successfully");
 
@@ -0,0 +1,345 @@
+//! Materialized views of the heavy stats queries. The listener refreshes them every
+//! STATS_REFRESH_INTERVAL, and the stats read from them report when that last happened so
+//! dashboards can show how stale they are
+use async_graphql::{Enum, OutputType, SimpleObject};
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+use tracing::{debug, warn};
+
+use super::resolver::IndexerStats;
+use crate::ListenerError;
+
+/// Views refreshed together, in refresh order
+pub const STATS_VIEWS: [&str; 3] = [
+    "indexer_activity_hourly",
+    "topic_activity_hourly",
+    "consensus_summaries",
+];
+
+/// Rows read from a materialized view, with the time the view was last refreshed
+#[derive(Clone, Debug, SimpleObject)]
+#[graphql(concrete(name = "MaterializedIndexerStats", params(IndexerStats)))]
+#[graphql(concrete(name = "MaterializedTopicStats", params(TopicStats)))]
+#[graphql(concrete(name = "MaterializedConsensusSummaries", params(ConsensusSummary)))]
+#[graphql(concrete(name = "MaterializedActivity", params(ActivityBucket)))]
+pub struct Materialized<T: OutputType> {
+    pub rows: Vec<T>,
+    /// Unix timestamp of the last refresh, messages received since are not counted
+    pub refreshed_at: Option<i64>,
+    pub staleness_seconds: Option<i64>,
+}
+
+/// Messages received on a content topic
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct TopicStats {
+    /// Null for messages stored without their content topic
+    pub content_topic: Option<String>,
+    pub message_count: i64,
+    pub sender_count: i64,
+    /// Unix timestamp of the latest message
+    pub last_received_at: i64,
+}
+
+/// Width of the buckets of an activity series
+#[derive(Enum, Copy, Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub enum ActivityGranularity {
+    #[default]
+    Hour,
+    /// UTC days, summed from the hourly buckets
+    Day,
+}
+
+impl ActivityGranularity {
+    fn unit(&self) -> &'static str {
+        match self {
+            ActivityGranularity::Hour => "hour",
+            ActivityGranularity::Day => "day",
+        }
+    }
+}
+
+/// Indexer messages received in one bucket of an activity series
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct ActivityBucket {
+    /// Unix timestamp of the start of the bucket
+    pub bucket_start: i64,
+    pub message_count: i64,
+    pub sender_count: i64,
+    pub subgraphs_count: i64,
+}
+
+/// Outcome of a consensus run
+#[derive(FromRow, SimpleObject, Serialize, Debug, Clone)]
+pub struct ConsensusSummary {
+    pub run_id: i64,
+    pub methodology: String,
+    pub from_ts: i64,
+    pub to_ts: i64,
+    pub status: String,
+    pub deployments: i64,
+    /// Deployment blocks a consensus was computed for
+    pub blocks: i64,
+    /// Average share of the vote weight behind the consensus POIs
+    pub mean_support: Option<f64>,
+    pub incidents: i64,
+    pub diverging_senders: i64,
+}
+
+/// Refresh every stats view without blocking readers, recording the refresh time of each
+pub async fn refresh_stats_views(pool: &PgPool) -> Result<(), ListenerError> {
+    for view in STATS_VIEWS {
+        let started = Instant::now();
+        sqlx::query(&format!("REFRESH MATERIALIZED VIEW CONCURRENTLY {}", view))
+            .execute(pool)
+            .await?;
+        sqlx::query(
+            r#"
+INSERT INTO stats_view_refreshes ( view_name, refreshed_at )
+VALUES ( $1, NOW() )
+ON CONFLICT (view_name) DO UPDATE SET refreshed_at = EXCLUDED.refreshed_at
+            "#,
+        )
+        .bind(view)
+        .execute(pool)
+        .await?;
+        debug!(
+            view,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Refreshed stats view"
+        );
+    }
+    Ok(())
+}
+
+async fn materialized<T: OutputType>(
+    pool: &PgPool,
+    view: &str,
+    rows: Vec<T>,
+) -> Result<Materialized<T>, ListenerError> {
+    let refreshed_at = sqlx::query_scalar::<_, i64>(
+        "SELECT EXTRACT(EPOCH FROM refreshed_at)::bigint FROM stats_view_refreshes WHERE view_name = $1",
+    )
+    .bind(view)
+    .fetch_optional(pool)
+    .await?;
+    Ok(Materialized {
+        rows,
+        refreshed_at,
+        staleness_seconds: refreshed_at.map(|at| (Utc::now().timestamp() - at).max(0)),
+    })
+}
+
+/// Indexer stats since `from_timestamp`, rounded down to the hour
+pub async fn materialized_indexer_stats(
+    pool: &PgPool,
+    namespace: &str,
+    indexers: Option<&[String]>,
+    from_timestamp: i64,
+) -> Result<Materialized<IndexerStats>, ListenerError> {
+    let rows = sqlx::query_as::<_, IndexerStats>(
+        r#"
+SELECT graph_account, SUM(message_count)::bigint AS message_count,
+       COUNT(DISTINCT identifier) AS subgraphs_count
+FROM indexer_activity_hourly
+WHERE namespace = $1 AND hour >= date_trunc('hour', to_timestamp($2))
+  AND ($3::text[] IS NULL OR graph_account = ANY($3))
+GROUP BY graph_account
+ORDER BY graph_account
+        "#,
+    )
+    .bind(namespace)
+    .bind(from_timestamp)
+    .bind(indexers)
+    .fetch_all(pool)
+    .await?;
+
+    materialized(pool, "indexer_activity_hourly", rows).await
+}
+
+/// Message and sender counts by content topic since `from_timestamp`, rounded down to the
+/// hour, busiest topics first
+pub async fn materialized_topic_stats(
+    pool: &PgPool,
+    namespace: &str,
+    from_timestamp: i64,
+) -> Result<Materialized<TopicStats>, ListenerError> {
+    let rows = sqlx::query_as::<_, TopicStats>(
+        r#"
+SELECT content_topic, SUM(message_count)::bigint AS message_count,
+       COUNT(DISTINCT graph_account) AS sender_count,
+       EXTRACT(EPOCH FROM MAX(last_received_at))::bigint AS last_received_at
+FROM topic_activity_hourly
+WHERE namespace = $1 AND hour >= date_trunc('hour', to_timestamp($2))
+GROUP BY content_topic
+ORDER BY message_count DESC
+        "#,
+    )
+    .bind(namespace)
+    .bind(from_timestamp)
+    .fetch_all(pool)
+    .await?;
+
+    materialized(pool, "topic_activity_hourly", rows).await
+}
+
+/// Indexer activity between `from_timestamp` and `to_timestamp` (open ended when None) in
+/// buckets of `granularity`, oldest first. Both ends are rounded down to the hour, buckets
+/// without messages are left out
+pub async fn materialized_activity(
+    pool: &PgPool,
+    namespace: &str,
+    granularity: ActivityGranularity,
+    from_timestamp: i64,
+    to_timestamp: Option<i64>,
+) -> Result<Materialized<ActivityBucket>, ListenerError> {
+    let rows = sqlx::query_as::<_, ActivityBucket>(
+        r#"
+SELECT EXTRACT(EPOCH FROM date_trunc($2, hour AT TIME ZONE 'UTC'))::bigint AS bucket_start,
+       SUM(message_count)::bigint AS message_count,
+       COUNT(DISTINCT graph_account) AS sender_count,
+       COUNT(DISTINCT identifier) AS subgraphs_count
+FROM indexer_activity_hourly
+WHERE namespace = $1 AND hour >= date_trunc('hour', to_timestamp($3))
+  AND ($4::bigint IS NULL OR hour < date_trunc('hour', to_timestamp($4)))
+GROUP BY bucket_start
+ORDER BY bucket_start
+        "#,
+    )
+    .bind(namespace)
+    .bind(granularity.unit())
+    .bind(from_timestamp)
+    .bind(to_timestamp)
+    .fetch_all(pool)
+    .await?;
+
+    materialized(pool, "indexer_activity_hourly", rows).await
+}
+
+/// Summaries of the latest consensus runs, newest first
+pub async fn materialized_consensus_summaries(
+    pool: &PgPool,
+    namespace: &str,
+    limit: i64,
+) -> Result<Materialized<ConsensusSummary>, ListenerError> {
+    let rows = sqlx::query_as::<_, ConsensusSummary>(
+        r#"
+SELECT run_id, methodology, from_ts, to_ts, status, deployments, blocks, mean_support,
+       incidents, diverging_senders
+FROM consensus_summaries
+WHERE namespace = $1
+ORDER BY run_id DESC
+LIMIT $2
+        "#,
+    )
+    .bind(namespace)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    materialized(pool, "consensus_summaries", rows).await
+}
+
+/// Background job refreshing the stats views, the views keep their previous rows when a
+/// refresh fails
+pub async fn run_stats_refresh(db: PgPool, refresh_interval: Duration, running: Arc<AtomicBool>) {
+    let mut refresh_interval = interval(refresh_interval);
+
+    while running.load(Ordering::SeqCst) {
+        refresh_interval.tick().await;
+        if let Err(e) = refresh_stats_views(&db).await {
+            warn!(err = e.to_string(), "Failed to refresh stats views");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::resolver::add_message;
+    use crate::message_types::PublicPoiMessage;
+
+    const TEST_NAMESPACE: &str = "default";
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_materialized_stats(pool: PgPool) {
+        let message = PublicPoiMessage {
+            identifier: "QmTamam".to_string(),
+            content: "0xText".to_string(),
+            nonce: 1707328517,
+            network: "testnet".to_string(),
+            block_number: 1,
+            block_hash: "hash".to_string(),
+            graph_account: "0xb4b4570df6f7fe320f10fdfb702dba7e35244550".to_string(),
+        };
+        add_message(&pool, TEST_NAMESPACE, message).await.unwrap();
+        let from_timestamp = Utc::now().timestamp() - 3600;
+
+        // Rows only show up once the views are refreshed
+        let stats = materialized_indexer_stats(&pool, TEST_NAMESPACE, None, from_timestamp)
+            .await
+            .unwrap();
+        assert!(stats.rows.is_empty());
+        assert!(stats.refreshed_at.is_some());
+
+        refresh_stats_views(&pool).await.unwrap();
+        let stats = materialized_indexer_stats(&pool, TEST_NAMESPACE, None, from_timestamp)
+            .await
+            .unwrap();
+        assert_eq!(stats.rows.len(), 1);
+        assert_eq!(stats.rows[0].message_count, 1);
+        assert_eq!(stats.rows[0].subgraphs_count, 1);
+        assert!(stats.staleness_seconds.unwrap() < 60);
+
+        let topics = materialized_topic_stats(&pool, TEST_NAMESPACE, from_timestamp)
+            .await
+            .unwrap();
+        assert_eq!(topics.rows.len(), 1);
+        assert_eq!(topics.rows[0].sender_count, 1);
+
+        let hourly = materialized_activity(
+            &pool,
+            TEST_NAMESPACE,
+            ActivityGranularity::Hour,
+            from_timestamp,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(hourly.rows.len(), 1);
+        assert_eq!(hourly.rows[0].bucket_start % 3600, 0);
+        let daily = materialized_activity(
+            &pool,
+            TEST_NAMESPACE,
+            ActivityGranularity::Day,
+            from_timestamp,
+            Some(Utc::now().timestamp() + 3600),
+        )
+        .await
+        .unwrap();
+        assert_eq!(daily.rows[0].bucket_start % 86400, 0);
+        assert_eq!(daily.rows[0].message_count, 1);
+        assert!(materialized_activity(
+            &pool,
+            TEST_NAMESPACE,
+            ActivityGranularity::Day,
+            from_timestamp,
+            Some(from_timestamp - 3600),
+        )
+        .await
+        .unwrap()
+        .rows
+        .is_empty());
+        assert!(materialized_consensus_summaries(&pool, TEST_NAMESPACE, 10)
+            .await
+            .unwrap()
+            .rows
+            .is_empty());
+    }
+}
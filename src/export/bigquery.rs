@@ -0,0 +1,322 @@
+use anyhow::anyhow;
+use chrono::Utc;
+use gcp_bigquery_client::{
+    google::cloud::bigquery::storage::v1::{
+        append_rows_request::Rows, append_rows_response::Response, ProtoRows,
+    },
+    storage::{ColumnMode, ColumnType, FieldDescriptor, StorageApi, StreamName, TableDescriptor},
+    Client,
+};
+use prost::Message;
+use sqlx::PgPool;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::time::interval;
+use tokio_stream::StreamExt;
+use tracing::{debug, error, warn};
+
+use crate::{
+    config::Config,
+    db::resolver::{export_cursor, list_messages_after, set_export_cursor},
+    metrics::{EXPORTED_MESSAGES, EXPORT_FAILURES},
+};
+
+const SINK: &str = "bigquery";
+
+/// Failed runs in a row before the export is reported as stuck. Rejected rows keep the
+/// cursor in place, so every later run fails on the same batch
+const STUCK_AFTER_FAILURES: u32 = 3;
+
+/// Destination table, configured as `project.dataset.table`
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigQueryTable {
+    pub project: String,
+    pub dataset: String,
+    pub table: String,
+}
+
+impl BigQueryTable {
+    pub fn parse(value: &str) -> Result<Self, anyhow::Error> {
+        match value.split('.').collect::<Vec<&str>>()[..] {
+            [project, dataset, table]
+                if !project.is_empty() && !dataset.is_empty() && !table.is_empty() =>
+            {
+                Ok(BigQueryTable {
+                    project: project.to_string(),
+                    dataset: dataset.to_string(),
+                    table: table.to_string(),
+                })
+            }
+            _ => Err(anyhow!(
+                "BigQuery table must be formatted as project.dataset.table, got {}",
+                value
+            )),
+        }
+    }
+}
+
+/// Settings of the BigQuery export, checked when the operator is built
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigQueryExport {
+    pub table: BigQueryTable,
+    /// Path to the service account key
+    pub credentials: String,
+    pub interval: Duration,
+    /// Age in seconds before messages are exported
+    pub safety_window: i64,
+}
+
+impl BigQueryExport {
+    /// None when BIGQUERY_TABLE is unset, an error when it is invalid or has no credentials
+    pub fn from_config(config: &Config) -> Result<Option<Self>, anyhow::Error> {
+        let Some(table) = &config.bigquery_table else {
+            return Ok(None);
+        };
+        let table = BigQueryTable::parse(table)?;
+        let credentials = config.bigquery_credentials.clone().ok_or_else(|| {
+            anyhow!("BIGQUERY_TABLE needs a service account key in GOOGLE_APPLICATION_CREDENTIALS")
+        })?;
+        Ok(Some(BigQueryExport {
+            table,
+            credentials,
+            interval: Duration::from_secs(config.bigquery_export_interval),
+            safety_window: config.bigquery_safety_window as i64,
+        }))
+    }
+}
+
+/// Row of the destination table: `id INT64, namespace STRING, created_at TIMESTAMP,
+/// schema_version INT64, message JSON`. Timestamps are written as microseconds and json as
+/// text, as the Storage Write API expects them
+#[derive(Clone, PartialEq, Message)]
+struct ExportRow {
+    #[prost(int64, tag = "1")]
+    id: i64,
+    #[prost(string, tag = "2")]
+    namespace: String,
+    #[prost(int64, tag = "3")]
+    created_at: i64,
+    #[prost(int64, tag = "4")]
+    schema_version: i64,
+    #[prost(string, tag = "5")]
+    message: String,
+}
+
+fn table_descriptor() -> TableDescriptor {
+    let field = |number, name: &str, typ| FieldDescriptor {
+        number,
+        name: name.to_string(),
+        typ,
+        mode: ColumnMode::Required,
+    };
+    TableDescriptor {
+        field_descriptors: vec![
+            field(1, "id", ColumnType::Int64),
+            field(2, "namespace", ColumnType::String),
+            field(3, "created_at", ColumnType::Int64),
+            field(4, "schema_version", ColumnType::Int64),
+            field(5, "message", ColumnType::String),
+        ],
+    }
+}
+
+/// Append request rows. The client encodes with a newer prost than the message types of
+/// this crate, so the rows are encoded here and only the writer schema comes from it
+fn append_rows(rows: &[ExportRow]) -> Rows {
+    let (mut append_rows, _) = StorageApi::create_rows::<ProtoRows>(&table_descriptor(), &[], 0);
+    let Rows::ProtoRows(data) = &mut append_rows;
+    data.rows = Some(ProtoRows {
+        serialized_rows: rows.iter().map(|row| row.encode_to_vec()).collect(),
+    });
+    append_rows
+}
+
+/// Append messages stored since the last export to the table's default stream in batches,
+/// the cursor only moves once BigQuery accepted a batch. Messages received within the
+/// safety window, and the ones stored after them, wait for the next export so messages
+/// committed out of id order are not skipped
+/// Returns the number of messages exported
+pub async fn export_to_bigquery(
+    pool: &PgPool,
+    namespace: &str,
+    client: &mut Client,
+    export: &BigQueryExport,
+    batch_size: i64,
+) -> Result<i64, anyhow::Error> {
+    let table = &export.table;
+    let stream = StreamName::new_default(
+        table.project.clone(),
+        table.dataset.clone(),
+        table.table.clone(),
+    );
+    let settled_before = Utc::now().timestamp() - export.safety_window;
+    let mut total_exported = 0i64;
+    let mut cursor = export_cursor(pool, namespace, SINK).await?;
+
+    loop {
+        let rows = list_messages_after(pool, namespace, cursor, settled_before, batch_size).await?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+
+        let export_rows = rows
+            .iter()
+            .map(|row| ExportRow {
+                id: row.id,
+                namespace: namespace.to_string(),
+                created_at: row.created_at * 1_000_000,
+                schema_version: row.schema_version as i64,
+                message: row.message.clone(),
+            })
+            .collect::<Vec<ExportRow>>();
+        let mut responses = client
+            .storage_mut()
+            .append_rows(&stream, append_rows(&export_rows), SINK.to_string())
+            .await?;
+        while let Some(response) = responses.next().await {
+            let response = response?;
+            if let Some(error) = response.row_errors.first() {
+                return Err(anyhow!(
+                    "BigQuery rejected {} rows, row {}: {}",
+                    response.row_errors.len(),
+                    error.index,
+                    error.message
+                ));
+            }
+            if let Some(Response::Error(status)) = response.response {
+                return Err(anyhow!("BigQuery rejected the rows: {}", status.message));
+            }
+        }
+
+        cursor = last.id;
+        set_export_cursor(pool, namespace, SINK, cursor).await?;
+        total_exported += rows.len() as i64;
+        EXPORTED_MESSAGES
+            .with_label_values(&[SINK])
+            .inc_by(rows.len() as u64);
+
+        if (rows.len() as i64) < batch_size {
+            break;
+        }
+    }
+
+    Ok(total_exported)
+}
+
+/// Background job periodically exporting new messages to BigQuery
+pub async fn run_bigquery_export(
+    db: PgPool,
+    namespace: String,
+    export: BigQueryExport,
+    running: Arc<AtomicBool>,
+) {
+    let mut client = match Client::from_service_account_key_file(&export.credentials).await {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(
+                err = tracing::field::debug(e),
+                "Could not authenticate with BigQuery, export is disabled"
+            );
+            return;
+        }
+    };
+    let mut export_interval = interval(export.interval);
+    let batch_size = 500;
+    let mut failures = 0u32;
+
+    while running.load(Ordering::SeqCst) {
+        export_interval.tick().await;
+        match export_to_bigquery(&db, &namespace, &mut client, &export, batch_size).await {
+            Ok(num_exported) => {
+                failures = 0;
+                debug!(num_exported, "Exported messages to BigQuery");
+            }
+            Err(e) => {
+                failures += 1;
+                EXPORT_FAILURES.with_label_values(&[SINK]).inc();
+                if failures >= STUCK_AFTER_FAILURES {
+                    error!(
+                        failures,
+                        err = tracing::field::debug(e),
+                        "BigQuery export is stuck, the same batch keeps failing"
+                    );
+                } else {
+                    warn!(
+                        failures,
+                        err = tracing::field::debug(e),
+                        "Failed to export messages to BigQuery"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gcp_bigquery_client::google::cloud::bigquery::storage::v1::append_rows_request::ProtoData;
+
+    #[test]
+    fn test_from_config() {
+        assert_eq!(
+            BigQueryExport::from_config(&Config::default()).unwrap(),
+            None
+        );
+
+        let config = Config {
+            bigquery_table: Some("project.dataset.messages".to_string()),
+            bigquery_export_interval: 300,
+            bigquery_safety_window: 60,
+            ..Default::default()
+        };
+        assert!(
+            BigQueryExport::from_config(&config).is_err(),
+            "A table without credentials should be rejected"
+        );
+        let export = BigQueryExport::from_config(&Config {
+            bigquery_credentials: Some("/secrets/key.json".to_string()),
+            ..config.clone()
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(export.table.table, "messages");
+        assert_eq!(export.safety_window, 60);
+        assert!(BigQueryExport::from_config(&Config {
+            bigquery_table: Some("dataset.messages".to_string()),
+            bigquery_credentials: Some("/secrets/key.json".to_string()),
+            ..config
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_append_rows() {
+        let row = ExportRow {
+            id: 7,
+            namespace: "default".to_string(),
+            created_at: 1707328500 * 1_000_000,
+            schema_version: 1,
+            message: "{}".to_string(),
+        };
+        let Rows::ProtoRows(ProtoData {
+            writer_schema,
+            rows,
+        }) = append_rows(std::slice::from_ref(&row));
+        let descriptor = writer_schema.unwrap().proto_descriptor.unwrap();
+        assert_eq!(
+            descriptor
+                .field
+                .iter()
+                .map(|field| field.name())
+                .collect::<Vec<_>>(),
+            vec!["id", "namespace", "created_at", "schema_version", "message"]
+        );
+        let rows = rows.unwrap().serialized_rows;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(ExportRow::decode(rows[0].as_slice()).unwrap(), row);
+    }
+}
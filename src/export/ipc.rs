@@ -0,0 +1,426 @@
+//! Streaming exports of stored messages for analytical pulls, as newline delimited JSON, CSV,
+//! Parquet or an Apache Arrow IPC stream that pandas and polars load without parsing every row
+use anyhow::anyhow;
+use arrow::array::{ArrayRef, Int64Array, StringArray, TimestampSecondArray};
+use arrow::csv::WriterBuilder as CsvWriterBuilder;
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::{io, path::Path, str::FromStr, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, OwnedSemaphorePermit};
+use tokio::time::timeout;
+use tokio::{fs::File, io::AsyncWriteExt};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tracing::warn;
+
+use crate::db::resolver::{begin_snapshot, list_message_records, MessageFilter, MessageRecord};
+
+/// Rows read from the database and encoded at once, one record batch in Arrow streams and
+/// one row group in Parquet files
+pub const EXPORT_BATCH_ROWS: i64 = 8192;
+
+/// Encoded chunks waiting for a slow client before reading pauses
+const EXPORT_BUFFERED_CHUNKS: usize = 4;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One json object per line
+    #[default]
+    #[serde(alias = "jsonl")]
+    #[value(alias = "jsonl")]
+    Ndjson,
+    /// Arrow IPC streaming format
+    Arrow,
+    /// Comma separated values with a header row, `message` as json text
+    Csv,
+    /// Parquet file, its footer comes with the last chunk
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Arrow => "application/vnd.apache.arrow.stream",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "ndjson" | "jsonl" => Ok(ExportFormat::Ndjson),
+            "arrow" => Ok(ExportFormat::Arrow),
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(format!(
+                "Unknown export format {}, expected ndjson, csv, parquet or arrow",
+                value
+            )),
+        }
+    }
+}
+
+/// Columns of exported messages, `message` holds the stored json. `content_hash` identifies
+/// a message across instances, `id` is only the row id of the exporting one
+pub fn record_schema() -> Arc<Schema> {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("content_hash", DataType::Utf8, false),
+        Field::new(
+            "created_at",
+            DataType::Timestamp(TimeUnit::Second, Some("+00:00".into())),
+            true,
+        ),
+        Field::new("message_type", DataType::Utf8, true),
+        Field::new("content_topic", DataType::Utf8, true),
+        Field::new("graph_account", DataType::Utf8, true),
+        Field::new("identifier", DataType::Utf8, true),
+        Field::new("nonce", DataType::Int64, true),
+        Field::new("message", DataType::Utf8, false),
+    ]))
+}
+
+pub fn record_batch(records: &[MessageRecord]) -> Result<RecordBatch, anyhow::Error> {
+    let text = |column: fn(&MessageRecord) -> Option<&str>| -> ArrayRef {
+        Arc::new(records.iter().map(column).collect::<StringArray>())
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(records.iter().map(|r| r.id).collect::<Int64Array>()),
+        text(|r| Some(r.content_hash.as_str())),
+        Arc::new(
+            records
+                .iter()
+                .map(|r| r.created_at)
+                .collect::<TimestampSecondArray>()
+                .with_timezone("+00:00"),
+        ),
+        text(|r| r.message_type.as_deref()),
+        text(|r| r.content_topic.as_deref()),
+        text(|r| r.graph_account.as_deref()),
+        text(|r| r.identifier.as_deref()),
+        Arc::new(records.iter().map(|r| r.nonce).collect::<Int64Array>()),
+        Arc::new(
+            records
+                .iter()
+                .map(|r| Some(r.message.as_str()))
+                .collect::<StringArray>(),
+        ),
+    ];
+    Ok(RecordBatch::try_new(record_schema(), columns)?)
+}
+
+fn ndjson_lines(records: &[MessageRecord]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut lines = vec![];
+    for record in records {
+        let message: serde_json::Value = serde_json::from_str(&record.message)?;
+        serde_json::to_writer(
+            &mut lines,
+            &serde_json::json!({
+                "id": record.id,
+                "content_hash": record.content_hash,
+                "created_at": record.created_at,
+                "message_type": record.message_type,
+                "content_topic": record.content_topic,
+                "graph_account": record.graph_account,
+                "identifier": record.identifier,
+                "nonce": record.nonce,
+                "message": message,
+            }),
+        )?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+/// Encodes pages of records into chunks of the response body
+enum Encoder {
+    Ndjson,
+    Arrow(StreamWriter<Vec<u8>>),
+    /// Whether the header row is still to be written
+    Csv(bool),
+    Parquet(ArrowWriter<Vec<u8>>),
+}
+
+impl Encoder {
+    /// Encoder with the chunk opening the stream
+    fn new(format: ExportFormat) -> Result<(Self, Vec<u8>), anyhow::Error> {
+        match format {
+            ExportFormat::Ndjson => Ok((Encoder::Ndjson, vec![])),
+            ExportFormat::Arrow => {
+                let mut writer = StreamWriter::try_new(vec![], &record_schema())?;
+                let header = std::mem::take(writer.get_mut());
+                Ok((Encoder::Arrow(writer), header))
+            }
+            ExportFormat::Csv => Ok((Encoder::Csv(true), vec![])),
+            ExportFormat::Parquet => {
+                let mut writer = ArrowWriter::try_new(vec![], record_schema(), None)?;
+                let header = std::mem::take(writer.inner_mut());
+                Ok((Encoder::Parquet(writer), header))
+            }
+        }
+    }
+
+    fn encode(&mut self, records: &[MessageRecord]) -> Result<Vec<u8>, anyhow::Error> {
+        match self {
+            Encoder::Ndjson => ndjson_lines(records),
+            Encoder::Arrow(writer) => {
+                writer.write(&record_batch(records)?)?;
+                Ok(std::mem::take(writer.get_mut()))
+            }
+            Encoder::Csv(header) => {
+                let mut writer = CsvWriterBuilder::new()
+                    .with_header(std::mem::take(header))
+                    .build(vec![]);
+                writer.write(&record_batch(records)?)?;
+                Ok(writer.into_inner())
+            }
+            Encoder::Parquet(writer) => {
+                writer.write(&record_batch(records)?)?;
+                // Close the row group so its bytes can be sent rather than buffered
+                writer.flush()?;
+                Ok(std::mem::take(writer.inner_mut()))
+            }
+        }
+    }
+
+    fn finish(self) -> Result<Vec<u8>, anyhow::Error> {
+        match self {
+            Encoder::Ndjson | Encoder::Csv(_) => Ok(vec![]),
+            Encoder::Arrow(mut writer) => {
+                writer.finish()?;
+                Ok(writer.into_inner()?)
+            }
+            Encoder::Parquet(writer) => Ok(writer.into_inner()?),
+        }
+    }
+}
+
+/// Hand a chunk to the client, false when it went away. A client taking no chunk within
+/// `idle_timeout` fails the export, so its snapshot does not keep a connection open
+async fn send_chunk(
+    sender: &mpsc::Sender<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    idle_timeout: Option<Duration>,
+) -> Result<bool, anyhow::Error> {
+    let sent = match idle_timeout {
+        Some(idle) => timeout(idle, sender.send(Ok(chunk))).await.map_err(|_| {
+            anyhow!(
+                "Client read nothing of the export for {} seconds",
+                idle.as_secs()
+            )
+        })?,
+        None => sender.send(Ok(chunk)).await,
+    };
+    Ok(sent.is_ok())
+}
+
+async fn stream_records(
+    db: &PgPool,
+    namespace: &str,
+    filter: &MessageFilter,
+    format: ExportFormat,
+    sender: &mpsc::Sender<io::Result<Vec<u8>>>,
+    idle_timeout: Option<Duration>,
+) -> Result<(), anyhow::Error> {
+    let (mut encoder, header) = Encoder::new(format)?;
+    let mut chunk = header;
+    let mut cursor = 0;
+    // Pages come from one snapshot, pruning during the export does not leave gaps in it
+    let mut snapshot = begin_snapshot(db).await?;
+    loop {
+        let records =
+            list_message_records(&mut *snapshot, namespace, filter, cursor, EXPORT_BATCH_ROWS)
+                .await?;
+        let Some(last) = records.last() else {
+            break;
+        };
+        cursor = last.id;
+        chunk.extend(encoder.encode(&records)?);
+        if !send_chunk(sender, std::mem::take(&mut chunk), idle_timeout).await? {
+            // The client went away
+            return Ok(());
+        }
+    }
+    chunk.extend(encoder.finish()?);
+    send_chunk(sender, chunk, idle_timeout).await?;
+    Ok(())
+}
+
+/// Stream the messages matching `filter` in id order, one page at a time so memory stays
+/// bounded by the page size. A failure midway ends the stream with an error, which aborts
+/// the response rather than leaving the client a silently truncated export. `permit` is
+/// released once the snapshot is, and a client stalling longer than `idle_timeout` fails
+/// the export
+pub fn export_messages(
+    db: PgPool,
+    namespace: String,
+    filter: MessageFilter,
+    format: ExportFormat,
+    idle_timeout: Option<Duration>,
+    permit: Option<OwnedSemaphorePermit>,
+) -> ReceiverStream<io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel(EXPORT_BUFFERED_CHUNKS);
+    tokio::spawn(async move {
+        let streamed =
+            stream_records(&db, &namespace, &filter, format, &sender, idle_timeout).await;
+        drop(permit);
+        if let Err(e) = streamed {
+            warn!(err = e.to_string(), "Message export failed");
+            let error = sender.send(Err(io::Error::other(e.to_string())));
+            match idle_timeout {
+                Some(idle) => {
+                    let _ = timeout(idle, error).await;
+                }
+                None => {
+                    let _ = error.await;
+                }
+            }
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+/// Write the messages matching `filter` to the file at `path`, returning the bytes written.
+/// The file is written as the pages come, so large exports do not sit in memory
+pub async fn export_to_file(
+    db: PgPool,
+    namespace: String,
+    filter: MessageFilter,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<u64, anyhow::Error> {
+    let mut file = File::create(path).await?;
+    let mut chunks = export_messages(db, namespace, filter, format, None, None);
+    let mut written = 0;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    file.flush().await?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Array;
+    use arrow::ipc::reader::StreamReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn record(id: i64, nonce: Option<i64>) -> MessageRecord {
+        MessageRecord {
+            id,
+            content_hash: format!("{:064x}", id),
+            created_at: Some(1707328517),
+            message_type: Some("public_poi".to_string()),
+            content_topic: None,
+            graph_account: Some("0xb4b4".to_string()),
+            identifier: Some("QmTamam".to_string()),
+            nonce,
+            message: r#"{"nonce":1707328517}"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_arrow_stream() {
+        let (mut encoder, mut bytes) = Encoder::new(ExportFormat::Arrow).unwrap();
+        bytes.extend(encoder.encode(&[record(1, Some(1707328517))]).unwrap());
+        bytes.extend(encoder.encode(&[record(2, None)]).unwrap());
+        bytes.extend(encoder.finish().unwrap());
+
+        let batches = StreamReader::try_new(io::Cursor::new(bytes), None)
+            .unwrap()
+            .collect::<Result<Vec<RecordBatch>, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].schema(), record_schema());
+        let nonces = batches[1]
+            .column_by_name("nonce")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert!(nonces.is_null(0));
+        assert_eq!(
+            batches[0]
+                .column_by_name("content_topic")
+                .unwrap()
+                .null_count(),
+            1
+        );
+    }
+
+    fn encode_all(format: ExportFormat) -> Vec<u8> {
+        let (mut encoder, mut bytes) = Encoder::new(format).unwrap();
+        bytes.extend(encoder.encode(&[record(1, Some(1707328517))]).unwrap());
+        bytes.extend(encoder.encode(&[record(2, None)]).unwrap());
+        bytes.extend(encoder.finish().unwrap());
+        bytes
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let csv = String::from_utf8(encode_all(ExportFormat::Csv)).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3, "A single header row");
+        assert!(lines[0].starts_with("id,content_hash,created_at,message_type"));
+        assert!(lines[1].starts_with(&format!("1,{:064x},2024-02-07T17:55:17", 1)));
+        assert!(lines[2].contains(",public_poi,,0xb4b4,QmTamam,,"));
+        assert!(lines[1].ends_with(r#","{""nonce"":1707328517}""#));
+    }
+
+    #[test]
+    fn test_parquet_file() {
+        let bytes = bytes::Bytes::from(encode_all(ExportFormat::Parquet));
+        let batches = ParquetRecordBatchReaderBuilder::try_new(bytes)
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<RecordBatch>, _>>()
+            .unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        assert_eq!(batches[0].schema(), record_schema());
+
+        assert_eq!(
+            "jsonl".parse::<ExportFormat>().unwrap(),
+            ExportFormat::Ndjson
+        );
+        assert!("xlsx".parse::<ExportFormat>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_chunk() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let idle = Some(Duration::from_millis(50));
+        assert!(send_chunk(&sender, vec![1], idle).await.unwrap());
+        // The client stopped reading, the buffer is full
+        assert!(send_chunk(&sender, vec![2], idle).await.is_err());
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), vec![1]);
+        drop(receiver);
+        assert!(!send_chunk(&sender, vec![3], idle).await.unwrap());
+    }
+
+    #[test]
+    fn test_ndjson_lines() {
+        let lines = ndjson_lines(&[record(1, Some(1707328517)), record(2, None)]).unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8(lines)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["message"]["nonce"], 1707328517);
+        assert!(lines[1]["nonce"].is_null());
+        assert_eq!(lines[1]["content_hash"], format!("{:064x}", 2));
+    }
+}
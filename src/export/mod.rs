@@ -0,0 +1,5 @@
+//! Exports of stored messages to external analytics systems, each sink keeps a cursor of
+//! the last exported message id so exports resume where they stopped. Analytical pulls
+//! stream from the API instead, see [`ipc`]
+pub mod bigquery;
+pub mod ipc;
@@ -0,0 +1,126 @@
+//! Imports of messages persisted by subgraph-radio, so indexers moving to a central listener
+//! keep the history their radios collected. Only signed Graphcast messages are imported, the
+//! local attestations and comparison results of the state file are derived data
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use std::{collections::HashMap, fs, path::Path};
+use tracing::info;
+
+use crate::{
+    db::resolver::import_message,
+    message_types::{PublicPoiMessage, StoredMessage, UpgradeIntentMessage},
+};
+
+/// Messages of the state file subgraph-radio writes to its `PERSISTENCE_FILE_PATH`, other
+/// fields are ignored
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SubgraphRadioState {
+    /// Public POI messages received from other indexers, `remote_messages` in releases
+    /// before upgrade intents were persisted
+    #[serde(alias = "remote_messages")]
+    pub remote_ppoi_messages: Vec<StoredMessage<PublicPoiMessage>>,
+    /// Latest upgrade intent by subgraph id
+    pub upgrade_intent_messages: HashMap<String, StoredMessage<UpgradeIntentMessage>>,
+}
+
+impl SubgraphRadioState {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = fs::read(path)
+            .with_context(|| format!("Could not read subgraph-radio state {}", path.display()))?;
+        serde_json::from_slice(&file)
+            .with_context(|| format!("Invalid subgraph-radio state {}", path.display()))
+    }
+
+    /// Messages as stored by the listener, with their message type
+    fn stored_messages(&self) -> anyhow::Result<Vec<(serde_json::Value, &'static str)>> {
+        let public_pois = self
+            .remote_ppoi_messages
+            .iter()
+            .map(|msg| Ok((serde_json::to_value(msg)?, "public_poi")));
+        let upgrade_intents = self
+            .upgrade_intent_messages
+            .values()
+            .map(|msg| Ok((serde_json::to_value(msg)?, "upgrade_intent")));
+        public_pois.chain(upgrade_intents).collect()
+    }
+}
+
+/// Messages read from a state file and the ones not stored before
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub read: usize,
+    pub imported: usize,
+}
+
+/// Store the messages of a subgraph-radio state file in a single transaction. Messages the
+/// listener already holds are skipped, so an import can be repeated
+pub async fn import_subgraph_radio_state(
+    db: &Pool<Postgres>,
+    namespace: &str,
+    path: &Path,
+) -> anyhow::Result<ImportSummary> {
+    let messages = SubgraphRadioState::load(path)?.stored_messages()?;
+
+    let mut tx = db.begin().await?;
+    let mut summary = ImportSummary {
+        read: messages.len(),
+        ..Default::default()
+    };
+    for (message, message_type) in &messages {
+        if import_message(&mut *tx, namespace, message, message_type)
+            .await?
+            .is_some()
+        {
+            summary.imported += 1;
+        }
+    }
+    tx.commit().await?;
+
+    info!(
+        path = path.display().to_string(),
+        read = summary.read,
+        imported = summary.imported,
+        "Imported subgraph-radio messages"
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subgraph_radio_state() {
+        let state: SubgraphRadioState = serde_json::from_str(
+            r#"{
+                "local_attestations": {},
+                "comparison_results": {},
+                "remote_messages": [{
+                    "identifier": "QmTamam",
+                    "nonce": 1707328517,
+                    "graph_account": "0xb4b4",
+                    "signature": "0x00",
+                    "payload": {
+                        "identifier": "QmTamam",
+                        "content": "0x01",
+                        "nonce": 1707328517,
+                        "network": "testnet",
+                        "block_number": 42,
+                        "block_hash": "hash",
+                        "graph_account": "0xb4b4"
+                    }
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let messages = state.stored_messages().unwrap();
+        assert_eq!(messages.len(), 1);
+        let (message, message_type) = &messages[0];
+        assert_eq!(*message_type, "public_poi");
+        assert_eq!(message["payload"]["block_number"], 42);
+        assert_eq!(message["graph_account"], "0xb4b4");
+    }
+}
@@ -1,146 +1,71 @@
-use async_graphql::{Error, ErrorExtensions};
-use autometrics::autometrics;
-
-use std::{
-    collections::HashMap,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-};
-use tokio::signal;
-use tracing::error;
-
-use graphcast_sdk::{
-    graphcast_agent::GraphcastAgentError,
-    graphql::client_network::query_network_subgraph,
-    graphql::{client_graph_node::get_indexing_statuses, QueryError},
-    networks::NetworkName,
-    BlockPointer,
-};
-
+//! Listener Radio monitors a Graphcast network and stores every message it receives.
+//!
+//! Messages flow through the [`pipeline`]: a [`pipeline::MessageSource`] delivers raw Waku
+//! messages, they are decoded and checked by a [`pipeline::Validator`] and persisted by a
+//! [`pipeline::MessageStore`]. The [`operator::RadioOperator`] wires the pipeline to a
+//! Graphcast agent and runs maintenance, while [`server`] serves the stored messages.
+//!
+//! The crate can be embedded in other services instead of running the `listener-radio`
+//! binary. Additional radio payload types are registered on [`MessageTypes`] and stored as
+//! json next to the built-in types, and [`Validator`] or [`MessageStore`] implementations
+//! replace the default checks and the Postgres storage. [`RadioOperatorBuilder`] accepts an
+//! existing pool, agent, notifier and pipeline, while [`message_processor`] drives a
+//! [`Pipeline`] from any [`MessageSource`] on worker tasks configured by [`ProcessorSettings`].
+pub mod archive;
 pub mod config;
+pub mod consensus;
 pub mod db;
+pub mod export;
+pub mod import;
+pub mod logging;
 pub mod message_types;
 pub mod metrics;
+pub mod network;
 pub mod operator;
+pub mod outbox;
+pub mod pipeline;
 pub mod server;
+pub mod shutdown;
 
-pub fn radio_name() -> &'static str {
-    "listener-radio"
-}
-
-/// Generate default topics that is operator address resolved to indexer address
-/// and then its active on-chain allocations -> function signature should just return
-/// A vec of strings for subtopics
-pub async fn active_allocation_hashes(
-    network_subgraph: &str,
-    indexer_address: &str,
-) -> Vec<String> {
-    query_network_subgraph(network_subgraph, indexer_address)
-        .await
-        .map(|result| result.indexer_allocations())
-        .unwrap_or_else(|e| {
-            error!(err = tracing::field::debug(&e), "Failed to generate topics");
-            vec![]
-        })
-}
-
-/// Generate content topics for all deployments that are syncing on Graph node
-/// filtering for deployments on an index node
-pub async fn syncing_deployment_hashes(
-    graph_node_endpoint: &str,
-    // graphQL filter
-) -> Vec<String> {
-    get_indexing_statuses(graph_node_endpoint)
-        .await
-        .map_err(|e| -> Vec<String> {
-            error!(err = tracing::field::debug(&e), "Topic generation error");
-            [].to_vec()
-        })
-        .unwrap()
-        .iter()
-        .filter(|&status| status.node.is_some() && status.node != Some(String::from("removed")))
-        .map(|s| s.subgraph.clone())
-        .collect::<Vec<String>>()
-}
-
-/// This function returns the string representation of a set of network mapped to their chainhead blocks
-#[autometrics]
-pub fn chainhead_block_str(
-    network_chainhead_blocks: &HashMap<NetworkName, BlockPointer>,
-) -> String {
-    let mut blocks_str = String::new();
-    blocks_str.push_str("{ ");
-    for (i, (network, block_pointer)) in network_chainhead_blocks.iter().enumerate() {
-        if i > 0 {
-            blocks_str.push_str(", ");
-        }
-        blocks_str.push_str(&format!("{}: {}", network, block_pointer.number));
-    }
-    blocks_str.push_str(" }");
-    blocks_str
-}
-
-/// Graceful shutdown when receive signal
-pub async fn shutdown_signal(running_program: Arc<AtomicBool>) {
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("failed to install Ctrl+C handler");
-    };
-
-    #[cfg(unix)]
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("failed to install signal handler")
-            .recv()
-            .await;
-    };
-
-    #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
-
-    tokio::select! {
-        _ = ctrl_c => {println!("Shutting down server...");},
-        _ = terminate => {},
-    }
-
-    running_program.store(false, Ordering::SeqCst);
-    opentelemetry::global::shutdown_tracer_provider();
-}
+pub use config::Config;
+pub use operator::{
+    message_processor, OperatorError, ProcessorSettings, RadioOperator, RadioOperatorBuilder,
+};
+pub use pipeline::{
+    AcceptAll, MessageSource, MessageStore, MessageTypes, Pipeline, PostgresStore, RadioMessage,
+    Validator,
+};
+pub use server::run_server;
+pub use shutdown::shutdown_signal;
 
+/// Failures of the listener, by the stage that failed
 #[derive(Debug, thiserror::Error)]
-pub enum OperationError {
-    #[error("Send message trigger isn't met: {0}")]
-    SendTrigger(String),
-    #[error("Message sent already, skip to avoid duplicates: {0}")]
-    SkipDuplicate(String),
-    #[error("Comparison trigger isn't met: {0}")]
-    CompareTrigger(String, u64, String),
-    #[error("Agent encountered problems: {0}")]
-    Agent(GraphcastAgentError),
-    #[error("Failed to query: {0}")]
-    Query(QueryError),
-    #[error("Others: {0}")]
-    Others(String),
+pub enum ListenerError {
+    #[error("Failed to decode message: {0}")]
+    Decode(String),
+    #[error("Message failed validation: {0}")]
+    Validation(String),
+    #[error("Message of ignored type {0}")]
+    Ignored(String),
+    #[error("Storage error: {0}")]
+    Storage(#[from] sqlx::Error),
+    #[error("Network error: {0}")]
+    Network(String),
 }
 
-impl OperationError {
-    pub fn clone_with_inner(&self) -> Self {
+impl ListenerError {
+    /// Short name of the failure kind, used for metric labels and dead letter categories
+    pub fn kind(&self) -> &'static str {
         match self {
-            OperationError::SendTrigger(msg) => OperationError::SendTrigger(msg.clone()),
-            OperationError::SkipDuplicate(msg) => OperationError::SkipDuplicate(msg.clone()),
-            OperationError::CompareTrigger(d, b, m) => {
-                OperationError::CompareTrigger(d.clone(), *b, m.clone())
-            }
-            e => OperationError::Others(e.to_string()),
+            ListenerError::Decode(_) => "decode",
+            ListenerError::Validation(_) => "validation",
+            ListenerError::Ignored(_) => "ignored",
+            ListenerError::Storage(_) => "storage",
+            ListenerError::Network(_) => "network",
         }
     }
 }
 
-impl ErrorExtensions for OperationError {
-    fn extend(&self) -> Error {
-        Error::new(format!("{}", self))
-    }
+pub fn radio_name() -> &'static str {
+    "listener-radio"
 }
@@ -0,0 +1,203 @@
+//! Tracing setup: formatted logs filtered by RUST_LOG, adjustable at runtime, and the most
+//! recent warnings and errors kept in memory for the API. [`waku`] feeds the Waku node's logs
+//! into the same subscriber
+use async_graphql::SimpleObject;
+use chrono::Utc;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Write},
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    subscriber::SetGlobalDefaultError,
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    fmt, layer::Context, prelude::*, registry::LookupSpan, reload, EnvFilter, Layer, Registry,
+};
+
+pub mod waku;
+
+/// Filter of the formatted logs, set up by [`init_tracing`]
+static LOG_FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+static RECENT_LOGS: Lazy<Mutex<VecDeque<LogEvent>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+/// Warnings and errors kept, 0 keeps none
+static RECENT_LOGS_CAPACITY: AtomicUsize = AtomicUsize::new(0);
+
+/// Warning or error logged by the listener
+#[derive(SimpleObject, Serialize, Debug, Clone, PartialEq)]
+pub struct LogEvent {
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+    pub level: String,
+    /// Module the event was logged from
+    pub target: String,
+    pub message: String,
+    /// Other fields of the event as `name=value` pairs
+    pub fields: Vec<String>,
+}
+
+/// Keep the last `capacity` warnings and errors, dropping older ones
+pub fn set_recent_logs_capacity(capacity: usize) {
+    RECENT_LOGS_CAPACITY.store(capacity, Ordering::SeqCst);
+    let mut logs = RECENT_LOGS.lock().unwrap();
+    while logs.len() > capacity {
+        logs.pop_front();
+    }
+}
+
+/// Most recent warnings and errors, newest first. Only errors with `errors_only`
+pub fn recent_logs(errors_only: bool, limit: usize) -> Vec<LogEvent> {
+    RECENT_LOGS
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .filter(|event| !errors_only || event.level == Level::ERROR.to_string())
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+fn push_recent_log(event: LogEvent) {
+    let capacity = RECENT_LOGS_CAPACITY.load(Ordering::Relaxed);
+    if capacity == 0 {
+        return;
+    }
+    let mut logs = RECENT_LOGS.lock().unwrap();
+    while logs.len() >= capacity {
+        logs.pop_front();
+    }
+    logs.push_back(event);
+}
+
+#[derive(Default)]
+struct EventVisitor {
+    message: String,
+    fields: Vec<String>,
+}
+
+impl Visit for EventVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.push(format!("{}={}", field.name(), value));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            self.fields.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+}
+
+/// Records warnings and errors into the recent logs buffer
+struct RecentLogsLayer;
+
+impl<S: Subscriber> Layer<S> for RecentLogsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = EventVisitor::default();
+        event.record(&mut visitor);
+        push_recent_log(LogEvent {
+            timestamp: Utc::now().timestamp_millis(),
+            level: event.metadata().level().to_string(),
+            target: event.metadata().target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+        });
+    }
+}
+
+/// Stdout, or the stdout from before [`waku::capture_waku_logs`] redirected it
+fn log_writer() -> Box<dyn io::Write> {
+    match waku::original_stdout() {
+        Some(stdout) => Box::new(stdout),
+        None => Box::new(io::stdout()),
+    }
+}
+
+fn fmt_layer<S>(format: &str) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let layer = fmt::layer().with_writer(log_writer);
+    match format {
+        "json" => layer.json().boxed(),
+        "full" => layer.boxed(),
+        "compact" => layer.compact().boxed(),
+        _ => layer.pretty().boxed(),
+    }
+}
+
+/// Set up the global subscriber: logs in `format` (pretty, json, full or compact) filtered by
+/// RUST_LOG, and warnings and errors for [`recent_logs`] whatever RUST_LOG is set to
+pub fn init_tracing(format: &str) -> Result<(), SetGlobalDefaultError> {
+    let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+    let subscriber = Registry::default()
+        .with(fmt_layer(format).with_filter(filter))
+        .with(RecentLogsLayer.with_filter(LevelFilter::WARN));
+    tracing::subscriber::set_global_default(subscriber)?;
+    let _ = LOG_FILTER.set(handle);
+    Ok(())
+}
+
+/// Directives currently filtering the logs, None when tracing was set up elsewhere
+pub fn log_filter() -> Option<String> {
+    LOG_FILTER
+        .get()?
+        .with_current(|filter| filter.to_string())
+        .ok()
+}
+
+/// Replace the log filter with RUST_LOG style `directives`, such as
+/// `info,listener_radio::operator=debug`. Returns the applied filter
+pub fn set_log_filter(directives: &str) -> Result<String, String> {
+    let handle = LOG_FILTER
+        .get()
+        .ok_or_else(|| "Logging is not managed by the listener".to_string())?;
+    let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+    let applied = filter.to_string();
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{error, info, warn};
+
+    #[test]
+    fn test_recent_logs() {
+        let subscriber = Registry::default().with(RecentLogsLayer.with_filter(LevelFilter::WARN));
+        set_recent_logs_capacity(2);
+        tracing::subscriber::with_default(subscriber, || {
+            info!("Not kept");
+            warn!(peers = 0, "First warning");
+            error!(err = "timeout", "Query failed");
+            warn!("Second warning");
+        });
+
+        let logs = recent_logs(false, 10);
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].message, "Second warning");
+        assert_eq!(logs[1].level, "ERROR");
+        assert_eq!(logs[1].fields, vec!["err=timeout".to_string()]);
+
+        let errors = recent_logs(true, 10);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Query failed");
+    }
+}
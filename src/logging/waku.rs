@@ -0,0 +1,249 @@
+//! Logs of the embedded Waku node. go-waku writes its own log lines to stdout, so with
+//! WAKU_LOG_CAPTURE stdout is redirected through a pipe, and every line go-waku wrote is
+//! parsed and emitted again as a tracing event with the `waku` target. Node errors then show
+//! up in the same structured stream as the listener's logs, in `recentLogs` and in
+//! `waku_log_events_total`. Other output written to stdout is passed through unchanged
+use once_cell::sync::OnceCell;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::thread;
+use tracing::{debug, error, info, trace, warn, Level};
+
+use crate::metrics::WAKU_LOG_EVENTS;
+
+/// Target of the events re-emitted from Waku log lines, for RUST_LOG directives like `waku=warn`
+pub const WAKU_LOG_TARGET: &str = "waku";
+
+/// Stdout as it was before the capture, where the listener's own logs keep going
+static ORIGINAL_STDOUT: OnceCell<File> = OnceCell::new();
+
+pub fn original_stdout() -> Option<&'static File> {
+    ORIGINAL_STDOUT.get()
+}
+
+/// Log line of the Waku node
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WakuLogLine {
+    pub level: Level,
+    /// Named logger of the go-waku component, such as `gowaku.node2`
+    pub logger: Option<String>,
+    /// Go source location
+    pub caller: Option<String>,
+    pub message: String,
+    /// Structured fields as json
+    pub fields: Option<String>,
+}
+
+fn parse_level(level: &str) -> Option<Level> {
+    match level.to_ascii_lowercase().as_str() {
+        "debug" => Some(Level::DEBUG),
+        "info" => Some(Level::INFO),
+        "warn" => Some(Level::WARN),
+        "error" | "dpanic" | "panic" | "fatal" => Some(Level::ERROR),
+        _ => None,
+    }
+}
+
+/// Remove the ANSI colors of the colorized console output
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip up to the final byte of the escape sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// zap json output: `{"level":"info","ts":...,"logger":...,"caller":...,"msg":...}` followed
+/// by the fields of the entry
+fn parse_json(line: &str) -> Option<WakuLogLine> {
+    let Value::Object(mut entry) = serde_json::from_str::<Value>(line).ok()? else {
+        return None;
+    };
+    let level = parse_level(entry.remove("level")?.as_str()?)?;
+    let message = entry.remove("msg")?.as_str()?.to_string();
+    let mut text = |key: &str| {
+        entry
+            .remove(key)
+            .and_then(|v| v.as_str().map(str::to_string))
+    };
+    let logger = text("logger");
+    let caller = text("caller");
+    entry.remove("ts");
+    let fields = (!entry.is_empty()).then(|| Value::Object(entry).to_string());
+    Some(WakuLogLine {
+        level,
+        logger,
+        caller,
+        message,
+        fields,
+    })
+}
+
+/// zap console output, tab separated: time, level, optional logger and caller, the message
+/// and optional json fields
+fn parse_console(line: &str) -> Option<WakuLogLine> {
+    let line = strip_ansi(line);
+    let mut parts = line.split('\t').skip(1);
+    let level = parse_level(parts.next()?.trim())?;
+    let mut rest: Vec<&str> = parts.collect();
+    // The logger name can only be told apart when the caller follows it
+    let (logger, caller) = match rest.iter().position(|part| part.contains(".go:")) {
+        Some(0) => (None, Some(rest.remove(0).to_string())),
+        Some(1) => {
+            let caller = rest.remove(1).to_string();
+            (Some(rest.remove(0).to_string()), Some(caller))
+        }
+        _ => (None, None),
+    };
+    if rest.is_empty() {
+        return None;
+    }
+    let message = rest.remove(0).to_string();
+    let fields = (!rest.is_empty()).then(|| rest.join("\t"));
+    Some(WakuLogLine {
+        level,
+        logger,
+        caller,
+        message,
+        fields,
+    })
+}
+
+/// Waku log line in either zap format, None for other output
+pub fn parse_line(line: &str) -> Option<WakuLogLine> {
+    if line.starts_with('{') {
+        parse_json(line)
+    } else {
+        parse_console(line)
+    }
+}
+
+impl WakuLogLine {
+    pub fn emit(&self) {
+        WAKU_LOG_EVENTS
+            .with_label_values(&[self.level.as_str()])
+            .inc();
+        macro_rules! waku_event {
+            ($event:ident) => {
+                $event!(
+                    target: WAKU_LOG_TARGET,
+                    logger = self.logger.as_deref(),
+                    caller = self.caller.as_deref(),
+                    fields = self.fields.as_deref(),
+                    "{}",
+                    self.message
+                )
+            };
+        }
+        match self.level {
+            Level::ERROR => waku_event!(error),
+            Level::WARN => waku_event!(warn),
+            Level::INFO => waku_event!(info),
+            Level::DEBUG => waku_event!(debug),
+            Level::TRACE => waku_event!(trace),
+        }
+    }
+}
+
+fn forward_lines(reader: File) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(['\r', '\n']);
+        match parse_line(line) {
+            Some(entry) => entry.emit(),
+            None => {
+                if let Some(mut stdout) = original_stdout() {
+                    let _ = writeln!(stdout, "{}", line);
+                }
+            }
+        }
+    }
+}
+
+/// Redirect stdout into the Waku log parser. Call before the Graphcast agent starts the node
+/// and before tracing is set up, so the listener's logs are written to the original stdout
+pub fn capture_waku_logs() -> io::Result<()> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe fills both descriptors on success, each is owned exactly once below
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    // SAFETY: dup returns a new descriptor owned by the file, or -1
+    let original = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if original < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let original = unsafe { File::from_raw_fd(original) };
+
+    io::stdout().flush()?;
+    // SAFETY: both descriptors are open, stdout now refers to the pipe
+    if unsafe { libc::dup2(writer.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    drop(writer);
+    let _ = ORIGINAL_STDOUT.set(original);
+
+    thread::Builder::new()
+        .name("waku-logs".to_string())
+        .spawn(move || forward_lines(reader))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_waku_lines() {
+        let console = parse_line(
+            "2024-02-08T12:00:00.000Z\t\u{1b}[31mERROR\u{1b}[0m\tgowaku.node2\tnode/wakunode2.go:412\tfailed to dial peer\t{\"peer\": \"16Uiu2\"}",
+        )
+        .unwrap();
+        assert_eq!(console.level, Level::ERROR);
+        assert_eq!(console.logger.as_deref(), Some("gowaku.node2"));
+        assert_eq!(console.caller.as_deref(), Some("node/wakunode2.go:412"));
+        assert_eq!(console.message, "failed to dial peer");
+        assert_eq!(console.fields.as_deref(), Some("{\"peer\": \"16Uiu2\"}"));
+
+        let bare = parse_line("2024-02-08T12:00:00.000Z\tINFO\tstarted node").unwrap();
+        assert_eq!(bare.level, Level::INFO);
+        assert_eq!(bare.logger, None);
+        assert_eq!(bare.message, "started node");
+
+        let json = parse_line(
+            r#"{"level":"warn","ts":1707393600.1,"logger":"gowaku.filter","caller":"filter/client.go:88","msg":"subscription expired","topic":"/graphcast/0/mainnet/proto"}"#,
+        )
+        .unwrap();
+        assert_eq!(json.level, Level::WARN);
+        assert_eq!(json.logger.as_deref(), Some("gowaku.filter"));
+        assert_eq!(json.message, "subscription expired");
+        assert_eq!(
+            json.fields.as_deref(),
+            Some(r#"{"topic":"/graphcast/0/mainnet/proto"}"#)
+        );
+
+        assert_eq!(parse_line("Listening on 0.0.0.0:7700"), None);
+        assert_eq!(parse_line(r#"{"healthy":true}"#), None);
+    }
+}
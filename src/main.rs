@@ -1,7 +1,14 @@
 use dotenv::dotenv;
 use graphcast_sdk::{graphcast_agent::GraphcastAgent, WakuMessage};
-use listener_radio::{config::Config, operator::RadioOperator};
-use std::sync::mpsc;
+use listener_radio::{
+    config::{Command, Config, ExportArgs},
+    db::{self, resolver::MessageFilter},
+    export::ipc::export_to_file,
+    import,
+    operator::RadioOperator,
+};
+use std::{path::Path, sync::mpsc};
+use tracing::{error, info};
 
 #[tokio::main]
 async fn main() {
@@ -9,6 +16,18 @@ async fn main() {
 
     // Parse basic configurations
     let radio_config = Config::args();
+    if radio_config.migrate_only {
+        std::process::exit(migrate_only(&radio_config.database_url).await);
+    }
+    match &radio_config.command {
+        Some(Command::Import(args)) => {
+            std::process::exit(import_subgraph_radio(&radio_config, Path::new(&args.path)).await)
+        }
+        Some(Command::Export(args)) => {
+            std::process::exit(export_messages(&radio_config, args).await)
+        }
+        None => {}
+    }
     let (sender, receiver) = mpsc::channel::<WakuMessage>();
     // Initialization
     let agent = GraphcastAgent::new(
@@ -18,8 +37,102 @@ async fn main() {
     .await
     .expect("Initialize Graphcast agent");
 
-    let radio_operator = RadioOperator::new(radio_config, agent, receiver).await;
+    let radio_operator = match RadioOperator::builder(radio_config)
+        .agent(agent, receiver)
+        .build()
+        .await
+    {
+        Ok(radio_operator) => radio_operator,
+        Err(e) => {
+            error!(err = e.to_string(), "Could not initialize radio operator");
+            std::process::exit(1);
+        }
+    };
 
     // Start radio operations
     radio_operator.run().await;
 }
+
+/// Run the migrations as a discrete step, returning the process exit code
+async fn migrate_only(database_url: &str) -> i32 {
+    let db = match db::connect(database_url).await {
+        Ok(db) => db,
+        Err(e) => {
+            error!(err = e.to_string(), "Could not connect to the database");
+            return 1;
+        }
+    };
+    match db::migrate(&db).await {
+        Ok(()) => {
+            info!("Database migrations applied");
+            0
+        }
+        Err(e) => {
+            error!(err = e.to_string(), "Could not run database migrations");
+            2
+        }
+    }
+}
+
+/// Migrate the database and import a subgraph-radio state file, returning the process exit code
+async fn import_subgraph_radio(config: &Config, path: &Path) -> i32 {
+    let db = match db::connect(&config.database_url).await {
+        Ok(db) => db,
+        Err(e) => {
+            error!(err = e.to_string(), "Could not connect to the database");
+            return 1;
+        }
+    };
+    if let Err(e) = db::migrate(&db).await {
+        error!(err = e.to_string(), "Could not run database migrations");
+        return 2;
+    }
+    match import::import_subgraph_radio_state(&db, &config.instance_namespace, path).await {
+        Ok(_) => 0,
+        Err(e) => {
+            error!(
+                err = format!("{:#}", e),
+                "Could not import subgraph-radio messages"
+            );
+            3
+        }
+    }
+}
+
+/// Write the stored messages to a file, returning the process exit code
+async fn export_messages(config: &Config, args: &ExportArgs) -> i32 {
+    let db = match db::connect(&config.database_url).await {
+        Ok(db) => db,
+        Err(e) => {
+            error!(err = e.to_string(), "Could not connect to the database");
+            return 1;
+        }
+    };
+    let path = Path::new(&args.path);
+    let filter = MessageFilter {
+        received_from: args.from,
+        received_to: args.to,
+        ..Default::default()
+    };
+    match export_to_file(
+        db,
+        config.instance_namespace.clone(),
+        filter,
+        args.format,
+        path,
+    )
+    .await
+    {
+        Ok(bytes) => {
+            info!(
+                path = path.display().to_string(),
+                bytes, "Exported messages"
+            );
+            0
+        }
+        Err(e) => {
+            error!(err = format!("{:#}", e), "Could not export messages");
+            3
+        }
+    }
+}
@@ -127,3 +127,227 @@ impl RadioPayload for UpgradeIntentMessage {
         }
     }
 }
+
+/// Upgrade announcement of radios predating [`UpgradeIntentMessage`], still sent by indexers
+/// on older subgraph-radio releases
+#[derive(Eip712, EthAbiType, Clone, Message, Serialize, Deserialize, PartialEq, SimpleObject)]
+#[eip712(
+    name = "VersionUpgradeMessage",
+    version = "0",
+    chain_id = 1,
+    verifying_contract = "0xc944e90c64b2c07662a292be6244bdf05cda44a7"
+)]
+pub struct VersionUpgradeMessage {
+    /// current subgraph deployment hash
+    #[prost(string, tag = "1")]
+    pub identifier: String,
+    /// deployment hash of the new version
+    #[prost(string, tag = "2")]
+    pub new_hash: String,
+    /// subgraph id shared by both versions of the subgraph deployment
+    #[prost(string, tag = "3")]
+    pub subgraph_id: String,
+    /// nonce cached to check against the next incoming message
+    #[prost(uint64, tag = "4")]
+    pub nonce: u64,
+    /// blockchain relevant to the message
+    #[prost(string, tag = "5")]
+    pub network: String,
+    /// block the indexers are expected to switch to the new version at
+    #[prost(uint64, tag = "6")]
+    pub migrate_block: u64,
+    /// Graph account sender - expect the sender to be subgraph owner
+    #[prost(string, tag = "7")]
+    pub graph_account: String,
+}
+
+impl RadioPayload for VersionUpgradeMessage {
+    /// Check duplicated fields: payload message has duplicated fields with GraphcastMessage, the values must be the same
+    fn valid_outer(&self, outer: &GraphcastMessage<Self>) -> Result<&Self, MessageError> {
+        if self.nonce == outer.nonce
+            && self.graph_account == outer.graph_account
+            && self.identifier == outer.identifier
+        {
+            Ok(self)
+        } else {
+            Err(MessageError::InvalidFields(anyhow::anyhow!(
+                "Radio message wrapped by inconsistent GraphcastMessage: {:#?} <- {:#?}",
+                &self,
+                &outer,
+            )))
+        }
+    }
+}
+
+/// Generic view over stored payloads used by the API, reading only the identifier and
+/// content fields shared by most radio message types
+#[derive(Eip712, EthAbiType, Clone, Message, Serialize, Deserialize, PartialEq, SimpleObject)]
+#[eip712(
+    name = "RadioPayloadMessage",
+    version = "0",
+    chain_id = 1,
+    verifying_contract = "0xc944e90c64b2c07662a292be6244bdf05cda44a7"
+)]
+pub struct RadioPayloadMessage {
+    #[prost(string, tag = "1")]
+    pub identifier: String,
+    #[prost(string, tag = "2")]
+    pub content: String,
+}
+
+impl RadioPayloadMessage {
+    pub fn new(identifier: String, content: String) -> Self {
+        RadioPayloadMessage {
+            identifier,
+            content,
+        }
+    }
+
+    pub fn payload_content(&self) -> String {
+        self.content.clone()
+    }
+}
+
+impl RadioPayload for RadioPayloadMessage {
+    fn valid_outer(&self, outer: &GraphcastMessage<Self>) -> Result<&Self, MessageError> {
+        if self.identifier == outer.identifier {
+            Ok(self)
+        } else {
+            Err(MessageError::InvalidFields(anyhow::anyhow!(
+                "Radio message wrapped by inconsistent GraphcastMessage: {:#?} <- {:#?}",
+                &self,
+                &outer,
+            )))
+        }
+    }
+}
+
+/// Outer fields of a Graphcast message, readable whatever the payload type. Payload bytes
+/// (tag 2) are skipped, so messages of unknown radio types still expose their sender
+#[derive(Clone, Message, PartialEq)]
+pub struct GraphcastEnvelope {
+    #[prost(string, tag = "1")]
+    pub identifier: String,
+    #[prost(uint64, tag = "3")]
+    pub nonce: u64,
+    #[prost(string, tag = "4")]
+    pub graph_account: String,
+    #[prost(string, tag = "5")]
+    pub signature: String,
+}
+
+/// Version of the json layout of stored and exported messages, recorded with every row.
+/// Bump it, with a migration and an entry in `docs/message-schema.md`, whenever the layout
+/// of [`StoredMessage`] changes. The tests pin the layout of every version
+pub const MESSAGE_SCHEMA_VERSION: i32 = 1;
+
+/// Stable json layout of a stored Graphcast message. Fields are copied explicitly from the
+/// SDK message, so a renamed SDK field fails to compile instead of silently changing the
+/// json that downstream consumers read
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StoredMessage<P> {
+    pub identifier: String,
+    pub nonce: u64,
+    pub graph_account: String,
+    pub signature: String,
+    pub payload: P,
+}
+
+impl<P: RadioPayload> From<GraphcastMessage<P>> for StoredMessage<P> {
+    fn from(msg: GraphcastMessage<P>) -> Self {
+        StoredMessage {
+            identifier: msg.identifier,
+            nonce: msg.nonce,
+            graph_account: msg.graph_account,
+            signature: msg.signature,
+            payload: msg.payload,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Json fields of the stored built-in message types at each message schema version. A
+    /// layout change is a new version with its own entry, earlier entries stay as they are
+    const MESSAGE_LAYOUTS: &[(i32, &[&str])] = &[(
+        1,
+        &[
+            "graph_account",
+            "identifier",
+            "nonce",
+            "payload",
+            "signature",
+            "public_poi.block_hash",
+            "public_poi.block_number",
+            "public_poi.content",
+            "public_poi.graph_account",
+            "public_poi.identifier",
+            "public_poi.network",
+            "public_poi.nonce",
+            "upgrade_intent.deployment",
+            "upgrade_intent.graph_account",
+            "upgrade_intent.new_hash",
+            "upgrade_intent.nonce",
+            "upgrade_intent.subgraph_id",
+            "version_upgrade.graph_account",
+            "version_upgrade.identifier",
+            "version_upgrade.migrate_block",
+            "version_upgrade.network",
+            "version_upgrade.new_hash",
+            "version_upgrade.nonce",
+            "version_upgrade.subgraph_id",
+            "simple.content",
+            "simple.identifier",
+        ],
+    )];
+
+    fn fields<P: Serialize>(payload: P) -> (Vec<String>, Vec<String>) {
+        let stored = StoredMessage {
+            identifier: String::new(),
+            nonce: 0,
+            graph_account: String::new(),
+            signature: String::new(),
+            payload,
+        };
+        let json = serde_json::to_value(stored).unwrap();
+        let keys = |value: &serde_json::Value| {
+            value
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect::<Vec<String>>()
+        };
+        (keys(&json), keys(&json["payload"]))
+    }
+
+    #[test]
+    fn test_message_schema_version_pins_layout() {
+        let (mut layout, _) = fields(SimpleMessage::default());
+        layout.sort();
+        for (name, mut payload) in [
+            ("public_poi", fields(PublicPoiMessage::default()).1),
+            ("upgrade_intent", fields(UpgradeIntentMessage::default()).1),
+            (
+                "version_upgrade",
+                fields(VersionUpgradeMessage::default()).1,
+            ),
+            ("simple", fields(SimpleMessage::default()).1),
+        ] {
+            payload.sort();
+            layout.extend(payload.iter().map(|field| format!("{}.{}", name, field)));
+        }
+
+        let pinned = MESSAGE_LAYOUTS
+            .iter()
+            .find(|(version, _)| *version == MESSAGE_SCHEMA_VERSION)
+            .map(|(_, fields)| fields.to_vec());
+        assert_eq!(
+            pinned,
+            Some(layout.iter().map(String::as_str).collect()),
+            "The stored layout changed, bump MESSAGE_SCHEMA_VERSION and pin its layout"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Label values of the per-deployment metrics. Labeling every series by deployment grows with
+//! the network, so METRIC_DEPLOYMENT_LABELS can fold deployments into fewer series: the message
+//! type only, the busiest deployments with the rest under `other`, or a fixed number of hashed
+//! buckets. Every per-deployment metric goes through [`deployment_label`], so the series of the
+//! different metrics stay comparable
+use once_cell::sync::OnceCell;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+use crate::config::{Config, DeploymentLabels};
+
+/// Label of the deployments outside the top ones
+pub const OTHER_LABEL: &str = "other";
+
+/// Label of metrics without a message type under the per type strategy
+pub const ALL_LABEL: &str = "all";
+
+#[derive(Debug)]
+struct Labeling {
+    strategy: DeploymentLabels,
+    buckets: u64,
+    top: RwLock<HashSet<String>>,
+}
+
+static LABELING: OnceCell<Labeling> = OnceCell::new();
+
+/// Apply the label strategy of the configuration, only the first call has an effect
+pub fn init_deployment_labels(config: &Config) {
+    let _ = LABELING.set(Labeling {
+        strategy: config.metric_deployment_labels,
+        buckets: config.metric_label_buckets.max(1),
+        top: RwLock::new(HashSet::new()),
+    });
+}
+
+/// Strategy in effect, full deployment labels until configured
+pub fn deployment_labels() -> DeploymentLabels {
+    LABELING
+        .get()
+        .map_or(DeploymentLabels::Deployment, |labeling| labeling.strategy)
+}
+
+/// Replace the deployments labeled on their own under the top strategy, returning the ones
+/// that left the top so their series can be removed
+pub fn set_top_deployments(deployments: impl IntoIterator<Item = String>) -> Vec<String> {
+    let Some(labeling) = LABELING.get() else {
+        return vec![];
+    };
+    let top: HashSet<String> = deployments.into_iter().collect();
+    let previous = std::mem::replace(
+        &mut *labeling.top.write().expect("Label lock poisoned"),
+        top.clone(),
+    );
+    previous.difference(&top).cloned().collect()
+}
+
+/// FNV-1a, stable across builds so buckets keep their deployments over restarts
+fn bucket(deployment: &str, buckets: u64) -> u64 {
+    let hash = deployment
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    hash % buckets
+}
+
+fn label<'a>(
+    strategy: DeploymentLabels,
+    buckets: u64,
+    top: &HashSet<String>,
+    deployment: &'a str,
+    message_type: Option<&'a str>,
+) -> Cow<'a, str> {
+    match strategy {
+        DeploymentLabels::Deployment => Cow::Borrowed(deployment),
+        DeploymentLabels::MessageType => Cow::Borrowed(message_type.unwrap_or(ALL_LABEL)),
+        DeploymentLabels::Top if top.contains(deployment) => Cow::Borrowed(deployment),
+        DeploymentLabels::Top => Cow::Borrowed(OTHER_LABEL),
+        DeploymentLabels::Hashed => Cow::Owned(format!("bucket_{}", bucket(deployment, buckets))),
+    }
+}
+
+/// Value of the deployment label of a series about `deployment`. `message_type` labels the
+/// series under the per type strategy, when the metric knows it
+pub fn deployment_label<'a>(deployment: &'a str, message_type: Option<&'a str>) -> Cow<'a, str> {
+    match LABELING.get() {
+        Some(labeling) => label(
+            labeling.strategy,
+            labeling.buckets,
+            &labeling.top.read().expect("Label lock poisoned"),
+            deployment,
+            message_type,
+        ),
+        None => Cow::Borrowed(deployment),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deployment_labels() {
+        let top: HashSet<String> = ["QmBusy".to_string()].into();
+        let labels = |strategy: DeploymentLabels, deployment: &str| {
+            label(strategy, 8, &top, deployment, Some("public_poi")).into_owned()
+        };
+        assert_eq!(labels(DeploymentLabels::Deployment, "QmQuiet"), "QmQuiet");
+        assert_eq!(
+            labels(DeploymentLabels::MessageType, "QmQuiet"),
+            "public_poi"
+        );
+        assert_eq!(
+            label(DeploymentLabels::MessageType, 8, &top, "QmQuiet", None),
+            ALL_LABEL
+        );
+        assert_eq!(labels(DeploymentLabels::Top, "QmBusy"), "QmBusy");
+        assert_eq!(labels(DeploymentLabels::Top, "QmQuiet"), OTHER_LABEL);
+
+        let hashed = labels(DeploymentLabels::Hashed, "QmQuiet");
+        assert!(hashed.starts_with("bucket_"));
+        assert_eq!(hashed, labels(DeploymentLabels::Hashed, "QmQuiet"));
+        let buckets: HashSet<String> = (0..1000)
+            .map(|i| labels(DeploymentLabels::Hashed, &format!("Qm{}", i)))
+            .collect();
+        assert_eq!(buckets.len(), 8);
+    }
+}
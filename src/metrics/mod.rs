@@ -4,10 +4,17 @@ use axum::routing::get;
 use axum::Router;
 use once_cell::sync::Lazy;
 use prometheus::{core::Collector, Registry};
-use prometheus::{IntCounter, IntCounterVec, IntGauge, Opts};
+use prometheus::{
+    Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts,
+};
 use std::{net::SocketAddr, str::FromStr};
 use tracing::{debug, info};
 
+use crate::shutdown::stopped;
+
+pub mod labels;
+
 /// Received (and validated) messages counter
 #[allow(dead_code)]
 pub static VALIDATED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
@@ -51,6 +58,119 @@ pub static CACHED_MESSAGES: Lazy<IntGauge> = Lazy::new(|| {
     m
 });
 
+/// Distinct deployments with a public POI message within the coverage window
+#[allow(dead_code)]
+pub static COVERED_DEPLOYMENTS: Lazy<IntGauge> = Lazy::new(|| {
+    let m = IntGauge::with_opts(
+        Opts::new(
+            "covered_deployments",
+            "Number of deployments with a POI message within the coverage window",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create covered_deployments gauge");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register covered_deployments gauge");
+    m
+});
+
+/// Messages of the most active indexers over the last day, refreshed on the summary tick
+#[allow(dead_code)]
+pub static INDEXER_MESSAGES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    let m = IntGaugeVec::new(
+        Opts::new(
+            "indexer_messages",
+            "Number of messages sent by the indexer in the last day",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["indexer"],
+    )
+    .expect("Failed to create indexer_messages gauges");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register indexer_messages gauges");
+    m
+});
+
+/// Subgraphs gossiped about by the most active indexers over the last day
+#[allow(dead_code)]
+pub static INDEXER_SUBGRAPHS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    let m = IntGaugeVec::new(
+        Opts::new(
+            "indexer_subgraphs",
+            "Number of distinct subgraphs the indexer sent messages about in the last day",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["indexer"],
+    )
+    .expect("Failed to create indexer_subgraphs gauges");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register indexer_subgraphs gauges");
+    m
+});
+
+#[allow(dead_code)]
+pub static DISTINCT_SUBGRAPHS: Lazy<IntGauge> = Lazy::new(|| {
+    let m = IntGauge::with_opts(
+        Opts::new(
+            "distinct_subgraphs",
+            "Number of distinct subgraphs with messages in the last day",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create distinct_subgraphs gauge");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register distinct_subgraphs gauge");
+    m
+});
+
+/// Content topics the listener subscribes to, the denominator of the deployment coverage
+#[allow(dead_code)]
+pub static SUBSCRIBED_TOPICS: Lazy<IntGauge> = Lazy::new(|| {
+    let m = IntGauge::with_opts(
+        Opts::new("subscribed_topics", "Number of subscribed content topics")
+            .namespace("graphcast")
+            .subsystem("listener_radio"),
+    )
+    .expect("Failed to create subscribed_topics gauge");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register subscribed_topics gauge");
+    m
+});
+
+/// Unix timestamp of the last message received per content topic
+#[allow(dead_code)]
+pub static TOPIC_LAST_MESSAGE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    let m = IntGaugeVec::new(
+        Opts::new(
+            "topic_last_message",
+            "Unix timestamp of the last message received on the topic",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["topic"],
+    )
+    .expect("Failed to create topic_last_message gauges");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register topic_last_message gauges");
+    m
+});
+
+/// Subscribed topics without messages beyond their silence threshold
+#[allow(dead_code)]
+pub static SILENT_TOPICS: Lazy<IntGauge> = Lazy::new(|| {
+    let m = IntGauge::with_opts(
+        Opts::new(
+            "silent_topics",
+            "Number of subscribed topics silent beyond their threshold",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create silent_topics gauge");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register silent_topics gauge");
+    m
+});
+
 /// Number of active peers discoverable by listener-radio
 /// Updated periodically for the recently received messages
 #[allow(dead_code)]
@@ -108,15 +228,604 @@ pub static RECEIVED_MESSAGES: Lazy<IntCounter> = Lazy::new(|| {
     m
 });
 
+/// Messages that exceeded the processing timeout
+#[allow(dead_code)]
+pub static PROCESSING_TIMEOUTS: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "processing_timeouts_total",
+            "Number of messages that exceeded the processing timeout",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create processing_timeouts_total counter");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register processing_timeouts_total counter");
+    m
+});
+
+/// Length of the ongoing outage without peers
+#[allow(dead_code)]
+pub static PEERLESS_SECONDS: Lazy<IntGauge> = Lazy::new(|| {
+    let m = IntGauge::with_opts(
+        Opts::new(
+            "peerless_seconds",
+            "Seconds since the listener last had peers, 0 while it has some",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create peerless_seconds gauge");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register peerless_seconds gauge");
+    m
+});
+
+/// Boot nodes listed by the DNS discovery trees at the last resolution
+#[allow(dead_code)]
+pub static DISCOVERED_NODES: Lazy<IntGauge> = Lazy::new(|| {
+    let m = IntGauge::with_opts(
+        Opts::new(
+            "discovered_nodes",
+            "Node addresses listed by the DNS discovery ENR trees",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create discovered_nodes gauge");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register discovered_nodes gauge");
+    m
+});
+
+/// Outcome of the latest reachability check of each boot node
+#[allow(dead_code)]
+pub static BOOT_NODE_REACHABLE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    let m = IntGaugeVec::new(
+        Opts::new(
+            "boot_node_reachable",
+            "Whether the boot node answered the latest dial, by address",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["address"],
+    )
+    .expect("Failed to create boot_node_reachable gauges");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register boot_node_reachable gauges");
+    m
+});
+
+/// Round trip of the latest dial of each boot node
+#[allow(dead_code)]
+pub static BOOT_NODE_DIAL_MS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    let m = IntGaugeVec::new(
+        Opts::new(
+            "boot_node_dial_ms",
+            "Dial round trip of the boot node in milliseconds, -1 when it failed",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["address"],
+    )
+    .expect("Failed to create boot_node_dial_ms gauges");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register boot_node_dial_ms gauges");
+    m
+});
+
+/// Messages replayed from the Waku store on startup
 #[allow(dead_code)]
-pub static PRUNED_MESSAGES: Lazy<IntGauge> = Lazy::new(|| {
+pub static BACKFILLED_MESSAGES: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "backfilled_messages",
+            "Messages replayed from Waku store nodes for the downtime before startup",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create backfilled_messages counter");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register backfilled_messages counter");
+    m
+});
+
+/// Encrypted payloads opened with a key of DECRYPTION_KEYS
+#[allow(dead_code)]
+pub static DECRYPTED_MESSAGES: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "decrypted_messages",
+            "Encrypted payloads decrypted with a configured key",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create decrypted_messages counter");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register decrypted_messages counter");
+    m
+});
+
+/// Log lines of the Waku node captured with WAKU_LOG_CAPTURE
+#[allow(dead_code)]
+pub static WAKU_LOG_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new("waku_log_events", "Log events of the Waku node by level")
+            .namespace("graphcast")
+            .subsystem("listener_radio"),
+        &["level"],
+    )
+    .expect("Failed to create waku_log_events counters");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register waku_log_events counters");
+    m
+});
+
+/// Received messages not yet picked up by a processing worker
+#[allow(dead_code)]
+pub static INGEST_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
     let m = IntGauge::with_opts(
+        Opts::new(
+            "ingest_queue_depth",
+            "Number of received messages waiting for a processing worker",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create ingest_queue_depth gauge");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register ingest_queue_depth gauge");
+    m
+});
+
+/// Messages per insert when INSERT_BATCH_SIZE batches inserts
+#[allow(dead_code)]
+pub static INSERT_BATCH_SIZE: Lazy<Histogram> = Lazy::new(|| {
+    let m = Histogram::with_opts(
+        HistogramOpts::new(
+            "insert_batch_size",
+            "Number of received messages processed per batch insert",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio")
+        .buckets(vec![
+            1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0,
+        ]),
+    )
+    .expect("Failed to create insert_batch_size histogram");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register insert_batch_size histogram");
+    m
+});
+
+/// Time received messages spend in each ingest stage: `queue` waiting for a worker, `decrypt`
+/// and `decode` reading the payload, `validation` checking it, `sender` checking the sender
+/// identity and `insert` storing it, once per batch with INSERT_BATCH_SIZE
+#[allow(dead_code)]
+pub static PIPELINE_STAGE_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    let m = HistogramVec::new(
+        HistogramOpts::new(
+            "pipeline_stage_seconds",
+            "Time spent by received messages in each stage of the ingest pipeline",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio")
+        .buckets(vec![
+            0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+        ]),
+        &["stage"],
+    )
+    .expect("Failed to create pipeline_stage_seconds histograms");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register pipeline_stage_seconds histograms");
+    m
+});
+
+/// Messages quarantined by a payload sanity check, by the failed check
+#[allow(dead_code)]
+pub static QUARANTINED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new(
+            "quarantined_messages",
+            "Number of messages quarantined by payload checks",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["check"],
+    )
+    .expect("Failed to create quarantined_messages counters");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register quarantined_messages counters");
+    m
+});
+
+/// Messages of types left out of MESSAGE_TYPES, dropped after decoding
+#[allow(dead_code)]
+pub static IGNORED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new(
+            "ignored_messages",
+            "Number of messages of types this listener is not configured to store",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["message_type"],
+    )
+    .expect("Failed to create ignored_messages counters");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register ignored_messages counters");
+    m
+});
+
+/// Messages carrying payload fields the listener could not decode, by message type
+#[allow(dead_code)]
+pub static UNDECODED_FIELD_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new(
+            "undecoded_field_messages",
+            "Number of messages with payload fields unknown to this listener",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["message_type"],
+    )
+    .expect("Failed to create undecoded_field_messages counters");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register undecoded_field_messages counters");
+    m
+});
+
+/// Messages no registered type could decode, stored as raw payloads
+#[allow(dead_code)]
+pub static RAW_MESSAGES: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "raw_messages",
+            "Number of received messages of unknown types stored as raw payloads",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create raw_messages counter");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register raw_messages counter");
+    m
+});
+
+/// Messages skipped because an identical copy was already stored
+#[allow(dead_code)]
+pub static DUPLICATE_MESSAGES: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "duplicate_messages",
+            "Number of received messages already stored",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create duplicate_messages counter");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register duplicate_messages counter");
+    m
+});
+
+/// Average copies received per distinct message over the last update interval
+#[allow(dead_code)]
+pub static DUPLICATION_FACTOR: Lazy<Gauge> = Lazy::new(|| {
+    let m = Gauge::with_opts(
+        Opts::new(
+            "duplication_factor",
+            "Average number of copies received per distinct message over the last interval",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create duplication_factor gauge");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register duplication_factor gauge");
+    m
+});
+
+#[allow(dead_code)]
+pub static TOPIC_DUPLICATION_FACTOR: Lazy<GaugeVec> = Lazy::new(|| {
+    let m = GaugeVec::new(
+        Opts::new(
+            "topic_duplication_factor",
+            "Average number of copies received per distinct message of the content topic over the last interval",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["topic"],
+    )
+    .expect("Failed to create topic_duplication_factor gauges");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register topic_duplication_factor gauges");
+    m
+});
+
+/// Messages deleted in total, by the reason of the deletion (retention, max_storage, manual)
+#[allow(dead_code)]
+pub static PRUNED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
         Opts::new("pruned_messages", "Number of messages pruned in total")
             .namespace("graphcast")
             .subsystem("listener_radio"),
+        &["reason"],
+    )
+    .expect("Failed to create pruned_messages counters");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register pruned_messages counters");
+    m
+});
+
+/// Messages deleted by the latest pruning run
+#[allow(dead_code)]
+pub static LAST_PRUNED_MESSAGES: Lazy<IntGauge> = Lazy::new(|| {
+    let m = IntGauge::with_opts(
+        Opts::new(
+            "last_pruned_messages",
+            "Number of messages pruned in the latest pruning run",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create last_pruned_messages gauge");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register last_pruned_messages gauge");
+    m
+});
+
+#[allow(dead_code)]
+pub static COLD_STORED_MESSAGES: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "cold_stored_messages",
+            "Number of messages moved to cold storage in total",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create cold_stored_messages counter");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register cold_stored_messages counter");
+    m
+});
+
+pub static ARCHIVED_MESSAGES: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "archived_messages",
+            "Number of pruned messages written to ARCHIVE_URL before their deletion in total",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create archived_messages counter");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register archived_messages counter");
+    m
+});
+
+/// Outbox entries accepted by the change data capture sink
+#[allow(dead_code)]
+pub static OUTBOX_PUBLISHED: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "outbox_published",
+            "Number of outbox entries published to the sink",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create outbox_published counter");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register outbox_published counter");
+    m
+});
+
+/// Filter subscriptions renewed after the health check found them dropped
+#[allow(dead_code)]
+pub static FILTER_RESUBSCRIPTIONS: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "filter_resubscriptions_total",
+            "Number of times dropped filter subscriptions were renewed",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create filter_resubscriptions_total counter");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register filter_resubscriptions_total counter");
+    m
+});
+
+/// Messages received during the last budget interval
+#[allow(dead_code)]
+pub static INGEST_MESSAGE_RATE: Lazy<IntGauge> = Lazy::new(|| {
+    let m = IntGauge::with_opts(
+        Opts::new(
+            "ingest_message_rate",
+            "Number of messages received in the last minute",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create ingest_message_rate gauge");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register ingest_message_rate gauge");
+    m
+});
+
+/// Payload bytes received during the last budget interval
+#[allow(dead_code)]
+pub static INGEST_BANDWIDTH: Lazy<IntGauge> = Lazy::new(|| {
+    let m = IntGauge::with_opts(
+        Opts::new(
+            "ingest_bandwidth",
+            "Payload bytes received in the last minute",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create ingest_bandwidth gauge");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register ingest_bandwidth gauge");
+    m
+});
+
+/// Messages dropped by sampling while over the ingest budget
+#[allow(dead_code)]
+pub static SAMPLED_OUT_MESSAGES: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "sampled_out_messages",
+            "Number of messages dropped by ingest sampling",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+    )
+    .expect("Failed to create sampled_out_messages counter");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register sampled_out_messages counter");
+    m
+});
+
+/// Export runs that failed, a sink failing every run is stuck on the same batch
+#[allow(dead_code)]
+pub static EXPORT_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new("export_failures", "Number of failed export runs")
+            .namespace("graphcast")
+            .subsystem("listener_radio"),
+        &["sink"],
+    )
+    .expect("Failed to create export_failures counters");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register export_failures counters");
+    m
+});
+
+/// Messages delivered to an export sink
+#[allow(dead_code)]
+pub static EXPORTED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new("exported_messages", "Number of messages exported in total")
+            .namespace("graphcast")
+            .subsystem("listener_radio"),
+        &["sink"],
+    )
+    .expect("Failed to create exported_messages counters");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register exported_messages counters");
+    m
+});
+
+/// GraphQL requests by API key id, `static` for API_TOKENS and `anonymous` without a key
+#[allow(dead_code)]
+pub static API_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new(
+            "api_requests",
+            "Number of GraphQL requests served per API key",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["api_key"],
+    )
+    .expect("Failed to create api_requests counters");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register api_requests counters");
+    m
+});
+
+#[allow(dead_code)]
+pub static API_RESPONSE_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new(
+            "api_response_bytes",
+            "Bytes of GraphQL responses returned per API key",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["api_key"],
+    )
+    .expect("Failed to create api_response_bytes counters");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register api_response_bytes counters");
+    m
+});
+
+/// Notifications sent per channel
+#[allow(dead_code)]
+pub static NOTIFICATION_ATTEMPTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new(
+            "notification_attempts",
+            "Number of notification deliveries attempted per channel",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["channel"],
+    )
+    .expect("Failed to create notification_attempts counters");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register notification_attempts counters");
+    m
+});
+
+/// Notification deliveries per channel by outcome, succeeded or failed
+#[allow(dead_code)]
+pub static NOTIFICATION_DELIVERIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let m = IntCounterVec::new(
+        Opts::new(
+            "notification_deliveries",
+            "Number of notification deliveries per channel and outcome",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["channel", "outcome"],
+    )
+    .expect("Failed to create notification_deliveries counters");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register notification_deliveries counters");
+    m
+});
+
+#[allow(dead_code)]
+pub static NOTIFICATION_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    let m = HistogramVec::new(
+        HistogramOpts::new(
+            "notification_latency_seconds",
+            "Time to deliver a notification per channel, failed deliveries included",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
+        &["channel"],
+    )
+    .expect("Failed to create notification_latency_seconds histograms");
+    prometheus::register(Box::new(m.clone()))
+        .expect("Failed to register notification_latency_seconds histograms");
+    m
+});
+
+/// Panics of listener threads and tasks
+#[allow(dead_code)]
+pub static PANICS: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new("panics", "Number of panics of listener threads and tasks")
+            .namespace("graphcast")
+            .subsystem("listener_radio"),
+    )
+    .expect("Failed to create panics counter");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register panics counter");
+    m
+});
+
+/// Message processing workers restarted after a panic
+#[allow(dead_code)]
+pub static WORKER_RESTARTS: Lazy<IntCounter> = Lazy::new(|| {
+    let m = IntCounter::with_opts(
+        Opts::new(
+            "worker_restarts",
+            "Number of message processing workers restarted after a panic",
+        )
+        .namespace("graphcast")
+        .subsystem("listener_radio"),
     )
-    .expect("Failed to create pruned_messages gauge");
-    prometheus::register(Box::new(m.clone())).expect("Failed to register pruned_messages gauge");
+    .expect("Failed to create worker_restarts counter");
+    prometheus::register(Box::new(m.clone())).expect("Failed to register worker_restarts counter");
     m
 });
 
@@ -142,8 +851,50 @@ pub fn start_metrics() {
             Box::new(ACTIVE_PEERS.clone()),
             Box::new(CONNECTED_PEERS.clone()),
             Box::new(GOSSIP_PEERS.clone()),
+            Box::new(PEERLESS_SECONDS.clone()),
+            Box::new(DISCOVERED_NODES.clone()),
+            Box::new(WAKU_LOG_EVENTS.clone()),
+            Box::new(DECRYPTED_MESSAGES.clone()),
+            Box::new(BACKFILLED_MESSAGES.clone()),
+            Box::new(BOOT_NODE_REACHABLE.clone()),
+            Box::new(BOOT_NODE_DIAL_MS.clone()),
             Box::new(RECEIVED_MESSAGES.clone()),
+            Box::new(DUPLICATE_MESSAGES.clone()),
+            Box::new(DUPLICATION_FACTOR.clone()),
+            Box::new(TOPIC_DUPLICATION_FACTOR.clone()),
+            Box::new(RAW_MESSAGES.clone()),
+            Box::new(QUARANTINED_MESSAGES.clone()),
+            Box::new(UNDECODED_FIELD_MESSAGES.clone()),
+            Box::new(IGNORED_MESSAGES.clone()),
+            Box::new(PROCESSING_TIMEOUTS.clone()),
+            Box::new(INSERT_BATCH_SIZE.clone()),
+            Box::new(PIPELINE_STAGE_SECONDS.clone()),
+            Box::new(INGEST_QUEUE_DEPTH.clone()),
             Box::new(PRUNED_MESSAGES.clone()),
+            Box::new(LAST_PRUNED_MESSAGES.clone()),
+            Box::new(COLD_STORED_MESSAGES.clone()),
+            Box::new(ARCHIVED_MESSAGES.clone()),
+            Box::new(OUTBOX_PUBLISHED.clone()),
+            Box::new(EXPORTED_MESSAGES.clone()),
+            Box::new(EXPORT_FAILURES.clone()),
+            Box::new(COVERED_DEPLOYMENTS.clone()),
+            Box::new(INDEXER_MESSAGES.clone()),
+            Box::new(INDEXER_SUBGRAPHS.clone()),
+            Box::new(DISTINCT_SUBGRAPHS.clone()),
+            Box::new(SUBSCRIBED_TOPICS.clone()),
+            Box::new(TOPIC_LAST_MESSAGE.clone()),
+            Box::new(SILENT_TOPICS.clone()),
+            Box::new(FILTER_RESUBSCRIPTIONS.clone()),
+            Box::new(INGEST_MESSAGE_RATE.clone()),
+            Box::new(INGEST_BANDWIDTH.clone()),
+            Box::new(SAMPLED_OUT_MESSAGES.clone()),
+            Box::new(API_REQUESTS.clone()),
+            Box::new(API_RESPONSE_BYTES.clone()),
+            Box::new(NOTIFICATION_ATTEMPTS.clone()),
+            Box::new(NOTIFICATION_DELIVERIES.clone()),
+            Box::new(NOTIFICATION_LATENCY.clone()),
+            Box::new(PANICS.clone()),
+            Box::new(WORKER_RESTARTS.clone()),
         ],
     );
 }
@@ -174,6 +925,7 @@ pub async fn handle_serve_metrics(host: String, port: u16) {
 
     server
         .serve(app.into_make_service())
+        .with_graceful_shutdown(stopped())
         .await
         .expect("Error starting API server");
 }
@@ -0,0 +1,190 @@
+//! Periodic snapshot of the network subgraph: indexer stake and active allocations, so
+//! gossip activity can be put next to economic weight without querying the subgraph per request
+use anyhow::anyhow;
+use reqwest::Client;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use sqlx::PgPool;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, warn};
+
+use crate::db::resolver::{replace_network_snapshot, NetworkAllocation, NetworkIndexer};
+
+/// Entities fetched per network subgraph request
+const PAGE_SIZE: usize = 1000;
+
+const INDEXERS_QUERY: &str = r#"
+query indexers($first: Int!, $lastId: String!) {
+    entities: indexers(first: $first, orderBy: id, where: { id_gt: $lastId, stakedTokens_gt: "0" }) {
+        id
+        stakedTokens
+        allocatedTokens
+        allocationCount
+    }
+}"#;
+
+const ALLOCATIONS_QUERY: &str = r#"
+query allocations($first: Int!, $lastId: String!) {
+    entities: allocations(first: $first, orderBy: id, where: { id_gt: $lastId, status: Active }) {
+        id
+        indexer { id }
+        allocatedTokens
+        subgraphDeployment { ipfsHash }
+    }
+}"#;
+
+#[derive(Deserialize)]
+struct Response<T> {
+    data: Option<Entities<T>>,
+    errors: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Entities<T> {
+    entities: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexerEntity {
+    id: String,
+    staked_tokens: String,
+    allocated_tokens: String,
+    allocation_count: i32,
+}
+
+#[derive(Deserialize)]
+struct Id {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Deployment {
+    ipfs_hash: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AllocationEntity {
+    id: String,
+    indexer: Id,
+    allocated_tokens: String,
+    subgraph_deployment: Deployment,
+}
+
+trait Entity {
+    fn id(&self) -> &str;
+}
+
+impl Entity for IndexerEntity {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Entity for AllocationEntity {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Fetch every entity of a query, paginating on the entity id
+async fn fetch_all<T: Entity + DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    query: &str,
+) -> Result<Vec<T>, anyhow::Error> {
+    let mut entities: Vec<T> = vec![];
+    loop {
+        let last_id = entities
+            .last()
+            .map(|e| e.id().to_string())
+            .unwrap_or_default();
+        let response: Response<T> = client
+            .post(url)
+            .json(&json!({
+                "query": query,
+                "variables": { "first": PAGE_SIZE, "lastId": last_id },
+            }))
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(errors) = response.errors {
+            return Err(anyhow!("Network subgraph query failed: {}", errors));
+        }
+        let page = response
+            .data
+            .ok_or_else(|| anyhow!("Network subgraph returned no data"))?
+            .entities;
+        let done = page.len() < PAGE_SIZE;
+        entities.extend(page);
+        if done {
+            return Ok(entities);
+        }
+    }
+}
+
+/// Replace the stored snapshot with the current indexers and active allocations
+/// Returns the number of indexers and allocations synced
+pub async fn sync_network(
+    pool: &PgPool,
+    client: &Client,
+    url: &str,
+) -> Result<(usize, usize), anyhow::Error> {
+    let indexers: Vec<NetworkIndexer> = fetch_all::<IndexerEntity>(client, url, INDEXERS_QUERY)
+        .await?
+        .into_iter()
+        .map(|i| NetworkIndexer {
+            id: i.id,
+            staked_tokens: i.staked_tokens,
+            allocated_tokens: i.allocated_tokens,
+            allocation_count: i.allocation_count,
+        })
+        .collect();
+    let allocations: Vec<NetworkAllocation> =
+        fetch_all::<AllocationEntity>(client, url, ALLOCATIONS_QUERY)
+            .await?
+            .into_iter()
+            .map(|a| NetworkAllocation {
+                id: a.id,
+                indexer: a.indexer.id,
+                deployment: a.subgraph_deployment.ipfs_hash,
+                allocated_tokens: a.allocated_tokens,
+            })
+            .collect();
+
+    replace_network_snapshot(pool, &indexers, &allocations).await?;
+    Ok((indexers.len(), allocations.len()))
+}
+
+pub async fn run_network_sync(
+    db: PgPool,
+    url: String,
+    sync_interval: Duration,
+    running: Arc<AtomicBool>,
+) {
+    let client = Client::new();
+    let mut sync_interval = interval(sync_interval);
+
+    while running.load(Ordering::SeqCst) {
+        sync_interval.tick().await;
+        match sync_network(&db, &client, &url).await {
+            Ok((indexers, allocations)) => {
+                debug!(indexers, allocations, "Synced network subgraph snapshot")
+            }
+            Err(e) => warn!(
+                err = tracing::field::debug(e),
+                "Failed to sync network subgraph, keeping the previous snapshot"
+            ),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Replay of the gossip missed while the listener was down. With STORE_NODES set, the Waku
+//! store nodes are asked on startup for the messages of the pubsub topic since the latest
+//! stored message, at most BACKFILL_WINDOW back, and the history is fed into the same channel
+//! as live gossip. Replayed messages go through the usual decoding, validation and storage,
+//! the ones already stored are skipped as duplicates
+use anyhow::anyhow;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Weak;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use waku_bindings::{Multiaddr, PagingOptions, ProtocolId, StoreQuery, WakuPubSubTopic};
+
+use graphcast_sdk::{graphcast_agent::GraphcastAgent, WakuMessage};
+
+use crate::{
+    config::Config, db::resolver::latest_message_time, metrics::BACKFILLED_MESSAGES,
+    pipeline::MessageSource, shutdown::stopping,
+};
+
+/// Messages per store query page
+const STORE_PAGE_SIZE: usize = 100;
+
+/// Time a store node gets to answer a page
+const STORE_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backfill {
+    pub store_nodes: Vec<Multiaddr>,
+    /// Longest downtime replayed, in seconds
+    pub window: i64,
+    pub pubsub_topic: String,
+}
+
+impl Backfill {
+    /// None without store nodes or with an empty window
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let store_nodes: Vec<Multiaddr> = config
+            .store_nodes
+            .iter()
+            .filter_map(|address| match Multiaddr::from_str(address) {
+                Ok(address) => Some(address),
+                Err(e) => {
+                    warn!(address, err = e.to_string(), "Invalid store node address");
+                    None
+                }
+            })
+            .collect();
+        if store_nodes.is_empty() || config.backfill_window == 0 {
+            return None;
+        }
+        Some(Backfill {
+            store_nodes,
+            window: config.backfill_window as i64,
+            pubsub_topic: config.pubsub_topic_name(),
+        })
+    }
+}
+
+/// Start of the replayed history: the latest stored message, unless it is older than the window
+fn replay_from(latest_stored: Option<i64>, now: i64, window: i64) -> i64 {
+    let earliest = now - window;
+    latest_stored.map_or(earliest, |latest| latest.max(earliest))
+}
+
+/// Every page of the history of `pubsub_topic` between `from` and `to` (unix
+/// seconds) on one store node, sent to `sender`. Returns the number of messages sent
+fn query_store_node(
+    agent: &GraphcastAgent,
+    address: &Multiaddr,
+    pubsub_topic: &WakuPubSubTopic,
+    from: i64,
+    to: i64,
+    sender: &Sender<WakuMessage>,
+) -> anyhow::Result<usize> {
+    let peer_id = agent
+        .node_handle
+        .add_peer(address, ProtocolId::Store)
+        .map_err(|e| anyhow!(e))?;
+    let mut query = StoreQuery {
+        pubsub_topic: Some(pubsub_topic.clone()),
+        content_topics: vec![],
+        start_time: Some((from as usize) * 1_000_000_000),
+        end_time: Some((to as usize) * 1_000_000_000),
+        paging_options: Some(PagingOptions {
+            page_size: STORE_PAGE_SIZE,
+            cursor: None,
+            forward: true,
+        }),
+    };
+    let mut sent = 0;
+    loop {
+        if stopping() {
+            break;
+        }
+        let response = agent
+            .node_handle
+            .store_query(&query, &peer_id, Some(STORE_QUERY_TIMEOUT))
+            .map_err(|e| anyhow!(e))?;
+        for message in response.messages() {
+            if sender.send(message.clone()).is_err() {
+                return Ok(sent);
+            }
+            sent += 1;
+        }
+        match response.paging_options() {
+            Some(paging) if paging.cursor.is_some() && !response.messages().is_empty() => {
+                query.paging_options = Some(paging.clone());
+            }
+            _ => break,
+        }
+    }
+    Ok(sent)
+}
+
+fn replay(agent: &GraphcastAgent, backfill: &Backfill, from: i64, sender: &Sender<WakuMessage>) {
+    let to = Utc::now().timestamp();
+    let pubsub_topic = match WakuPubSubTopic::from_str(&backfill.pubsub_topic) {
+        Ok(topic) => topic,
+        Err(e) => {
+            warn!(
+                err = e.to_string(),
+                "Invalid pubsub topic, skipping backfill"
+            );
+            return;
+        }
+    };
+    for address in &backfill.store_nodes {
+        match query_store_node(agent, address, &pubsub_topic, from, to, sender) {
+            Ok(messages) => {
+                BACKFILLED_MESSAGES.inc_by(messages as u64);
+                info!(
+                    store_node = address.to_string(),
+                    messages,
+                    seconds = to - from,
+                    "Replayed Waku store history"
+                );
+                // Other store nodes hold the same history
+                return;
+            }
+            Err(e) => warn!(
+                store_node = address.to_string(),
+                err = e.to_string(),
+                "Store query failed, trying the next store node"
+            ),
+        }
+    }
+    warn!("No store node answered, messages sent while the listener was down are missing");
+}
+
+/// Channel carrying both the live gossip of `receiver` and the replayed history. It closes
+/// once the live source closed and the replay is over
+pub fn with_backfill(
+    mut receiver: Receiver<WakuMessage>,
+    agent: Weak<GraphcastAgent>,
+    db: PgPool,
+    namespace: String,
+    backfill: Backfill,
+) -> Receiver<WakuMessage> {
+    let (sender, merged) = mpsc::channel();
+
+    let live = sender.clone();
+    thread::spawn(move || {
+        while let Some(msg) = receiver.next_message() {
+            if live.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let latest_stored = match latest_message_time(&db, &namespace).await {
+            Ok(latest) => latest,
+            Err(e) => {
+                warn!(
+                    err = e.to_string(),
+                    "Could not read the latest stored message, replaying the whole window"
+                );
+                None
+            }
+        };
+        let from = replay_from(latest_stored, Utc::now().timestamp(), backfill.window);
+        debug!(from, "Starting backfill from the Waku store");
+        // Store queries block in the Waku bindings
+        let replayed = tokio::task::spawn_blocking(move || {
+            // Nothing to replay into once the listener stopped
+            if let Some(agent) = agent.upgrade() {
+                replay(&agent, &backfill, from, &sender);
+            }
+        })
+        .await;
+        if let Err(e) = replayed {
+            warn!(err = e.to_string(), "Backfill failed");
+        }
+    });
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_from() {
+        assert_eq!(replay_from(None, 10_000, 3600), 6400);
+        assert_eq!(replay_from(Some(9000), 10_000, 3600), 9000);
+        assert_eq!(
+            replay_from(Some(1000), 10_000, 3600),
+            6400,
+            "Downtimes longer than the window are cut"
+        );
+    }
+}
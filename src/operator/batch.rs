@@ -0,0 +1,106 @@
+//! Batched inserts of received messages. Workers collect accepted messages and run them
+//! through the pipeline once the batch is full or the flush interval elapsed, storing every
+//! valid message of the batch with a single insert
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::{interval, timeout, MissedTickBehavior};
+use tracing::{debug, debug_span, Instrument};
+
+use super::{handle_outcome, next_delivery, receive, Delivery, DeliveryQueue};
+use crate::{
+    config::Config,
+    metrics::INSERT_BATCH_SIZE,
+    pipeline::{MessageStore, Pipeline, Validator},
+};
+
+/// Size and flush interval of insert batches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InsertBatching {
+    pub size: usize,
+    /// Longest time a received message waits for its batch to fill
+    pub interval: Duration,
+}
+
+impl InsertBatching {
+    /// None when INSERT_BATCH_SIZE keeps inserting messages one by one
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.insert_batch_size > 1).then(|| InsertBatching {
+            size: config.insert_batch_size,
+            interval: Duration::from_millis(config.insert_batch_interval),
+        })
+    }
+}
+
+/// Worker processing queued messages in batches, `processing_timeout` bounds the processing
+/// of a whole batch
+pub(super) async fn process_batches<V: Validator, S: MessageStore>(
+    queue: DeliveryQueue,
+    pipeline: Arc<Pipeline<V, S>>,
+    processing_timeout: Duration,
+    batching: InsertBatching,
+) {
+    let mut batch = Vec::with_capacity(batching.size);
+    let mut flush_interval = interval(batching.interval);
+    flush_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            delivery = next_delivery(&queue) => match delivery {
+                Some((msg, peer)) => {
+                    if receive(&pipeline, &msg, peer.as_deref()).await {
+                        batch.push((msg, peer));
+                    }
+                    if batch.len() < batching.size {
+                        continue;
+                    }
+                }
+                // Store what is left once the source closes
+                None => {
+                    flush(&pipeline, &mut batch, processing_timeout).await;
+                    break;
+                }
+            },
+            _ = flush_interval.tick() => {}
+        }
+        flush(&pipeline, &mut batch, processing_timeout).await;
+    }
+}
+
+async fn flush<V: Validator, S: MessageStore>(
+    pipeline: &Pipeline<V, S>,
+    batch: &mut Vec<Delivery>,
+    processing_timeout: Duration,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    INSERT_BATCH_SIZE.observe(batch.len() as f64);
+
+    let started = Instant::now();
+    let process_res = timeout(processing_timeout, pipeline.process_batch(batch))
+        .instrument(debug_span!("batch", messages = batch.len()))
+        .await;
+    let elapsed = started.elapsed();
+    if elapsed > processing_timeout / 2 {
+        debug!(
+            messages = batch.len(),
+            elapsed_ms = elapsed.as_millis() as u64,
+            timeout_ms = processing_timeout.as_millis() as u64,
+            "Slow batch processing"
+        );
+    }
+
+    match process_res {
+        Ok(results) => {
+            for ((msg, peer), result) in batch.iter().zip(results) {
+                handle_outcome(pipeline, msg, peer.as_deref(), Ok(result)).await;
+            }
+        }
+        Err(e) => {
+            let error = e.to_string();
+            for (msg, peer) in batch.iter() {
+                handle_outcome(pipeline, msg, peer.as_deref(), Err(error.clone())).await;
+            }
+        }
+    }
+    batch.clear();
+}
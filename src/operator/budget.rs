@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Messages and bytes received since the last budget check
+static WINDOW_MESSAGES: AtomicU64 = AtomicU64::new(0);
+static WINDOW_BYTES: AtomicU64 = AtomicU64::new(0);
+/// Keep one in `SAMPLE_EVERY` received messages, 0 or 1 keeps everything
+static SAMPLE_EVERY: AtomicU64 = AtomicU64::new(0);
+static SAMPLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Count a received message towards the ingest budget
+pub fn record_ingest(bytes: usize) {
+    WINDOW_MESSAGES.fetch_add(1, Ordering::Relaxed);
+    WINDOW_BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Messages and bytes received since the previous call
+pub fn take_window() -> (u64, u64) {
+    (
+        WINDOW_MESSAGES.swap(0, Ordering::Relaxed),
+        WINDOW_BYTES.swap(0, Ordering::Relaxed),
+    )
+}
+
+/// Keep a `rate` fraction of the received messages, None turns sampling off
+pub fn set_sampling(rate: Option<f64>) {
+    let every = rate
+        .filter(|rate| *rate > 0.0 && *rate < 1.0)
+        .map(|rate| (1.0 / rate).round() as u64)
+        .unwrap_or(0);
+    SAMPLE_EVERY.store(every, Ordering::SeqCst);
+}
+
+/// Whether sampling drops the next received message
+pub fn sampled_out() -> bool {
+    let every = SAMPLE_EVERY.load(Ordering::SeqCst);
+    every > 1
+        && !SAMPLE_COUNTER
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(every)
+}
+
+/// Ingest thresholds per check interval
+#[derive(Clone, Debug, Default)]
+pub struct IngestBudget {
+    pub max_messages: Option<u64>,
+    pub max_bytes: Option<u64>,
+    /// Consecutive intervals over (or back under) budget before the state changes
+    pub sustain: u32,
+}
+
+/// Change of the budget state, reported once per transition
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BudgetEvent {
+    Exceeded { messages: u64, bytes: u64 },
+    Recovered,
+}
+
+/// Tracks how long ingest has been over budget
+#[derive(Debug, Default)]
+pub struct BudgetMonitor {
+    budget: IngestBudget,
+    streak: u32,
+    exceeded: bool,
+}
+
+impl BudgetMonitor {
+    pub fn new(budget: IngestBudget) -> Self {
+        BudgetMonitor {
+            budget,
+            streak: 0,
+            exceeded: false,
+        }
+    }
+
+    pub fn observe(&mut self, messages: u64, bytes: u64) -> Option<BudgetEvent> {
+        let over = self.budget.max_messages.is_some_and(|max| messages > max)
+            || self.budget.max_bytes.is_some_and(|max| bytes > max);
+        // Count intervals that disagree with the current state
+        if over != self.exceeded {
+            self.streak += 1;
+        } else {
+            self.streak = 0;
+        }
+        if self.streak < self.budget.sustain.max(1) {
+            return None;
+        }
+
+        self.streak = 0;
+        self.exceeded = over;
+        Some(if over {
+            BudgetEvent::Exceeded { messages, bytes }
+        } else {
+            BudgetEvent::Recovered
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sustained_budget() {
+        let mut monitor = BudgetMonitor::new(IngestBudget {
+            max_messages: Some(100),
+            max_bytes: None,
+            sustain: 2,
+        });
+        assert_eq!(monitor.observe(500, 0), None);
+        assert_eq!(monitor.observe(50, 0), None);
+        assert_eq!(monitor.observe(500, 0), None);
+        assert_eq!(
+            monitor.observe(600, 0),
+            Some(BudgetEvent::Exceeded {
+                messages: 600,
+                bytes: 0
+            })
+        );
+        assert_eq!(monitor.observe(700, 0), None);
+        assert_eq!(monitor.observe(10, 0), None);
+        assert_eq!(monitor.observe(10, 0), Some(BudgetEvent::Recovered));
+    }
+}
@@ -0,0 +1,368 @@
+use anyhow::anyhow;
+use graphcast_sdk::{graphcast_agent::GraphcastAgent, WakuMessage};
+use sqlx::{Pool, Postgres};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+use super::{
+    backfill::{with_backfill, Backfill},
+    message_processor,
+    notifier::Notifier,
+    templates::TemplateFile,
+    topics::{set_topic_scope, TopicScope},
+    ProcessorSettings, RadioOperator,
+};
+use crate::{
+    archive::{pruned::ArchiveSink, ColdStorage},
+    config::{Config, NonceOrdering},
+    db,
+    export::bigquery::BigQueryExport,
+    metrics::{handle_serve_metrics, labels::init_deployment_labels},
+    pipeline::{
+        decryption::PayloadKeys, descriptors::load_descriptors, identity::SenderIdentity,
+        nonces::NonceTracker, AcceptAll, MessageStore, MessageTypes, PayloadValidator, Pipeline,
+        PostgresStore, Validator,
+    },
+    ListenerError,
+};
+
+/// Failures while assembling a radio operator
+#[derive(Debug, thiserror::Error)]
+pub enum OperatorError {
+    #[error("Graphcast agent was not provided")]
+    MissingAgent,
+    #[error("Could not connect to the database: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Incompatible database schema: {0}")]
+    Schema(#[from] db::SchemaError),
+    #[error("Could not set up cold storage: {0}")]
+    ColdStorage(anyhow::Error),
+    #[error("Could not set up the archive of pruned messages: {0}")]
+    Archive(anyhow::Error),
+    #[error("Invalid BigQuery export: {0}")]
+    BigQuery(anyhow::Error),
+    #[error("Could not load notification templates: {0}")]
+    NotificationTemplates(anyhow::Error),
+    #[error("Invalid message types: {0}")]
+    MessageTypes(anyhow::Error),
+    #[error("Could not load sender nonces: {0}")]
+    SenderNonces(ListenerError),
+}
+
+type SpawnProcessor =
+    Box<dyn FnOnce(Receiver<WakuMessage>, ProcessorSettings) -> JoinHandle<()> + Send>;
+
+/// Assemble a [`RadioOperator`], every component not provided is derived from the config
+pub struct RadioOperatorBuilder {
+    config: Config,
+    db: Option<Pool<Postgres>>,
+    agent: Option<(GraphcastAgent, Receiver<WakuMessage>)>,
+    notifier: Option<Notifier>,
+    processor: Option<SpawnProcessor>,
+}
+
+impl RadioOperatorBuilder {
+    pub fn new(config: Config) -> Self {
+        RadioOperatorBuilder {
+            config,
+            db: None,
+            agent: None,
+            notifier: None,
+            processor: None,
+        }
+    }
+
+    /// Use an existing pool instead of connecting to `database_url`
+    pub fn pool(mut self, db: Pool<Postgres>) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Graphcast agent and the receiving end of the channel it was created with
+    pub fn agent(mut self, agent: GraphcastAgent, receiver: Receiver<WakuMessage>) -> Self {
+        self.agent = Some((agent, receiver));
+        self
+    }
+
+    pub fn notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Persist received messages with a custom store, keeping the default message types and validation
+    pub fn store<S: MessageStore + 'static>(self, store: S) -> Self {
+        self.pipeline(Pipeline::new(MessageTypes::default(), AcceptAll, store))
+    }
+
+    /// Process received messages with a custom pipeline
+    pub fn pipeline<V, S>(mut self, pipeline: Pipeline<V, S>) -> Self
+    where
+        V: Validator + 'static,
+        S: MessageStore + 'static,
+    {
+        let pipeline = Arc::new(pipeline);
+        self.processor = Some(Box::new(move |receiver, settings| {
+            message_processor(receiver, pipeline, settings)
+        }));
+        self
+    }
+
+    pub async fn build(self) -> Result<RadioOperator, OperatorError> {
+        let config = self.config;
+        // Settings that only depend on the config are checked before anything is connected
+        let templates = config
+            .notification_templates
+            .as_deref()
+            .map(|path| TemplateFile::load(Path::new(path)))
+            .transpose()
+            .map_err(OperatorError::NotificationTemplates)?;
+        let cold_storage = config
+            .cold_storage_url
+            .as_deref()
+            .map(ColdStorage::new)
+            .transpose()
+            .map_err(OperatorError::ColdStorage)?;
+        if config.archive_before_prune && cold_storage.is_none() {
+            return Err(OperatorError::ColdStorage(anyhow!(
+                "ARCHIVE_BEFORE_PRUNE needs COLD_STORAGE_URL"
+            )));
+        }
+        let archive = config
+            .archive_url
+            .as_deref()
+            .map(ArchiveSink::new)
+            .transpose()
+            .map_err(OperatorError::Archive)?;
+        let bigquery = BigQueryExport::from_config(&config).map_err(OperatorError::BigQuery)?;
+
+        let (graphcast_agent, receiver) = self.agent.ok_or(OperatorError::MissingAgent)?;
+        let graphcast_agent = Arc::new(graphcast_agent);
+        let notifier = self
+            .notifier
+            .unwrap_or_else(|| Notifier::from_config(&config));
+
+        let db = match self.db {
+            Some(db) => db,
+            None => {
+                debug!("Connecting to database");
+                db::connect(&config.database_url).await?
+            }
+        };
+
+        let mut notifier = notifier.with_database(
+            db.clone(),
+            config.instance_namespace.clone(),
+            config.notification_retry_max_age > 0,
+        );
+        if let Some(templates) = templates {
+            notifier = notifier.with_templates(templates);
+        }
+
+        if config.skip_migrations {
+            info!("Skipping database migrations, schema is expected to be managed externally");
+            db::check_schema_version(&db).await?;
+        } else {
+            debug!("Check for database migration");
+            db::migrate(&db).await?;
+        }
+
+        // Set up Prometheus metrics url if configured
+        if let Some(port) = config.metrics_port {
+            debug!("Initializing metrics port");
+            tokio::spawn(handle_serve_metrics(config.metrics_host.clone(), port));
+        }
+
+        init_deployment_labels(&config);
+        db::resolver::set_nonce_skew(config.nonce_skew);
+        set_topic_scope(if config.filter_protocol == Some(true) {
+            TopicScope::Configured
+        } else {
+            TopicScope::All
+        });
+        if let Some(true) = config.filter_protocol {
+            // Provide generated topics to Graphcast agent
+            let topics = config.topics.to_vec();
+            debug!(
+                topics = tracing::field::debug(&topics),
+                "Found content topics for subscription",
+            );
+            graphcast_agent.update_content_topics(topics.clone());
+        }
+
+        // Replay the gossip missed while down along with the live messages if configured
+        let receiver = match Backfill::from_config(&config) {
+            Some(backfill) => with_backfill(
+                receiver,
+                Arc::downgrade(&graphcast_agent),
+                db.clone(),
+                config.instance_namespace.clone(),
+                backfill,
+            ),
+            None => receiver,
+        };
+
+        let settings = ProcessorSettings::from_config(&config);
+        let mut nonce_tracker = None;
+        let message_processor_handle = match self.processor {
+            Some(spawn) => spawn(receiver, settings),
+            None => {
+                let mut pipeline =
+                    default_pipeline(&config, db.clone()).map_err(OperatorError::MessageTypes)?;
+                if config.nonce_ordering != NonceOrdering::Off {
+                    let tracker = Arc::new(
+                        NonceTracker::load(
+                            db.clone(),
+                            config.instance_namespace.clone(),
+                            config.nonce_ordering,
+                        )
+                        .await
+                        .map_err(OperatorError::SenderNonces)?,
+                    );
+                    pipeline = pipeline.with_nonce_tracker(tracker.clone());
+                    nonce_tracker = Some(tracker);
+                }
+                message_processor(receiver, Arc::new(pipeline), settings)
+            }
+        };
+
+        debug!("Initialized Radio Operator");
+        Ok(RadioOperator {
+            config,
+            db,
+            graphcast_agent,
+            notifier,
+            running: Arc::new(AtomicBool::new(true)),
+            message_processor_handle,
+            cold_storage,
+            archive,
+            bigquery,
+            nonce_tracker,
+        })
+    }
+}
+
+/// Pipeline of the listener binary: the built-in message types, then the ones of PROTO_DESCRIPTORS,
+/// restricted to MESSAGE_TYPES and decrypted with DECRYPTION_KEYS, checked against the configured payload checks and stored in Postgres
+/// with the outcome of the ID_VALIDATION check of their sender
+pub fn default_pipeline(
+    config: &Config,
+    db: Pool<Postgres>,
+) -> anyhow::Result<Pipeline<PayloadValidator, PostgresStore>> {
+    let mut message_types = MessageTypes::default();
+    if let Some(path) = &config.proto_descriptors {
+        let descriptors = load_descriptors(Path::new(path))?;
+        message_types =
+            message_types.register_descriptors(&descriptors, &config.proto_message_types)?;
+    }
+    if !config.message_types.is_empty() {
+        message_types = message_types.only(&config.message_types)?;
+    }
+    let pipeline = Pipeline::new(
+        message_types,
+        PayloadValidator::new(
+            config.payload_checks.clone(),
+            config.network_allowlist.clone(),
+        ),
+        PostgresStore::new(db, config.instance_namespace.clone())
+            .with_notify_channel(config.notify_channel.clone())
+            .with_outbox(config.outbox_webhook.is_some()),
+    );
+    let pipeline = match PayloadKeys::parse(&config.decryption_keys)? {
+        Some(keys) => pipeline.with_decryption_keys(Arc::new(keys)),
+        None => pipeline,
+    };
+    Ok(match SenderIdentity::from_config(config) {
+        Some(identity) => pipeline.with_sender_identity(Arc::new(identity)),
+        None => pipeline,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+
+    #[tokio::test]
+    async fn test_build_validates_config() {
+        let build = |config: Config| async move {
+            RadioOperatorBuilder::new(config)
+                .build()
+                .await
+                .err()
+                .unwrap()
+        };
+
+        assert!(matches!(
+            build(Config::default()).await,
+            OperatorError::MissingAgent
+        ));
+        assert!(matches!(
+            build(Config {
+                archive_before_prune: true,
+                ..Default::default()
+            })
+            .await,
+            OperatorError::ColdStorage(_)
+        ));
+        assert!(matches!(
+            build(Config {
+                cold_storage_url: Some("unknown://bucket".to_string()),
+                ..Default::default()
+            })
+            .await,
+            OperatorError::ColdStorage(_)
+        ));
+        assert!(matches!(
+            build(Config {
+                archive_url: Some("unknown://bucket".to_string()),
+                ..Default::default()
+            })
+            .await,
+            OperatorError::Archive(_)
+        ));
+        assert!(matches!(
+            build(Config {
+                bigquery_table: Some("project.dataset.messages".to_string()),
+                ..Default::default()
+            })
+            .await,
+            OperatorError::BigQuery(_)
+        ));
+        assert!(matches!(
+            build(Config {
+                notification_templates: Some("/nonexistent/templates.toml".to_string()),
+                ..Default::default()
+            })
+            .await,
+            OperatorError::NotificationTemplates(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_default_pipeline() {
+        let db = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/listener_radio")
+            .unwrap();
+        let config = Config {
+            message_types: vec!["public_poi".to_string()],
+            ..Default::default()
+        };
+        assert!(default_pipeline(&config, db.clone()).is_ok());
+
+        let config = Config {
+            message_types: vec!["unknown".to_string()],
+            ..Default::default()
+        };
+        assert!(default_pipeline(&config, db.clone()).is_err());
+
+        let config = Config {
+            proto_descriptors: Some("/nonexistent/radio.pb".to_string()),
+            ..Default::default()
+        };
+        assert!(default_pipeline(&config, db).is_err());
+    }
+}
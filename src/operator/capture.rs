@@ -0,0 +1,26 @@
+use chrono::Utc;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Longest capture a single mutation can start, raw traffic adds up quickly
+pub const MAX_CAPTURE_MINUTES: u64 = 60;
+
+/// Unix timestamp the debug capture runs until, 0 when off
+static CAPTURE_UNTIL: AtomicI64 = AtomicI64::new(0);
+
+/// Record every Waku message for the next `minutes`, capped at [`MAX_CAPTURE_MINUTES`].
+/// Returns the unix timestamp the capture ends at
+pub fn start_capture(minutes: u64) -> i64 {
+    let until = Utc::now().timestamp() + (minutes.min(MAX_CAPTURE_MINUTES) * 60) as i64;
+    CAPTURE_UNTIL.store(until, Ordering::SeqCst);
+    until
+}
+
+pub fn stop_capture() {
+    CAPTURE_UNTIL.store(0, Ordering::SeqCst);
+}
+
+/// End of the running capture, None when capture is off or expired
+pub fn capture_until() -> Option<i64> {
+    let until = CAPTURE_UNTIL.load(Ordering::SeqCst);
+    (until > Utc::now().timestamp()).then_some(until)
+}
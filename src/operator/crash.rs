@@ -0,0 +1,107 @@
+use chrono::Utc;
+use sqlx::PgPool;
+use std::{
+    backtrace::Backtrace,
+    fs::OpenOptions,
+    io::Write,
+    panic::{self, PanicHookInfo},
+    thread,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{error, warn};
+
+use crate::{
+    db::resolver::add_crash,
+    metrics::PANICS,
+    operator::{
+        notifier::Notifier,
+        templates::{Alert, AlertKind},
+    },
+};
+
+/// Panic of a listener thread or task
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrashReport {
+    pub timestamp: i64,
+    pub thread: String,
+    pub location: String,
+    pub message: String,
+    pub backtrace: String,
+}
+
+impl CrashReport {
+    fn from_panic(info: &PanicHookInfo<'_>) -> Self {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Box<dyn Any>".to_string());
+        CrashReport {
+            timestamp: Utc::now().timestamp(),
+            thread: thread::current().name().unwrap_or("unnamed").to_string(),
+            location: info
+                .location()
+                .map(|location| location.to_string())
+                .unwrap_or_default(),
+            message,
+            backtrace: Backtrace::force_capture().to_string(),
+        }
+    }
+}
+
+fn append_crash_file(path: &str, report: &CrashReport) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(
+        file,
+        "{} panic in thread '{}' at {}: {}\n{}",
+        report.timestamp, report.thread, report.location, report.message, report.backtrace
+    )
+}
+
+/// Report panics on top of the default hook: count them, append them to `crash_file` so
+/// panics taking the process down leave a trace, and pass them to [`run_crash_reporter`]
+pub fn install_panic_hook(crash_file: Option<String>) -> UnboundedReceiver<CrashReport> {
+    let (sender, receiver): (UnboundedSender<CrashReport>, _) = unbounded_channel();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        PANICS.inc();
+        let report = CrashReport::from_panic(info);
+        error!(
+            thread = report.thread,
+            location = report.location,
+            message = report.message,
+            "Listener thread panicked"
+        );
+        if let Some(path) = &crash_file {
+            if let Err(e) = append_crash_file(path, &report) {
+                warn!(err = e.to_string(), "Failed to write crash file");
+            }
+        }
+        let _ = sender.send(report);
+    }));
+    receiver
+}
+
+/// Store reported panics and alert the notifiers about them
+pub async fn run_crash_reporter(
+    db: PgPool,
+    namespace: String,
+    notifier: Notifier,
+    mut crashes: UnboundedReceiver<CrashReport>,
+) {
+    while let Some(report) = crashes.recv().await {
+        if let Err(e) = add_crash(&db, &namespace, &report).await {
+            warn!(
+                err = tracing::field::debug(e),
+                "Failed to record crash report"
+            );
+        }
+        let alert = Alert::new(AlertKind::Panic)
+            .with("thread", &report.thread)
+            .with("location", &report.location)
+            .with("message", &report.message);
+        notifier.clone().notify(alert).await;
+    }
+}
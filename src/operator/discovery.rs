@@ -0,0 +1,127 @@
+//! Waku DNS discovery: the boot nodes are read from EIP-1459 ENR trees (`enrtree://` URLs)
+//! rather than a static list. The trees are resolved at startup and again every
+//! DNS_DISCOVERY_INTERVAL, and nodes that joined a tree since are dialed, so a listener stays
+//! connected while the fleet rotates its boot nodes
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, info, warn};
+use url::Url;
+use waku_bindings::{waku_dns_discovery, Multiaddr};
+
+use graphcast_sdk::graphcast_agent::GraphcastAgent;
+
+use crate::metrics::DISCOVERED_NODES;
+
+/// Time a tree gets to resolve, including every subtree lookup
+const DNS_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiscoveredNodes {
+    pub addresses: Vec<Multiaddr>,
+    /// Base64 ENRs of the nodes, for discv5 bootstrapping
+    pub enrs: Vec<String>,
+}
+
+fn resolve(urls: &[String]) -> DiscoveredNodes {
+    let mut nodes = DiscoveredNodes::default();
+    for url in urls {
+        let parsed = match Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                warn!(url, err = e.to_string(), "Invalid DNS discovery URL");
+                continue;
+            }
+        };
+        match waku_dns_discovery(&parsed, None, Some(DNS_DISCOVERY_TIMEOUT)) {
+            Ok(found) => {
+                debug!(url, nodes = found.len(), "Resolved ENR tree");
+                for node in found {
+                    for address in node.addresses {
+                        if !nodes.addresses.contains(&address) {
+                            nodes.addresses.push(address);
+                        }
+                    }
+                    if let Some(enr) = node.enr.map(|enr| enr.to_base64()) {
+                        if !nodes.enrs.contains(&enr) {
+                            nodes.enrs.push(enr);
+                        }
+                    }
+                }
+            }
+            Err(e) => warn!(url, err = e.to_string(), "Could not resolve ENR tree"),
+        }
+    }
+    DISCOVERED_NODES.set(nodes.addresses.len() as i64);
+    nodes
+}
+
+/// Nodes of every ENR tree in `urls`, a tree that fails to resolve only costs a warning
+pub async fn discover_nodes(urls: &[String]) -> DiscoveredNodes {
+    if urls.is_empty() {
+        return DiscoveredNodes::default();
+    }
+    let urls = urls.to_vec();
+    // The resolution blocks in the Waku bindings
+    match tokio::task::spawn_blocking(move || resolve(&urls)).await {
+        Ok(nodes) => nodes,
+        Err(e) => {
+            warn!(err = e.to_string(), "DNS discovery failed");
+            DiscoveredNodes::default()
+        }
+    }
+}
+
+/// Resolve the ENR trees every `refresh` and dial the nodes that were not listed at the
+/// previous resolution. A resolution without any node keeps the previous list
+pub async fn run_dns_discovery(
+    agent: Weak<GraphcastAgent>,
+    urls: Vec<String>,
+    refresh: Duration,
+    running: Arc<AtomicBool>,
+) {
+    let mut refresh = interval(refresh);
+    // The startup resolution already provided the boot nodes
+    refresh.tick().await;
+    let mut known: Option<HashSet<String>> = None;
+
+    while running.load(Ordering::SeqCst) {
+        refresh.tick().await;
+        let nodes = discover_nodes(&urls).await;
+        if nodes.addresses.is_empty() {
+            continue;
+        }
+        // The node stopped with the listener
+        let Some(agent) = agent.upgrade() else {
+            break;
+        };
+        let listed: HashSet<String> = nodes.addresses.iter().map(ToString::to_string).collect();
+        let mut dialed = 0;
+        for address in &nodes.addresses {
+            if known
+                .as_ref()
+                .is_some_and(|known| known.contains(&address.to_string()))
+            {
+                continue;
+            }
+            dialed += 1;
+            if let Err(e) = agent.node_handle.connect_peer_with_address(address, None) {
+                debug!(
+                    address = address.to_string(),
+                    err = e.to_string(),
+                    "Could not dial discovered node"
+                );
+            }
+        }
+        let retired = known
+            .as_ref()
+            .map_or(0, |known| known.difference(&listed).count());
+        info!(
+            nodes = listed.len(),
+            dialed, retired, "Refreshed DNS discovery"
+        );
+        known = Some(listed);
+    }
+}
@@ -0,0 +1,196 @@
+//! Gossip amplification. Relay delivers a message once per mesh peer forwarding it, so the
+//! listener sees most messages several times before they are deduplicated at storage. Every
+//! received payload is counted by hash per content topic, and each update interval closes
+//! with the average number of copies per unique message, overall and per topic
+use async_graphql::SimpleObject;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use crate::metrics::{labels::deployment_label, DUPLICATION_FACTOR, TOPIC_DUPLICATION_FACTOR};
+
+/// Copies of the received messages, shared by the message processor and the API
+pub static DUPLICATION: Lazy<Duplication> = Lazy::new(Duplication::new);
+
+/// Copies received of the messages of a content topic over an interval
+#[derive(Clone, Debug, PartialEq, Serialize, SimpleObject)]
+pub struct TopicDuplication {
+    pub topic: String,
+    /// Messages received, copies included
+    pub observed: i64,
+    /// Distinct messages received
+    pub unique: i64,
+    /// Average copies received per distinct message
+    pub factor: f64,
+}
+
+/// Duplication of the last completed interval
+#[derive(Clone, Debug, Default, PartialEq, Serialize, SimpleObject)]
+pub struct DuplicationReport {
+    /// Unix timestamps of the interval, 0 before the first interval completed
+    pub from: i64,
+    pub to: i64,
+    pub observed: i64,
+    pub unique: i64,
+    /// Average copies per distinct message over every topic, 0 without messages
+    pub factor: f64,
+    pub topics: Vec<TopicDuplication>,
+}
+
+#[derive(Debug)]
+struct Interval {
+    started_at: i64,
+    /// Copies of each payload hash, by content topic
+    copies: HashMap<String, HashMap<u64, u32>>,
+}
+
+#[derive(Debug)]
+pub struct Duplication {
+    current: Mutex<Interval>,
+    last: Mutex<DuplicationReport>,
+}
+
+impl Default for Duplication {
+    fn default() -> Self {
+        Duplication::new()
+    }
+}
+
+fn payload_hash(payload: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    payload.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn factor(observed: i64, unique: i64) -> f64 {
+    if unique == 0 {
+        0.0
+    } else {
+        observed as f64 / unique as f64
+    }
+}
+
+impl Duplication {
+    pub fn new() -> Self {
+        Duplication {
+            current: Mutex::new(Interval {
+                started_at: Utc::now().timestamp(),
+                copies: HashMap::new(),
+            }),
+            last: Mutex::new(DuplicationReport::default()),
+        }
+    }
+
+    /// Count a received message, before any deduplication
+    pub fn record(&self, topic: &str, payload: &[u8]) {
+        let mut current = self.current.lock().expect("Duplication lock poisoned");
+        *current
+            .copies
+            .entry(topic.to_string())
+            .or_default()
+            .entry(payload_hash(payload))
+            .or_default() += 1;
+    }
+
+    /// Close the interval at `now`, updating the gauges, and start the next one
+    pub fn rotate(&self, now: i64) -> DuplicationReport {
+        let interval = {
+            let mut current = self.current.lock().expect("Duplication lock poisoned");
+            std::mem::replace(
+                &mut *current,
+                Interval {
+                    started_at: now,
+                    copies: HashMap::new(),
+                },
+            )
+        };
+        let mut topics: Vec<TopicDuplication> = interval
+            .copies
+            .into_iter()
+            .map(|(topic, copies)| {
+                let observed = copies.values().map(|&n| n as i64).sum();
+                let unique = copies.len() as i64;
+                TopicDuplication {
+                    topic,
+                    observed,
+                    unique,
+                    factor: factor(observed, unique),
+                }
+            })
+            .collect();
+        topics.sort_by(|a, b| a.topic.cmp(&b.topic));
+
+        let observed = topics.iter().map(|t| t.observed).sum();
+        let unique = topics.iter().map(|t| t.unique).sum();
+        let report = DuplicationReport {
+            from: interval.started_at,
+            to: now,
+            observed,
+            unique,
+            factor: factor(observed, unique),
+            topics,
+        };
+
+        DUPLICATION_FACTOR.set(report.factor);
+        // Topics sharing a label are reported together
+        let mut labeled: HashMap<String, (i64, i64)> = HashMap::new();
+        for topic in &report.topics {
+            let counts = labeled
+                .entry(deployment_label(&topic.topic, None).into_owned())
+                .or_default();
+            counts.0 += topic.observed;
+            counts.1 += topic.unique;
+        }
+        TOPIC_DUPLICATION_FACTOR.reset();
+        for (label, (observed, unique)) in labeled {
+            TOPIC_DUPLICATION_FACTOR
+                .with_label_values(&[label.as_str()])
+                .set(factor(observed, unique));
+        }
+        *self.last.lock().expect("Duplication lock poisoned") = report.clone();
+        report
+    }
+
+    pub fn last_report(&self) -> DuplicationReport {
+        self.last.lock().expect("Duplication lock poisoned").clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplication_report() {
+        let duplication = Duplication::new();
+        for _ in 0..3 {
+            duplication.record("poi", b"first");
+        }
+        duplication.record("poi", b"second");
+        duplication.record("upgrade", b"first");
+
+        let report = duplication.rotate(Utc::now().timestamp());
+        assert_eq!((report.observed, report.unique), (5, 3));
+        assert!((report.factor - 5.0 / 3.0).abs() < f64::EPSILON);
+        assert_eq!(
+            report.topics[0],
+            TopicDuplication {
+                topic: "poi".to_string(),
+                observed: 4,
+                unique: 2,
+                factor: 2.0,
+            }
+        );
+        assert_eq!(report.topics[1].factor, 1.0);
+        assert_eq!(duplication.last_report(), report);
+
+        let empty = duplication.rotate(report.to + 600);
+        assert_eq!(empty.from, report.to);
+        assert_eq!(empty.factor, 0.0);
+        assert!(empty.topics.is_empty());
+    }
+}
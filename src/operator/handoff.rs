@@ -0,0 +1,130 @@
+//! Ingest handoff between two instances of a namespace during upgrades. The ingesting instance
+//! holds a Postgres advisory lock. A replacement started with HANDOFF_FROM joins the network
+//! without ingesting, asks the old instance to drain once it has peers, and ingests as soon as
+//! the old instance releases the lock
+use once_cell::sync::Lazy;
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::{sync::Notify, time::sleep};
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    db::resolver::{release_ingest_lock, try_ingest_lock},
+};
+
+/// Whether received messages are stored, off while waiting for a handoff and once drained
+static INGESTING: AtomicBool = AtomicBool::new(true);
+static DRAIN: Lazy<Notify> = Lazy::new(Notify::new);
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn ingesting() -> bool {
+    INGESTING.load(Ordering::SeqCst)
+}
+
+/// Stop storing received messages and release the ingest lock to the instance taking over
+pub fn drain() {
+    if INGESTING.swap(false, Ordering::SeqCst) {
+        info!("Draining, received messages are no longer stored");
+    }
+    DRAIN.notify_one();
+}
+
+/// Instance to take the ingest over from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Handoff {
+    pub url: String,
+    pub token: Option<String>,
+    pub timeout: Duration,
+}
+
+impl Handoff {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.handoff_from.as_ref().map(|url| Handoff {
+            url: url.trim_end_matches('/').to_string(),
+            token: config.handoff_token.clone(),
+            timeout: Duration::from_secs(config.handoff_timeout),
+        })
+    }
+
+    async fn request_drain(&self) -> Result<(), reqwest::Error> {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/drain", self.url))
+            .timeout(DRAIN_TIMEOUT);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Hold the ingest lock of `namespace` until drained. When another instance holds it, take it
+/// over through `handoff` once `peers` reports a connection, or ingest alongside that instance
+/// without a handoff or after its timeout
+pub async fn run_ingest_handoff<F>(
+    db: PgPool,
+    namespace: String,
+    handoff: Option<Handoff>,
+    peers: F,
+) where
+    F: Fn() -> usize + Send,
+{
+    let mut conn = match db.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!(err = e.to_string(), "Could not connect for the ingest lock");
+            return;
+        }
+    };
+    match try_ingest_lock(&mut *conn, &namespace).await {
+        Ok(true) => {}
+        Ok(false) => {
+            let Some(handoff) = handoff else {
+                info!("Another instance holds the ingest lock, ingesting alongside it");
+                return;
+            };
+            INGESTING.store(false, Ordering::SeqCst);
+            let deadline = Instant::now() + handoff.timeout;
+            while peers() == 0 && Instant::now() < deadline {
+                sleep(Duration::from_secs(1)).await;
+            }
+            info!(from = handoff.url, "Asking the previous instance to drain");
+            if let Err(e) = handoff.request_drain().await {
+                warn!(err = e.to_string(), "Drain request failed");
+            }
+            loop {
+                match try_ingest_lock(&mut *conn, &namespace).await {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(e) => warn!(err = e.to_string(), "Could not take the ingest lock"),
+                }
+                if Instant::now() >= deadline {
+                    warn!("Timed out waiting for the ingest lock, ingesting alongside the previous instance");
+                    INGESTING.store(true, Ordering::SeqCst);
+                    return;
+                }
+                sleep(LOCK_POLL_INTERVAL).await;
+            }
+            INGESTING.store(true, Ordering::SeqCst);
+            info!("Took the ingest over from the previous instance");
+        }
+        Err(e) => {
+            warn!(err = e.to_string(), "Could not take the ingest lock");
+            return;
+        }
+    }
+
+    DRAIN.notified().await;
+    if let Err(e) = release_ingest_lock(&mut *conn, &namespace).await {
+        // Closing the session releases its locks as well
+        warn!(
+            err = e.to_string(),
+            "Could not release the ingest lock, closing its connection"
+        );
+        let _ = conn.close().await;
+    }
+}
@@ -0,0 +1,177 @@
+//! Status of the listener's components for readiness probes: the database, the Waku peers,
+//! the time since the last received message and the message processor. A component that is
+//! down or degraded makes `/health` answer 503 with the breakdown
+use async_graphql::SimpleObject;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::Duration;
+use tokio::time::timeout;
+
+use super::topics::TOPIC_ACTIVITY;
+use crate::{
+    config::Config,
+    db::ping,
+    metrics::{GOSSIP_PEERS, INGEST_QUEUE_DEPTH},
+};
+
+/// Time the database gets to answer before it counts as down
+const DATABASE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Queued messages without any taken by a worker for this long mean the processor is stuck
+pub const PROCESSOR_STALL_AFTER: i64 = 60;
+
+/// Whether the processing workers run, cleared once they all exited
+static PROCESSOR_RUNNING: AtomicBool = AtomicBool::new(false);
+/// Unix timestamp of the last message a worker took from the queue
+static LAST_DEQUEUED: AtomicI64 = AtomicI64::new(0);
+
+pub fn set_processor_running(running: bool) {
+    PROCESSOR_RUNNING.store(running, Ordering::SeqCst);
+}
+
+pub fn record_dequeued(timestamp: i64) {
+    LAST_DEQUEUED.store(timestamp, Ordering::Relaxed);
+}
+
+#[derive(Clone, Debug, Serialize, SimpleObject, PartialEq, Eq)]
+pub struct ComponentHealth {
+    pub component: String,
+    pub healthy: bool,
+    pub detail: String,
+}
+
+impl ComponentHealth {
+    fn new(component: &str, healthy: bool, detail: String) -> Self {
+        ComponentHealth {
+            component: component.to_string(),
+            healthy,
+            detail,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, SimpleObject)]
+pub struct HealthReport {
+    /// Whether every component is healthy
+    pub healthy: bool,
+    pub components: Vec<ComponentHealth>,
+}
+
+/// Limits past which a component is reported degraded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HealthThresholds {
+    pub min_peers: i64,
+    /// Seconds since the last received message, on any topic
+    pub max_message_age: i64,
+}
+
+impl HealthThresholds {
+    pub fn from_config(config: &Config) -> Self {
+        HealthThresholds {
+            min_peers: config.health_min_peers as i64,
+            max_message_age: config.health_max_message_age as i64,
+        }
+    }
+}
+
+fn peer_health(peers: i64, min_peers: i64) -> ComponentHealth {
+    ComponentHealth::new(
+        "peers",
+        peers >= min_peers,
+        format!("{} gossip peers, {} required", peers, min_peers),
+    )
+}
+
+/// `last_message` is None when nothing was received since `started_at`
+fn message_health(
+    last_message: Option<i64>,
+    started_at: i64,
+    now: i64,
+    max_age: i64,
+) -> ComponentHealth {
+    let age = now - last_message.unwrap_or(started_at);
+    let detail = match last_message {
+        Some(_) => format!("Last message received {}s ago", age),
+        None => format!("No message received in the {}s since startup", age),
+    };
+    ComponentHealth::new("messages", age <= max_age, detail)
+}
+
+fn processor_health(running: bool, queued: i64, last_dequeued: i64, now: i64) -> ComponentHealth {
+    if !running {
+        return ComponentHealth::new("processor", false, "Not running".to_string());
+    }
+    let idle = now - last_dequeued;
+    if queued > 0 && idle > PROCESSOR_STALL_AFTER {
+        return ComponentHealth::new(
+            "processor",
+            false,
+            format!("{} messages queued, none taken in {}s", queued, idle),
+        );
+    }
+    ComponentHealth::new("processor", true, format!("{} messages queued", queued))
+}
+
+async fn database_health(db: &PgPool) -> ComponentHealth {
+    match timeout(DATABASE_CHECK_TIMEOUT, ping(db)).await {
+        Ok(Ok(())) => ComponentHealth::new("database", true, "Connected".to_string()),
+        Ok(Err(e)) => ComponentHealth::new("database", false, e.to_string()),
+        Err(_) => ComponentHealth::new(
+            "database",
+            false,
+            format!("No answer within {}s", DATABASE_CHECK_TIMEOUT.as_secs()),
+        ),
+    }
+}
+
+/// Check every component, peers are read from the gauge the operator loop keeps current
+pub async fn check_health(db: &PgPool, thresholds: HealthThresholds) -> HealthReport {
+    let now = Utc::now().timestamp();
+    let components = vec![
+        database_health(db).await,
+        peer_health(GOSSIP_PEERS.get(), thresholds.min_peers),
+        message_health(
+            TOPIC_ACTIVITY.last_message(),
+            TOPIC_ACTIVITY.started_at(),
+            now,
+            thresholds.max_message_age,
+        ),
+        processor_health(
+            PROCESSOR_RUNNING.load(Ordering::SeqCst),
+            INGEST_QUEUE_DEPTH.get(),
+            LAST_DEQUEUED.load(Ordering::Relaxed),
+            now,
+        ),
+    ];
+    HealthReport {
+        healthy: components.iter().all(|c| c.healthy),
+        components,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_component_health() {
+        assert!(peer_health(3, 1).healthy);
+        assert!(!peer_health(0, 1).healthy);
+
+        assert!(message_health(Some(1000), 0, 1300, 600).healthy);
+        assert!(!message_health(Some(1000), 0, 1700, 600).healthy);
+        let waiting = message_health(None, 1000, 1200, 600);
+        assert!(waiting.healthy, "Startup gets the full window");
+        assert!(waiting.detail.contains("since startup"));
+
+        assert!(!processor_health(false, 0, 0, 1000).healthy);
+        assert!(
+            processor_health(true, 0, 0, 1000).healthy,
+            "An idle processor with an empty queue is fine"
+        );
+        assert!(processor_health(true, 12, 990, 1000).healthy);
+        assert!(!processor_health(true, 12, 900, 1000).healthy);
+    }
+}
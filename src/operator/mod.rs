@@ -1,33 +1,96 @@
-use anyhow::anyhow;
+use chrono::Utc;
 use graphcast_sdk::WakuMessage;
-use sqlx::postgres::PgPoolOptions;
 use sqlx::{Pool, Postgres};
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
 use std::sync::Arc;
-use std::thread::{self, JoinHandle};
-use std::time::Duration;
-use tokio::runtime::Runtime;
-use tokio::time::{interval, sleep, timeout};
-use tracing::{debug, info, trace, warn};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{interval, sleep, timeout, timeout_at};
+use tracing::{debug, debug_span, error, info, trace, warn, Instrument};
 
-use graphcast_sdk::graphcast_agent::{message_typing::GraphcastMessage, GraphcastAgent};
+use graphcast_sdk::graphcast_agent::GraphcastAgent;
 
-use crate::db::resolver::{count_messages, prune_old_messages, retain_max_storage};
-use crate::metrics::{CONNECTED_PEERS, GOSSIP_PEERS, PRUNED_MESSAGES, RECEIVED_MESSAGES};
+use crate::db::resolver::{
+    busiest_identifiers, count_covered_deployments, count_distinct_subgraphs, count_messages,
+    get_indexer_stats, prune_raw_messages,
+};
+use crate::db::views::run_stats_refresh;
+use crate::metrics::{
+    labels::{deployment_label, deployment_labels, set_top_deployments},
+    CONNECTED_PEERS, COVERED_DEPLOYMENTS, DISTINCT_SUBGRAPHS, DUPLICATE_MESSAGES,
+    FILTER_RESUBSCRIPTIONS, GOSSIP_PEERS, IGNORED_MESSAGES, INDEXER_MESSAGES, INDEXER_SUBGRAPHS,
+    INGEST_BANDWIDTH, INGEST_MESSAGE_RATE, INGEST_QUEUE_DEPTH, LAST_PRUNED_MESSAGES,
+    PEERLESS_SECONDS, PIPELINE_STAGE_SECONDS, PROCESSING_TIMEOUTS, PRUNED_MESSAGES, RAW_MESSAGES,
+    RECEIVED_MESSAGES, SAMPLED_OUT_MESSAGES, SILENT_TOPICS, SUBSCRIBED_TOPICS, TOPIC_LAST_MESSAGE,
+    VALIDATED_MESSAGES, WORKER_RESTARTS,
+};
 use crate::{
-    config::Config,
-    db::resolver::add_message,
-    message_types::{PublicPoiMessage, SimpleMessage, UpgradeIntentMessage},
-    metrics::{handle_serve_metrics, ACTIVE_PEERS, CACHED_MESSAGES},
-    server::run_server,
+    archive::{pruned::ArchiveSink, run_cold_storage_job, ColdStorage},
+    config::{Config, DeploymentLabels},
+    consensus::run_divergence_alerts,
+    export::bigquery::{run_bigquery_export, BigQueryExport},
+    metrics::{ACTIVE_PEERS, CACHED_MESSAGES},
+    network::run_network_sync,
+    outbox::{run_outbox_relay, WebhookSink},
+    pipeline::{
+        nonces::{run_nonce_flush, NonceTracker},
+        MessageSource, MessageStore, Pipeline, Validator,
+    },
+    server::{links::ApiLinks, run_server},
+    shutdown::{shutdown_signal, stop},
+    ListenerError,
+};
+
+use self::budget::{
+    record_ingest, sampled_out, set_sampling, take_window, BudgetEvent, BudgetMonitor, IngestBudget,
 };
+use self::capture::capture_until;
+use self::crash::{install_panic_hook, run_crash_reporter};
+use self::discovery::run_dns_discovery;
+use self::duplication::DUPLICATION;
+use self::handoff::{ingesting, run_ingest_handoff, Handoff};
+use self::health::{record_dequeued, set_processor_running};
+use self::node::record_node;
+use self::notifier::{run_notification_retries, Notifier};
+use self::peers::{PeerBackoff, PeerEvent, PeerlessMonitor};
+use self::prune::{prune_excess, prune_expired};
+use self::reciprocity::{checked_boot_nodes, run_peer_checks};
+use self::summary::MonitoringSummary;
+use self::templates::{Alert, AlertKind};
+use self::topics::{scoped_topics, silence_cause, topic_scope, TopicScope, TOPIC_ACTIVITY};
+use self::watchdog::Watchdog;
 
-use self::notifier::Notifier;
+use self::batch::process_batches;
+pub use self::batch::InsertBatching;
+pub use self::builder::{default_pipeline, OperatorError, RadioOperatorBuilder};
 
+pub mod backfill;
+pub mod batch;
+pub mod budget;
+pub mod builder;
+pub mod capture;
+pub mod crash;
+pub mod discovery;
+pub mod duplication;
+pub mod handoff;
+pub mod health;
+pub mod node;
 pub mod notifier;
-pub mod operation;
-pub mod radio_types;
+pub mod peers;
+pub mod prune;
+pub mod reciprocity;
+pub mod summary;
+pub mod templates;
+pub mod topics;
+pub mod watchdog;
+
+/// Seconds between writes of the tracked sender nonces to the database
+const NONCE_FLUSH_INTERVAL: u64 = 60;
 
 /// Radio operator contains all states needed for radio operations
 #[allow(unused)]
@@ -38,6 +101,10 @@ pub struct RadioOperator {
     notifier: Notifier,
     running: Arc<AtomicBool>,
     message_processor_handle: JoinHandle<()>,
+    cold_storage: Option<ColdStorage>,
+    archive: Option<ArchiveSink>,
+    bigquery: Option<BigQueryExport>,
+    nonce_tracker: Option<Arc<NonceTracker>>,
 }
 
 impl RadioOperator {
@@ -48,68 +115,171 @@ impl RadioOperator {
         graphcast_agent: GraphcastAgent,
         receiver: Receiver<WakuMessage>,
     ) -> RadioOperator {
-        let running = Arc::new(AtomicBool::new(true));
-
-        debug!("Set global static instance of graphcast_agent");
-        let graphcast_agent = Arc::new(graphcast_agent);
-        let notifier = Notifier::from_config(&config);
+        RadioOperator::builder(config)
+            .agent(graphcast_agent, receiver)
+            .build()
+            .await
+            .expect("Could not initialize radio operator")
+    }
 
-        debug!("Connecting to database");
+    pub fn builder(config: Config) -> RadioOperatorBuilder {
+        RadioOperatorBuilder::new(config)
+    }
 
-        let db = PgPoolOptions::new()
-            .max_connections(50)
-            .acquire_timeout(Duration::from_secs(3))
-            .connect(&config.database_url)
-            .await
-            .expect("Could not connect to DATABASE_URL");
+    pub fn graphcast_agent(&self) -> &GraphcastAgent {
+        &self.graphcast_agent
+    }
 
-        debug!("Check for database migration");
-        sqlx::migrate!()
-            .run(&db)
-            .await
-            .expect("Could not run migration");
+    /// Export the most active indexers and the distinct subgraph count as gauges, stale
+    /// indexer labels are dropped on every refresh
+    async fn update_aggregate_metrics(&self, update_timeout: Duration) {
+        let namespace = &self.config.instance_namespace;
+        let from_timestamp = Utc::now().timestamp() - 24 * 60 * 60;
 
-        // Set up Prometheus metrics url if configured
-        if let Some(port) = config.metrics_port {
-            debug!("Initializing metrics port");
-            tokio::spawn(handle_serve_metrics(config.metrics_host.clone(), port));
+        match timeout(
+            update_timeout,
+            count_distinct_subgraphs(&self.db, namespace, from_timestamp),
+        )
+        .await
+        {
+            Ok(Ok(count)) => DISTINCT_SUBGRAPHS.set(count),
+            Ok(Err(e)) => warn!(
+                err = tracing::field::debug(e),
+                "Error counting distinct subgraphs"
+            ),
+            Err(e) => debug!(
+                err = tracing::field::debug(e),
+                "Counting distinct subgraphs timed out"
+            ),
         }
 
-        if let Some(true) = config.filter_protocol {
-            // Provide generated topics to Graphcast agent
-            let topics = config.topics.to_vec();
-            debug!(
-                topics = tracing::field::debug(&topics),
-                "Found content topics for subscription",
-            );
-            graphcast_agent.update_content_topics(topics.clone());
+        if deployment_labels() == DeploymentLabels::Top {
+            match timeout(
+                update_timeout,
+                busiest_identifiers(
+                    &self.db,
+                    namespace,
+                    from_timestamp,
+                    self.config.metric_top_deployments as i64,
+                ),
+            )
+            .await
+            {
+                Ok(Ok(top)) => {
+                    for deployment in set_top_deployments(top) {
+                        let _ = VALIDATED_MESSAGES.remove_label_values(&[deployment.as_str()]);
+                        let _ = TOPIC_LAST_MESSAGE.remove_label_values(&[deployment.as_str()]);
+                    }
+                }
+                Ok(Err(e)) => warn!(
+                    err = tracing::field::debug(e),
+                    "Error finding the busiest deployments"
+                ),
+                Err(e) => debug!(
+                    err = tracing::field::debug(e),
+                    "Finding the busiest deployments timed out"
+                ),
+            }
         }
 
-        let message_processor_handle = message_processor(db.clone(), receiver).await;
-        debug!("Initialized Radio Operator");
-        RadioOperator {
-            config,
-            db,
-            graphcast_agent,
-            notifier,
-            running,
-            message_processor_handle,
+        if self.config.stats_top_indexers == 0 {
+            return;
+        }
+        match timeout(
+            update_timeout,
+            get_indexer_stats(&self.db, namespace, None, from_timestamp),
+        )
+        .await
+        {
+            Ok(Ok(mut stats)) => {
+                stats.sort_by_key(|stat| std::cmp::Reverse(stat.message_count));
+                INDEXER_MESSAGES.reset();
+                INDEXER_SUBGRAPHS.reset();
+                for stat in stats.iter().take(self.config.stats_top_indexers) {
+                    INDEXER_MESSAGES
+                        .with_label_values(&[stat.graph_account.as_str()])
+                        .set(stat.message_count);
+                    INDEXER_SUBGRAPHS
+                        .with_label_values(&[stat.graph_account.as_str()])
+                        .set(stat.subgraphs_count);
+                }
+            }
+            Ok(Err(e)) => warn!(
+                err = tracing::field::debug(e),
+                "Error aggregating indexer stats"
+            ),
+            Err(e) => debug!(
+                err = tracing::field::debug(e),
+                "Aggregating indexer stats timed out"
+            ),
         }
     }
 
-    pub fn graphcast_agent(&self) -> &GraphcastAgent {
-        &self.graphcast_agent
+    /// Update the content topics of the agent after a scope switch: the configured scope keeps
+    /// the configured content topics, the all scope clears the topic list to keep every topic
+    fn apply_topic_scope(&self, scope: TopicScope) {
+        let topics = scoped_topics(scope, &self.config.topics);
+        info!(
+            scope = tracing::field::debug(scope),
+            topics = tracing::field::debug(&topics),
+            "Switching topic scope"
+        );
+        self.graphcast_agent.update_content_topics(topics);
     }
 
-    /// Radio operations
-    pub async fn run(&self) {
+    /// Filter subscriptions are dropped silently when the upstream peer goes away or forgets
+    /// them, renew them when there is no peer to serve them or every subscribed topic went quiet
+    fn check_filter_subscriptions(&self) {
+        let dropped = match self.graphcast_agent.connected_peer_count() {
+            Ok(0) => Some("no connected filter peer"),
+            Err(_) => Some("connected peers unavailable"),
+            Ok(_) => {
+                let statuses = TOPIC_ACTIVITY.report(
+                    &self.config.topics,
+                    self.config.topic_silence_threshold,
+                    &self.config.topic_silence_thresholds,
+                    Utc::now().timestamp(),
+                );
+                (silence_cause(&statuses) == Some("subscription"))
+                    .then_some("every subscribed topic is silent")
+            }
+        };
+
+        if let Some(reason) = dropped {
+            warn!(reason, "Filter subscriptions look dropped, re-subscribing");
+            self.graphcast_agent
+                .update_content_topics(self.config.topics.to_vec());
+            FILTER_RESUBSCRIPTIONS.inc();
+        }
+    }
+
+    /// Radio operations, until SIGINT, SIGTERM or the running flag is cleared
+    pub async fn run(self) {
         // Control flow
         let running = self.running.clone();
+        let interrupt = Arc::new(Notify::new());
+        tokio::spawn({
+            let running = running.clone();
+            let interrupt = interrupt.clone();
+            async move {
+                shutdown_signal(running).await;
+                // Wakes the loop in whatever it waits on
+                interrupt.notify_one();
+            }
+        });
         let skip_iteration = Arc::new(AtomicBool::new(false));
         let skip_iteration_clone = skip_iteration.clone();
 
         let mut network_update_interval = interval(Duration::from_secs(600));
         let mut summary_interval = interval(Duration::from_secs(180));
+        let mut budget_interval = interval(Duration::from_secs(60));
+        let mut budget_monitor = BudgetMonitor::new(IngestBudget {
+            max_messages: self.config.max_message_rate,
+            max_bytes: self.config.max_ingest_bandwidth,
+            sustain: self.config.budget_sustain_minutes,
+        });
+        let mut filter_check_interval =
+            interval(Duration::from_secs(self.config.filter_check_interval));
 
         let iteration_timeout = Duration::from_secs(180);
         let update_timeout = Duration::from_secs(5);
@@ -120,33 +290,234 @@ impl RadioOperator {
             skip_iteration_clone.store(true, Ordering::SeqCst);
         });
 
-        // Initialize Http server with graceful shutdown if configured
-        if self.config.server_port().is_some() {
+        // Report panics of any thread or task to the database and the notifiers
+        let crashes = install_panic_hook(self.config.crash_file.clone());
+        tokio::spawn(run_crash_reporter(
+            self.db.clone(),
+            self.config.instance_namespace.clone(),
+            self.notifier.clone(),
+            crashes,
+        ));
+
+        // Record the Waku identity of this listener for static peering
+        record_node(
+            &self.db,
+            &self.config.instance_namespace,
+            &self.graphcast_agent,
+            &self.notifier,
+            self.config.announce_node,
+        )
+        .await;
+
+        // Hold the ingest lock, or take it over from the instance being replaced
+        let agent = Arc::downgrade(&self.graphcast_agent);
+        tokio::spawn(run_ingest_handoff(
+            self.db.clone(),
+            self.config.instance_namespace.clone(),
+            Handoff::from_config(&self.config),
+            move || agent.upgrade().map_or(0, |agent| agent.number_of_peers()),
+        ));
+
+        // Initialize Http server with graceful shutdown if configured, the listener shuts
+        // down when the server cannot be set up
+        let server = self.config.server_port().map(|_| {
             let config = self.config.clone();
             let db = self.db.clone();
-            tokio::spawn(run_server(config, db, running.clone()));
+            let running = running.clone();
+            let interrupt = interrupt.clone();
+            tokio::spawn(async move {
+                if let Err(e) = run_server(config, db, running.clone()).await {
+                    error!(err = e.to_string(), "HTTP server failed, shutting down");
+                    running.store(false, Ordering::SeqCst);
+                    interrupt.notify_one();
+                }
+            })
+        });
+
+        // Move aged messages to the cold tier in the background if configured
+        if let Some(cold_storage) = &self.cold_storage {
+            tokio::spawn(run_cold_storage_job(
+                self.db.clone(),
+                self.config.instance_namespace.clone(),
+                cold_storage.clone(),
+                self.config.cold_storage_age,
+                self.config.archive_before_prune,
+                running.clone(),
+            ));
+        }
+
+        // Relay the change data capture outbox if configured
+        if let Some(url) = &self.config.outbox_webhook {
+            tokio::spawn(run_outbox_relay(
+                self.db.clone(),
+                self.config.instance_namespace.clone(),
+                WebhookSink::new(url.clone()),
+                self.config.outbox_retention,
+                running.clone(),
+            ));
+        }
+
+        // Export new messages to BigQuery if configured
+        if let Some(export) = &self.bigquery {
+            tokio::spawn(run_bigquery_export(
+                self.db.clone(),
+                self.config.instance_namespace.clone(),
+                export.clone(),
+                running.clone(),
+            ));
+        }
+
+        // Dial boot nodes that joined the DNS discovery trees if configured
+        if !self.config.dns_discovery_urls.is_empty() {
+            tokio::spawn(run_dns_discovery(
+                Arc::downgrade(&self.graphcast_agent),
+                self.config.dns_discovery_urls.clone(),
+                Duration::from_secs(self.config.dns_discovery_interval),
+                running.clone(),
+            ));
+        }
+
+        // Check that the boot nodes still keep the connection if configured
+        if let Some(check_interval) = self.config.peer_check_interval {
+            tokio::spawn(run_peer_checks(
+                self.db.clone(),
+                self.config.instance_namespace.clone(),
+                Arc::downgrade(&self.graphcast_agent),
+                self.notifier.clone(),
+                checked_boot_nodes(&self.config),
+                Duration::from_secs(check_interval),
+                running.clone(),
+            ));
+        }
+
+        // Snapshot indexer stake and allocations from the network subgraph if configured
+        if let Some(sync_interval) = self.config.network_sync_interval {
+            tokio::spawn(run_network_sync(
+                self.db.clone(),
+                self.config.network_subgraph.clone(),
+                Duration::from_secs(sync_interval),
+                running.clone(),
+            ));
+        }
+
+        // Persist the latest nonce of every sender
+        if let Some(tracker) = &self.nonce_tracker {
+            tokio::spawn(run_nonce_flush(
+                tracker.clone(),
+                Duration::from_secs(NONCE_FLUSH_INTERVAL),
+                running.clone(),
+            ));
+        }
+
+        // Keep the materialized stats views fresh if configured
+        if let Some(refresh_interval) = self.config.stats_refresh_interval {
+            tokio::spawn(run_stats_refresh(
+                self.db.clone(),
+                Duration::from_secs(refresh_interval),
+                running.clone(),
+            ));
+        }
+
+        // Alert on senders diverging from the POI consensus if configured
+        if let Some(check_interval) = self.config.divergence_check_interval {
+            tokio::spawn(run_divergence_alerts(
+                self.db.clone(),
+                self.config.instance_namespace.clone(),
+                self.config.consensus_strategy,
+                Duration::from_secs(check_interval),
+                self.notifier.clone(),
+                ApiLinks::from_config(&self.config),
+                running.clone(),
+            ));
+        }
+
+        // Retry failed notifications in the background
+        if self.config.notification_retry_max_age > 0 {
+            tokio::spawn(run_notification_retries(
+                self.notifier.clone(),
+                Duration::from_secs(self.config.notification_retry_max_age),
+                running.clone(),
+            ));
         }
 
         // Main loop for sending messages, can factor out
         // and take radio specific query and parsing for radioPayload
+        let mut applied_scope = topic_scope();
+        let mut watchdog =
+            Watchdog::from_env(self.config.heartbeat_file.as_ref().map(PathBuf::from));
+        watchdog.ready();
+        let mut heartbeat_interval = interval(watchdog.interval());
+        let mut peerless = PeerlessMonitor::new(PeerBackoff::from_config(&self.config));
         while running.load(Ordering::SeqCst) {
-            if self.graphcast_agent.number_of_peers() == 0 {
-                info!("No active peers on the network, sleep for 10 seconds");
-                let _ = sleep(Duration::from_secs(10)).await;
+            watchdog.beat();
+            let scope = topic_scope();
+            if scope != applied_scope {
+                self.apply_topic_scope(scope);
+                applied_scope = scope;
+            }
+            let peers = self.graphcast_agent.number_of_peers();
+            GOSSIP_PEERS.set(peers.try_into().unwrap_or_default());
+            if peers == 0 {
+                let (delay, event) = peerless.peerless(Instant::now());
+                PEERLESS_SECONDS.set(peerless.peerless_for(Instant::now()).as_secs() as i64);
+                if let Some(PeerEvent::Lost { minutes }) = event {
+                    warn!(minutes, "No peers on the network for too long");
+                    self.notifier
+                        .clone()
+                        .notify(Alert::new(AlertKind::NoPeers).metric("minutes", minutes))
+                        .await;
+                }
+                info!(
+                    backoff_secs = delay.as_secs(),
+                    "No active peers on the network, backing off"
+                );
+                // Keep signaling liveness, the loop is waiting rather than hanging
+                let resume_at = Instant::now() + delay;
+                while running.load(Ordering::SeqCst) && Instant::now() < resume_at {
+                    let wait = watchdog
+                        .interval()
+                        .min(resume_at.saturating_duration_since(Instant::now()));
+                    tokio::select! {
+                        _ = sleep(wait) => {},
+                        _ = interrupt.notified() => {},
+                    }
+                    watchdog.beat();
+                }
+            } else {
+                PEERLESS_SECONDS.set(0);
+                if let Some(PeerEvent::Recovered { minutes }) = peerless.connected(Instant::now()) {
+                    info!(minutes, "Peers are back");
+                    self.notifier
+                        .clone()
+                        .notify(Alert::new(AlertKind::PeersRecovered).metric("minutes", minutes))
+                        .await;
+                }
             }
             // Run event intervals sequentially by satisfication of other intervals and corresponding tick
             tokio::select! {
+                // Only wakes the loop, the heartbeat is sent at the top of every iteration
+                _ = heartbeat_interval.tick() => {},
+                _ = interrupt.notified() => {},
                 _ = network_update_interval.tick() => {
                     trace!("Network update");
+                    let duplication = DUPLICATION.rotate(Utc::now().timestamp());
+                    debug!(
+                        factor = duplication.factor,
+                        observed = duplication.observed,
+                        unique = duplication.unique,
+                        "Gossip duplication"
+                    );
                     let connection = self.graphcast_agent.network_check();
                     debug!(network_check = tracing::field::debug(&connection), "Network condition");
 
                     // Update the number of peers connected
-                    let connected_peers = self.graphcast_agent.connected_peer_count().unwrap_or_default() as i64;
-                    CONNECTED_PEERS.set(connected_peers);
+                    match self.graphcast_agent.connected_peer_count().map_err(|e| ListenerError::Network(e.to_string())) {
+                        Ok(connected_peers) => CONNECTED_PEERS.set(connected_peers as i64),
+                        Err(e) => debug!(err = e.to_string(), "Could not read connected peer count"),
+                    }
                     GOSSIP_PEERS.set(self.graphcast_agent.number_of_peers().try_into().unwrap_or_default());
 
-                    if let Some(true) = self.config.filter_protocol {
+                    if topic_scope() == TopicScope::Configured {
                         if skip_iteration.load(Ordering::SeqCst) {
                             skip_iteration.store(false, Ordering::SeqCst);
                             continue;
@@ -160,6 +531,43 @@ impl RadioOperator {
                             .set(self.graphcast_agent.number_of_peers().try_into().unwrap());
                     }
                 },
+                _ = filter_check_interval.tick(), if self.config.filter_protocol == Some(true) => {
+                    trace!("Filter subscription check");
+                    self.check_filter_subscriptions();
+                },
+                _ = budget_interval.tick() => {
+                    let (messages, bytes) = take_window();
+                    INGEST_MESSAGE_RATE.set(messages as i64);
+                    INGEST_BANDWIDTH.set(bytes as i64);
+                    match budget_monitor.observe(messages, bytes) {
+                        Some(BudgetEvent::Exceeded { messages, bytes }) => {
+                            warn!(messages, bytes, "Ingest is over budget");
+                            set_sampling(self.config.budget_sample_rate);
+                            let sampling = self.config.budget_sample_rate
+                                .map(|rate| format!(", keeping {}% of the messages until it recovers", rate * 100.0))
+                                .unwrap_or_default();
+                            let alert = Alert::new(AlertKind::BudgetExceeded)
+                                .metric("minutes", self.config.budget_sustain_minutes.into())
+                                .metric("messages", messages as i64)
+                                .metric("bytes", bytes as i64)
+                                .with("sampling", sampling);
+                            let alert = match ApiLinks::from_config(&self.config) {
+                                Some(links) => alert.link(
+                                    "Peer delivery share",
+                                    links.peer_delivery_share(self.config.budget_sustain_minutes.into()),
+                                ),
+                                None => alert,
+                            };
+                            self.notifier.clone().notify(alert).await;
+                        }
+                        Some(BudgetEvent::Recovered) => {
+                            info!("Ingest is back within budget");
+                            set_sampling(None);
+                            self.notifier.clone().notify(Alert::new(AlertKind::BudgetRecovered)).await;
+                        }
+                        None => {}
+                    }
+                },
                 _ = summary_interval.tick() => {
                     trace!("Local summary update");
                     if skip_iteration.load(Ordering::SeqCst) {
@@ -169,97 +577,653 @@ impl RadioOperator {
 
                     let mut total_num_pruned: i64 = 0;
 
+                    // Messages are archived before they are deleted when ARCHIVE_BEFORE_PRUNE or ARCHIVE_URL is set
+                    let archive = self.cold_storage.as_ref().filter(|_| self.config.archive_before_prune);
+                    let sink = self.archive.as_ref();
+                    let namespace = &self.config.instance_namespace;
+
                     // Conditionally prune based on max_storage if provided
                     if let Some(max_storage) = self.config.max_storage {
-                        let max_storage_usize = max_storage as usize;
-                        match timeout(
-                            update_timeout,
-                            retain_max_storage(&self.db, max_storage_usize)
-                        ).await {
+                        let pruning = prune_excess(&self.db, namespace, archive, sink, max_storage as usize);
+                        match timeout(update_timeout, pruning).await {
                             Err(e) => debug!(err = tracing::field::debug(e), "Pruning by max storage timed out"),
                             Ok(Ok(num_pruned)) => {
                                 total_num_pruned += num_pruned;
-                                PRUNED_MESSAGES.set(total_num_pruned);
+                                PRUNED_MESSAGES.with_label_values(&["max_storage"]).inc_by(num_pruned as u64);
                             },
                             Ok(Err(e)) => warn!(err = tracing::field::debug(e), "Error during pruning by max storage"),
                         };
                     }
 
-                    let batch_size = 1000;
-
                     // Always prune old messages based on RETENTION
-                    match timeout(
-                        update_timeout,
-                        prune_old_messages(&self.db, self.config.retention, batch_size)
-                    ).await {
+                    let pruning = prune_expired(&self.db, namespace, archive, sink, self.config.retention);
+                    match timeout(update_timeout, pruning).await {
                         Err(e) => debug!(err = tracing::field::debug(e), "Pruning by retention timed out"),
                         Ok(Ok(num_pruned)) => {
                             total_num_pruned += num_pruned;
-                            PRUNED_MESSAGES.set(total_num_pruned);
+                            PRUNED_MESSAGES.with_label_values(&["retention"]).inc_by(num_pruned as u64);
                         },
                         Ok(Err(e)) => warn!(err = tracing::field::debug(e), "Error during pruning by retention"),
                     };
+                    LAST_PRUNED_MESSAGES.set(total_num_pruned);
+                    if let Err(e) = prune_raw_messages(&self.db, &self.config.instance_namespace, self.config.retention).await {
+                        warn!(err = tracing::field::debug(e), "Error during pruning of raw messages");
+                    }
+
+                    // Deployment coverage of the subscribed topics
+                    SUBSCRIBED_TOPICS.set(self.config.topics.len() as i64);
+                    let from_timestamp = Utc::now().timestamp() - (self.config.coverage_window * 60) as i64;
+                    match timeout(
+                        update_timeout,
+                        count_covered_deployments(&self.db, &self.config.instance_namespace, from_timestamp)
+                    ).await {
+                        Err(e) => debug!(err = tracing::field::debug(e), "Coverage query timed out"),
+                        Ok(Ok(covered)) => COVERED_DEPLOYMENTS.set(covered),
+                        Ok(Err(e)) => warn!(err = tracing::field::debug(e), "Error during coverage query"),
+                    };
+
+                    // Participation over the last day for alerting rules
+                    self.update_aggregate_metrics(update_timeout).await;
+
+                    // Report subscribed topics gone silent
+                    let statuses = TOPIC_ACTIVITY.report(
+                        &self.config.topics,
+                        self.config.topic_silence_threshold,
+                        &self.config.topic_silence_thresholds,
+                        Utc::now().timestamp(),
+                    );
+                    let silent_topics: Vec<&str> = statuses
+                        .iter()
+                        .filter(|s| s.silent)
+                        .map(|s| s.topic.as_str())
+                        .collect();
+                    SILENT_TOPICS.set(silent_topics.len() as i64);
+                    if let Some(cause) = silence_cause(&statuses) {
+                        warn!(
+                            silent_topics = tracing::field::debug(&silent_topics),
+                            likely_cause = cause,
+                            "Subscribed topics went silent"
+                        );
+                    }
 
                     // List the remaining messages
-                    let result = timeout(update_timeout, count_messages(&self.db)).await.expect("could not count messages");
+                    let result = timeout(update_timeout, count_messages(&self.db, &self.config.instance_namespace)).await.expect("could not count messages");
 
                     match result {
                         Err(e) => warn!(err = tracing::field::debug(e), "Database query for message count timed out"),
                         Ok(count) => {
                             CACHED_MESSAGES.set(count);
-                            info!(total_messages = count,
-                                  total_num_pruned,
-                                  "Monitoring summary"
-                            )
+                            let summary = MonitoringSummary::collect(&self.config.instance_namespace, count, total_num_pruned);
+                            summary.log(self.config.summary_log_level);
+                            if let Some(url) = &self.config.summary_webhook {
+                                summary.post(url);
+                            }
                         }
                     }
                 },
                 else => break,
             }
 
-            sleep(Duration::from_secs(5)).await;
+            tokio::select! {
+                _ = sleep(Duration::from_secs(5)) => {},
+                _ = interrupt.notified() => {},
+            }
             continue;
         }
+        watchdog.stopping();
+        self.shutdown(server).await;
     }
+
+    /// Stop receiving and store what was already received within SHUTDOWN_TIMEOUT. The Waku
+    /// node stops first, once the background tasks borrowing it let go, the source then
+    /// closes once empty, so the processor drains its queue and flushes pending batches
+    /// before its workers exit
+    async fn shutdown(self, server: Option<JoinHandle<()>>) {
+        let release = tokio::time::Instant::now() + AGENT_RELEASE_TIMEOUT;
+        match sole_owner(self.graphcast_agent, release).await {
+            Ok(agent) => match agent.stop() {
+                Ok(()) => debug!("Waku node stopped"),
+                Err(e) => warn!(err = e.to_string(), "Could not stop the Waku node"),
+            },
+            Err(_) => warn!("Waku node is still in use, it stops with the process"),
+        }
+        stop();
+
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_secs(self.config.shutdown_timeout);
+        match timeout_at(deadline, self.message_processor_handle).await {
+            Ok(Ok(())) => info!("Received messages are stored"),
+            Ok(Err(e)) => warn!(
+                err = e.to_string(),
+                "Message processor failed while draining"
+            ),
+            Err(_) => warn!(
+                queued = INGEST_QUEUE_DEPTH.get(),
+                "Shutdown timed out, dropping the messages left in the queue"
+            ),
+        }
+        if let Some(tracker) = &self.nonce_tracker {
+            if let Err(e) = tracker.flush().await {
+                warn!(err = e.to_string(), "Failed to persist sender nonces");
+            }
+        }
+        if let Some(server) = server {
+            if timeout_at(deadline, server).await.is_err() {
+                warn!("Shutdown timed out before the open API requests finished");
+            }
+        }
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// Bookkeeping of a received message, returns whether it should go through the pipeline
+async fn receive<V: Validator, S: MessageStore>(
+    pipeline: &Pipeline<V, S>,
+    msg: &WakuMessage,
+    peer: Option<&str>,
+) -> bool {
+    trace!("Message processing");
+    RECEIVED_MESSAGES.inc();
+    let topic = &msg.content_topic().content_topic_name;
+    let received_at = Utc::now().timestamp();
+    TOPIC_ACTIVITY.record(topic, received_at);
+    DUPLICATION.record(topic, msg.payload());
+    TOPIC_LAST_MESSAGE
+        .with_label_values(&[deployment_label(topic, None).as_ref()])
+        .set(received_at);
+    if capture_until().is_some() {
+        if let Err(e) = pipeline.store().store_capture(msg, peer).await {
+            warn!(err = tracing::field::debug(e), "Failed to capture message");
+        }
+    }
+
+    if !ingesting() {
+        trace!("Message dropped, ingest is handed off");
+        return false;
+    }
+
+    record_ingest(msg.payload().len());
+    if sampled_out() {
+        SAMPLED_OUT_MESSAGES.inc();
+        trace!("Message dropped by ingest sampling");
+        return false;
+    }
+    true
 }
 
-pub async fn message_processor(
-    db_ref: Pool<Postgres>,
-    receiver: Receiver<WakuMessage>,
-) -> JoinHandle<()> {
+/// Act on the result of processing a message, `Err` holding the timeout error when
+/// processing timed out
+async fn handle_outcome<V: Validator, S: MessageStore>(
+    pipeline: &Pipeline<V, S>,
+    msg: &WakuMessage,
+    peer: Option<&str>,
+    process_res: Result<Result<Option<i64>, ListenerError>, String>,
+) {
+    match process_res {
+        Ok(Ok(Some(r))) => trace!(msg_row_id = r, "New message added to DB"),
+        Ok(Ok(None)) => {
+            DUPLICATE_MESSAGES.inc();
+            trace!("Message already stored, skipped duplicate");
+        }
+        // Undecodable messages usually belong to other radios sharing the topic, keep them raw
+        Ok(Err(e @ ListenerError::Decode(_))) => {
+            trace!(err = tracing::field::debug(&e), "Failed to process message");
+            match pipeline.store().store_raw(msg, peer).await {
+                Ok(()) => RAW_MESSAGES.inc(),
+                Err(e) => warn!(
+                    err = tracing::field::debug(e),
+                    "Failed to store raw message"
+                ),
+            }
+        }
+        Ok(Err(ListenerError::Ignored(message_type))) => {
+            IGNORED_MESSAGES
+                .with_label_values(&[message_type.as_str()])
+                .inc();
+            trace!(message_type, "Message type is not stored, skipped");
+        }
+        Ok(Err(e @ ListenerError::Validation(_))) => {
+            debug!(
+                err = e.to_string(),
+                "Message failed validation, quarantining message in dead letters"
+            );
+            if let Err(e) = pipeline
+                .store()
+                .store_dead_letter(msg, e.kind(), &e.to_string())
+                .await
+            {
+                warn!(
+                    err = tracing::field::debug(e),
+                    "Failed to store dead letter"
+                );
+            }
+        }
+        Ok(Err(e)) => {
+            warn!(
+                err = tracing::field::debug(&e),
+                kind = e.kind(),
+                "Failed to process message"
+            );
+        }
+        Err(e) => {
+            PROCESSING_TIMEOUTS.inc();
+            debug!(
+                error = e,
+                content_topic = msg.content_topic().to_string(),
+                "Message processor timed out, moving message to dead letters"
+            );
+            if let Err(e) = pipeline.store().store_dead_letter(msg, "timeout", &e).await {
+                warn!(
+                    err = tracing::field::debug(e),
+                    "Failed to store dead letter"
+                );
+            }
+        }
+    }
+}
+
+/// Received messages waiting for a processing worker
+const INGEST_QUEUE_CAPACITY: usize = 4096;
+
+type Delivery = (WakuMessage, Option<String>);
+/// Deliveries with the time they were queued
+type DeliveryQueue = Arc<Mutex<mpsc::Receiver<(Delivery, Instant)>>>;
+
+/// How received messages are processed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProcessorSettings {
+    /// Time budget for processing a message, or a batch of messages
+    pub timeout: Duration,
+    /// Concurrent processing tasks, messages may be stored out of order with more than one
+    pub workers: usize,
+    /// Store messages in batches instead of one by one
+    pub batching: Option<InsertBatching>,
+}
+
+impl ProcessorSettings {
+    /// One worker storing messages one by one
+    pub fn new(timeout: Duration) -> Self {
+        ProcessorSettings {
+            timeout,
+            workers: 1,
+            batching: None,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        ProcessorSettings {
+            timeout: Duration::from_millis(config.processing_timeout),
+            workers: config.processing_workers.max(1),
+            batching: InsertBatching::from_config(config),
+        }
+    }
+}
+
+/// Pull messages from the blocking source on a dedicated thread into the queue of the
+/// processing workers, the queue closes along with the source
+fn spawn_source_reader<M: MessageSource>(mut source: M) -> DeliveryQueue {
+    let (sender, receiver) = mpsc::channel(INGEST_QUEUE_CAPACITY);
     thread::spawn(move || {
-        let rt = Runtime::new().expect("Could not create Tokio runtime");
-        let db_ref_rt = db_ref.clone();
-        for msg in receiver {
-            rt.block_on(async {
-                trace!("Message processing");
-                RECEIVED_MESSAGES.inc();
-                let timeout_duration = Duration::from_secs(1);
-                let process_res = timeout(timeout_duration, process_message(&db_ref_rt, msg)).await;
-                match process_res {
-                    Ok(Ok(r)) => trace!(msg_row_id = r, "New message added to DB"),
-                    Ok(Err(e)) => {
-                        trace!(err = tracing::field::debug(&e), "Failed to process message");
-                    }
-                    Err(e) => debug!(error = e.to_string(), "Message processor timed out"),
-                }
-            });
+        while let Some(delivery) = source.next_delivery() {
+            INGEST_QUEUE_DEPTH.inc();
+            if sender.blocking_send((delivery, Instant::now())).is_err() {
+                INGEST_QUEUE_DEPTH.dec();
+                break;
+            }
         }
-    })
+    });
+    Arc::new(Mutex::new(receiver))
+}
+
+/// Next queued message, None once the source closed and the queue is drained
+async fn next_delivery(queue: &DeliveryQueue) -> Option<Delivery> {
+    let (delivery, queued_at) = queue.lock().await.recv().await?;
+    INGEST_QUEUE_DEPTH.dec();
+    PIPELINE_STAGE_SECONDS
+        .with_label_values(&["queue"])
+        .observe(queued_at.elapsed().as_secs_f64());
+    record_dequeued(Utc::now().timestamp());
+    Some(delivery)
 }
 
-pub async fn process_message(db: &Pool<Postgres>, msg: WakuMessage) -> Result<i64, anyhow::Error> {
-    if let Ok(msg) = GraphcastMessage::<PublicPoiMessage>::decode(msg.payload()) {
-        add_message(db, msg).await
-    } else if let Ok(msg) = GraphcastMessage::<UpgradeIntentMessage>::decode(msg.payload()) {
-        add_message(db, msg).await
-    } else if let Ok(msg) = GraphcastMessage::<SimpleMessage>::decode(msg.payload()) {
-        add_message(db, msg).await
-    } else {
-        trace!(
-            topic = tracing::field::debug(msg.content_topic()),
-            "Message decode failed"
+async fn process_messages<V: Validator, S: MessageStore>(
+    queue: DeliveryQueue,
+    pipeline: Arc<Pipeline<V, S>>,
+    processing_timeout: Duration,
+) {
+    while let Some((msg, peer)) = next_delivery(&queue).await {
+        if !receive(&pipeline, &msg, peer.as_deref()).await {
+            continue;
+        }
+
+        let started = Instant::now();
+        let span = debug_span!(
+            "message",
+            content_topic = msg.content_topic().to_string(),
+            peer = peer.as_deref()
         );
-        Err(anyhow!("Unsupported message types"))
+        let process_res = timeout(
+            processing_timeout,
+            pipeline.process_from(&msg, peer.as_deref()),
+        )
+        .instrument(span)
+        .await;
+        let elapsed = started.elapsed();
+        if elapsed > processing_timeout / 2 {
+            debug!(
+                content_topic = msg.content_topic().to_string(),
+                elapsed_ms = elapsed.as_millis() as u64,
+                timeout_ms = processing_timeout.as_millis() as u64,
+                "Slow message processing"
+            );
+        }
+
+        let process_res = process_res.map_err(|e| e.to_string());
+        handle_outcome(&pipeline, &msg, peer.as_deref(), process_res).await;
+    }
+}
+
+/// Run the ingest pipeline on worker tasks of the current Tokio runtime until the source
+/// closes. The blocking source is read on a dedicated thread into a bounded queue, so slow
+/// writes only hold up the Waku receiver once the queue is full
+pub fn message_processor<M, V, S>(
+    source: M,
+    pipeline: Arc<Pipeline<V, S>>,
+    settings: ProcessorSettings,
+) -> JoinHandle<()>
+where
+    M: MessageSource,
+    V: Validator + 'static,
+    S: MessageStore + 'static,
+{
+    let queue = spawn_source_reader(source);
+    set_processor_running(true);
+    let spawn_worker = move |workers: &mut JoinSet<()>| match settings.batching {
+        Some(batching) => {
+            workers.spawn(process_batches(
+                queue.clone(),
+                pipeline.clone(),
+                settings.timeout,
+                batching,
+            ));
+        }
+        None => {
+            workers.spawn(process_messages(
+                queue.clone(),
+                pipeline.clone(),
+                settings.timeout,
+            ));
+        }
+    };
+    tokio::spawn(async move {
+        let mut workers = JoinSet::new();
+        for _ in 0..settings.workers.max(1) {
+            spawn_worker(&mut workers);
+        }
+        // Workers end once the source closed and the queue is drained, a panicking worker is
+        // replaced so the queue keeps being processed
+        let mut restarts = WorkerRestarts::default();
+        while let Some(result) = workers.join_next().await {
+            let Err(e) = result else {
+                continue;
+            };
+            if !e.is_panic() {
+                continue;
+            }
+            if !restarts.allow(Instant::now()) {
+                error!(
+                    restarts = MAX_WORKER_RESTARTS,
+                    "Message processing workers keep panicking, exiting"
+                );
+                set_processor_running(false);
+                std::process::exit(1);
+            }
+            WORKER_RESTARTS.inc();
+            warn!(
+                err = e.to_string(),
+                "Message processing worker panicked, restarting it"
+            );
+            spawn_worker(&mut workers);
+        }
+        set_processor_running(false);
+    })
+}
+
+/// Panicking workers restarted within [`WORKER_RESTART_WINDOW`] before the listener exits,
+/// leaving the restart to the process supervisor
+/// Time the background tasks get to let go of the agent on shutdown, a boot node dial or a
+/// store query in flight keeps it borrowed
+const AGENT_RELEASE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Take `shared` back once every other owner dropped it, tasks only upgrading a `Weak` for
+/// a single call. Gives it back as is when still shared at `deadline`
+async fn sole_owner<T>(mut shared: Arc<T>, deadline: tokio::time::Instant) -> Result<T, Arc<T>> {
+    loop {
+        shared = match Arc::try_unwrap(shared) {
+            Ok(owned) => return Ok(owned),
+            Err(shared) if tokio::time::Instant::now() >= deadline => return Err(shared),
+            Err(shared) => shared,
+        };
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+const MAX_WORKER_RESTARTS: usize = 10;
+const WORKER_RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+/// Restart times of the processing workers within the last window
+#[derive(Debug, Default)]
+struct WorkerRestarts(VecDeque<Instant>);
+
+impl WorkerRestarts {
+    /// Whether a worker may be restarted at `now`, recording the restart when it may
+    fn allow(&mut self, now: Instant) -> bool {
+        while self
+            .0
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) > WORKER_RESTART_WINDOW)
+        {
+            self.0.pop_front();
+        }
+        if self.0.len() >= MAX_WORKER_RESTARTS {
+            return false;
+        }
+        self.0.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_types::PublicPoiMessage;
+    use crate::pipeline::{AcceptAll, MessageTypes, RadioMessage};
+    use graphcast_sdk::graphcast_agent::message_typing::GraphcastMessage;
+    use prost::Message;
+    use std::str::FromStr;
+    use std::sync::Mutex as SyncMutex;
+    use waku_bindings::WakuContentTopic;
+
+    /// Delivers the queued messages, then closes
+    struct StubSource(VecDeque<WakuMessage>);
+
+    impl MessageSource for StubSource {
+        fn next_message(&mut self) -> Option<WakuMessage> {
+            self.0.pop_front()
+        }
+    }
+
+    /// Nonce of messages the stub store panics on
+    const PANIC_NONCE: i64 = 1707328666;
+
+    /// Keeps stored messages in memory, skipping payloads it already stored
+    #[derive(Default)]
+    struct StubStore {
+        stored: SyncMutex<Vec<serde_json::Value>>,
+        raw: SyncMutex<usize>,
+        dead_letters: SyncMutex<Vec<String>>,
+    }
+
+    impl MessageStore for StubStore {
+        async fn store(&self, message: RadioMessage) -> Result<Option<i64>, ListenerError> {
+            if message.sender().map(|(_, nonce)| nonce) == Some(PANIC_NONCE) {
+                panic!("Stub store failure");
+            }
+            let mut stored = self.stored.lock().unwrap();
+            let json = message.stored_json();
+            if stored.contains(&json) {
+                return Ok(None);
+            }
+            stored.push(json);
+            Ok(Some(stored.len() as i64))
+        }
+
+        async fn store_dead_letter(
+            &self,
+            _msg: &WakuMessage,
+            category: &str,
+            _error: &str,
+        ) -> Result<i64, ListenerError> {
+            let mut dead_letters = self.dead_letters.lock().unwrap();
+            dead_letters.push(category.to_string());
+            Ok(dead_letters.len() as i64)
+        }
+
+        async fn store_raw(
+            &self,
+            _msg: &WakuMessage,
+            _peer: Option<&str>,
+        ) -> Result<(), ListenerError> {
+            *self.raw.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    fn waku_message(payload: Vec<u8>) -> WakuMessage {
+        let topic = WakuContentTopic::from_str("/graphcast/0/QmTamam/proto").unwrap();
+        WakuMessage::new(payload, topic, 0, 0, Vec::<u8>::new(), false)
+    }
+
+    fn poi_payload(nonce: u64, content: &str) -> Vec<u8> {
+        let poi = PublicPoiMessage {
+            identifier: "QmTamam".to_string(),
+            content: content.to_string(),
+            nonce,
+            network: "goerli".to_string(),
+            block_number: nonce,
+            block_hash: "hash".to_string(),
+            graph_account: "0xb4b4".to_string(),
+        };
+        GraphcastMessage::new(
+            "QmTamam".to_string(),
+            nonce,
+            "0xb4b4".to_string(),
+            poi,
+            "0xsig".to_string(),
+        )
+        .unwrap()
+        .encode_to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_message_processor() {
+        let poi = format!("0x{:064x}", 1);
+        let source = StubSource(VecDeque::from([
+            waku_message(poi_payload(1707328517, &poi)),
+            waku_message(poi_payload(1707328517, &poi)),
+            waku_message(b"not a graphcast message".to_vec()),
+            waku_message(poi_payload(1707328518, "not a poi")),
+        ]));
+        let pipeline = Arc::new(Pipeline::new(
+            MessageTypes::default(),
+            AcceptAll,
+            StubStore::default(),
+        ));
+
+        message_processor(
+            source,
+            pipeline.clone(),
+            ProcessorSettings::new(Duration::from_secs(5)),
+        )
+        .await
+        .unwrap();
+
+        // The duplicate is skipped, the undecodable payload kept raw and the invalid POI
+        // quarantined
+        let store = pipeline.store();
+        let stored = store.stored.lock().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0]["payload"]["content"], poi);
+        assert_eq!(*store.raw.lock().unwrap(), 1);
+        assert_eq!(*store.dead_letters.lock().unwrap(), vec!["validation"]);
+    }
+
+    #[tokio::test]
+    async fn test_message_processor_restarts_panicking_workers() {
+        let poi = format!("0x{:064x}", 1);
+        let source = StubSource(VecDeque::from([
+            waku_message(poi_payload(PANIC_NONCE as u64, &poi)),
+            waku_message(poi_payload(1707328517, &poi)),
+        ]));
+        let pipeline = Arc::new(Pipeline::new(
+            MessageTypes::default(),
+            AcceptAll,
+            StubStore::default(),
+        ));
+        let restarts = WORKER_RESTARTS.get();
+
+        message_processor(
+            source,
+            pipeline.clone(),
+            ProcessorSettings::new(Duration::from_secs(5)),
+        )
+        .await
+        .unwrap();
+
+        // The worker taking the panicking message is replaced and processes the rest
+        assert_eq!(pipeline.store().stored.lock().unwrap().len(), 1);
+        assert!(WORKER_RESTARTS.get() > restarts);
+    }
+
+    #[test]
+    fn test_worker_restarts() {
+        let mut restarts = WorkerRestarts::default();
+        let start = Instant::now();
+        for _ in 0..MAX_WORKER_RESTARTS {
+            assert!(restarts.allow(start));
+        }
+        assert!(!restarts.allow(start + Duration::from_secs(30)));
+        // Restarts older than the window no longer count
+        assert!(restarts.allow(start + WORKER_RESTART_WINDOW + Duration::from_secs(1)));
+    }
+
+    #[tokio::test]
+    async fn test_sole_owner() {
+        let node = Arc::new(AtomicBool::new(true));
+        // A peer count reader and a dial in flight, like the handoff and boot node checks
+        let peers = Arc::downgrade(&node);
+        let reader = tokio::spawn(async move {
+            while let Some(node) = peers.upgrade() {
+                let _ = node.load(Ordering::SeqCst);
+                drop(node);
+                sleep(Duration::from_millis(10)).await;
+            }
+        });
+        let dial = node.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(100)).await;
+            drop(dial);
+        });
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        let node = sole_owner(node, deadline).await.unwrap();
+        node.store(false, Ordering::SeqCst);
+        reader.await.unwrap();
+
+        // An owner that never lets go is given up on at the deadline
+        let node = Arc::new(AtomicBool::new(true));
+        let _held = node.clone();
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(100);
+        assert!(sole_owner(node, deadline).await.is_err());
     }
 }
@@ -0,0 +1,109 @@
+//! Waku identity of this listener. The peer id and listen addresses of the node are read once
+//! the agent started it, recorded in `listener_nodes`, reported by `/info` and the `node`
+//! query, and with ANNOUNCE_NODE sent to the notifiers, so other operators can add the
+//! listener as a static peer
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use graphcast_sdk::graphcast_agent::GraphcastAgent;
+
+use super::{
+    notifier::Notifier,
+    templates::{Alert, AlertKind},
+};
+use crate::db::resolver::{upsert_listener_node, ListenerNode};
+
+static NODE: OnceCell<ListenerNode> = OnceCell::new();
+
+/// Node of this listener, None until it is recorded
+pub fn local_node() -> Option<&'static ListenerNode> {
+    NODE.get()
+}
+
+/// Listen address with the peer id appended, as static peer lists expect
+fn dialable(address: &str, peer_id: &str) -> String {
+    if address.contains("/p2p/") {
+        address.to_string()
+    } else {
+        format!("{}/p2p/{}", address, peer_id)
+    }
+}
+
+fn read_node(agent: &GraphcastAgent) -> Option<ListenerNode> {
+    let peer_id = match agent.node_handle.peer_id() {
+        Ok(peer_id) => peer_id.to_string(),
+        Err(e) => {
+            warn!(err = e.to_string(), "Could not read the Waku peer id");
+            return None;
+        }
+    };
+    let multiaddrs = match agent.node_handle.listen_addresses() {
+        Ok(addresses) => addresses
+            .iter()
+            .map(|address| dialable(&address.to_string(), &peer_id))
+            .collect(),
+        Err(e) => {
+            warn!(
+                err = e.to_string(),
+                "Could not read the Waku listen addresses"
+            );
+            vec![]
+        }
+    };
+    Some(ListenerNode {
+        peer_id,
+        multiaddrs,
+        started_at: Utc::now().timestamp(),
+    })
+}
+
+/// Record the node of this listener, announcing it when `announce` is set
+pub async fn record_node(
+    db: &PgPool,
+    namespace: &str,
+    agent: &GraphcastAgent,
+    notifier: &Notifier,
+    announce: bool,
+) {
+    let Some(node) = read_node(agent) else {
+        return;
+    };
+    info!(
+        peer_id = node.peer_id,
+        multiaddrs = tracing::field::debug(&node.multiaddrs),
+        "Waku node started"
+    );
+    if let Err(e) = upsert_listener_node(db, namespace, &node).await {
+        warn!(err = e.to_string(), "Could not record the listener node");
+    }
+    if announce {
+        notifier
+            .clone()
+            .notify(
+                Alert::new(AlertKind::NodeStarted)
+                    .with("peer_id", &node.peer_id)
+                    .with("multiaddrs", node.multiaddrs.join(", ")),
+            )
+            .await;
+    }
+    let _ = NODE.set(node);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dialable() {
+        assert_eq!(
+            dialable("/ip4/10.0.0.1/tcp/60000", "16Uiu2HAm"),
+            "/ip4/10.0.0.1/tcp/60000/p2p/16Uiu2HAm"
+        );
+        assert_eq!(
+            dialable("/ip4/10.0.0.1/tcp/60000/p2p/16Uiu2HAm", "16Uiu2HAm"),
+            "/ip4/10.0.0.1/tcp/60000/p2p/16Uiu2HAm"
+        );
+    }
+}
@@ -1,18 +1,89 @@
+use chrono::Utc;
 use derive_getters::Getters;
 use graphcast_sdk::bots::{DiscordBot, SlackBot, TelegramBot};
 
 use serde_derive::{Deserialize, Serialize};
-use tracing::warn;
+use sqlx::{Pool, Postgres};
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::time::interval;
+use tracing::{info, warn};
 
-use crate::{config::Config, radio_name};
+use crate::{
+    config::Config,
+    db::resolver::{
+        delete_notification, due_notifications, enqueue_notification, expire_notifications,
+        notification_template, reschedule_notification,
+    },
+    metrics::{NOTIFICATION_ATTEMPTS, NOTIFICATION_DELIVERIES, NOTIFICATION_LATENCY},
+    operator::templates::{Alert, TemplateFile},
+    radio_name,
+};
 
-#[derive(Clone, Debug, Getters, Serialize, Deserialize, PartialEq)]
+const SLACK: &str = "slack";
+const DISCORD: &str = "discord";
+const TELEGRAM: &str = "telegram";
+const WEBHOOK: &str = "webhook";
+
+/// Time allowed for the generic webhook to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Seconds before the first retry of a failed delivery, doubled after every further failure
+const RETRY_BASE_DELAY: i64 = 30;
+const RETRY_MAX_DELAY: i64 = 60 * 60;
+/// Queued deliveries retried per tick
+const RETRY_BATCH: i64 = 100;
+
+/// Channel a notification is delivered to
+#[derive(async_graphql::Enum, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum NotificationChannel {
+    Slack,
+    Discord,
+    Telegram,
+    /// Generic webhook receiving structured JSON
+    Webhook,
+}
+
+impl NotificationChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationChannel::Slack => SLACK,
+            NotificationChannel::Discord => DISCORD,
+            NotificationChannel::Telegram => TELEGRAM,
+            NotificationChannel::Webhook => WEBHOOK,
+        }
+    }
+}
+
+/// Database the notifier reads templates from and, with retries enabled, queues failed
+/// deliveries in
+#[derive(Clone, Debug)]
+struct NotifierStore {
+    db: Pool<Postgres>,
+    namespace: String,
+    retries: bool,
+}
+
+#[derive(Clone, Debug, Getters, Serialize, Deserialize)]
 pub struct Notifier {
     radio_name: String,
     slack_webhook: Option<String>,
     discord_webhook: Option<String>,
     telegram_token: Option<String>,
     telegram_chat_id: Option<i64>,
+    webhook_url: Option<String>,
+    #[getter(skip)]
+    #[serde(skip)]
+    templates: TemplateFile,
+    #[getter(skip)]
+    #[serde(skip)]
+    store: Option<NotifierStore>,
 }
 
 impl Notifier {
@@ -29,6 +100,9 @@ impl Notifier {
             discord_webhook,
             telegram_token,
             telegram_chat_id,
+            webhook_url: None,
+            templates: TemplateFile::default(),
+            store: None,
         }
     }
 
@@ -39,46 +113,267 @@ impl Notifier {
         let telegram_token = config.telegram_token.clone();
         let telegram_chat_id = config.telegram_chat_id;
 
-        Notifier::new(
+        let notifier = Notifier::new(
             radio_name,
             slack_webhook,
             discord_webhook,
             telegram_token,
             telegram_chat_id,
-        )
+        );
+        match &config.webhook_url {
+            Some(url) => notifier.with_webhook(url.clone()),
+            None => notifier,
+        }
     }
 
-    pub async fn notify(self, content: String) {
-        if let Some(url) = &self.slack_webhook {
-            if let Err(e) = SlackBot::send_webhook(url, &self.radio_name, &content).await {
-                warn!(
+    /// Also post alerts as JSON to a generic webhook
+    pub fn with_webhook(mut self, url: String) -> Self {
+        self.webhook_url = Some(url);
+        self
+    }
+
+    /// Use templates stored for `namespace`, and with `retries` queue deliveries that fail
+    /// for [`run_notification_retries`]
+    pub fn with_database(mut self, db: Pool<Postgres>, namespace: String, retries: bool) -> Self {
+        self.store = Some(NotifierStore {
+            db,
+            namespace,
+            retries,
+        });
+        self
+    }
+
+    /// Templates used when none is stored in the database
+    pub fn with_templates(mut self, templates: TemplateFile) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// Message of `alert` for `channel` followed by its links, or wrapped in a JSON payload
+    /// with the links for the generic webhook
+    async fn render(&self, alert: &Alert, channel: &str) -> String {
+        let message = self.render_message(alert, channel).await;
+        if channel == WEBHOOK {
+            return alert.webhook_payload(&self.radio_name, message);
+        }
+        message + &alert.link_lines()
+    }
+
+    async fn render_message(&self, alert: &Alert, channel: &str) -> String {
+        if let Some(store) = &self.store {
+            match notification_template(&store.db, &store.namespace, alert.kind.name(), channel)
+                .await
+            {
+                Ok(Some(template)) => return alert.render(Some(&template)),
+                Ok(None) => {}
+                Err(e) => warn!(
                     err = tracing::field::debug(e),
-                    "Failed to send notification to Slack"
-                );
+                    "Failed to load notification template, using the configured one"
+                ),
             }
         }
+        alert.render(self.templates.get(alert.kind, channel))
+    }
 
-        if let Some(webhook_url) = self.discord_webhook.clone() {
-            if let Err(e) = DiscordBot::send_webhook(&webhook_url, &self.radio_name, &content).await
-            {
+    /// Configured channels
+    fn channels(&self) -> Vec<&'static str> {
+        let mut channels = vec![];
+        if self.slack_webhook.is_some() {
+            channels.push(SLACK);
+        }
+        if self.discord_webhook.is_some() {
+            channels.push(DISCORD);
+        }
+        if self.telegram_token.is_some() && self.telegram_chat_id.is_some() {
+            channels.push(TELEGRAM);
+        }
+        if self.webhook_url.is_some() {
+            channels.push(WEBHOOK);
+        }
+        channels
+    }
+
+    /// Deliver `content` to one channel, returns whether it was delivered
+    async fn send(&self, channel: &str, content: &str) -> bool {
+        let started = Instant::now();
+        match channel {
+            SLACK => match &self.slack_webhook {
+                Some(url) => record_delivery(
+                    channel,
+                    started,
+                    SlackBot::send_webhook(url, &self.radio_name, content).await,
+                ),
+                None => false,
+            },
+            DISCORD => match &self.discord_webhook {
+                Some(url) => record_delivery(
+                    channel,
+                    started,
+                    DiscordBot::send_webhook(url, &self.radio_name, content).await,
+                ),
+                None => false,
+            },
+            TELEGRAM => match (self.telegram_token.clone(), self.telegram_chat_id) {
+                (Some(token), Some(chat_id)) => record_delivery(
+                    channel,
+                    started,
+                    TelegramBot::new(token)
+                        .send_message(chat_id, &self.radio_name, content)
+                        .await,
+                ),
+                _ => false,
+            },
+            WEBHOOK => match &self.webhook_url {
+                Some(url) => record_delivery(channel, started, post_json(url, content).await),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    pub async fn notify(self, alert: Alert) {
+        for channel in self.channels() {
+            let content = self.render(&alert, channel).await;
+            if self.send(channel, &content).await {
+                continue;
+            }
+            if let Some(queue) = self.store.as_ref().filter(|store| store.retries) {
+                let next_attempt_at = Utc::now().timestamp() + retry_delay(1);
+                if let Err(e) = enqueue_notification(
+                    &queue.db,
+                    &queue.namespace,
+                    channel,
+                    &content,
+                    next_attempt_at,
+                )
+                .await
+                {
+                    warn!(
+                        err = tracing::field::debug(e),
+                        channel, "Failed to queue notification for retry"
+                    );
+                }
+            }
+        }
+    }
+}
+
+async fn post_json(url: &str, body: &str) -> Result<(), reqwest::Error> {
+    reqwest::Client::new()
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .timeout(WEBHOOK_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Seconds to wait before the next attempt of a delivery that failed `attempts` times
+fn retry_delay(attempts: i32) -> i64 {
+    let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    (RETRY_BASE_DELAY << doublings).min(RETRY_MAX_DELAY)
+}
+
+/// Count a delivery attempt to `channel` with its outcome and latency, logging failures.
+/// Returns whether the delivery succeeded
+fn record_delivery<T, E: Debug>(channel: &str, started: Instant, result: Result<T, E>) -> bool {
+    NOTIFICATION_ATTEMPTS.with_label_values(&[channel]).inc();
+    NOTIFICATION_LATENCY
+        .with_label_values(&[channel])
+        .observe(started.elapsed().as_secs_f64());
+    let outcome = match &result {
+        Ok(_) => "succeeded",
+        Err(e) => {
+            warn!(
+                err = tracing::field::debug(e),
+                channel, "Failed to send notification"
+            );
+            "failed"
+        }
+    };
+    NOTIFICATION_DELIVERIES
+        .with_label_values(&[channel, outcome])
+        .inc();
+    result.is_ok()
+}
+
+/// Retry queued deliveries of the notifier as they become due, with exponential backoff.
+/// Deliveries still failing `max_age` after they were first sent are dropped
+pub async fn run_notification_retries(
+    notifier: Notifier,
+    max_age: Duration,
+    running: Arc<AtomicBool>,
+) {
+    let Some(queue) = notifier.store.clone().filter(|store| store.retries) else {
+        return;
+    };
+    let mut retry_interval = interval(Duration::from_secs(RETRY_BASE_DELAY as u64));
+
+    while running.load(Ordering::SeqCst) {
+        retry_interval.tick().await;
+        let now = Utc::now().timestamp();
+        match expire_notifications(&queue.db, &queue.namespace, now - max_age.as_secs() as i64)
+            .await
+        {
+            Ok(0) => {}
+            Ok(expired) => warn!(
+                expired,
+                "Dropped notifications that could not be delivered within the retry max age"
+            ),
+            Err(e) => warn!(
+                err = tracing::field::debug(e),
+                "Failed to expire queued notifications"
+            ),
+        }
+
+        let due = match due_notifications(&queue.db, &queue.namespace, now, RETRY_BATCH).await {
+            Ok(due) => due,
+            Err(e) => {
                 warn!(
                     err = tracing::field::debug(e),
-                    "Failed to send notification to Discord"
+                    "Failed to load queued notifications"
                 );
+                continue;
             }
-        }
-
-        if let (Some(token), Some(chat_id)) = (self.telegram_token.clone(), self.telegram_chat_id) {
-            let telegram_bot = TelegramBot::new(token);
-            if let Err(e) = telegram_bot
-                .send_message(chat_id, &self.radio_name, &content)
+        };
+        for notification in due {
+            let result = if notifier
+                .send(&notification.channel, &notification.content)
                 .await
             {
+                info!(
+                    channel = notification.channel,
+                    attempts = notification.attempts + 1,
+                    "Delivered queued notification"
+                );
+                delete_notification(&queue.db, notification.id).await
+            } else {
+                let next_attempt_at = now + retry_delay(notification.attempts + 1);
+                reschedule_notification(&queue.db, notification.id, next_attempt_at).await
+            };
+            if let Err(e) = result {
                 warn!(
                     err = tracing::field::debug(e),
-                    "Failed to send notification to Telegram"
+                    id = notification.id,
+                    "Failed to update queued notification"
                 );
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), 30);
+        assert_eq!(retry_delay(2), 60);
+        assert_eq!(retry_delay(4), 240);
+        assert_eq!(retry_delay(8), RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(i32::MAX), RETRY_MAX_DELAY);
+    }
+}
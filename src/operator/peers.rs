@@ -0,0 +1,151 @@
+//! Backoff of the operator loop while the listener has no peers. Checks are spaced out
+//! exponentially with jitter during long network outages, and an alert is raised once the
+//! outage lasts longer than NO_PEER_ALERT_AFTER
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// Outage length before alerting, None never alerts
+    pub alert_after: Option<Duration>,
+}
+
+impl PeerBackoff {
+    pub fn from_config(config: &Config) -> Self {
+        PeerBackoff {
+            initial: Duration::from_secs(config.no_peer_backoff.max(1)),
+            max: Duration::from_secs(config.no_peer_backoff_max.max(config.no_peer_backoff)),
+            alert_after: config.no_peer_alert_after.map(Duration::from_secs),
+        }
+    }
+
+    /// Delay before the check following `attempts` consecutive peerless ones, doubling up to
+    /// the maximum, plus up to a quarter of it so restarted listeners do not retry in step
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let base = self
+            .initial
+            .saturating_mul(1 << attempts.min(16))
+            .min(self.max);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        base + base.mul_f64(f64::from(nanos % 1000) / 4000.0)
+    }
+}
+
+/// Change of the peer state that is alerted on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// Without peers for longer than the alert threshold
+    Lost { minutes: i64 },
+    /// Peers are back after an alerted outage
+    Recovered { minutes: i64 },
+}
+
+/// Tracks the ongoing outage, if any
+#[derive(Debug)]
+pub struct PeerlessMonitor {
+    backoff: PeerBackoff,
+    attempts: u32,
+    since: Option<Instant>,
+    alerted: bool,
+}
+
+impl PeerlessMonitor {
+    pub fn new(backoff: PeerBackoff) -> Self {
+        PeerlessMonitor {
+            backoff,
+            attempts: 0,
+            since: None,
+            alerted: false,
+        }
+    }
+
+    /// Time since the listener last had peers, zero while it has some
+    pub fn peerless_for(&self, now: Instant) -> Duration {
+        self.since
+            .map(|since| now.saturating_duration_since(since))
+            .unwrap_or_default()
+    }
+
+    /// Record a check without peers, returning how long to wait before the next one
+    pub fn peerless(&mut self, now: Instant) -> (Duration, Option<PeerEvent>) {
+        let since = *self.since.get_or_insert(now);
+        let delay = self.backoff.delay(self.attempts);
+        self.attempts = self.attempts.saturating_add(1);
+
+        let outage = now.saturating_duration_since(since);
+        let event = match self.backoff.alert_after {
+            Some(threshold) if !self.alerted && outage >= threshold => {
+                self.alerted = true;
+                Some(PeerEvent::Lost {
+                    minutes: (outage.as_secs() / 60) as i64,
+                })
+            }
+            _ => None,
+        };
+        (delay, event)
+    }
+
+    /// Record a check with peers, ending the outage
+    pub fn connected(&mut self, now: Instant) -> Option<PeerEvent> {
+        let outage = self.peerless_for(now);
+        let alerted = self.alerted;
+        self.attempts = 0;
+        self.since = None;
+        self.alerted = false;
+        alerted.then_some(PeerEvent::Recovered {
+            minutes: (outage.as_secs() / 60) as i64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peerless_backoff() {
+        let backoff = PeerBackoff {
+            initial: Duration::from_secs(10),
+            max: Duration::from_secs(60),
+            alert_after: Some(Duration::from_secs(120)),
+        };
+        let within = |delay: Duration, secs: u64| {
+            delay >= Duration::from_secs(secs) && delay <= Duration::from_secs(secs) * 5 / 4
+        };
+        let mut monitor = PeerlessMonitor::new(backoff);
+        let start = Instant::now();
+
+        let (delay, event) = monitor.peerless(start);
+        assert!(within(delay, 10));
+        assert_eq!(event, None);
+        let (delay, _) = monitor.peerless(start + Duration::from_secs(10));
+        assert!(within(delay, 20));
+        let (delay, _) = monitor.peerless(start + Duration::from_secs(30));
+        assert!(within(delay, 40));
+        let (delay, event) = monitor.peerless(start + Duration::from_secs(70));
+        assert!(within(delay, 60), "Backoff is capped");
+        assert_eq!(event, None);
+
+        let (_, event) = monitor.peerless(start + Duration::from_secs(130));
+        assert_eq!(event, Some(PeerEvent::Lost { minutes: 2 }));
+        let (_, event) = monitor.peerless(start + Duration::from_secs(190));
+        assert_eq!(event, None, "Outages are alerted once");
+        assert_eq!(
+            monitor.peerless_for(start + Duration::from_secs(200)),
+            Duration::from_secs(200)
+        );
+
+        assert_eq!(
+            monitor.connected(start + Duration::from_secs(200)),
+            Some(PeerEvent::Recovered { minutes: 3 })
+        );
+        let (delay, _) = monitor.peerless(start + Duration::from_secs(300));
+        assert!(within(delay, 10), "Backoff restarts with the next outage");
+    }
+}
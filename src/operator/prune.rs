@@ -0,0 +1,279 @@
+//! Message retention. The summary interval prunes by RETENTION and MAX_STORAGE, and operators
+//! can prune on demand through the `pruneMessages` mutation or `POST /api/v1/admin/prune` to
+//! reclaim space without waiting for it. Both archive the pruned messages to the cold tier
+//! first when ARCHIVE_BEFORE_PRUNE is set or to ARCHIVE_URL when set, and neither touches
+//! messages under a retention hold.
+//! Deleting every message goes through [`start_message_deletion`] instead, in batches
+use async_graphql::SimpleObject;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    archive::{
+        archive_excess_messages, archive_expired_messages, pruned::ArchiveSink, ColdStorage,
+    },
+    db::resolver::{
+        delete_message_batch, finish_message_deletion, prune_old_messages, retain_max_storage,
+        MessageDeletion,
+    },
+    metrics::PRUNED_MESSAGES,
+    shutdown::stopping,
+};
+
+/// Rows deleted per pruning transaction
+pub const PRUNE_BATCH_SIZE: i64 = 1000;
+
+/// Delete the messages received more than `minutes` ago, archiving them to the cold tier
+/// `archive` or writing them to `sink` first when set. Returns the number of messages pruned
+pub async fn prune_expired(
+    db: &PgPool,
+    namespace: &str,
+    archive: Option<&ColdStorage>,
+    sink: Option<&ArchiveSink>,
+    minutes: i32,
+) -> Result<i64, anyhow::Error> {
+    match archive {
+        Some(storage) => {
+            archive_expired_messages(db, namespace, storage, minutes, PRUNE_BATCH_SIZE).await
+        }
+        None => prune_old_messages(db, namespace, minutes, PRUNE_BATCH_SIZE, sink).await,
+    }
+}
+
+/// Keep the `keep_newest` newest messages, archiving the others to the cold tier `archive`
+/// or writing them to `sink` first when set. Returns the number of messages pruned
+pub async fn prune_excess(
+    db: &PgPool,
+    namespace: &str,
+    archive: Option<&ColdStorage>,
+    sink: Option<&ArchiveSink>,
+    keep_newest: usize,
+) -> Result<i64, anyhow::Error> {
+    match archive {
+        Some(storage) => {
+            archive_excess_messages(db, namespace, storage, keep_newest, PRUNE_BATCH_SIZE).await
+        }
+        None => retain_max_storage(db, namespace, keep_newest, PRUNE_BATCH_SIZE, sink).await,
+    }
+}
+
+/// Conditions of an on demand prune, at least one is required
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct PruneRequest {
+    /// Delete the messages received more than this many minutes ago
+    pub older_than_minutes: Option<i32>,
+    /// Then keep only this many newest messages
+    pub keep_newest: Option<i64>,
+}
+
+impl PruneRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.older_than_minutes, self.keep_newest) {
+            (None, None) => Err("Provide olderThanMinutes, keepNewest or both".to_string()),
+            (Some(minutes), _) if minutes < 0 => {
+                Err("olderThanMinutes must not be negative".to_string())
+            }
+            (_, Some(keep)) if keep < 0 => Err("keepNewest must not be negative".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Messages deleted by an on demand prune
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, SimpleObject)]
+pub struct PruneOutcome {
+    /// Deleted for being older than `olderThanMinutes`
+    pub expired: i64,
+    /// Deleted for being outside the `keepNewest` newest
+    pub excess: i64,
+}
+
+/// Prune on demand by age, by count or both, counted under the `manual` reason. The
+/// request is expected to be validated
+pub async fn prune_now(
+    db: &PgPool,
+    namespace: &str,
+    archive: Option<&ColdStorage>,
+    sink: Option<&ArchiveSink>,
+    request: PruneRequest,
+) -> Result<PruneOutcome, anyhow::Error> {
+    let mut outcome = PruneOutcome::default();
+    if let Some(minutes) = request.older_than_minutes {
+        outcome.expired = prune_expired(db, namespace, archive, sink, minutes).await?;
+    }
+    if let Some(keep_newest) = request.keep_newest {
+        outcome.excess = prune_excess(db, namespace, archive, sink, keep_newest as usize).await?;
+    }
+    PRUNED_MESSAGES
+        .with_label_values(&["manual"])
+        .inc_by((outcome.expired + outcome.excess) as u64);
+    info!(
+        expired = outcome.expired,
+        excess = outcome.excess,
+        "Pruned messages on demand"
+    );
+    Ok(outcome)
+}
+
+/// Deletions with a worker in this process
+static RUNNING_DELETIONS: Lazy<Mutex<HashSet<i64>>> = Lazy::new(Default::default);
+
+/// Delete the messages of `deletion` in the background, unless a worker of this process
+/// already does. Returns whether a worker was started
+pub fn start_message_deletion(db: PgPool, namespace: String, deletion: MessageDeletion) -> bool {
+    if !RUNNING_DELETIONS
+        .lock()
+        .expect("Deletion lock poisoned")
+        .insert(deletion.id)
+    {
+        return false;
+    }
+    tokio::spawn(async move {
+        run_message_deletion(&db, &namespace, &deletion).await;
+        RUNNING_DELETIONS
+            .lock()
+            .expect("Deletion lock poisoned")
+            .remove(&deletion.id);
+    });
+    true
+}
+
+/// Delete batch by batch until nothing is left, then mark the deletion finished or failed.
+/// A deletion stopped by the shutdown stays running, to be resumed after the restart
+async fn run_message_deletion(db: &PgPool, namespace: &str, deletion: &MessageDeletion) {
+    let status = loop {
+        if stopping() {
+            info!(id = deletion.id, "Deletion interrupted by the shutdown");
+            return;
+        }
+        match delete_message_batch(db, namespace, deletion, PRUNE_BATCH_SIZE).await {
+            Ok(deleted) => {
+                PRUNED_MESSAGES
+                    .with_label_values(&["manual"])
+                    .inc_by(deleted as u64);
+                if deleted < PRUNE_BATCH_SIZE {
+                    info!(id = deletion.id, "Deleted every message");
+                    break "finished";
+                }
+            }
+            Err(e) => {
+                warn!(
+                    id = deletion.id,
+                    err = e.to_string(),
+                    "Message deletion failed"
+                );
+                break "failed";
+            }
+        }
+    };
+    if let Err(e) = finish_message_deletion(db, deletion.id, status).await {
+        warn!(
+            id = deletion.id,
+            err = e.to_string(),
+            "Failed to update message deletion"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::resolver::{
+        add_message, count_messages, create_message_deletion, message_deletion,
+    };
+    use crate::message_types::PublicPoiMessage;
+
+    const TEST_NAMESPACE: &str = "default";
+
+    fn poi_message(nonce: u64) -> PublicPoiMessage {
+        PublicPoiMessage {
+            identifier: "QmTamam".to_string(),
+            content: "0xpoi".to_string(),
+            nonce,
+            network: "goerli".to_string(),
+            block_number: nonce,
+            block_hash: "hash".to_string(),
+            graph_account: "0xb4b4".to_string(),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_prune_now(pool: PgPool) {
+        for nonce in 1707328500..1707328505 {
+            add_message(&pool, TEST_NAMESPACE, poi_message(nonce))
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE messages SET created_at = NOW() - INTERVAL '2 hours' WHERE nonce < $1")
+            .bind(1707328502_i64)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let request = PruneRequest {
+            older_than_minutes: Some(60),
+            keep_newest: Some(2),
+        };
+        assert!(request.validate().is_ok());
+        let outcome = prune_now(&pool, TEST_NAMESPACE, None, None, request)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            PruneOutcome {
+                expired: 2,
+                excess: 1
+            },
+            "Pruning by count applies to the messages left after pruning by age"
+        );
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 2);
+
+        let request = PruneRequest {
+            keep_newest: Some(5),
+            ..Default::default()
+        };
+        let outcome = prune_now(&pool, TEST_NAMESPACE, None, None, request)
+            .await
+            .unwrap();
+        assert_eq!(outcome, PruneOutcome::default());
+
+        assert!(PruneRequest::default().validate().is_err());
+        assert!(PruneRequest {
+            older_than_minutes: Some(-5),
+            keep_newest: None
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_run_message_deletion(pool: PgPool) {
+        for nonce in 1707328500..1707328505 {
+            add_message(&pool, TEST_NAMESPACE, poi_message(nonce))
+                .await
+                .unwrap();
+        }
+        let deletion = create_message_deletion(&pool, TEST_NAMESPACE)
+            .await
+            .unwrap();
+        add_message(&pool, TEST_NAMESPACE, poi_message(1707328505))
+            .await
+            .unwrap();
+
+        run_message_deletion(&pool, TEST_NAMESPACE, &deletion).await;
+        let finished = message_deletion(&pool, TEST_NAMESPACE, deletion.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (finished.deleted, finished.status.as_str()),
+            (5, "finished")
+        );
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 1);
+    }
+}
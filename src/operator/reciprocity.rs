@@ -0,0 +1,204 @@
+//! Reachability of the boot nodes. Every PEER_CHECK_INTERVAL each boot node is dialed, the
+//! dial round trip is measured and the node is looked up among the connected peers, which
+//! shows whether it keeps the connection with this listener. Outcomes are kept in the `peers`
+//! table, and a node of CRITICAL_BOOT_NODES failing several checks in a row is alerted
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+use tracing::{debug, warn};
+use waku_bindings::Multiaddr;
+
+use graphcast_sdk::graphcast_agent::GraphcastAgent;
+
+use super::{
+    notifier::Notifier,
+    templates::{Alert, AlertKind},
+};
+use crate::{
+    config::Config,
+    db::resolver::{record_peer_check, PeerCheck},
+    metrics::{BOOT_NODE_DIAL_MS, BOOT_NODE_REACHABLE},
+};
+
+/// Time a dial gets before the node counts as unreachable
+const DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Consecutive failed checks before a critical boot node is alerted
+pub const UNREACHABLE_AFTER: i32 = 3;
+
+/// Boot nodes to check, critical ones included even when not among BOOT_NODE_ADDRESSES
+pub fn checked_boot_nodes(config: &Config) -> Vec<(String, bool)> {
+    let mut nodes: Vec<(String, bool)> = config
+        .boot_node_addresses
+        .iter()
+        .map(|address| {
+            (
+                address.clone(),
+                config.critical_boot_nodes.contains(address),
+            )
+        })
+        .collect();
+    for address in &config.critical_boot_nodes {
+        if !config.boot_node_addresses.contains(address) {
+            nodes.push((address.clone(), true));
+        }
+    }
+    nodes
+}
+
+/// Peer id of a `/p2p/<peer id>` terminated address
+fn address_peer_id(address: &str) -> Option<String> {
+    address
+        .rsplit_once("/p2p/")
+        .map(|(_, peer_id)| peer_id.to_string())
+        .filter(|peer_id| !peer_id.is_empty() && !peer_id.contains('/'))
+}
+
+fn check_boot_node(agent: &GraphcastAgent, address: &str, critical: bool) -> PeerCheck {
+    let peer_id = address_peer_id(address);
+    let dial = Multiaddr::from_str(address)
+        .map_err(|e| e.to_string())
+        .and_then(|multiaddr| {
+            let started = Instant::now();
+            agent
+                .node_handle
+                .connect_peer_with_address(&multiaddr, Some(DIAL_TIMEOUT))
+                .map(|_| started.elapsed().as_millis() as i64)
+        });
+    let dial_ms = match dial {
+        Ok(ms) => Some(ms),
+        Err(e) => {
+            debug!(address, err = e, "Boot node dial failed");
+            None
+        }
+    };
+    let connected = match (&peer_id, agent.node_handle.peers()) {
+        (Some(peer_id), Ok(peers)) => peers
+            .iter()
+            .any(|peer| peer.peer_id() == peer_id && peer.connected()),
+        _ => dial_ms.is_some(),
+    };
+    PeerCheck {
+        address: address.to_string(),
+        peer_id,
+        critical,
+        reachable: dial_ms.is_some(),
+        connected,
+        dial_ms,
+    }
+}
+
+/// Change of a critical boot node that is alerted on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootNodeEvent {
+    Unreachable,
+    Recovered,
+}
+
+/// Alert for a check that took the consecutive failures from `previous` to `failures`
+pub fn boot_node_event(previous: i32, failures: i32) -> Option<BootNodeEvent> {
+    if failures == UNREACHABLE_AFTER {
+        Some(BootNodeEvent::Unreachable)
+    } else if failures == 0 && previous >= UNREACHABLE_AFTER {
+        Some(BootNodeEvent::Recovered)
+    } else {
+        None
+    }
+}
+
+pub async fn run_peer_checks(
+    db: PgPool,
+    namespace: String,
+    agent: Weak<GraphcastAgent>,
+    notifier: Notifier,
+    boot_nodes: Vec<(String, bool)>,
+    check_interval: Duration,
+    running: Arc<AtomicBool>,
+) {
+    let mut check_interval = interval(check_interval);
+    let mut failures: HashMap<String, i32> = HashMap::new();
+
+    while running.load(Ordering::SeqCst) {
+        check_interval.tick().await;
+        for (address, critical) in &boot_nodes {
+            if !running.load(Ordering::SeqCst) {
+                return;
+            }
+            let check = {
+                // The node stopped with the listener
+                let Some(agent) = agent.upgrade() else {
+                    return;
+                };
+                let address = address.clone();
+                let critical = *critical;
+                // Dials block in the Waku bindings
+                match tokio::task::spawn_blocking(move || {
+                    check_boot_node(&agent, &address, critical)
+                })
+                .await
+                {
+                    Ok(check) => check,
+                    Err(e) => {
+                        warn!(err = e.to_string(), "Boot node check failed");
+                        continue;
+                    }
+                }
+            };
+            BOOT_NODE_REACHABLE
+                .with_label_values(&[address])
+                .set(check.reachable as i64);
+            BOOT_NODE_DIAL_MS
+                .with_label_values(&[address])
+                .set(check.dial_ms.unwrap_or(-1));
+
+            let count = match record_peer_check(&db, &namespace, &check).await {
+                Ok(count) => count,
+                Err(e) => {
+                    warn!(err = e.to_string(), "Could not record boot node check");
+                    continue;
+                }
+            };
+            let previous = failures.insert(address.clone(), count).unwrap_or(0);
+            if !critical {
+                continue;
+            }
+            let kind = match boot_node_event(previous, count) {
+                Some(BootNodeEvent::Unreachable) => AlertKind::BootNodeUnreachable,
+                Some(BootNodeEvent::Recovered) => AlertKind::BootNodeRecovered,
+                None => continue,
+            };
+            notifier
+                .clone()
+                .notify(
+                    Alert::new(kind)
+                        .with("address", address)
+                        .metric("failures", previous.max(count) as i64),
+                )
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_node_events() {
+        assert_eq!(
+            address_peer_id("/dns4/boot.example.org/tcp/31900/p2p/16Uiu2HAmBoot").as_deref(),
+            Some("16Uiu2HAmBoot")
+        );
+        assert_eq!(address_peer_id("/ip4/10.0.0.1/tcp/60000"), None);
+
+        assert_eq!(boot_node_event(0, 1), None);
+        assert_eq!(boot_node_event(2, 3), Some(BootNodeEvent::Unreachable));
+        assert_eq!(boot_node_event(3, 4), None, "Outages are alerted once");
+        assert_eq!(boot_node_event(4, 0), Some(BootNodeEvent::Recovered));
+        assert_eq!(boot_node_event(1, 0), None, "Short outages are not alerted");
+    }
+}
@@ -0,0 +1,115 @@
+//! Monitoring summary of every update interval, logged as a structured event and optionally
+//! posted to SUMMARY_WEBHOOK so external systems can follow the listener's heartbeat
+use chrono::Utc;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{
+    config::SummaryLevel,
+    metrics::{
+        ACTIVE_PEERS, CONNECTED_PEERS, COVERED_DEPLOYMENTS, GOSSIP_PEERS, INGEST_QUEUE_DEPTH,
+        SILENT_TOPICS,
+    },
+};
+
+const SUMMARY_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct MonitoringSummary {
+    pub namespace: String,
+    /// Unix timestamp of the update
+    pub timestamp: i64,
+    pub total_messages: i64,
+    /// Messages pruned by retention and max storage during the update
+    pub total_num_pruned: i64,
+    pub connected_peers: i64,
+    pub gossip_peers: i64,
+    pub active_peers: i64,
+    /// Received messages waiting for a processing worker
+    pub queue_depth: i64,
+    pub covered_deployments: i64,
+    pub silent_topics: i64,
+}
+
+impl MonitoringSummary {
+    /// Summary of an update, peer, queue and coverage figures are read from their gauges
+    pub fn collect(namespace: &str, total_messages: i64, total_num_pruned: i64) -> Self {
+        MonitoringSummary {
+            namespace: namespace.to_string(),
+            timestamp: Utc::now().timestamp(),
+            total_messages,
+            total_num_pruned,
+            connected_peers: CONNECTED_PEERS.get(),
+            gossip_peers: GOSSIP_PEERS.get(),
+            active_peers: ACTIVE_PEERS.get(),
+            queue_depth: INGEST_QUEUE_DEPTH.get(),
+            covered_deployments: COVERED_DEPLOYMENTS.get(),
+            silent_topics: SILENT_TOPICS.get(),
+        }
+    }
+
+    /// Log the summary with every figure as a field, the json log format keeps them apart
+    pub fn log(&self, level: SummaryLevel) {
+        macro_rules! summary_event {
+            ($event:ident) => {
+                $event!(
+                    namespace = self.namespace.as_str(),
+                    total_messages = self.total_messages,
+                    total_num_pruned = self.total_num_pruned,
+                    connected_peers = self.connected_peers,
+                    gossip_peers = self.gossip_peers,
+                    active_peers = self.active_peers,
+                    queue_depth = self.queue_depth,
+                    covered_deployments = self.covered_deployments,
+                    silent_topics = self.silent_topics,
+                    "Monitoring summary"
+                )
+            };
+        }
+        match level {
+            SummaryLevel::Off => {}
+            SummaryLevel::Error => summary_event!(error),
+            SummaryLevel::Warn => summary_event!(warn),
+            SummaryLevel::Info => summary_event!(info),
+            SummaryLevel::Debug => summary_event!(debug),
+            SummaryLevel::Trace => summary_event!(trace),
+        }
+    }
+
+    /// Post the summary in the background, a slow or failing endpoint only costs a warning
+    pub fn post(&self, url: &str) {
+        let request = reqwest::Client::new()
+            .post(url)
+            .json(self)
+            .timeout(SUMMARY_WEBHOOK_TIMEOUT);
+        tokio::spawn(async move {
+            let result = async { request.send().await?.error_for_status() }.await;
+            if let Err(e) = result {
+                warn!(err = e.to_string(), "Could not post monitoring summary");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_json() {
+        let summary = MonitoringSummary::collect("default", 42, 3);
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["namespace"], "default");
+        assert_eq!(json["total_messages"], 42);
+        assert_eq!(json["total_num_pruned"], 3);
+        for field in [
+            "connected_peers",
+            "gossip_peers",
+            "queue_depth",
+            "silent_topics",
+        ] {
+            assert!(json[field].is_i64(), "{} should be a count", field);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Notification messages rendered from operator templates. Templates are plain text with
+//! `{{variable}}` placeholders, looked up per alert and channel from the database, then the
+//! NOTIFICATION_TEMPLATES file, then the built-in default
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+#[derive(async_graphql::Enum, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AlertKind {
+    /// Senders diverged from the POI consensus of a divergence check
+    Divergence,
+    /// Ingest stayed over MAX_MESSAGE_RATE or MAX_INGEST_BANDWIDTH
+    BudgetExceeded,
+    /// Ingest is back within budget
+    BudgetRecovered,
+    /// A listener thread or task panicked
+    Panic,
+    /// The listener had no peers for longer than NO_PEER_ALERT_AFTER
+    NoPeers,
+    /// Peers are back after an alerted outage
+    PeersRecovered,
+    /// The Waku node started, with the addresses to reach it, sent with ANNOUNCE_NODE
+    NodeStarted,
+    /// A critical boot node failed several reachability checks in a row
+    BootNodeUnreachable,
+    /// A critical boot node is reachable again after an alerted outage
+    BootNodeRecovered,
+}
+
+impl AlertKind {
+    /// Name of the alert in template files and the `notification_templates` table
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertKind::Divergence => "divergence",
+            AlertKind::BudgetExceeded => "budget_exceeded",
+            AlertKind::BudgetRecovered => "budget_recovered",
+            AlertKind::Panic => "panic",
+            AlertKind::NoPeers => "no_peers",
+            AlertKind::PeersRecovered => "peers_recovered",
+            AlertKind::NodeStarted => "node_started",
+            AlertKind::BootNodeUnreachable => "boot_node_unreachable",
+            AlertKind::BootNodeRecovered => "boot_node_recovered",
+        }
+    }
+
+    fn default_template(&self) -> &'static str {
+        match self {
+            AlertKind::Divergence => "{{indexers}} indexers sent POIs diverging from the {{methodology}} consensus on {{deployments}} deployments ({{divergences}} divergences in consensus run {{run_id}})",
+            AlertKind::BudgetExceeded => "Ingest over budget for {{minutes}} minutes: {{messages}} messages and {{bytes}} bytes in the last minute{{sampling}}",
+            AlertKind::BudgetRecovered => "Ingest is back within budget",
+            AlertKind::Panic => "Listener thread {{thread}} panicked at {{location}}: {{message}}",
+            AlertKind::NoPeers => "No peers on the Graphcast network for {{minutes}} minutes, messages are not received",
+            AlertKind::PeersRecovered => "Peers are back after {{minutes}} minutes without any",
+            AlertKind::NodeStarted => "Listener node {{peer_id}} started, add it as a static peer with {{multiaddrs}}",
+            AlertKind::BootNodeUnreachable => "Boot node {{address}} is unreachable, {{failures}} checks failed in a row",
+            AlertKind::BootNodeRecovered => "Boot node {{address}} is reachable again after {{failures}} failed checks",
+        }
+    }
+
+    pub fn severity(&self) -> AlertSeverity {
+        match self {
+            AlertKind::Divergence
+            | AlertKind::Panic
+            | AlertKind::NoPeers
+            | AlertKind::BootNodeUnreachable => AlertSeverity::Critical,
+            AlertKind::BudgetExceeded => AlertSeverity::Warning,
+            AlertKind::BudgetRecovered
+            | AlertKind::PeersRecovered
+            | AlertKind::NodeStarted
+            | AlertKind::BootNodeRecovered => AlertSeverity::Info,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+/// Link to more details about an alert
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct AlertLink {
+    pub title: String,
+    pub url: String,
+}
+
+/// Alert with the values its template can refer to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    variables: Vec<(&'static str, String)>,
+    metrics: BTreeMap<&'static str, i64>,
+    entities: BTreeMap<&'static str, Vec<String>>,
+    links: Vec<AlertLink>,
+}
+
+impl Alert {
+    pub fn new(kind: AlertKind) -> Self {
+        Alert {
+            kind,
+            variables: vec![],
+            metrics: BTreeMap::new(),
+            entities: BTreeMap::new(),
+            links: vec![],
+        }
+    }
+
+    /// Set `{{name}}` to `value`
+    pub fn with(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.variables.push((name, value.to_string()));
+        self
+    }
+
+    /// Set `{{name}}` to a count, also reported in the `metrics` of webhook payloads
+    pub fn metric(mut self, name: &'static str, value: i64) -> Self {
+        self.metrics.insert(name, value);
+        self.with(name, value)
+    }
+
+    /// Indexers, deployments or other entities the alert is about, by kind
+    pub fn entities(mut self, kind: &'static str, entities: Vec<String>) -> Self {
+        self.entities.insert(kind, entities);
+        self
+    }
+
+    pub fn link(mut self, title: &str, url: String) -> Self {
+        self.links.push(AlertLink {
+            title: title.to_string(),
+            url,
+        });
+        self
+    }
+
+    /// Fill in the variables of `template`, or of the default template of the alert.
+    /// Unknown placeholders are kept as they are so typos show up in the delivered message
+    pub fn render(&self, template: Option<&str>) -> String {
+        let mut content = template.unwrap_or(self.kind.default_template()).to_string();
+        for (name, value) in &self.variables {
+            content = content.replace(&format!("{{{{{}}}}}", name), value);
+        }
+        content
+    }
+
+    /// Links as text lines following a rendered message, empty without links
+    pub fn link_lines(&self) -> String {
+        self.links
+            .iter()
+            .map(|link| format!("\n{}: {}", link.title, link.url))
+            .collect()
+    }
+
+    /// JSON body of the generic webhook channel with the rendered `message`
+    pub fn webhook_payload(&self, radio: &str, message: String) -> String {
+        let payload = WebhookPayload {
+            radio,
+            alert: self.kind.name(),
+            severity: self.kind.severity(),
+            message,
+            timestamp: Utc::now().timestamp(),
+            entities: &self.entities,
+            metrics: &self.metrics,
+            links: &self.links,
+        };
+        serde_json::to_string(&payload).expect("Alert payloads serialize to JSON")
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    radio: &'a str,
+    alert: &'static str,
+    severity: AlertSeverity,
+    message: String,
+    timestamp: i64,
+    entities: &'a BTreeMap<&'static str, Vec<String>>,
+    metrics: &'a BTreeMap<&'static str, i64>,
+    links: &'a [AlertLink],
+}
+
+/// Templates of the NOTIFICATION_TEMPLATES file, a JSON object keyed by alert name for every
+/// channel, or by `alert.channel` (e.g. `divergence.slack`) for one channel
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct TemplateFile(HashMap<String, String>);
+
+impl TemplateFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file)?)
+    }
+
+    pub fn get(&self, alert: AlertKind, channel: &str) -> Option<&str> {
+        self.0
+            .get(&format!("{}.{}", alert.name(), channel))
+            .or_else(|| self.0.get(alert.name()))
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let alert = Alert::new(AlertKind::BudgetExceeded)
+            .metric("minutes", 5)
+            .metric("messages", 1200)
+            .metric("bytes", 4096)
+            .with("sampling", "");
+        assert_eq!(
+            alert.render(None),
+            "Ingest over budget for 5 minutes: 1200 messages and 4096 bytes in the last minute"
+        );
+        assert_eq!(
+            alert.render(Some(":warning: {{messages}} msgs/min ({{unknown}})")),
+            ":warning: 1200 msgs/min ({{unknown}})"
+        );
+    }
+
+    #[test]
+    fn test_webhook_payload() {
+        let alert = Alert::new(AlertKind::Divergence)
+            .metric("divergences", 3)
+            .entities("indexers", vec!["0xb4b4".to_string()]);
+        let payload: serde_json::Value = serde_json::from_str(
+            &alert.webhook_payload("listener-radio", "3 divergences".to_string()),
+        )
+        .unwrap();
+        assert_eq!(payload["alert"], "divergence");
+        assert_eq!(payload["severity"], "critical");
+        assert_eq!(payload["message"], "3 divergences");
+        assert_eq!(payload["metrics"]["divergences"], 3);
+        assert_eq!(payload["entities"]["indexers"][0], "0xb4b4");
+        assert_eq!(payload["links"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_template_file() {
+        let file: TemplateFile = serde_json::from_str(
+            r#"{"divergence": "Divergence in run {{run_id}}", "divergence.slack": ":rotating_light: {{run_id}}"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            file.get(AlertKind::Divergence, "slack"),
+            Some(":rotating_light: {{run_id}}")
+        );
+        assert_eq!(
+            file.get(AlertKind::Divergence, "discord"),
+            Some("Divergence in run {{run_id}}")
+        );
+        assert_eq!(file.get(AlertKind::BudgetRecovered, "slack"), None);
+    }
+}
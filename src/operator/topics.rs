@@ -0,0 +1,189 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Content topics the listener keeps, switchable at runtime. This only changes which
+/// received messages are kept: the Waku protocol, relay or filter, is set by
+/// FILTER_PROTOCOL when the node starts and stays the same
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum TopicScope {
+    /// Every content topic received on the pubsub topic
+    All,
+    /// Only the configured content topics
+    Configured,
+}
+
+static CONFIGURED_SCOPE: AtomicBool = AtomicBool::new(false);
+
+/// Requested scope, the operator updates the content topics of the agent when it changes
+pub fn set_topic_scope(scope: TopicScope) {
+    CONFIGURED_SCOPE.store(scope == TopicScope::Configured, Ordering::SeqCst);
+}
+
+pub fn topic_scope() -> TopicScope {
+    if CONFIGURED_SCOPE.load(Ordering::SeqCst) {
+        TopicScope::Configured
+    } else {
+        TopicScope::All
+    }
+}
+
+/// Content topics the agent matches messages against in `scope`, an empty list keeps every
+/// topic
+pub fn scoped_topics(scope: TopicScope, configured: &[String]) -> Vec<String> {
+    match scope {
+        TopicScope::All => vec![],
+        TopicScope::Configured => configured.to_vec(),
+    }
+}
+
+/// Receive times of the content topics, shared by the message processor and the API
+pub static TOPIC_ACTIVITY: Lazy<TopicActivity> = Lazy::new(TopicActivity::new);
+
+/// Last time a message was received on each content topic
+#[derive(Debug)]
+pub struct TopicActivity {
+    /// Topics that never received a message are measured from startup
+    started_at: i64,
+    last_seen: Mutex<HashMap<String, i64>>,
+}
+
+/// Silence of a subscribed topic against its threshold
+#[derive(Clone, Debug, Serialize, SimpleObject)]
+pub struct TopicStatus {
+    pub topic: String,
+    /// Unix timestamp of the last received message, null if none since startup
+    pub last_message_at: Option<i64>,
+    pub threshold_minutes: u64,
+    pub silent: bool,
+}
+
+impl Default for TopicActivity {
+    fn default() -> Self {
+        TopicActivity::new()
+    }
+}
+
+impl TopicActivity {
+    pub fn new() -> Self {
+        TopicActivity {
+            started_at: Utc::now().timestamp(),
+            last_seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, topic: &str, timestamp: i64) {
+        self.last_seen
+            .lock()
+            .expect("Topic activity lock poisoned")
+            .insert(topic.to_string(), timestamp);
+    }
+
+    pub fn last_seen(&self, topic: &str) -> Option<i64> {
+        self.last_seen
+            .lock()
+            .expect("Topic activity lock poisoned")
+            .get(topic)
+            .copied()
+    }
+
+    pub fn started_at(&self) -> i64 {
+        self.started_at
+    }
+
+    /// Latest receive time on any topic
+    pub fn last_message(&self) -> Option<i64> {
+        self.last_seen
+            .lock()
+            .expect("Topic activity lock poisoned")
+            .values()
+            .max()
+            .copied()
+    }
+
+    /// Status of the subscribed topics, `thresholds` overrides the default threshold per topic
+    pub fn report(
+        &self,
+        topics: &[String],
+        default_threshold: u64,
+        thresholds: &[(String, u64)],
+        now: i64,
+    ) -> Vec<TopicStatus> {
+        topics
+            .iter()
+            .map(|topic| {
+                let threshold_minutes = thresholds
+                    .iter()
+                    .find(|(t, _)| t == topic)
+                    .map(|(_, minutes)| *minutes)
+                    .unwrap_or(default_threshold);
+                let last_message_at = self.last_seen(topic);
+                let silent_since = last_message_at.unwrap_or(self.started_at);
+                TopicStatus {
+                    topic: topic.clone(),
+                    last_message_at,
+                    threshold_minutes,
+                    silent: now - silent_since > (threshold_minutes * 60) as i64,
+                }
+            })
+            .collect()
+    }
+}
+
+/// A few silent topics point to dead deployments, while every topic going silent at once
+/// points to a failed Waku filter subscription
+pub fn silence_cause(statuses: &[TopicStatus]) -> Option<&'static str> {
+    let silent = statuses.iter().filter(|s| s.silent).count();
+    match silent {
+        0 => None,
+        n if n == statuses.len() => Some("subscription"),
+        _ => Some("deployment"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silent_topics() {
+        let activity = TopicActivity::new();
+        let now = activity.started_at + 3600;
+        activity.record("QmActive", now - 60);
+        activity.record("QmQuiet", now - 1800);
+        let topics = vec![
+            "QmActive".to_string(),
+            "QmQuiet".to_string(),
+            "QmNever".to_string(),
+        ];
+
+        let statuses = activity.report(&topics, 20, &[("QmQuiet".to_string(), 45)], now);
+        let silent: Vec<_> = statuses.iter().map(|s| s.silent).collect();
+        assert_eq!(silent, vec![false, false, true]);
+        assert_eq!(statuses[1].threshold_minutes, 45);
+        assert_eq!(silence_cause(&statuses), Some("deployment"));
+
+        let statuses = activity.report(&topics, 0, &[], now);
+        assert_eq!(silence_cause(&statuses), Some("subscription"));
+
+        let statuses = activity.report(&topics[..1], 20, &[], now);
+        assert_eq!(silence_cause(&statuses), None);
+    }
+
+    #[test]
+    fn test_topic_scope() {
+        let configured = vec!["QmTamam".to_string()];
+        set_topic_scope(TopicScope::Configured);
+        assert_eq!(topic_scope(), TopicScope::Configured);
+        assert_eq!(scoped_topics(topic_scope(), &configured), configured);
+
+        // The all scope clears the topic list, which the agent matches every topic against
+        set_topic_scope(TopicScope::All);
+        assert_eq!(topic_scope(), TopicScope::All);
+        assert!(scoped_topics(topic_scope(), &configured).is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+//! Liveness signals of the operator loop. Under systemd the loop reports readiness and pings
+//! the watchdog through NOTIFY_SOCKET, and with HEARTBEAT_FILE it touches a file that
+//! container liveness probes can check the age of. Both are driven by the loop itself, so a
+//! hung loop stops them while the process stays alive
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Longest time between heartbeats when systemd does not set a watchdog interval
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Send a state update to the systemd notification socket. Abstract socket addresses start
+/// with `@`
+fn sd_notify(socket: &str, state: &str) -> io::Result<()> {
+    let sender = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sender.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Abstract sockets are only available on Linux",
+            ));
+        }
+        None => {
+            sender.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct Watchdog {
+    notify_socket: Option<String>,
+    heartbeat_file: Option<PathBuf>,
+    interval: Duration,
+    last_beat: Option<Instant>,
+}
+
+impl Watchdog {
+    /// Watchdog of the systemd unit this process runs in, if any, and of the heartbeat file
+    pub fn from_env(heartbeat_file: Option<PathBuf>) -> Self {
+        let notify_socket = env::var("NOTIFY_SOCKET").ok().filter(|s| !s.is_empty());
+        // systemd expects a ping within WATCHDOG_USEC, pinging at half of it leaves a margin
+        let watchdog_usec = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0);
+        let interval = watchdog_usec
+            .map(|usec| Duration::from_micros(usec / 2).min(HEARTBEAT_INTERVAL))
+            .unwrap_or(HEARTBEAT_INTERVAL);
+        Watchdog::new(notify_socket, heartbeat_file, interval)
+    }
+
+    pub fn new(
+        notify_socket: Option<String>,
+        heartbeat_file: Option<PathBuf>,
+        interval: Duration,
+    ) -> Self {
+        Watchdog {
+            notify_socket,
+            heartbeat_file,
+            interval,
+            last_beat: None,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn notify(&self, state: &str) {
+        if let Some(socket) = &self.notify_socket {
+            if let Err(e) = sd_notify(socket, state) {
+                warn!(err = e.to_string(), state, "Could not notify systemd");
+            }
+        }
+    }
+
+    /// Report that the operator loop started
+    pub fn ready(&mut self) {
+        self.notify("READY=1");
+        self.beat();
+    }
+
+    /// Signal liveness, at most once per interval
+    pub fn beat(&mut self) {
+        if self
+            .last_beat
+            .is_some_and(|last| last.elapsed() < self.interval)
+        {
+            return;
+        }
+        self.last_beat = Some(Instant::now());
+        self.notify("WATCHDOG=1");
+        if let Some(path) = &self.heartbeat_file {
+            // The write updates the modification time probes look at
+            let now = chrono::Utc::now().timestamp().to_string();
+            if let Err(e) = fs::write(path, now) {
+                warn!(
+                    err = e.to_string(),
+                    path = path.display().to_string(),
+                    "Could not touch heartbeat file"
+                );
+            }
+        }
+        debug!("Operator heartbeat");
+    }
+
+    /// Report that the operator loop is shutting down, so systemd does not treat the missing
+    /// pings as a hang
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_notifications() {
+        let dir = env::temp_dir().join(format!("listener-radio-watchdog-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket_path = dir.join("notify.sock");
+        let _ = fs::remove_file(&socket_path);
+        let systemd = UnixDatagram::bind(&socket_path).unwrap();
+        systemd.set_nonblocking(true).unwrap();
+        let heartbeat = dir.join("heartbeat");
+
+        let mut watchdog = Watchdog::new(
+            Some(socket_path.display().to_string()),
+            Some(heartbeat.clone()),
+            Duration::from_secs(60),
+        );
+        watchdog.ready();
+        // Within the interval, no second ping
+        watchdog.beat();
+        watchdog.stopping();
+
+        let mut buf = [0u8; 64];
+        let mut states = vec![];
+        while let Ok(len) = systemd.recv(&mut buf) {
+            states.push(String::from_utf8_lossy(&buf[..len]).to_string());
+        }
+        assert_eq!(states, vec!["READY=1", "WATCHDOG=1", "STOPPING=1"]);
+        assert!(fs::read_to_string(&heartbeat)
+            .unwrap()
+            .parse::<i64>()
+            .is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+use reqwest::Client;
+use sqlx::PgPool;
+use std::future::Future;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, trace, warn};
+
+use crate::{
+    db::resolver::{
+        claim_outbox_entries, mark_outbox_published, prune_outbox, release_outbox_entries,
+        OutboxEntry,
+    },
+    metrics::OUTBOX_PUBLISHED,
+};
+
+/// Destination of the change data capture stream. Entries are delivered at least once and
+/// in outbox order, consumers deduplicate on the outbox `id`
+pub trait OutboxSink: Send + Sync {
+    fn publish(
+        &self,
+        entry: &OutboxEntry,
+    ) -> impl Future<Output = Result<(), anyhow::Error>> + Send;
+}
+
+/// POST every entry as json to a webhook, with the outbox id as idempotency key
+#[derive(Clone, Debug)]
+pub struct WebhookSink {
+    client: Client,
+    url: String,
+}
+
+impl WebhookSink {
+    pub fn new(url: String) -> Self {
+        WebhookSink {
+            client: Client::new(),
+            url,
+        }
+    }
+}
+
+impl OutboxSink for WebhookSink {
+    async fn publish(&self, entry: &OutboxEntry) -> Result<(), anyhow::Error> {
+        self.client
+            .post(&self.url)
+            .header("Idempotency-Key", entry.id.to_string())
+            .json(entry)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Seconds a relay holds its claim on a batch of outbox entries, enough for every entry of
+/// a batch to reach the webhook timeout
+const CLAIM_LEASE: i64 = 1200;
+
+/// Publish pending outbox entries in order until the outbox is drained or the sink fails.
+/// Batches are claimed and marked in short statements, no transaction stays open while the
+/// sink is called. Entries are only marked once the sink accepted them so a crash leads to a
+/// redelivery rather than a gap
+/// Returns the number of entries published
+pub async fn relay_outbox<S: OutboxSink>(
+    pool: &PgPool,
+    namespace: &str,
+    sink: &S,
+    batch_size: i64,
+) -> Result<i64, anyhow::Error> {
+    let mut total_published = 0i64;
+
+    loop {
+        let entries = claim_outbox_entries(pool, namespace, batch_size, CLAIM_LEASE).await?;
+
+        let mut published = vec![];
+        let mut failure = None;
+        for entry in &entries {
+            match sink.publish(entry).await {
+                Ok(()) => published.push(entry.id),
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+
+        mark_outbox_published(pool, &published).await?;
+        total_published += published.len() as i64;
+        OUTBOX_PUBLISHED.inc_by(published.len() as u64);
+
+        if let Some(e) = failure {
+            let unpublished = entries[published.len()..]
+                .iter()
+                .map(|entry| entry.id)
+                .collect::<Vec<i64>>();
+            release_outbox_entries(pool, &unpublished).await?;
+            return Err(e);
+        }
+        if (entries.len() as i64) < batch_size {
+            break;
+        }
+    }
+
+    Ok(total_published)
+}
+
+/// Background task relaying the outbox to the sink
+pub async fn run_outbox_relay<S: OutboxSink>(
+    db: PgPool,
+    namespace: String,
+    sink: S,
+    retention: i32,
+    running: Arc<AtomicBool>,
+) {
+    let mut relay_interval = interval(Duration::from_secs(5));
+    let batch_size = 100;
+
+    while running.load(Ordering::SeqCst) {
+        relay_interval.tick().await;
+        match relay_outbox(&db, &namespace, &sink, batch_size).await {
+            Ok(0) => trace!("No outbox entries to relay"),
+            Ok(num_published) => debug!(num_published, "Relayed outbox entries"),
+            Err(e) => warn!(
+                err = tracing::field::debug(e),
+                "Failed to relay outbox entries, retrying on next tick"
+            ),
+        }
+
+        // Published entries are kept `retention` minutes for inspection
+        if let Err(e) = prune_outbox(&db, &namespace, retention).await {
+            warn!(
+                err = tracing::field::debug(e),
+                "Failed to prune published outbox entries"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::resolver::{add_message, add_outbox_entry};
+    use std::sync::Mutex;
+
+    const TEST_NAMESPACE: &str = "default";
+
+    /// Records published nonces and fails on the given one
+    struct StubSink {
+        published: Mutex<Vec<u64>>,
+        fail_on: Mutex<Option<u64>>,
+    }
+
+    impl OutboxSink for StubSink {
+        async fn publish(&self, entry: &OutboxEntry) -> Result<(), anyhow::Error> {
+            let nonce = entry.payload["nonce"].as_u64().unwrap();
+            if *self.fail_on.lock().unwrap() == Some(nonce) {
+                return Err(anyhow::anyhow!("Sink unavailable"));
+            }
+            self.published.lock().unwrap().push(nonce);
+            Ok(())
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_relay_outbox(pool: PgPool) {
+        for nonce in 1..=5u64 {
+            let message = serde_json::json!({
+                "identifier": "QmTamam",
+                "nonce": nonce,
+                "graph_account": "0xb4b4",
+                "payload": { "content": nonce.to_string() },
+            });
+            let mut tx = pool.begin().await.unwrap();
+            let id = add_message(&mut *tx, TEST_NAMESPACE, message)
+                .await
+                .unwrap()
+                .unwrap();
+            add_outbox_entry(&mut *tx, id).await.unwrap();
+            tx.commit().await.unwrap();
+        }
+        let sink = StubSink {
+            published: Mutex::new(vec![]),
+            fail_on: Mutex::new(Some(3)),
+        };
+
+        // A failing entry stops the relay, the entries after it stay pending in order
+        assert!(relay_outbox(&pool, TEST_NAMESPACE, &sink, 2).await.is_err());
+        assert_eq!(*sink.published.lock().unwrap(), vec![1, 2]);
+
+        *sink.fail_on.lock().unwrap() = None;
+        assert_eq!(
+            relay_outbox(&pool, TEST_NAMESPACE, &sink, 2).await.unwrap(),
+            3
+        );
+        assert_eq!(*sink.published.lock().unwrap(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(
+            relay_outbox(&pool, TEST_NAMESPACE, &sink, 2).await.unwrap(),
+            0
+        );
+
+        sqlx::query("UPDATE outbox SET published_at = NOW() - INTERVAL '2 hours'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(prune_outbox(&pool, TEST_NAMESPACE, 60).await.unwrap(), 5);
+    }
+}
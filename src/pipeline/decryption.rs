@@ -0,0 +1,106 @@
+//! Decryption of payloads gossiped on private content topics. Closed test networks may encrypt
+//! their messages with a shared AES-256-GCM key (Waku payload version 1). With DECRYPTION_KEYS
+//! set, such payloads are decrypted with the key of their content topic, or with the keys
+//! shared by every topic, before they are decoded like any other payload
+use aes_gcm::{Aes256Gcm, Key};
+use anyhow::{anyhow, Context};
+use graphcast_sdk::WakuMessage;
+use std::collections::HashMap;
+
+use super::validation::decode_hex;
+use crate::{metrics::DECRYPTED_MESSAGES, ListenerError};
+
+/// Waku payload version of symmetrically encrypted payloads
+pub const ENCRYPTED_PAYLOAD_VERSION: usize = 1;
+
+#[derive(Clone, Debug, Default)]
+pub struct PayloadKeys {
+    by_topic: HashMap<String, Vec<Key<Aes256Gcm>>>,
+    /// Keys tried on every content topic, after the topic's own
+    shared: Vec<Key<Aes256Gcm>>,
+}
+
+fn parse_key(key: &str) -> anyhow::Result<Key<Aes256Gcm>> {
+    let bytes =
+        decode_hex(key.trim().trim_start_matches("0x")).ok_or_else(|| anyhow!("Key is not hex"))?;
+    if bytes.len() != 32 {
+        return Err(anyhow!("Key is {} bytes, 32 expected", bytes.len()));
+    }
+    Ok(*Key::<Aes256Gcm>::from_slice(&bytes))
+}
+
+impl PayloadKeys {
+    /// Keys of DECRYPTION_KEYS entries, `<content topic>=<hex key>` or a bare hex key for
+    /// every topic. None when no key is configured
+    pub fn parse(entries: &[String]) -> anyhow::Result<Option<Self>> {
+        let mut keys = PayloadKeys::default();
+        for entry in entries.iter().filter(|entry| !entry.trim().is_empty()) {
+            match entry.rsplit_once('=') {
+                Some((topic, key)) => {
+                    let key = parse_key(key)
+                        .with_context(|| format!("Invalid decryption key of {}", topic))?;
+                    keys.by_topic
+                        .entry(topic.trim().to_string())
+                        .or_default()
+                        .push(key);
+                }
+                None => keys
+                    .shared
+                    .push(parse_key(entry).context("Invalid decryption key")?),
+            }
+        }
+        Ok((!keys.is_empty()).then_some(keys))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_topic.is_empty() && self.shared.is_empty()
+    }
+
+    fn keys_for<'a>(&'a self, topic: &str) -> impl Iterator<Item = &'a Key<Aes256Gcm>> {
+        self.by_topic
+            .get(topic)
+            .into_iter()
+            .flatten()
+            .chain(&self.shared)
+    }
+
+    /// Plaintext of an encrypted message, trying each key of its content topic
+    pub fn decrypt(&self, msg: &WakuMessage) -> Result<Vec<u8>, ListenerError> {
+        let topic = msg.content_topic().to_string();
+        for key in self.keys_for(&topic) {
+            if let Ok(decoded) = msg.try_decode_symmetric(key) {
+                DECRYPTED_MESSAGES.inc();
+                return Ok(decoded.data().to_vec());
+            }
+        }
+        Err(ListenerError::Decode(format!(
+            "No decryption key of {} opens the payload",
+            topic
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_payload_keys() {
+        let key = "0x".to_string() + &"ab".repeat(32);
+        let topic = "/graphcast/0/devnet/proto";
+        let keys = PayloadKeys::parse(&[format!("{}={}", topic, key), "cd".repeat(32)])
+            .unwrap()
+            .expect("Keys are configured");
+        assert_eq!(keys.keys_for(topic).count(), 2);
+        assert_eq!(
+            keys.keys_for("/graphcast/0/other/proto").count(),
+            1,
+            "Shared keys apply to every topic"
+        );
+        assert_eq!(keys.keys_for(topic).next().unwrap()[0], 0xab);
+
+        assert!(PayloadKeys::parse(&[]).unwrap().is_none());
+        assert!(PayloadKeys::parse(&["abcd".to_string()]).is_err());
+        assert!(PayloadKeys::parse(&[format!("{}=not hex", topic)]).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+//! Decoding of payload types the crate has no built-in type for, from a protobuf descriptor
+//! set supplied at runtime. Payloads are decoded as dynamic messages and stored as json under
+//! the fully qualified name of their protobuf message
+use anyhow::{anyhow, Context};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, SerializeOptions};
+use std::{fs, path::Path};
+
+use super::{MessageTypes, RadioMessage};
+use crate::message_types::StoredMessage;
+
+/// Graphcast message with its payload left encoded
+#[derive(Clone, Message, PartialEq)]
+struct EncodedGraphcastMessage {
+    #[prost(string, tag = "1")]
+    identifier: String,
+    #[prost(bytes = "vec", tag = "2")]
+    payload: Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    nonce: u64,
+    #[prost(string, tag = "4")]
+    graph_account: String,
+    #[prost(string, tag = "5")]
+    signature: String,
+}
+
+/// Read a descriptor set as written by `protoc --include_imports --descriptor_set_out`
+pub fn load_descriptors(path: &Path) -> anyhow::Result<DescriptorPool> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("Could not read descriptor set {}", path.display()))?;
+    Ok(DescriptorPool::decode(bytes.as_slice())?)
+}
+
+/// Fields the payload shares with the envelope must carry the same values, the check the
+/// built-in types do in `valid_outer`
+fn agrees_with_envelope(payload: &DynamicMessage, msg: &EncodedGraphcastMessage) -> bool {
+    let field = |name: &str| payload.get_field_by_name(name);
+    field("identifier").is_none_or(|value| value.as_str() == Some(msg.identifier.as_str()))
+        && field("graph_account")
+            .is_none_or(|value| value.as_str() == Some(msg.graph_account.as_str()))
+        && field("nonce").is_none_or(|value| value.as_u64() == Some(msg.nonce))
+}
+
+fn decode_dynamic(descriptor: &MessageDescriptor, payload: &[u8]) -> Option<RadioMessage> {
+    let msg = EncodedGraphcastMessage::decode(payload).ok()?;
+    if msg.payload.is_empty() {
+        return None;
+    }
+    let decoded = DynamicMessage::decode(descriptor.clone(), msg.payload.as_slice()).ok()?;
+    // Protobuf decoding accepts most byte strings for most messages, so payloads are only
+    // taken as this type when every byte was read as one of its fields
+    if decoded.encoded_len() != msg.payload.len() || !agrees_with_envelope(&decoded, &msg) {
+        return None;
+    }
+    let options = SerializeOptions::new()
+        .use_proto_field_name(true)
+        .stringify_64_bit_integers(false);
+    let payload = decoded
+        .serialize_with_options(serde_json::value::Serializer, &options)
+        .ok()?;
+    let stored = StoredMessage {
+        identifier: msg.identifier.clone(),
+        nonce: msg.nonce,
+        graph_account: msg.graph_account,
+        signature: msg.signature,
+        payload,
+    };
+    Some(RadioMessage::Other {
+        message_type: descriptor.full_name().to_string(),
+        identifier: msg.identifier,
+        message: serde_json::to_value(stored).ok()?,
+    })
+}
+
+impl MessageTypes {
+    /// Register messages of a descriptor set as payload types, by fully qualified name or
+    /// every message of the set when `names` is empty. They are tried after the types
+    /// registered so far
+    pub fn register_descriptors(
+        mut self,
+        pool: &DescriptorPool,
+        names: &[String],
+    ) -> anyhow::Result<Self> {
+        let descriptors: Vec<MessageDescriptor> = if names.is_empty() {
+            pool.all_messages()
+                .filter(|descriptor| !descriptor.is_map_entry())
+                .collect()
+        } else {
+            names
+                .iter()
+                .map(|name| {
+                    pool.get_message_by_name(name)
+                        .ok_or_else(|| anyhow!("Message {} is not in the descriptor set", name))
+                })
+                .collect::<anyhow::Result<_>>()?
+        };
+        for descriptor in descriptors {
+            let name = descriptor.full_name().to_string();
+            self =
+                self.register_decoder(&name, move |payload| decode_dynamic(&descriptor, payload));
+        }
+        Ok(self)
+    }
+}
@@ -0,0 +1,127 @@
+//! Sender identity checks of Graphcast messages. The signer of every decoded message is
+//! verified with the SDK against ID_VALIDATION, looking senders up in the registry and network
+//! subgraphs. Messages failing the check are still stored, flagged through
+//! [`MessageOrigin::sender_valid`](super::MessageOrigin), so they can be filtered out or
+//! inspected
+use graphcast_sdk::graphcast_agent::message_typing::{
+    GraphcastMessage, IdentityValidation, RadioPayload,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use super::RadioMessage;
+use crate::{config::Config, metrics::INVALIDATED_MESSAGES};
+
+/// How long the outcome for a signer and graph account is reused, so a busy sender does not
+/// cost a subgraph query per message
+pub const IDENTITY_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Signer address and the graph account the message claims
+type SenderKey = (String, String);
+
+#[derive(Debug)]
+pub struct SenderIdentity {
+    id_validation: IdentityValidation,
+    registry_subgraph: String,
+    network_subgraph: String,
+    /// Address of this listener, as the SDK refuses messages claiming it
+    local_sender_id: String,
+    outcomes: Mutex<HashMap<SenderKey, (bool, Instant)>>,
+}
+
+impl SenderIdentity {
+    /// None when ID_VALIDATION does not check senders
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if matches!(config.id_validation, IdentityValidation::NoCheck) {
+            return None;
+        }
+        Some(SenderIdentity {
+            id_validation: config.id_validation.clone(),
+            registry_subgraph: config.registry_subgraph.clone(),
+            network_subgraph: config.network_subgraph.clone(),
+            local_sender_id: config.indexer_address.clone().unwrap_or("none".to_string()),
+            outcomes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Whether the sender of a message passes the check, None for messages outside the
+    /// Graphcast envelope which carry no signature
+    pub async fn verify(&self, message: &RadioMessage) -> Option<bool> {
+        let valid = match message {
+            RadioMessage::PublicPoi(msg) => self.valid_sender(msg).await,
+            RadioMessage::UpgradeIntent(msg) => self.valid_sender(msg).await,
+            RadioMessage::VersionUpgrade(msg) => self.valid_sender(msg).await,
+            RadioMessage::Simple(msg) => self.valid_sender(msg).await,
+            RadioMessage::Other { .. } => return None,
+        };
+        if !valid {
+            INVALIDATED_MESSAGES
+                .with_label_values(&["sender_identity"])
+                .inc();
+        }
+        Some(valid)
+    }
+
+    async fn valid_sender<T: RadioPayload>(&self, msg: &GraphcastMessage<T>) -> bool {
+        let signer = match msg.recover_sender_address() {
+            Ok(signer) => signer,
+            Err(e) => {
+                debug!(
+                    graph_account = msg.graph_account.as_str(),
+                    err = e.to_string(),
+                    "Could not recover message signer"
+                );
+                return false;
+            }
+        };
+        let key = (signer, msg.graph_account.clone());
+        if let Some(valid) = self.cached(&key) {
+            return valid;
+        }
+
+        let valid = match msg
+            .valid_sender(
+                &self.registry_subgraph,
+                &self.network_subgraph,
+                self.local_sender_id.clone(),
+                &self.id_validation,
+            )
+            .await
+        {
+            Ok(_) => true,
+            Err(e) => {
+                debug!(
+                    signer = key.0.as_str(),
+                    graph_account = key.1.as_str(),
+                    err = e.to_string(),
+                    "Message sender failed identity validation"
+                );
+                false
+            }
+        };
+        self.remember(key, valid);
+        valid
+    }
+
+    fn cached(&self, key: &SenderKey) -> Option<bool> {
+        let outcomes = self
+            .outcomes
+            .lock()
+            .expect("Identity outcomes lock poisoned");
+        outcomes
+            .get(key)
+            .filter(|(_, checked_at)| checked_at.elapsed() < IDENTITY_CACHE_TTL)
+            .map(|(valid, _)| *valid)
+    }
+
+    fn remember(&self, key: SenderKey, valid: bool) {
+        let mut outcomes = self
+            .outcomes
+            .lock()
+            .expect("Identity outcomes lock poisoned");
+        outcomes.retain(|_, (_, checked_at)| checked_at.elapsed() < IDENTITY_CACHE_TTL);
+        outcomes.insert(key, (valid, Instant::now()));
+    }
+}
@@ -0,0 +1,344 @@
+//! Candidate message types inferred from payloads no registered type decodes. Payloads are
+//! read as protobuf wire format without a schema and grouped by content topic and field
+//! layout, so maintainers can see which decoders are worth adding
+use async_graphql::SimpleObject;
+use prost::encoding::decode_varint;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+use crate::db::resolver::UndecodedPayload;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WireKind {
+    Varint,
+    Fixed64,
+    Fixed32,
+    String,
+    Bytes,
+    Message,
+}
+
+impl fmt::Display for WireKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WireKind::Varint => "varint",
+            WireKind::Fixed64 => "fixed64",
+            WireKind::Fixed32 => "fixed32",
+            WireKind::String => "string",
+            WireKind::Bytes => "bytes",
+            WireKind::Message => "message",
+        };
+        f.write_str(name)
+    }
+}
+
+struct Field<'a> {
+    number: u32,
+    kind: WireKind,
+    value: &'a [u8],
+}
+
+/// Guess what a length delimited value holds: printable text, a nested message, or bytes
+fn length_delimited_kind(value: &[u8]) -> WireKind {
+    match std::str::from_utf8(value) {
+        Ok(text) if text.chars().all(|c| !c.is_control() || c.is_whitespace()) => WireKind::String,
+        _ if !value.is_empty() && parse_fields(value).is_some() => WireKind::Message,
+        _ => WireKind::Bytes,
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    let value = bytes.get(..len)?;
+    *bytes = &bytes[len..];
+    Some(value)
+}
+
+/// Fields of a protobuf message in wire order, None when the bytes are not one
+fn parse_fields(mut bytes: &[u8]) -> Option<Vec<Field<'_>>> {
+    let mut fields = vec![];
+    while !bytes.is_empty() {
+        let key = decode_varint(&mut bytes).ok()?;
+        let number = u32::try_from(key >> 3).ok().filter(|number| *number > 0)?;
+        let (kind, value) = match key & 0x7 {
+            0 => {
+                let start = bytes;
+                decode_varint(&mut bytes).ok()?;
+                (WireKind::Varint, &start[..start.len() - bytes.len()])
+            }
+            1 => (WireKind::Fixed64, take(&mut bytes, 8)?),
+            2 => {
+                let len = usize::try_from(decode_varint(&mut bytes).ok()?).ok()?;
+                let value = take(&mut bytes, len)?;
+                (length_delimited_kind(value), value)
+            }
+            5 => (WireKind::Fixed32, take(&mut bytes, 4)?),
+            _ => return None,
+        };
+        fields.push(Field {
+            number,
+            kind,
+            value,
+        });
+    }
+    Some(fields)
+}
+
+/// Field layout of a payload, by field number with whether the field was repeated
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Shape {
+    graphcast_envelope: bool,
+    fields: Option<BTreeMap<u32, (WireKind, bool)>>,
+}
+
+impl Shape {
+    /// Layout of the radio payload of Graphcast messages, of the whole bytes otherwise
+    fn of(payload: &[u8]) -> Self {
+        let fields = parse_fields(payload);
+        let envelope_payload = fields.as_ref().and_then(|fields| {
+            let kind = |number| {
+                fields
+                    .iter()
+                    .find(|field| field.number == number)
+                    .map(|field| field.kind)
+            };
+            let is_envelope = kind(1) == Some(WireKind::String)
+                && kind(3) == Some(WireKind::Varint)
+                && kind(4) == Some(WireKind::String);
+            is_envelope
+                .then(|| fields.iter().find(|field| field.number == 2))
+                .flatten()
+                .map(|field| field.value)
+        });
+        let fields = match envelope_payload {
+            Some(value) => parse_fields(value),
+            None => fields,
+        };
+        Shape {
+            graphcast_envelope: envelope_payload.is_some(),
+            fields: fields.map(|fields| {
+                let mut layout = BTreeMap::new();
+                for field in fields {
+                    layout
+                        .entry(field.number)
+                        .and_modify(|(_, repeated)| *repeated = true)
+                        .or_insert((field.kind, false));
+                }
+                layout
+            }),
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.fields.as_ref().map_or(0, BTreeMap::len)
+    }
+
+    /// Whether `other` could be the same type with fields left at their default, which
+    /// protobuf does not encode
+    fn includes(&self, other: &Shape) -> bool {
+        if self.graphcast_envelope != other.graphcast_envelope {
+            return false;
+        }
+        match (&self.fields, &other.fields) {
+            (Some(fields), Some(other)) => other.iter().all(|(number, (kind, _))| {
+                fields
+                    .get(number)
+                    .is_some_and(|(field_kind, _)| field_kind == kind)
+            }),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    fn merge(&mut self, other: &Shape) {
+        if let (Some(fields), Some(other)) = (&mut self.fields, &other.fields) {
+            for (number, (_, repeated)) in other {
+                if let Some(field) = fields.get_mut(number) {
+                    field.1 |= repeated;
+                }
+            }
+        }
+    }
+}
+
+/// Payloads sharing a content topic and field layout, likely of one message type
+#[derive(Clone, Debug, SimpleObject)]
+pub struct CandidateType {
+    pub content_topic: String,
+    /// Fields as `number:kind`, `[]` marking repeated ones. Empty when the payloads are not
+    /// protobuf messages
+    pub fields: Vec<String>,
+    /// Whether the payloads are wrapped in a Graphcast message, whose radio payload the
+    /// fields describe
+    pub graphcast_envelope: bool,
+    pub message_count: i64,
+    pub sender_count: i64,
+    /// Unix timestamps of the first and last payload received
+    pub first_seen: i64,
+    pub last_seen: i64,
+    /// Hex encoded payloads
+    pub samples: Vec<String>,
+}
+
+struct Group<'a> {
+    content_topic: &'a str,
+    shape: Shape,
+    senders: HashSet<&'a str>,
+    message_count: i64,
+    first_seen: i64,
+    last_seen: i64,
+    samples: Vec<String>,
+}
+
+/// Group payloads into candidate types, the most frequent first, with up to `max_samples`
+/// sample payloads each
+pub fn infer_candidate_types(
+    payloads: &[UndecodedPayload],
+    max_samples: usize,
+) -> Vec<CandidateType> {
+    let mut shaped: Vec<(&UndecodedPayload, Shape)> = payloads
+        .iter()
+        .map(|payload| (payload, Shape::of(&payload.payload)))
+        .collect();
+    // Widest layouts first, so payloads with fields left at their default join them
+    shaped.sort_by_key(|(_, shape)| Reverse(shape.width()));
+
+    let mut groups: Vec<Group> = vec![];
+    for (payload, shape) in shaped {
+        let group = match groups.iter().position(|group| {
+            group.content_topic == payload.content_topic && group.shape.includes(&shape)
+        }) {
+            Some(index) => {
+                groups[index].shape.merge(&shape);
+                &mut groups[index]
+            }
+            None => {
+                groups.push(Group {
+                    content_topic: &payload.content_topic,
+                    shape,
+                    senders: HashSet::new(),
+                    message_count: 0,
+                    first_seen: payload.created_at,
+                    last_seen: payload.created_at,
+                    samples: vec![],
+                });
+                groups.last_mut().expect("Group was just added")
+            }
+        };
+        group.message_count += 1;
+        group.first_seen = group.first_seen.min(payload.created_at);
+        group.last_seen = group.last_seen.max(payload.created_at);
+        if let Some(sender) = &payload.graph_account {
+            group.senders.insert(sender);
+        }
+        if group.samples.len() < max_samples {
+            group.samples.push(
+                payload
+                    .payload
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect(),
+            );
+        }
+    }
+
+    groups.sort_by_key(|group| Reverse(group.message_count));
+    groups
+        .into_iter()
+        .map(|group| CandidateType {
+            content_topic: group.content_topic.to_string(),
+            fields: group
+                .shape
+                .fields
+                .iter()
+                .flatten()
+                .map(|(number, (kind, repeated))| {
+                    format!("{}:{}{}", number, kind, if *repeated { "[]" } else { "" })
+                })
+                .collect(),
+            graphcast_envelope: group.shape.graphcast_envelope,
+            message_count: group.message_count,
+            sender_count: group.senders.len() as i64,
+            first_seen: group.first_seen,
+            last_seen: group.last_seen,
+            samples: group.samples,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[derive(Clone, Message, PartialEq)]
+    struct Status {
+        #[prost(string, tag = "1")]
+        deployment: String,
+        #[prost(uint64, tag = "2")]
+        latest_block: u64,
+        #[prost(string, repeated, tag = "3")]
+        errors: Vec<String>,
+    }
+
+    #[derive(Clone, Message, PartialEq)]
+    struct Envelope {
+        #[prost(string, tag = "1")]
+        identifier: String,
+        #[prost(message, optional, tag = "2")]
+        payload: Option<Status>,
+        #[prost(uint64, tag = "3")]
+        nonce: u64,
+        #[prost(string, tag = "4")]
+        graph_account: String,
+    }
+
+    fn undecoded(content_topic: &str, payload: Vec<u8>, created_at: i64) -> UndecodedPayload {
+        UndecodedPayload {
+            content_topic: content_topic.to_string(),
+            payload,
+            graph_account: Some("0xb4b4".to_string()),
+            created_at,
+        }
+    }
+
+    #[test]
+    fn test_infer_candidate_types() {
+        let status = |latest_block, errors: &[&str]| {
+            Envelope {
+                identifier: "QmTamam".to_string(),
+                payload: Some(Status {
+                    deployment: "QmTamam".to_string(),
+                    latest_block,
+                    errors: errors.iter().map(|e| e.to_string()).collect(),
+                }),
+                nonce: 1707328517,
+                graph_account: "0xb4b4".to_string(),
+            }
+            .encode_to_vec()
+        };
+        let payloads = vec![
+            undecoded("status", status(10, &["timeout", "reverted"]), 3),
+            // Default values are not encoded, still the same type
+            undecoded("status", status(0, &[]), 2),
+            undecoded("status", b"\xff\xff".to_vec(), 1),
+        ];
+
+        let candidates = infer_candidate_types(&payloads, 1);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(
+            candidates[0].fields,
+            vec!["1:string", "2:varint", "3:string[]"]
+        );
+        assert!(candidates[0].graphcast_envelope);
+        assert_eq!(candidates[0].message_count, 2);
+        assert_eq!(candidates[0].sender_count, 1);
+        assert_eq!((candidates[0].first_seen, candidates[0].last_seen), (2, 3));
+        assert_eq!(candidates[0].samples.len(), 1);
+        assert!(candidates[1].fields.is_empty());
+        assert!(!candidates[1].graphcast_envelope);
+        assert_eq!(candidates[1].samples, vec!["ffff"]);
+    }
+}
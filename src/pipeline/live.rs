@@ -0,0 +1,59 @@
+//! Feed of newly stored messages for GraphQL subscribers. Messages are only copied into the
+//! feed while someone is subscribed, and subscribers falling behind miss the oldest ones
+use async_graphql::SimpleObject;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast;
+
+use super::RadioMessage;
+
+/// Messages kept for subscribers that have not read them yet
+const FEED_CAPACITY: usize = 1024;
+
+static FEED: Lazy<broadcast::Sender<LiveMessage>> =
+    Lazy::new(|| broadcast::channel(FEED_CAPACITY).0);
+
+/// Newly stored message
+#[derive(Clone, Debug, SimpleObject)]
+pub struct LiveMessage {
+    pub id: i64,
+    pub message_type: String,
+    pub identifier: String,
+    pub graph_account: Option<String>,
+    pub nonce: Option<i64>,
+    /// Unix timestamp the message was stored at
+    pub received_at: i64,
+    /// Json of the message as stored, see docs/message-schema.md
+    pub message: String,
+}
+
+impl LiveMessage {
+    pub fn new(id: i64, message: &RadioMessage) -> Self {
+        let stored = message.stored_json();
+        LiveMessage {
+            id,
+            message_type: message.message_type().to_string(),
+            identifier: message.identifier().to_string(),
+            graph_account: stored
+                .get("graph_account")
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_string),
+            nonce: stored.get("nonce").and_then(serde_json::Value::as_i64),
+            received_at: Utc::now().timestamp(),
+            message: stored.to_string(),
+        }
+    }
+}
+
+pub fn subscribed() -> bool {
+    FEED.receiver_count() > 0
+}
+
+pub fn publish(message: LiveMessage) {
+    // Fails only when the last subscriber left since the check
+    let _ = FEED.send(message);
+}
+
+pub fn subscribe() -> broadcast::Receiver<LiveMessage> {
+    FEED.subscribe()
+}
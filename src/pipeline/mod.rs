@@ -0,0 +1,1010 @@
+//! Ingest pipeline of the listener: raw Waku messages are pulled from a [`MessageSource`],
+//! decoded into a [`RadioMessage`], checked by a [`Validator`] and persisted by a [`MessageStore`]
+use graphcast_sdk::{
+    graphcast_agent::message_typing::{GraphcastMessage, RadioPayload},
+    WakuMessage,
+};
+use prost::Message;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{PgExecutor, Pool, Postgres};
+use std::borrow::Cow;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{trace, trace_span, warn, Instrument};
+
+pub mod decryption;
+pub mod descriptors;
+pub mod identity;
+pub mod inference;
+pub mod live;
+pub mod nonces;
+pub mod validation;
+
+use self::decryption::{PayloadKeys, ENCRYPTED_PAYLOAD_VERSION};
+use self::identity::SenderIdentity;
+use self::live::LiveMessage;
+use self::nonces::NonceTracker;
+pub use self::validation::PayloadValidator;
+use self::validation::{canonical_deployment, normalize_poi};
+
+use crate::{
+    db::resolver::{
+        add_captured_message, add_dead_letter, add_message_from, add_messages_from,
+        add_outbox_entry, add_raw_message, notify_message,
+    },
+    message_types::{
+        PublicPoiMessage, SimpleMessage, StoredMessage, UpgradeIntentMessage, VersionUpgradeMessage,
+    },
+    metrics::{
+        labels::deployment_label, INVALIDATED_MESSAGES, PIPELINE_STAGE_SECONDS,
+        QUARANTINED_MESSAGES, UNDECODED_FIELD_MESSAGES, VALIDATED_MESSAGES,
+    },
+    shutdown::stopping,
+    ListenerError,
+};
+
+/// Set once a message with undecoded fields is seen, so the upgrade hint is logged once
+static UNDECODED_FIELDS_SEEN: AtomicBool = AtomicBool::new(false);
+
+/// Source of raw Waku messages, blocking until the next message is available
+pub trait MessageSource: Send + 'static {
+    /// Returns None once the source is closed
+    fn next_message(&mut self) -> Option<WakuMessage>;
+
+    /// Next message along with the peer that delivered it, for sources that know it
+    fn next_delivery(&mut self) -> Option<(WakuMessage, Option<String>)> {
+        self.next_message().map(|msg| (msg, None))
+    }
+}
+
+/// How often a waiting source checks whether the listener is shutting down
+const SOURCE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The Graphcast agent delivers gossip through a std channel. The agent keeps its sender, so
+/// the channel is closed on shutdown once it stayed empty for a poll interval
+impl MessageSource for Receiver<WakuMessage> {
+    fn next_message(&mut self) -> Option<WakuMessage> {
+        loop {
+            match self.recv_timeout(SOURCE_POLL_INTERVAL) {
+                Ok(msg) => return Some(msg),
+                Err(RecvTimeoutError::Timeout) if !stopping() => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// Decoded Graphcast message, built-in radio types are kept typed while types registered
+/// by library users are carried as json
+#[derive(Clone, Debug)]
+pub enum RadioMessage {
+    PublicPoi(GraphcastMessage<PublicPoiMessage>),
+    UpgradeIntent(GraphcastMessage<UpgradeIntentMessage>),
+    VersionUpgrade(GraphcastMessage<VersionUpgradeMessage>),
+    Simple(GraphcastMessage<SimpleMessage>),
+    Other {
+        message_type: String,
+        identifier: String,
+        message: serde_json::Value,
+    },
+}
+
+impl RadioMessage {
+    pub fn identifier(&self) -> &str {
+        match self {
+            RadioMessage::PublicPoi(msg) => &msg.identifier,
+            RadioMessage::UpgradeIntent(msg) => &msg.identifier,
+            RadioMessage::VersionUpgrade(msg) => &msg.identifier,
+            RadioMessage::Simple(msg) => &msg.identifier,
+            RadioMessage::Other { identifier, .. } => identifier,
+        }
+    }
+
+    /// Graph account and nonce of the envelope, None for json messages without them
+    pub fn sender(&self) -> Option<(&str, i64)> {
+        let (graph_account, nonce) = match self {
+            RadioMessage::PublicPoi(msg) => (msg.graph_account.as_str(), msg.nonce),
+            RadioMessage::UpgradeIntent(msg) => (msg.graph_account.as_str(), msg.nonce),
+            RadioMessage::VersionUpgrade(msg) => (msg.graph_account.as_str(), msg.nonce),
+            RadioMessage::Simple(msg) => (msg.graph_account.as_str(), msg.nonce),
+            RadioMessage::Other { message, .. } => (
+                message.get("graph_account")?.as_str()?,
+                message.get("nonce")?.as_u64()?,
+            ),
+        };
+        Some((graph_account, i64::try_from(nonce).ok()?))
+    }
+
+    /// Rewrite fields with several equivalent spellings into their canonical form, so
+    /// identical values are grouped together. Fields that cannot be normalized are
+    /// reported as a validation failure
+    pub fn normalize(&mut self) -> Result<(), ListenerError> {
+        match self {
+            RadioMessage::PublicPoi(msg) => {
+                normalize_deployment(&mut msg.identifier);
+                normalize_deployment(&mut msg.payload.identifier);
+            }
+            RadioMessage::UpgradeIntent(msg) => {
+                normalize_deployment(&mut msg.payload.deployment);
+                normalize_deployment(&mut msg.payload.new_hash);
+            }
+            RadioMessage::VersionUpgrade(msg) => {
+                normalize_deployment(&mut msg.identifier);
+                normalize_deployment(&mut msg.payload.identifier);
+                normalize_deployment(&mut msg.payload.new_hash);
+            }
+            RadioMessage::Simple(_) | RadioMessage::Other { .. } => {}
+        }
+        if let RadioMessage::PublicPoi(msg) = self {
+            msg.payload.content = normalize_poi(&msg.payload.content).map_err(|reason| {
+                QUARANTINED_MESSAGES
+                    .with_label_values(&["poi_normalization"])
+                    .inc();
+                ListenerError::Validation(format!("poi_normalization: {}", reason))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Protobuf length of the decoded message, None for types carried as json
+    fn encoded_len(&self) -> Option<usize> {
+        match self {
+            RadioMessage::PublicPoi(msg) => Some(msg.encoded_len()),
+            RadioMessage::UpgradeIntent(msg) => Some(msg.encoded_len()),
+            RadioMessage::VersionUpgrade(msg) => Some(msg.encoded_len()),
+            RadioMessage::Simple(msg) => Some(msg.encoded_len()),
+            RadioMessage::Other { .. } => None,
+        }
+    }
+
+    /// Json of the message in the [`StoredMessage`] layout
+    pub fn stored_json(&self) -> serde_json::Value {
+        match self {
+            RadioMessage::PublicPoi(msg) => serde_json::to_value(StoredMessage::from(msg.clone())),
+            RadioMessage::UpgradeIntent(msg) => {
+                serde_json::to_value(StoredMessage::from(msg.clone()))
+            }
+            RadioMessage::VersionUpgrade(msg) => {
+                serde_json::to_value(StoredMessage::from(msg.clone()))
+            }
+            RadioMessage::Simple(msg) => serde_json::to_value(StoredMessage::from(msg.clone())),
+            RadioMessage::Other { message, .. } => Ok(message.clone()),
+        }
+        .unwrap_or_default()
+    }
+
+    /// Name the message type was registered under
+    pub fn message_type(&self) -> &str {
+        match self {
+            RadioMessage::PublicPoi(_) => "public_poi",
+            RadioMessage::UpgradeIntent(_) => "upgrade_intent",
+            RadioMessage::VersionUpgrade(_) => "version_upgrade",
+            RadioMessage::Simple(_) => "simple",
+            RadioMessage::Other { message_type, .. } => message_type,
+        }
+    }
+}
+
+/// Where a message was received from and how it was decoded, stored along with it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MessageOrigin<'a> {
+    /// Name of the registered type the message was decoded as
+    pub message_type: Option<&'a str>,
+    /// Peer that delivered the message, when the Waku layer exposes it
+    pub peer: Option<&'a str>,
+    /// Content topic the message was received on
+    pub content_topic: Option<&'a str>,
+    /// Graphcast protocol version of the content topic
+    pub protocol_version: Option<i32>,
+    /// Payload bytes of fields the decoder skipped, a sign of a sender on a newer SDK
+    pub unknown_bytes: Option<i32>,
+    /// Whether the sender passed the identity check, None when it was not checked
+    pub sender_valid: Option<bool>,
+}
+
+/// Origin of a message received from the Waku network, `content_topic` being its rendered topic
+fn delivery_origin<'a>(
+    msg: &WakuMessage,
+    content_topic: &'a str,
+    peer: Option<&'a str>,
+) -> MessageOrigin<'a> {
+    MessageOrigin {
+        peer,
+        content_topic: Some(content_topic),
+        protocol_version: msg.content_topic().version.parse::<i32>().ok(),
+        ..Default::default()
+    }
+}
+
+/// Rewrite deployment hashes sent as bytes32 hex into the CIDv0 form, other identifiers are kept
+fn normalize_deployment(identifier: &mut String) {
+    if let Some(canonical) = canonical_deployment(identifier) {
+        *identifier = canonical;
+    }
+}
+
+type DecodeFn = Box<dyn Fn(&[u8]) -> Option<RadioMessage> + Send + Sync>;
+
+/// Registry of the message types the pipeline attempts to decode, tried in registration order
+pub struct MessageTypes {
+    decoders: Vec<(String, DecodeFn)>,
+    /// Types left out by [`MessageTypes::only`], only tried to recognize messages to drop
+    ignored: Vec<(String, DecodeFn)>,
+}
+
+impl Default for MessageTypes {
+    /// The radio message types shipped with listener-radio
+    fn default() -> Self {
+        MessageTypes::empty()
+            .register_decoder("public_poi", |payload| {
+                GraphcastMessage::<PublicPoiMessage>::decode(payload)
+                    .ok()
+                    .map(RadioMessage::PublicPoi)
+            })
+            // Upgrade intents also decode as version upgrades, so those are only accepted
+            // when the payload agrees with the envelope
+            .register_decoder("version_upgrade", |payload| {
+                GraphcastMessage::<VersionUpgradeMessage>::decode(payload)
+                    .ok()
+                    .filter(|msg| msg.payload.valid_outer(msg).is_ok())
+                    .map(RadioMessage::VersionUpgrade)
+            })
+            .register_decoder("upgrade_intent", |payload| {
+                GraphcastMessage::<UpgradeIntentMessage>::decode(payload)
+                    .ok()
+                    .map(RadioMessage::UpgradeIntent)
+            })
+            .register_decoder("simple", |payload| {
+                GraphcastMessage::<SimpleMessage>::decode(payload)
+                    .ok()
+                    .map(RadioMessage::Simple)
+            })
+    }
+}
+
+impl MessageTypes {
+    pub fn empty() -> Self {
+        MessageTypes {
+            decoders: vec![],
+            ignored: vec![],
+        }
+    }
+
+    /// Register an additional radio payload type, decoded messages are stored as json in the
+    /// [`StoredMessage`] layout
+    pub fn register<T>(self, name: &str) -> Self
+    where
+        T: RadioPayload + Serialize,
+    {
+        let message_type = name.to_string();
+        self.register_decoder(name, move |payload| {
+            let msg = GraphcastMessage::<T>::decode(payload).ok()?;
+            Some(RadioMessage::Other {
+                message_type: message_type.clone(),
+                identifier: msg.identifier.clone(),
+                message: serde_json::to_value(StoredMessage::from(msg)).ok()?,
+            })
+        })
+    }
+
+    /// Register a custom decoding function under a message type name
+    pub fn register_decoder<F>(mut self, name: &str, decode: F) -> Self
+    where
+        F: Fn(&[u8]) -> Option<RadioMessage> + Send + Sync + 'static,
+    {
+        self.decoders.push((name.to_string(), Box::new(decode)));
+        self
+    }
+
+    /// Decode and store the `names` types only. Messages of the other registered types are
+    /// dropped once recognized, instead of being kept as raw messages
+    pub fn only(mut self, names: &[String]) -> anyhow::Result<Self> {
+        if let Some(unknown) = names
+            .iter()
+            .find(|name| !self.names().contains(&name.as_str()))
+        {
+            anyhow::bail!(
+                "Unknown message type {}, registered types are {}",
+                unknown,
+                self.names().join(", ")
+            );
+        }
+        let (decoders, ignored): (Vec<_>, Vec<_>) = self
+            .decoders
+            .into_iter()
+            .partition(|(name, _)| names.contains(name));
+        self.decoders = decoders;
+        self.ignored.extend(ignored);
+        Ok(self)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.decoders
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    pub fn decode(&self, payload: &[u8]) -> Result<RadioMessage, ListenerError> {
+        if let Some(message) = self.decoders.iter().find_map(|(_, decode)| decode(payload)) {
+            return Ok(message);
+        }
+        match self
+            .ignored
+            .iter()
+            .find(|(_, decode)| decode(payload).is_some())
+        {
+            Some((name, _)) => Err(ListenerError::Ignored(name.clone())),
+            None => Err(ListenerError::Decode(
+                "Unsupported message types".to_string(),
+            )),
+        }
+    }
+}
+
+/// Check applied to decoded messages before they are stored, rejections are reported as
+/// [`ListenerError::Validation`]
+pub trait Validator: Send + Sync {
+    fn validate(&self, message: &RadioMessage) -> Result<(), ListenerError>;
+}
+
+/// Accept every message that decodes
+#[derive(Clone, Debug, Default)]
+pub struct AcceptAll;
+
+impl Validator for AcceptAll {
+    fn validate(&self, _message: &RadioMessage) -> Result<(), ListenerError> {
+        Ok(())
+    }
+}
+
+/// Persistence for decoded messages and for raw messages that could not be processed
+pub trait MessageStore: Send + Sync {
+    /// Returns the new row id, or None if the message was already stored
+    fn store(
+        &self,
+        message: RadioMessage,
+    ) -> impl Future<Output = Result<Option<i64>, ListenerError>> + Send;
+
+    /// Store a message along with its origin, stores that do not track origins drop it
+    fn store_from(
+        &self,
+        message: RadioMessage,
+        _origin: MessageOrigin<'_>,
+    ) -> impl Future<Output = Result<Option<i64>, ListenerError>> + Send {
+        self.store(message)
+    }
+
+    /// Store messages received together, returning the new row id or None for each in
+    /// order. Stores without a bulk insert store them one by one
+    fn store_batch(
+        &self,
+        messages: &[(RadioMessage, MessageOrigin<'_>)],
+    ) -> impl Future<Output = Result<Vec<Option<i64>>, ListenerError>> + Send {
+        async move {
+            let mut ids = Vec::with_capacity(messages.len());
+            for (message, origin) in messages {
+                ids.push(self.store_from(message.clone(), *origin).await?);
+            }
+            Ok(ids)
+        }
+    }
+
+    fn store_dead_letter(
+        &self,
+        msg: &WakuMessage,
+        category: &str,
+        error: &str,
+    ) -> impl Future<Output = Result<i64, ListenerError>> + Send;
+
+    /// Keep a message no registered type could decode, stores without a raw table drop it
+    fn store_raw(
+        &self,
+        _msg: &WakuMessage,
+        _peer: Option<&str>,
+    ) -> impl Future<Output = Result<(), ListenerError>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Record a raw message for the debug capture, stores without a capture table ignore it
+    fn store_capture(
+        &self,
+        _msg: &WakuMessage,
+        _peer: Option<&str>,
+    ) -> impl Future<Output = Result<(), ListenerError>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// Postgres storage of an instance namespace
+#[derive(Clone, Debug)]
+pub struct PostgresStore {
+    pool: Pool<Postgres>,
+    namespace: String,
+    notify_channel: Option<String>,
+    outbox: bool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: Pool<Postgres>, namespace: String) -> Self {
+        PostgresStore {
+            pool,
+            namespace,
+            notify_channel: None,
+            outbox: false,
+        }
+    }
+
+    /// Publish a `pg_notify` on the channel for every newly stored message, in the inserting
+    /// transaction
+    pub fn with_notify_channel(mut self, notify_channel: Option<String>) -> Self {
+        self.notify_channel = notify_channel;
+        self
+    }
+
+    /// Record every newly stored message in the outbox within the same transaction
+    pub fn with_outbox(mut self, outbox: bool) -> Self {
+        self.outbox = outbox;
+        self
+    }
+}
+
+async fn insert_message<'e, E: PgExecutor<'e>>(
+    executor: E,
+    namespace: &str,
+    message: RadioMessage,
+    origin: MessageOrigin<'_>,
+) -> Result<Option<i64>, ListenerError> {
+    let message_type = message.message_type().to_string();
+    let origin = MessageOrigin {
+        message_type: Some(&message_type),
+        ..origin
+    };
+    match message {
+        RadioMessage::PublicPoi(msg) => {
+            add_message_from(executor, namespace, StoredMessage::from(msg), origin).await
+        }
+        RadioMessage::UpgradeIntent(msg) => {
+            add_message_from(executor, namespace, StoredMessage::from(msg), origin).await
+        }
+        RadioMessage::VersionUpgrade(msg) => {
+            add_message_from(executor, namespace, StoredMessage::from(msg), origin).await
+        }
+        RadioMessage::Simple(msg) => {
+            add_message_from(executor, namespace, StoredMessage::from(msg), origin).await
+        }
+        RadioMessage::Other { message, .. } => {
+            add_message_from(executor, namespace, message, origin).await
+        }
+    }
+}
+
+impl MessageStore for PostgresStore {
+    async fn store(&self, message: RadioMessage) -> Result<Option<i64>, ListenerError> {
+        self.store_from(message, MessageOrigin::default()).await
+    }
+
+    async fn store_from(
+        &self,
+        message: RadioMessage,
+        origin: MessageOrigin<'_>,
+    ) -> Result<Option<i64>, ListenerError> {
+        let (pool, namespace) = (&self.pool, self.namespace.as_str());
+        let summary = json!({
+            "namespace": namespace,
+            "message_type": message.message_type(),
+            "identifier": message.identifier(),
+        });
+        // The outbox entry and notification are part of the inserting transaction, so a
+        // message is either stored with both or not at all
+        if !self.outbox && self.notify_channel.is_none() {
+            return insert_message(pool, namespace, message, origin).await;
+        }
+        let mut tx = pool.begin().await?;
+        let id = insert_message(&mut *tx, namespace, message, origin).await?;
+        if let Some(id) = id {
+            if self.outbox {
+                add_outbox_entry(&mut *tx, id).await?;
+            }
+            if let Some(channel) = &self.notify_channel {
+                notify_message(&mut *tx, channel, id, summary).await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    async fn store_batch(
+        &self,
+        messages: &[(RadioMessage, MessageOrigin<'_>)],
+    ) -> Result<Vec<Option<i64>>, ListenerError> {
+        let (pool, namespace) = (&self.pool, self.namespace.as_str());
+        let rows: Vec<(serde_json::Value, MessageOrigin)> = messages
+            .iter()
+            .map(|(message, origin)| {
+                let origin = MessageOrigin {
+                    message_type: Some(message.message_type()),
+                    ..*origin
+                };
+                (message.stored_json(), origin)
+            })
+            .collect();
+        if !self.outbox && self.notify_channel.is_none() {
+            return add_messages_from(pool, namespace, &rows).await;
+        }
+        let mut tx = pool.begin().await?;
+        let ids = add_messages_from(&mut *tx, namespace, &rows).await?;
+        for ((message, _), id) in messages.iter().zip(&ids) {
+            let Some(id) = *id else {
+                continue;
+            };
+            if self.outbox {
+                add_outbox_entry(&mut *tx, id).await?;
+            }
+            if let Some(channel) = &self.notify_channel {
+                let summary = json!({
+                    "namespace": namespace,
+                    "message_type": message.message_type(),
+                    "identifier": message.identifier(),
+                });
+                notify_message(&mut *tx, channel, id, summary).await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(ids)
+    }
+
+    async fn store_dead_letter(
+        &self,
+        msg: &WakuMessage,
+        category: &str,
+        error: &str,
+    ) -> Result<i64, ListenerError> {
+        add_dead_letter(
+            &self.pool,
+            &self.namespace,
+            &msg.content_topic().to_string(),
+            msg.payload(),
+            msg.timestamp() as i64,
+            category,
+            error,
+        )
+        .await
+    }
+
+    async fn store_raw(&self, msg: &WakuMessage, peer: Option<&str>) -> Result<(), ListenerError> {
+        add_raw_message(
+            &self.pool,
+            &self.namespace,
+            &msg.content_topic().to_string(),
+            msg.version() as i32,
+            msg.payload(),
+            msg.timestamp() as i64,
+            peer,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn store_capture(
+        &self,
+        msg: &WakuMessage,
+        peer: Option<&str>,
+    ) -> Result<(), ListenerError> {
+        add_captured_message(
+            &self.pool,
+            &self.namespace,
+            &msg.content_topic().to_string(),
+            msg.version() as i32,
+            msg.payload(),
+            msg.timestamp() as i64,
+            peer,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// Decode, validate and store messages
+pub struct Pipeline<V: Validator, S: MessageStore> {
+    message_types: MessageTypes,
+    validator: V,
+    store: S,
+    nonces: Option<Arc<NonceTracker>>,
+    identity: Option<Arc<SenderIdentity>>,
+    keys: Option<Arc<PayloadKeys>>,
+}
+
+impl<V: Validator, S: MessageStore> Pipeline<V, S> {
+    pub fn new(message_types: MessageTypes, validator: V, store: S) -> Self {
+        Pipeline {
+            message_types,
+            validator,
+            store,
+            nonces: None,
+            identity: None,
+            keys: None,
+        }
+    }
+
+    /// Check the nonce ordering of every sender after validation. The tracker only persists
+    /// its nonces when flushed, see [`nonces::run_nonce_flush`]
+    pub fn with_nonce_tracker(mut self, nonces: Arc<NonceTracker>) -> Self {
+        self.nonces = Some(nonces);
+        self
+    }
+
+    /// Check the sender identity of every valid message, recording the outcome with it
+    pub fn with_sender_identity(mut self, identity: Arc<SenderIdentity>) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Decrypt encrypted payloads with the keys of their content topic before decoding
+    pub fn with_decryption_keys(mut self, keys: Arc<PayloadKeys>) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the new row id, or None for an already stored message
+    pub async fn process(&self, msg: &WakuMessage) -> Result<Option<i64>, ListenerError> {
+        self.process_from(msg, None).await
+    }
+
+    /// Process a message delivered by `peer`
+    pub async fn process_from(
+        &self,
+        msg: &WakuMessage,
+        peer: Option<&str>,
+    ) -> Result<Option<i64>, ListenerError> {
+        let content_topic = msg.content_topic().to_string();
+        let origin = delivery_origin(msg, &content_topic, peer);
+        let payload = self.plaintext(msg)?;
+        self.process_origin(&payload, origin).await.map_err(|e| {
+            if let ListenerError::Decode(_) = e {
+                trace!(
+                    topic = tracing::field::debug(msg.content_topic()),
+                    "Message decode failed"
+                );
+            }
+            e
+        })
+    }
+
+    /// Process messages delivered together, storing the valid ones with a single
+    /// [`MessageStore::store_batch`]. Results are in delivery order. When the batch cannot be
+    /// stored its messages are stored one by one, so one failing message does not fail the rest
+    pub async fn process_batch(
+        &self,
+        deliveries: &[(WakuMessage, Option<String>)],
+    ) -> Vec<Result<Option<i64>, ListenerError>> {
+        let content_topics: Vec<String> = deliveries
+            .iter()
+            .map(|(msg, _)| msg.content_topic().to_string())
+            .collect();
+        let mut results = Vec::with_capacity(deliveries.len());
+        let mut batch = vec![];
+        let mut positions = vec![];
+        for ((msg, peer), content_topic) in deliveries.iter().zip(&content_topics) {
+            let mut origin = delivery_origin(msg, content_topic, peer.as_deref());
+            match self
+                .plaintext(msg)
+                .and_then(|payload| self.prepare(&payload, &mut origin))
+            {
+                Ok(message) => {
+                    origin.sender_valid = self.verify_sender(&message).await;
+                    positions.push(results.len());
+                    batch.push((message, origin));
+                    results.push(Ok(None));
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+        if batch.is_empty() {
+            return results;
+        }
+
+        let insert = PIPELINE_STAGE_SECONDS
+            .with_label_values(&["insert"])
+            .start_timer();
+        let stored = self
+            .store
+            .store_batch(&batch)
+            .instrument(trace_span!("insert", messages = batch.len()))
+            .await;
+        insert.observe_duration();
+        match stored {
+            Ok(ids) => {
+                for ((position, (message, _)), id) in positions.iter().zip(&batch).zip(ids) {
+                    if let Some(id) = id {
+                        if live::subscribed() {
+                            live::publish(LiveMessage::new(id, message));
+                        }
+                    }
+                    results[*position] = Ok(id);
+                }
+            }
+            Err(e) => {
+                warn!(
+                    err = tracing::field::debug(e),
+                    messages = batch.len(),
+                    "Batch insert failed, storing the messages one by one"
+                );
+                for (position, (message, origin)) in positions.into_iter().zip(batch) {
+                    results[position] = self.store_prepared(message, origin).await;
+                }
+            }
+        }
+        results
+    }
+
+    /// Process a raw payload, such as a requeued dead letter
+    pub async fn process_payload(
+        &self,
+        payload: &[u8],
+        peer: Option<&str>,
+    ) -> Result<Option<i64>, ListenerError> {
+        let origin = MessageOrigin {
+            peer,
+            ..Default::default()
+        };
+        self.process_origin(payload, origin).await
+    }
+
+    async fn process_origin(
+        &self,
+        payload: &[u8],
+        mut origin: MessageOrigin<'_>,
+    ) -> Result<Option<i64>, ListenerError> {
+        let message = self.prepare(payload, &mut origin)?;
+        origin.sender_valid = self.verify_sender(&message).await;
+        self.store_prepared(message, origin).await
+    }
+
+    /// Payload of a delivered message, decrypted when it is encrypted and keys are configured.
+    /// Encrypted payloads no key opens fail to decode
+    fn plaintext<'m>(&self, msg: &'m WakuMessage) -> Result<Cow<'m, [u8]>, ListenerError> {
+        match &self.keys {
+            Some(keys) if msg.version() == ENCRYPTED_PAYLOAD_VERSION => {
+                let _span = trace_span!("decrypt").entered();
+                let _timer = PIPELINE_STAGE_SECONDS
+                    .with_label_values(&["decrypt"])
+                    .start_timer();
+                keys.decrypt(msg).map(Cow::Owned).inspect_err(|e| {
+                    INVALIDATED_MESSAGES.with_label_values(&[e.kind()]).inc();
+                })
+            }
+            _ => Ok(Cow::Borrowed(msg.payload())),
+        }
+    }
+
+    /// Decode, normalize and validate a payload, recording what the decoder skipped in `origin`
+    fn prepare(
+        &self,
+        payload: &[u8],
+        origin: &mut MessageOrigin<'_>,
+    ) -> Result<RadioMessage, ListenerError> {
+        let decode = PIPELINE_STAGE_SECONDS
+            .with_label_values(&["decode"])
+            .start_timer();
+        let mut message = trace_span!("decode")
+            .in_scope(|| self.message_types.decode(payload))
+            .map_err(|e| {
+                if !matches!(e, ListenerError::Ignored(_)) {
+                    INVALIDATED_MESSAGES.with_label_values(&[e.kind()]).inc();
+                }
+                e
+            })?;
+        decode.observe_duration();
+        let _span = trace_span!("validation", message_type = message.message_type()).entered();
+        let _timer = PIPELINE_STAGE_SECONDS
+            .with_label_values(&["validation"])
+            .start_timer();
+        // Checked before normalization, which may change the encoded length
+        origin.unknown_bytes = message
+            .encoded_len()
+            .map(|len| payload.len().saturating_sub(len) as i32);
+        if origin.unknown_bytes.is_some_and(|bytes| bytes > 0) {
+            UNDECODED_FIELD_MESSAGES
+                .with_label_values(&[message.message_type()])
+                .inc();
+            if !UNDECODED_FIELDS_SEEN.swap(true, Ordering::Relaxed) {
+                warn!(
+                    message_type = message.message_type(),
+                    "Received message fields this listener cannot decode, senders may run a newer Graphcast SDK"
+                );
+            }
+        }
+        if let Err(e) = message
+            .normalize()
+            .and_then(|_| self.validator.validate(&message))
+        {
+            INVALIDATED_MESSAGES.with_label_values(&[e.kind()]).inc();
+            return Err(e);
+        }
+        // Counted by the tracker under its own label
+        if let Some(nonces) = &self.nonces {
+            nonces.check(&message)?;
+        }
+        let deployment = deployment_label(message.identifier(), Some(message.message_type()));
+        VALIDATED_MESSAGES
+            .with_label_values(&[deployment.as_ref()])
+            .inc();
+        Ok(message)
+    }
+
+    async fn verify_sender(&self, message: &RadioMessage) -> Option<bool> {
+        let identity = self.identity.as_ref()?;
+        let _timer = PIPELINE_STAGE_SECONDS
+            .with_label_values(&["sender"])
+            .start_timer();
+        identity
+            .verify(message)
+            .instrument(trace_span!("verify_sender"))
+            .await
+    }
+
+    async fn store_prepared(
+        &self,
+        message: RadioMessage,
+        origin: MessageOrigin<'_>,
+    ) -> Result<Option<i64>, ListenerError> {
+        // The store takes the message, a copy is only made for live subscribers
+        let subscribed = live::subscribed().then(|| message.clone());
+        let insert = PIPELINE_STAGE_SECONDS
+            .with_label_values(&["insert"])
+            .start_timer();
+        let id = self
+            .store
+            .store_from(message, origin)
+            .instrument(trace_span!("insert"))
+            .await;
+        insert.observe_duration();
+        let id = id?;
+        if let (Some(id), Some(message)) = (id, subscribed) {
+            live::publish(LiveMessage::new(id, &message));
+        }
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::resolver::count_messages;
+    use sqlx::postgres::PgListener;
+
+    const TEST_NAMESPACE: &str = "default";
+
+    fn other_message(nonce: u64) -> RadioMessage {
+        RadioMessage::Other {
+            message_type: "test".to_string(),
+            identifier: "QmTamam".to_string(),
+            message: json!({
+                "identifier": "QmTamam",
+                "nonce": nonce,
+                "graph_account": "0xb4b4",
+                "signature": "0xsig",
+                "payload": { "content": nonce.to_string() },
+            }),
+        }
+    }
+
+    fn simple_payload(content: &str) -> Vec<u8> {
+        GraphcastMessage::new(
+            "QmTamam".to_string(),
+            1707328517,
+            "0xb4b4".to_string(),
+            SimpleMessage {
+                identifier: "QmTamam".to_string(),
+                content: content.to_string(),
+            },
+            "0xsig".to_string(),
+        )
+        .unwrap()
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn test_register_message_types() {
+        // Registered types are tried in order, the first decoder taking the payload wins
+        let types = MessageTypes::empty()
+            .register_decoder("never", |_| None)
+            .register::<SimpleMessage>("custom_simple")
+            .register_decoder("fallback", |_| {
+                Some(RadioMessage::Other {
+                    message_type: "fallback".to_string(),
+                    identifier: String::new(),
+                    message: json!({}),
+                })
+            });
+        assert_eq!(types.names(), vec!["never", "custom_simple", "fallback"]);
+
+        let message = types.decode(&simple_payload("ping")).unwrap();
+        assert_eq!(message.message_type(), "custom_simple");
+        assert_eq!(message.identifier(), "QmTamam");
+        assert_eq!(message.sender(), Some(("0xb4b4", 1707328517)));
+        assert_eq!(message.stored_json()["payload"]["content"], "ping");
+        assert_eq!(types.decode(b"garbage").unwrap().message_type(), "fallback");
+
+        assert!(matches!(
+            MessageTypes::empty().decode(b"garbage"),
+            Err(ListenerError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_only_message_types() {
+        assert!(MessageTypes::default()
+            .only(&["unknown".to_string()])
+            .is_err());
+
+        let exact = |name: &'static str| {
+            move |payload: &[u8]| {
+                (payload == name.as_bytes()).then(|| RadioMessage::Other {
+                    message_type: name.to_string(),
+                    identifier: String::new(),
+                    message: json!({}),
+                })
+            }
+        };
+        let types = MessageTypes::empty()
+            .register_decoder("kept", exact("kept"))
+            .register_decoder("dropped", exact("dropped"))
+            .only(&["kept".to_string()])
+            .unwrap();
+        assert_eq!(types.names(), vec!["kept"]);
+        assert_eq!(types.decode(b"kept").unwrap().message_type(), "kept");
+        // Types left out are recognized and dropped instead of failing to decode
+        assert!(matches!(
+            types.decode(b"dropped"),
+            Err(ListenerError::Ignored(name)) if name == "dropped"
+        ));
+        assert!(matches!(
+            types.decode(b"unknown"),
+            Err(ListenerError::Decode(_))
+        ));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_store_notifies_in_transaction(pool: Pool<Postgres>) {
+        let mut listener = PgListener::connect_with(&pool).await.unwrap();
+        listener.listen("listener_radio_messages").await.unwrap();
+        let store = PostgresStore::new(pool.clone(), TEST_NAMESPACE.to_string())
+            .with_notify_channel(Some("listener_radio_messages".to_string()))
+            .with_outbox(true);
+
+        let id = store.store(other_message(1707328500)).await.unwrap();
+        let ids = store
+            .store_batch(&[
+                (other_message(1707328501), MessageOrigin::default()),
+                (other_message(1707328500), MessageOrigin::default()),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(ids[1], None);
+        for expected in [id, ids[0]] {
+            let notification = listener.recv().await.unwrap();
+            let payload: serde_json::Value = serde_json::from_str(notification.payload()).unwrap();
+            assert_eq!(payload["id"], expected.unwrap());
+        }
+
+        // A failed notification rolls the insert back instead of failing after the commit
+        let store = PostgresStore::new(pool.clone(), TEST_NAMESPACE.to_string())
+            .with_notify_channel(Some(String::new()));
+        assert!(store.store(other_message(1707328502)).await.is_err());
+        assert!(store
+            .store_batch(&[(other_message(1707328503), MessageOrigin::default())])
+            .await
+            .is_err());
+        assert_eq!(count_messages(&pool, TEST_NAMESPACE).await.unwrap(), 2);
+    }
+}
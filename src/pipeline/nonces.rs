@@ -0,0 +1,172 @@
+//! Ordering of nonces per sender. Graphcast nonces are send times, so a message older than
+//! the last one seen from the same sender about the same identifier is replayed or was sent
+//! from a misconfigured clock. The latest nonces are kept in memory and persisted in the
+//! `sender_state` table every flush
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, warn};
+
+use super::RadioMessage;
+use crate::{
+    config::NonceOrdering,
+    db::resolver::{list_sender_nonces, upsert_sender_nonces, SenderNonce},
+    metrics::INVALIDATED_MESSAGES,
+    ListenerError,
+};
+
+type SenderKey = (String, String);
+
+/// Latest nonce per (graph account, identifier)
+#[derive(Debug)]
+pub struct NonceTracker {
+    ordering: NonceOrdering,
+    db: PgPool,
+    namespace: String,
+    latest: Mutex<HashMap<SenderKey, i64>>,
+    /// Nonces advanced since the last flush
+    dirty: Mutex<HashMap<SenderKey, i64>>,
+}
+
+impl NonceTracker {
+    /// Tracker starting from the nonces persisted for the namespace
+    pub async fn load(
+        db: PgPool,
+        namespace: String,
+        ordering: NonceOrdering,
+    ) -> Result<Self, ListenerError> {
+        let latest = list_sender_nonces(&db, &namespace)
+            .await?
+            .into_iter()
+            .map(|n| ((n.graph_account, n.identifier), n.last_nonce))
+            .collect();
+        Ok(NonceTracker {
+            ordering,
+            db,
+            namespace,
+            latest: Mutex::new(latest),
+            dirty: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Record the nonce of a message, failing for a nonce older than the sender's latest
+    /// when out of order messages are rejected. Messages without a sender are not tracked
+    pub fn check(&self, message: &RadioMessage) -> Result<(), ListenerError> {
+        let Some((graph_account, nonce)) = message.sender() else {
+            return Ok(());
+        };
+        let key = (graph_account.to_string(), message.identifier().to_string());
+        let mut latest = self.latest.lock().expect("Sender nonces lock poisoned");
+        match latest.get(&key) {
+            Some(last) if nonce < *last => {
+                INVALIDATED_MESSAGES
+                    .with_label_values(&["nonce_order"])
+                    .inc();
+                let reason = format!(
+                    "nonce_order: Nonce {} is older than the latest nonce {} of the sender",
+                    nonce, last
+                );
+                match self.ordering {
+                    NonceOrdering::Reject => return Err(ListenerError::Validation(reason)),
+                    _ => debug!(
+                        graph_account,
+                        identifier = key.1.as_str(),
+                        reason = reason.as_str(),
+                        "Flagged out of order message"
+                    ),
+                }
+            }
+            Some(last) if nonce == *last => {}
+            _ => {
+                latest.insert(key.clone(), nonce);
+                self.dirty
+                    .lock()
+                    .expect("Sender nonces lock poisoned")
+                    .insert(key, nonce);
+            }
+        }
+        Ok(())
+    }
+
+    /// Persist the nonces advanced since the last flush, returning the number of senders
+    pub async fn flush(&self) -> Result<usize, ListenerError> {
+        let dirty = std::mem::take(&mut *self.dirty.lock().expect("Sender nonces lock poisoned"));
+        if dirty.is_empty() {
+            return Ok(0);
+        }
+        let nonces: Vec<SenderNonce> = dirty
+            .iter()
+            .map(|((graph_account, identifier), last_nonce)| SenderNonce {
+                graph_account: graph_account.clone(),
+                identifier: identifier.clone(),
+                last_nonce: *last_nonce,
+            })
+            .collect();
+        if let Err(e) = upsert_sender_nonces(&self.db, &self.namespace, &nonces).await {
+            // Keep them for the next flush, unless the sender advanced further meanwhile
+            let mut pending = self.dirty.lock().expect("Sender nonces lock poisoned");
+            for (key, nonce) in dirty {
+                pending.entry(key).or_insert(nonce);
+            }
+            return Err(e);
+        }
+        Ok(nonces.len())
+    }
+}
+
+/// Background job persisting the tracked nonces, with a last flush on shutdown
+pub async fn run_nonce_flush(
+    tracker: Arc<NonceTracker>,
+    flush_interval: Duration,
+    running: Arc<AtomicBool>,
+) {
+    let mut flush_interval = interval(flush_interval);
+
+    loop {
+        flush_interval.tick().await;
+        let stopping = !running.load(Ordering::SeqCst);
+        if let Err(e) = tracker.flush().await {
+            warn!(err = e.to_string(), "Failed to persist sender nonces");
+        }
+        if stopping {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(nonce: u64) -> RadioMessage {
+        RadioMessage::Other {
+            message_type: "status".to_string(),
+            identifier: "QmTamam".to_string(),
+            message: serde_json::json!({ "graph_account": "0xb4b4", "nonce": nonce }),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_nonce_ordering(pool: PgPool) {
+        let tracker =
+            NonceTracker::load(pool.clone(), "default".to_string(), NonceOrdering::Reject)
+                .await
+                .unwrap();
+        tracker.check(&message(1707328518)).unwrap();
+        tracker.check(&message(1707328518)).unwrap();
+        assert!(tracker.check(&message(1707328517)).is_err());
+        assert_eq!(tracker.flush().await.unwrap(), 1);
+
+        // A restarted listener keeps rejecting, flagging only counts the message
+        let restarted = NonceTracker::load(pool, "default".to_string(), NonceOrdering::Flag)
+            .await
+            .unwrap();
+        assert!(restarted.check(&message(1707328517)).is_ok());
+        assert_eq!(restarted.flush().await.unwrap(), 0);
+    }
+}
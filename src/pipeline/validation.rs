@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::{RadioMessage, Validator};
+use crate::{config::PayloadCheck, metrics::QUARANTINED_MESSAGES, ListenerError};
+
+/// Sanity checks on payload fields, so malformed messages are quarantined instead of
+/// polluting the stored data
+#[derive(Debug, Default)]
+pub struct PayloadValidator {
+    checks: Vec<PayloadCheck>,
+    network_allowlist: Vec<String>,
+    /// Latest block number per (graph account, deployment)
+    latest_blocks: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl PayloadValidator {
+    pub fn new(checks: Vec<PayloadCheck>, network_allowlist: Vec<String>) -> Self {
+        PayloadValidator {
+            checks,
+            network_allowlist,
+            latest_blocks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn enabled(&self, check: PayloadCheck) -> bool {
+        self.checks.contains(&check)
+    }
+
+    fn check(&self, message: &RadioMessage) -> Result<(), (PayloadCheck, String)> {
+        let identifier = match message {
+            RadioMessage::PublicPoi(msg) => Some(&msg.identifier),
+            RadioMessage::UpgradeIntent(msg) => Some(&msg.payload.deployment),
+            RadioMessage::VersionUpgrade(msg) => Some(&msg.identifier),
+            RadioMessage::Simple(_) | RadioMessage::Other { .. } => None,
+        };
+        if let Some(identifier) = identifier {
+            if self.enabled(PayloadCheck::IdentifierFormat) && !is_valid_identifier(identifier) {
+                return Err((
+                    PayloadCheck::IdentifierFormat,
+                    format!("Malformed identifier {}", identifier),
+                ));
+            }
+        }
+
+        let RadioMessage::PublicPoi(msg) = message else {
+            return Ok(());
+        };
+        let payload = &msg.payload;
+
+        if self.enabled(PayloadCheck::PoiFormat) && !is_valid_poi(&payload.content) {
+            return Err((
+                PayloadCheck::PoiFormat,
+                format!("Malformed POI {}", payload.content),
+            ));
+        }
+
+        if self.enabled(PayloadCheck::Network)
+            && !self.network_allowlist.is_empty()
+            && !self.network_allowlist.contains(&payload.network)
+        {
+            return Err((
+                PayloadCheck::Network,
+                format!("Network {} is not allowed", payload.network),
+            ));
+        }
+
+        if self.enabled(PayloadCheck::BlockNumber) {
+            let mut latest_blocks = self
+                .latest_blocks
+                .lock()
+                .expect("Latest block numbers lock poisoned");
+            let latest = latest_blocks
+                .entry((msg.graph_account.clone(), msg.identifier.clone()))
+                .or_default();
+            if payload.block_number < *latest {
+                return Err((
+                    PayloadCheck::BlockNumber,
+                    format!(
+                        "Block number {} is behind the latest block {} of the sender",
+                        payload.block_number, latest
+                    ),
+                ));
+            }
+            *latest = payload.block_number;
+        }
+
+        Ok(())
+    }
+}
+
+impl Validator for PayloadValidator {
+    fn validate(&self, message: &RadioMessage) -> Result<(), ListenerError> {
+        self.check(message).map_err(|(check, reason)| {
+            QUARANTINED_MESSAGES
+                .with_label_values(&[check.name()])
+                .inc();
+            ListenerError::Validation(format!("{}: {}", check.name(), reason))
+        })
+    }
+}
+
+/// IPFS deployment hashes (Qm + 44 base58 characters) or 0x prefixed hex ids
+pub fn is_valid_identifier(identifier: &str) -> bool {
+    if let Some(hash) = identifier.strip_prefix("Qm") {
+        hash.len() == 44
+            && hash
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() && !matches!(c, '0' | 'O' | 'I' | 'l'))
+    } else if let Some(hex) = identifier.strip_prefix("0x") {
+        !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit())
+    } else {
+        false
+    }
+}
+
+/// Multihash prefix of a sha2-256 digest, as used by CIDv0 deployment hashes
+const SHA2_256_MULTIHASH: [u8; 2] = [0x12, 0x20];
+
+/// Canonical deployment hash: the CIDv0 (`Qm...`) form. Deployments given as 0x prefixed
+/// bytes32 hex are converted, anything else is not a deployment hash and returns None
+pub fn canonical_deployment(identifier: &str) -> Option<String> {
+    let identifier = identifier.trim();
+    if identifier.starts_with("Qm") {
+        return deployment_hex(identifier).map(|_| identifier.to_string());
+    }
+    let digest = decode_hex(identifier.strip_prefix("0x")?)?;
+    if digest.len() != 32 {
+        return None;
+    }
+    let multihash = [SHA2_256_MULTIHASH.as_slice(), &digest].concat();
+    Some(bs58::encode(multihash).into_string())
+}
+
+/// Bytes32 hex form of a deployment hash given in either representation
+pub fn deployment_hex(identifier: &str) -> Option<String> {
+    let identifier = identifier.trim();
+    if let Some(hex) = identifier.strip_prefix("0x") {
+        return (hex.len() == 64 && decode_hex(hex).is_some())
+            .then(|| format!("0x{}", hex.to_ascii_lowercase()));
+    }
+    let multihash = bs58::decode(identifier).into_vec().ok()?;
+    let digest = multihash.strip_prefix(SHA2_256_MULTIHASH.as_slice())?;
+    if digest.len() != 32 {
+        return None;
+    }
+    Some(format!(
+        "0x{}",
+        digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    ))
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Canonical POI form: trimmed, lowercase and 0x prefixed, with exactly 32 bytes of hex.
+/// Same rules as the `normalize_poi` SQL function used for rows stored before normalization
+pub fn normalize_poi(poi: &str) -> Result<String, String> {
+    let trimmed = poi.trim();
+    let hex = trimmed
+        .strip_prefix("0x")
+        .or_else(|| trimmed.strip_prefix("0X"))
+        .unwrap_or(trimmed);
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("POI {} is not 32 bytes of hex", poi));
+    }
+    Ok(format!("0x{}", hex.to_ascii_lowercase()))
+}
+
+/// 0x prefixed 32 byte hex string
+pub fn is_valid_poi(poi: &str) -> bool {
+    poi.strip_prefix("0x")
+        .map(|hex| hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifier_format() {
+        assert!(is_valid_identifier(
+            "QmVhiE4nax9i86UBnBmQCYDzvjWuwHShYh7aspGPQhU5Sj"
+        ));
+        assert!(is_valid_identifier(
+            "0xb4b4570df6f7fe320f10fdfb702dba7e35244550"
+        ));
+        assert!(!is_valid_identifier("QmTamam"));
+        assert!(!is_valid_identifier("0x"));
+        assert!(!is_valid_identifier("ping"));
+    }
+
+    #[test]
+    fn test_deployment_conversions() {
+        let ipfs = "QmVhiE4nax9i86UBnBmQCYDzvjWuwHShYh7aspGPQhU5Sj";
+        let hex = deployment_hex(ipfs).unwrap();
+        assert_eq!(hex.len(), 66);
+        assert_eq!(canonical_deployment(&hex).unwrap(), ipfs);
+        assert_eq!(
+            canonical_deployment(&hex.to_uppercase().replace("0X", "0x")).unwrap(),
+            ipfs
+        );
+        assert_eq!(canonical_deployment(ipfs).unwrap(), ipfs);
+        assert_eq!(deployment_hex(&hex).unwrap(), hex);
+
+        assert!(canonical_deployment("0xb4b4570df6f7fe320f10fdfb702dba7e35244550").is_none());
+        assert!(canonical_deployment("QmTamam").is_none());
+        assert!(canonical_deployment("ping").is_none());
+    }
+
+    #[test]
+    fn test_normalize_poi() {
+        let canonical = format!("0x{}", "ab".repeat(32));
+        assert_eq!(normalize_poi(&canonical).unwrap(), canonical);
+        assert_eq!(normalize_poi(&"AB".repeat(32)).unwrap(), canonical);
+        assert_eq!(
+            normalize_poi(&format!(" 0X{} ", "aB".repeat(32))).unwrap(),
+            canonical
+        );
+        assert!(normalize_poi(&format!("0x{}", "ab".repeat(31))).is_err());
+        assert!(normalize_poi("0xText").is_err());
+    }
+
+    #[test]
+    fn test_poi_format() {
+        assert!(is_valid_poi(&format!("0x{}", "ab".repeat(32))));
+        assert!(!is_valid_poi(&"ab".repeat(32)));
+        assert!(!is_valid_poi("0xText"));
+        assert!(!is_valid_poi(&format!("0x{}", "zz".repeat(32))));
+    }
+}
@@ -0,0 +1,136 @@
+use async_graphql::{Context, Guard, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::server::limits::AdminNetwork;
+
+/// Access level of an API token, each role includes the permissions of the ones before it
+#[derive(
+    clap::ValueEnum,
+    async_graphql::Enum,
+    Clone,
+    Copy,
+    Debug,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+pub enum Role {
+    /// Read stored messages and statistics
+    Viewer,
+    /// Also run exports, raw traffic and dead letter inspection and consensus recomputation
+    Analyst,
+    /// Also delete data and change runtime settings
+    Admin,
+}
+
+impl Role {
+    /// Name of the role as configured and stored with API keys
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Analyst => "analyst",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// Token a request authenticated with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Credential {
+    /// No token, on an open API or the public profile
+    Anonymous,
+    /// One of API_TOKENS
+    Static,
+    /// API key by id
+    ApiKey(i64),
+}
+
+impl Credential {
+    /// Label of the usage metrics
+    pub fn label(&self) -> String {
+        match self {
+            Credential::Anonymous => "anonymous".to_string(),
+            Credential::Static => "static".to_string(),
+            Credential::ApiKey(id) => id.to_string(),
+        }
+    }
+}
+
+/// Rejects resolvers called by a request without at least the given role
+pub struct RoleGuard {
+    role: Role,
+}
+
+impl RoleGuard {
+    pub fn new(role: Role) -> Self {
+        RoleGuard { role }
+    }
+}
+
+#[async_trait::async_trait]
+impl Guard for RoleGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        if self.role == Role::Admin && ctx.data_opt::<AdminNetwork>() == Some(&AdminNetwork(false))
+        {
+            return Err("Admin operations are not allowed from this address".into());
+        }
+        match ctx.data_opt::<Role>() {
+            Some(role) if *role >= self.role => Ok(()),
+            _ => Err(format!("Requires the {:?} role", self.role).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::{EmptyMutation, EmptySubscription, Object, Request, Schema};
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+        async fn analyst(&self) -> bool {
+            true
+        }
+
+        #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+        async fn admin(&self) -> bool {
+            true
+        }
+    }
+
+    async fn allowed(field: &str, role: Option<Role>, admin_network: bool) -> bool {
+        let schema = Schema::new(Query, EmptyMutation, EmptySubscription);
+        let mut request =
+            Request::new(format!("{{ {} }}", field)).data(AdminNetwork(admin_network));
+        if let Some(role) = role {
+            request = request.data(role);
+        }
+        schema.execute(request).await.errors.is_empty()
+    }
+
+    #[test]
+    fn test_role_ordering() {
+        assert!(Role::Viewer < Role::Analyst);
+        assert!(Role::Analyst < Role::Admin);
+    }
+
+    #[tokio::test]
+    async fn test_role_guard() {
+        assert!(!allowed("analyst", Some(Role::Viewer), true).await);
+        assert!(allowed("analyst", Some(Role::Analyst), true).await);
+        assert!(allowed("analyst", Some(Role::Admin), true).await);
+        assert!(!allowed("admin", Some(Role::Analyst), true).await);
+        assert!(allowed("admin", Some(Role::Admin), true).await);
+        // Requests without a role are rejected by any guard
+        assert!(!allowed("analyst", None, true).await);
+        // Admins outside ADMIN_ALLOWLIST only keep the lower roles
+        assert!(!allowed("admin", Some(Role::Admin), false).await);
+        assert!(allowed("analyst", Some(Role::Admin), false).await);
+    }
+}
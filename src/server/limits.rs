@@ -0,0 +1,316 @@
+use async_graphql::{
+    extensions::{Extension, ExtensionContext, ExtensionFactory, NextParseQuery},
+    parser::types::{ExecutableDocument, OperationType},
+    ServerError, ServerResult, Variables,
+};
+use axum::{
+    extract::ConnectInfo,
+    http::{header, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, ServerProfile};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Clients tracked before expired windows are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Limits of the API, the public profile capping the configured ones with its own
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ApiLimits {
+    /// Requests per minute of a client address
+    pub rate: Option<u32>,
+    pub query_complexity: Option<usize>,
+    pub query_depth: Option<usize>,
+}
+
+fn stricter<T: Ord + Copy>(limit: Option<T>, cap: T) -> Option<T> {
+    Some(limit.map_or(cap, |limit| limit.min(cap)))
+}
+
+impl ApiLimits {
+    pub fn from_config(config: &Config) -> Self {
+        let limits = ApiLimits {
+            rate: config.rate_limit,
+            query_complexity: config.max_query_complexity,
+            query_depth: config.max_query_depth,
+        };
+        match config.server_profile {
+            ServerProfile::Default => limits,
+            ServerProfile::Public => ApiLimits {
+                rate: stricter(limits.rate, config.public_rate_limit),
+                query_complexity: stricter(limits.query_complexity, config.public_query_complexity),
+                query_depth: stricter(limits.query_depth, config.public_query_depth),
+            },
+        }
+    }
+}
+
+pub(crate) fn has_mutation(document: &ExecutableDocument) -> bool {
+    document
+        .operations
+        .iter()
+        .any(|(_, operation)| operation.node.ty == OperationType::Mutation)
+}
+
+/// Rejects mutation operations before they are validated or executed
+pub struct ReadOnly;
+
+impl ExtensionFactory for ReadOnly {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ReadOnlyExtension)
+    }
+}
+
+struct ReadOnlyExtension;
+
+#[async_trait::async_trait]
+impl Extension for ReadOnlyExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        if has_mutation(&document) {
+            return Err(ServerError::new(
+                "Mutations are disabled on this read-only listener",
+                None,
+            ));
+        }
+        Ok(document)
+    }
+}
+
+/// IPv4 or IPv6 network in CIDR notation, a plain address is a single host network
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (network, prefix) = value.split_once('/').unwrap_or((value, ""));
+        let network: IpAddr = network
+            .parse()
+            .map_err(|e| format!("Invalid network address {}: {}", network, e))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => max_prefix,
+            prefix => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length in {}", value))?,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+/// Whether the client address of a request is within ADMIN_ALLOWLIST
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdminNetwork(pub bool);
+
+/// Rejects mutation operations of clients outside the admin allowlist, whatever their token
+pub struct AdminAllowlist;
+
+impl ExtensionFactory for AdminAllowlist {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(AdminAllowlistExtension)
+    }
+}
+
+struct AdminAllowlistExtension;
+
+#[async_trait::async_trait]
+impl Extension for AdminAllowlistExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        if has_mutation(&document) && ctx.data_opt::<AdminNetwork>() != Some(&AdminNetwork(true)) {
+            return Err(ServerError::new(
+                "Mutations are not allowed from this address",
+                None,
+            ));
+        }
+        Ok(document)
+    }
+}
+
+/// Fixed window request counter per client address
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            limit,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request of `client`, false once the client is over the limit of its window
+    pub fn check(&self, client: IpAddr, now: Instant) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED_CLIENTS {
+            clients.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+        let (start, count) = clients.entry(client).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.limit
+    }
+}
+
+/// Reject requests of clients over the rate limit with 429
+pub async fn rate_limit<B>(
+    limiter: Arc<RateLimiter>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(client) = client {
+        if !limiter.check(client, Instant::now()) {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Rate limit exceeded, retry in a minute",
+            )
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Reject request bodies larger than `max_bytes` with 413 before they are read. Bodies
+/// without a content length cannot be checked upfront and are rejected with 411
+pub async fn body_limit<B>(max_bytes: usize, request: Request<B>, next: Next<B>) -> Response {
+    if request.method() == Method::POST {
+        let length = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<usize>().ok());
+        match length {
+            None => {
+                return (StatusCode::LENGTH_REQUIRED, "Content-Length is required").into_response()
+            }
+            Some(length) if length > max_bytes => {
+                return (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Request body is larger than {} bytes", max_bytes),
+                )
+                    .into_response()
+            }
+            _ => {}
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_windows() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check(client, start));
+        assert!(limiter.check(client, start));
+        assert!(!limiter.check(client, start + Duration::from_secs(30)));
+        assert!(limiter.check(other, start + Duration::from_secs(30)));
+        // A new window starts a minute after the first request
+        assert!(limiter.check(client, start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_api_limits() {
+        let mut config = Config {
+            public_rate_limit: 60,
+            public_query_complexity: 500,
+            public_query_depth: 10,
+            ..Default::default()
+        };
+        assert_eq!(ApiLimits::from_config(&config), ApiLimits::default());
+
+        config.rate_limit = Some(600);
+        config.max_query_depth = Some(5);
+        assert_eq!(ApiLimits::from_config(&config).rate, Some(600));
+
+        config.server_profile = ServerProfile::Public;
+        assert_eq!(
+            ApiLimits::from_config(&config),
+            ApiLimits {
+                rate: Some(60),
+                query_complexity: Some(500),
+                query_depth: Some(5),
+            },
+            "The public profile applies the stricter limits"
+        );
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let network: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(network.contains("10.1.200.3".parse().unwrap()));
+        assert!(!network.contains("10.2.0.1".parse().unwrap()));
+        // IPv4 clients of a dual stack listener
+        assert!(network.contains("::ffff:10.1.0.1".parse().unwrap()));
+
+        let host: Cidr = "fd00::1".parse().unwrap();
+        assert!(host.contains("fd00::1".parse().unwrap()));
+        assert!(!host.contains("fd00::2".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    }
+}
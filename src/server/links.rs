@@ -0,0 +1,91 @@
+use reqwest::Url;
+use serde_json::json;
+
+use crate::config::Config;
+
+/// Path of the GraphQL endpoint, also serving the playground
+pub const GRAPHQL_PATH: &str = "/api/v1/graphql";
+
+/// Path of the GraphQL subscriptions websocket
+pub const GRAPHQL_WS_PATH: &str = "/api/v1/graphql/ws";
+
+/// Path of the streaming message export
+pub const EXPORT_PATH: &str = "/api/v1/export";
+
+/// Path of on demand pruning
+pub const ADMIN_PRUNE_PATH: &str = "/api/v1/admin/prune";
+
+/// Builds URLs running a GraphQL query against this listener's API, for alerts to link to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiLinks {
+    endpoint: Url,
+}
+
+impl ApiLinks {
+    /// Links to PUBLIC_API_URL, or to the server host and port. None when the API is not served
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let base = match (&config.public_api_url, config.server_port) {
+            (Some(url), _) => url.trim_end_matches('/').to_string(),
+            (None, Some(port)) => format!("http://{}:{}", config.server_host, port),
+            (None, None) => return None,
+        };
+        ApiLinks::new(&base)
+    }
+
+    pub fn new(base: &str) -> Option<Self> {
+        let endpoint = Url::parse(&format!("{}{}", base, GRAPHQL_PATH)).ok()?;
+        Some(ApiLinks { endpoint })
+    }
+
+    /// GET request URL of `query` with its `variables`
+    pub fn query(&self, query: &str, variables: serde_json::Value) -> String {
+        let mut url = self.endpoint.clone();
+        url.query_pairs_mut()
+            .append_pair("query", query)
+            .append_pair("variables", &variables.to_string());
+        url.to_string()
+    }
+
+    pub fn divergence_incidents(&self, run_id: i64) -> String {
+        self.query(
+            "query($runId: Int!) { divergenceIncidents(runId: $runId) { deployment blockNumber graphAccount poi consensusPoi } }",
+            json!({ "runId": run_id }),
+        )
+    }
+
+    /// Stats of `indexers` over the last `minutes_ago` minutes
+    pub fn indexer_stats(&self, indexers: &[String], minutes_ago: u64) -> String {
+        self.query(
+            "query($indexers: [String!], $minutesAgo: Int) { queryIndexerStats(indexers: $indexers, minutesAgo: $minutesAgo) { graphAccount messageCount subgraphsCount } }",
+            json!({ "indexers": indexers, "minutesAgo": minutes_ago }),
+        )
+    }
+
+    /// Share of the messages delivered by each peer over the last `minutes_ago` minutes
+    pub fn peer_delivery_share(&self, minutes_ago: u64) -> String {
+        self.query(
+            "query($minutesAgo: Int) { peerDeliveryShare(minutesAgo: $minutesAgo) { peer messageCount share } }",
+            json!({ "minutesAgo": minutes_ago }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_link() {
+        let links = ApiLinks::new("https://listener.example.com").unwrap();
+        let link = links.divergence_incidents(7);
+        assert!(link.starts_with("https://listener.example.com/api/v1/graphql?query=query"));
+
+        let url = Url::parse(&link).unwrap();
+        let (_, variables) = url
+            .query_pairs()
+            .find(|(name, _)| name == "variables")
+            .unwrap();
+        assert_eq!(variables, r#"{"runId":7}"#);
+        assert!(ApiLinks::new("not a url").is_none());
+    }
+}
@@ -2,64 +2,113 @@ use std::{
     net::SocketAddr,
     str::FromStr,
     sync::{atomic::AtomicBool, Arc},
+    time::Duration,
 };
 
-use axum::{extract::Extension, routing::get, Router, Server};
+use axum::{
+    extract::Extension,
+    http::{header, Method},
+    middleware,
+    routing::{get, post},
+    Router, Server,
+};
 use sqlx::{Pool, Postgres};
 use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use crate::{
-    config::Config,
+    config::{Config, ServerProfile},
+    db::resolver::has_api_keys,
     server::{
+        limits::{body_limit, rate_limit, ApiLimits, RateLimiter},
+        links::{ADMIN_PRUNE_PATH, EXPORT_PATH, GRAPHQL_PATH, GRAPHQL_WS_PATH},
         model::{build_schema, RadioContext},
-        routes::{graphql_handler, graphql_playground, health},
+        routes::{drain, export, graphql_get, graphql_handler, graphql_ws, health, info, prune},
     },
+    shutdown::stopped,
 };
 
+pub mod auth;
+pub mod limits;
+pub mod links;
 pub mod model;
+pub mod persisted;
 pub mod routes;
 
 /// Run HTTP server to provide API services
-/// Set up the routes for a radio health endpoint at `/health`
-/// and a versioned GraphQL endpoint at `api/v1/graphql`
-/// This function starts a API server at the configured server_host and server_port
-pub async fn run_server(config: Config, db: Pool<Postgres>, _running_program: Arc<AtomicBool>) {
-    if config.server_port().is_none() {
-        return;
+/// Set up the routes for the component status at `/health`, build and schema
+/// versions at `/info`, the ingest handoff at `/drain`, a versioned GraphQL endpoint at
+/// `api/v1/graphql`, its subscriptions websocket at `api/v1/graphql/ws`, message
+/// exports at `api/v1/export` and on demand pruning at `api/v1/admin/prune`
+/// This function starts a API server at the configured server_host and server_port, which
+/// finishes the open requests and returns once the shutdown starts. Errors setting it up
+/// are returned
+pub async fn run_server(
+    config: Config,
+    db: Pool<Postgres>,
+    _running_program: Arc<AtomicBool>,
+) -> Result<(), anyhow::Error> {
+    let Some(port) = config.server_port() else {
+        return Ok(());
+    };
+    let context = Arc::new(RadioContext::init(config.clone(), db.clone())?);
+    if config.api_open
+        && config.api_tokens.is_empty()
+        && !has_api_keys(&db, context.namespace()).await.unwrap_or(true)
+    {
+        warn!("The API is open and treats every request as admin until an API token or key is set up, unset API_OPEN once the first admin key exists");
     }
-    let port = config.server_port().unwrap();
-    let context = Arc::new(RadioContext::init(config.clone(), db.clone()));
 
-    let schema = build_schema(Arc::clone(&context)).await;
+    let schema = build_schema(Arc::clone(&context)).await?;
 
     debug!("Setting up HTTP service");
 
-    // Configure CORS
-    let cors = CorsLayer::new()
-        .allow_origin(AllowOrigin::any())
-        .allow_methods(AllowMethods::any())
-        .allow_headers(AllowHeaders::any());
+    // Configure CORS, browsers of any origin may only read through the public profile
+    let cors = match config.server_profile {
+        ServerProfile::Default => CorsLayer::new()
+            .allow_origin(AllowOrigin::any())
+            .allow_methods(AllowMethods::any())
+            .allow_headers(AllowHeaders::any()),
+        ServerProfile::Public => CorsLayer::new()
+            .allow_origin(AllowOrigin::any())
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION]),
+    };
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/health", get(health))
-        .route(
-            "/api/v1/graphql",
-            get(graphql_playground).post(graphql_handler),
-        )
+        .route("/info", get(info))
+        .route("/drain", post(drain))
+        .route(GRAPHQL_PATH, get(graphql_get).post(graphql_handler))
+        .route(GRAPHQL_WS_PATH, get(graphql_ws))
+        .route(EXPORT_PATH, get(export))
+        .route(ADMIN_PRUNE_PATH, post(prune));
+    if let Some(rate) = ApiLimits::from_config(&config).rate {
+        let limiter = Arc::new(RateLimiter::new(rate, Duration::from_secs(60)));
+        app = app.layer(middleware::from_fn(move |request, next| {
+            rate_limit(limiter.clone(), request, next)
+        }));
+    }
+    let max_request_bytes = config.max_request_bytes;
+    let app = app
+        .layer(middleware::from_fn(move |request, next| {
+            body_limit(max_request_bytes, request, next)
+        }))
         .layer(cors)
         .layer(Extension(schema))
         .layer(Extension(context));
-    let addr = SocketAddr::from_str(&format!("{}:{}", config.server_host(), port))
-        .expect("Create address");
+    let addr = SocketAddr::from_str(&format!("{}:{}", config.server_host(), port))?;
 
     info!(
         host = tracing::field::debug(config.server_host()),
-        port, "Bind and serve"
+        port,
+        profile = tracing::field::debug(config.server_profile),
+        "Bind and serve"
     );
     Server::bind(&addr)
-        .serve(app.into_make_service())
-        // .with_graceful_shutdown(shutdown_signal(running_program))
-        .await
-        .unwrap();
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(stopped())
+        .await?;
+
+    Ok(())
 }
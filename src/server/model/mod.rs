@@ -1,37 +1,216 @@
-use async_graphql::{Context, EmptySubscription, Object, OutputType, Schema, SimpleObject};
+use async_graphql::{
+    ComplexObject, Context, Object, OutputType, Schema, SimpleObject, Subscription,
+};
 
 use chrono::Utc;
+use clap::ValueEnum;
 use serde::{de::DeserializeOwned, Serialize};
 use sqlx::{Pool, Postgres};
-use std::{sync::Arc, time::Duration};
+use std::{net::IpAddr, path::Path, sync::Arc, time::Duration};
 use thiserror::Error;
+use tokio::sync::Semaphore;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tracing::info;
 
 use crate::{
-    config::Config,
+    archive::{cold_messages, indexer_stats_as_of, pruned::ArchiveSink, ColdStorage},
+    config::{Config, CoverageLevel, ServerProfile},
+    consensus::{block_consensus, recompute_consensus, start_consensus_run, ConsensusStrategy},
     db::resolver::{
-        delete_message_all, delete_message_by_id, get_indexer_stats, list_active_indexers,
-        list_messages, list_rows, message_by_id, IndexerStats,
+        api_key_role, count_covered_deployments, count_deletion_remaining, count_filtered_messages,
+        count_messages, create_api_key, create_message_deletion, dead_letter_payloads,
+        delete_captured_messages, delete_dead_letters, delete_message_by_hash,
+        delete_message_by_id, delete_notification_template, delete_persisted_query,
+        get_indexer_stats, has_api_keys, hold_divergence_evidence, hold_messages,
+        list_active_indexers, list_allocated_deployments, list_api_key_usage, list_api_keys,
+        list_block_votes, list_captured_messages, list_consensus_runs, list_coverage_gaps,
+        list_crashes, list_dead_letters, list_divergence_incidents, list_filtered_messages,
+        list_listener_nodes, list_messages, list_notification_templates, list_payloads,
+        list_peer_statuses, list_persisted_queries, list_raw_messages, list_retention_holds,
+        list_rows, list_undecoded_payloads, message_by_hash, message_by_id, message_deletion,
+        message_type_stats, network_indexer, peer_delivery_share, poi_submission,
+        protocol_compatibility, release_retention_hold, revoke_api_key, set_explain_queries,
+        set_notification_template, topic_identifier_stats, update_dead_letter,
+        upsert_persisted_query, ApiKey, ApiKeyUsage, CapturedMessage, ConsensusRun, Crash,
+        DeadLetter, DeadLetterFilter, DivergenceIncident, IndexerStats, ListenerNode,
+        MessageDeletion, MessageFilter, MessageTypeStats, NetworkIndexer, NotificationTemplate,
+        PeerShare, PeerStatus, PersistedQuery, PoiSubmission, ProtocolCompatibility, RawMessage,
+        RetentionHold, Row, TopicIdentifierStats,
+    },
+    db::views::{
+        materialized_activity, materialized_consensus_summaries, materialized_indexer_stats,
+        materialized_topic_stats, ActivityBucket, ActivityGranularity, ConsensusSummary,
+        Materialized, TopicStats,
     },
-    operator::radio_types::RadioPayloadMessage,
+    logging::{log_filter, recent_logs, set_log_filter, LogEvent},
+    message_types::{RadioPayloadMessage, VersionUpgradeMessage},
+    metrics::PRUNED_MESSAGES,
+    operator::capture::{capture_until, start_capture, stop_capture},
+    operator::default_pipeline,
+    operator::duplication::{DuplicationReport, DUPLICATION},
+    operator::health::{check_health, HealthReport, HealthThresholds},
+    operator::node::local_node,
+    operator::notifier::NotificationChannel,
+    operator::prune::{prune_now, start_message_deletion, PruneOutcome, PruneRequest},
+    operator::templates::AlertKind,
+    operator::topics::{set_topic_scope, topic_scope, TopicScope, TopicStatus, TOPIC_ACTIVITY},
+    pipeline::{
+        inference::{infer_candidate_types, CandidateType},
+        live::{self, LiveMessage},
+        validation::{canonical_deployment, deployment_hex},
+    },
+    server::auth::{Credential, Role, RoleGuard},
+    server::limits::{AdminAllowlist, AdminNetwork, ApiLimits, ReadOnly},
+    server::persisted::{register_persisted_queries, QueryAllowlist},
+    ListenerError,
 };
 use graphcast_sdk::{graphcast_agent::message_typing::GraphcastMessage, graphql::QueryError};
 
-pub type RadioSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+pub type RadioSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
 
-pub async fn build_schema(ctx: Arc<RadioContext>) -> RadioSchema {
-    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
-        .data(ctx.db.clone())
-        .finish()
+/// Build the GraphQL schema for the context, registering the persisted queries of
+/// PERSISTED_QUERIES_DIR first. Errors reading or storing them are returned
+pub async fn build_schema(ctx: Arc<RadioContext>) -> Result<RadioSchema, anyhow::Error> {
+    let mut builder = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot).data(ctx.db.clone());
+    if !ctx.radio_config.admin_allowlist.is_empty() {
+        builder = builder.extension(AdminAllowlist);
+    }
+    if let Some(dir) = &ctx.radio_config.persisted_queries_dir {
+        register_persisted_queries(&ctx.db, ctx.namespace(), Path::new(dir))
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not register persisted queries: {}", e))?;
+    }
+    if ctx.radio_config.query_allowlist {
+        builder = builder.extension(QueryAllowlist::new(
+            ctx.db.clone(),
+            ctx.namespace().to_string(),
+        ));
+    }
+    if ctx.radio_config.server_profile == ServerProfile::Public {
+        builder = builder.extension(ReadOnly);
+    }
+    let limits = ApiLimits::from_config(&ctx.radio_config);
+    if let Some(complexity) = limits.query_complexity {
+        builder = builder.limit_complexity(complexity);
+    }
+    if let Some(depth) = limits.query_depth {
+        builder = builder.limit_depth(depth);
+    }
+    Ok(builder.finish())
 }
 
 pub struct RadioContext {
     pub radio_config: Config,
     pub db: Pool<Postgres>,
+    pub cold_storage: Option<ColdStorage>,
+    pub archive: Option<ArchiveSink>,
+    /// Permits of the exports that may run at once, each holding a database snapshot
+    pub exports: Arc<Semaphore>,
 }
 
 impl RadioContext {
-    pub fn init(radio_config: Config, db: Pool<Postgres>) -> Self {
-        Self { radio_config, db }
+    pub fn init(radio_config: Config, db: Pool<Postgres>) -> Result<Self, anyhow::Error> {
+        let cold_storage = radio_config
+            .cold_storage_url
+            .as_deref()
+            .map(ColdStorage::new)
+            .transpose()?;
+        let archive = radio_config
+            .archive_url
+            .as_deref()
+            .map(ArchiveSink::new)
+            .transpose()?;
+        let exports = Arc::new(Semaphore::new(radio_config.export_concurrency.max(1)));
+        Ok(Self {
+            radio_config,
+            db,
+            cold_storage,
+            archive,
+            exports,
+        })
+    }
+
+    /// Namespace every query and mutation is scoped to
+    pub fn namespace(&self) -> &str {
+        &self.radio_config.instance_namespace
+    }
+
+    /// Cold tier that messages are archived to before pruning, with ARCHIVE_BEFORE_PRUNE
+    pub fn prune_archive(&self) -> Option<&ColdStorage> {
+        self.cold_storage
+            .as_ref()
+            .filter(|_| self.radio_config.archive_before_prune)
+    }
+
+    /// Most rows a list query may return: MAX_RESULT_ROWS, or PUBLIC_PAGE_LIMIT when lower on
+    /// the public profile. None when unlimited
+    pub fn max_rows(&self) -> Option<i64> {
+        let config = &self.radio_config;
+        let public =
+            (config.server_profile == ServerProfile::Public).then_some(config.public_page_limit);
+        let configured = (config.max_result_rows > 0).then_some(config.max_result_rows);
+        public.into_iter().chain(configured).min()
+    }
+
+    /// Reject results over [`Self::max_rows`] instead of returning them partially
+    pub fn check_rows<T>(&self, rows: Vec<T>) -> Result<Vec<T>, HttpServiceError> {
+        match self.max_rows() {
+            Some(max) if rows.len() as i64 > max => Err(HttpServiceError::TooManyRows(max)),
+            _ => Ok(rows),
+        }
+    }
+
+    /// Role and credential of a request with the bearer `token`, checked against API_TOKENS
+    /// and the API keys. None when the request is not allowed. While no token or key is set
+    /// up, every request is admin with API_OPEN and requests without a token are viewers
+    /// unless API_AUTH_REQUIRED is set. Requests without a token are viewers on the public
+    /// profile
+    pub async fn authenticate(
+        &self,
+        token: Option<&str>,
+    ) -> Result<Option<(Role, Credential)>, ListenerError> {
+        let tokens = &self.radio_config.api_tokens;
+        if let Some(token) = token {
+            if let Some((_, role)) = tokens.iter().find(|(t, _)| t == token) {
+                return Ok(Some((*role, Credential::Static)));
+            }
+            if let Some((id, role)) = api_key_role(&self.db, self.namespace(), token).await? {
+                return Ok(Role::from_str(&role, true)
+                    .ok()
+                    .map(|role| (role, Credential::ApiKey(id))));
+            }
+        }
+        let config = &self.radio_config;
+        if (config.api_open || token.is_none() && !config.api_auth_required)
+            && tokens.is_empty()
+            && !has_api_keys(&self.db, self.namespace()).await?
+        {
+            let role = if config.api_open {
+                Role::Admin
+            } else {
+                Role::Viewer
+            };
+            return Ok(Some((role, Credential::Anonymous)));
+        }
+        Ok(
+            (token.is_none() && config.server_profile == ServerProfile::Public)
+                .then_some((Role::Viewer, Credential::Anonymous)),
+        )
+    }
+
+    /// Whether mutations and admin queries are allowed from the client address
+    pub fn admin_network(&self, client: Option<IpAddr>) -> AdminNetwork {
+        let allowlist = &self.radio_config.admin_allowlist;
+        AdminNetwork(
+            allowlist.is_empty()
+                || client.is_some_and(|ip| allowlist.iter().any(|cidr| cidr.contains(ip))),
+        )
+    }
+
+    /// Requested `limit` of a list query or its `default`, capped at [`Self::max_rows`]
+    pub fn page_limit(&self, limit: Option<i64>, default: i64) -> i64 {
+        let limit = limit.unwrap_or(default);
+        self.max_rows().map_or(limit, |max| limit.min(max))
     }
 }
 
@@ -45,88 +224,892 @@ impl QueryRoot {
         "Healthy"
     }
 
+    /// Status of the database, the Waku peers, message reception and the message processor
+    async fn health_detailed(&self, ctx: &Context<'_>) -> HealthReport {
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        check_health(
+            &context.db,
+            HealthThresholds::from_config(&context.radio_config),
+        )
+        .await
+    }
+
+    /// Waku peer id and dialable addresses of this listener, null until the node started
+    async fn node(&self) -> Option<ListenerNode> {
+        local_node().cloned()
+    }
+
+    /// Nodes of the listeners that ran on this namespace, most recently started first
+    async fn listener_nodes(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+    ) -> Result<Vec<ListenerNode>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let nodes = list_listener_nodes(pool, context.namespace(), context.page_limit(limit, 20))
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(nodes)
+    }
+
+    /// Latest reachability check of every boot node, unreachable ones first
+    async fn boot_nodes(&self, ctx: &Context<'_>) -> Result<Vec<PeerStatus>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let peers = list_peer_statuses(pool, namespace)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(peers)
+    }
+
     // List rows but without filter options since msg fields are saved in jsonb
     // Later flatten the messages to have columns from graphcast message.
+    /// Page of stored rows in insertion order, `first` (or `limit`, default 100) rows after the
+    /// `after` cursor, or `offset` rows in without a cursor
     async fn rows(
         &self,
         ctx: &Context<'_>,
-    ) -> Result<Vec<GraphQLRow<GraphcastMessage<RadioPayloadMessage>>>, HttpServiceError> {
+        first: Option<i64>,
+        after: Option<String>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Connection<GraphQLRow<GraphcastMessage<RadioPayloadMessage>>>, HttpServiceError>
+    {
         let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let page = PageArgs::new(context, first.or(limit), after, offset)?;
 
-        let rows: Vec<GraphQLRow<GraphcastMessage<RadioPayloadMessage>>> = list_rows(pool).await?;
-        Ok(rows)
+        let rows: Vec<GraphQLRow<GraphcastMessage<RadioPayloadMessage>>> = list_rows(
+            pool,
+            context.namespace(),
+            page.after,
+            page.offset,
+            Some(page.size + 1),
+        )
+        .await?;
+        let total_count = count_messages(pool, context.namespace())
+            .await
+            .map_err(anyhow::Error::from)?;
+        let rows = rows.into_iter().map(|row| (row.id, row)).collect();
+        Ok(page.connection(rows, total_count))
     }
 
+    /// Indexers with messages in the last `minutes_ago` (default 1440). With `asOf` (unix
+    /// timestamp) the window ends then instead, and includes messages moved to the cold tier
     async fn query_active_indexers(
         &self,
         ctx: &Context<'_>,
         indexers: Option<Vec<String>>,
         minutes_ago: Option<u64>,
+        as_of: Option<i64>,
     ) -> Result<Vec<String>, HttpServiceError> {
         let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
         // Use a default time window if not specified
         // Default to 1440 minutes (24 hours) if not provided
         let minutes_ago = minutes_ago.unwrap_or(1440);
-        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
 
-        let active_indexers = list_active_indexers(pool, indexers, from_timestamp).await?;
+        if let Some(as_of) = as_of {
+            let stats = indexer_stats_as_of(
+                pool,
+                context.namespace(),
+                context.cold_storage.as_ref(),
+                indexers.as_deref(),
+                as_of - minutes_ago as i64 * 60,
+                as_of,
+            )
+            .await?;
+            return Ok(stats.into_iter().map(|stat| stat.graph_account).collect());
+        }
+
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+        let active_indexers =
+            list_active_indexers(pool, context.namespace(), indexers, from_timestamp).await?;
         Ok(active_indexers)
     }
 
+    /// Message and subgraph counts of indexers in the last `minutes_ago` (default 1440).
+    /// With `asOf` (unix timestamp) the window ends then instead, and includes messages
+    /// moved to the cold tier when it predates hot retention
     async fn query_indexer_stats(
         &self,
         ctx: &Context<'_>,
         indexers: Option<Vec<String>>,
         minutes_ago: Option<u64>,
+        as_of: Option<i64>,
     ) -> Result<Vec<IndexerStats>, HttpServiceError> {
         let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
         let minutes_ago = minutes_ago.unwrap_or(1440);
+
+        if let Some(as_of) = as_of {
+            let stats = indexer_stats_as_of(
+                pool,
+                context.namespace(),
+                context.cold_storage.as_ref(),
+                indexers.as_deref(),
+                as_of - minutes_ago as i64 * 60,
+                as_of,
+            )
+            .await?;
+            return Ok(stats);
+        }
+
         let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+        let stats = get_indexer_stats(pool, context.namespace(), indexers, from_timestamp).await?;
+        Ok(stats)
+    }
 
-        let stats = get_indexer_stats(pool, indexers, from_timestamp).await?;
+    /// Deployments with a public POI message in the last `minutes_ago` (defaults to the
+    /// configured coverage window) against the number of subscribed topics
+    async fn coverage(
+        &self,
+        ctx: &Context<'_>,
+        minutes_ago: Option<u64>,
+    ) -> Result<Coverage, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let minutes_ago = minutes_ago.unwrap_or(context.radio_config.coverage_window);
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+
+        let covered_deployments =
+            count_covered_deployments(pool, context.namespace(), from_timestamp)
+                .await
+                .map_err(anyhow::Error::from)?;
+        let subscribed_topics = context.radio_config.topics.len() as i64;
+        Ok(Coverage {
+            covered_deployments,
+            subscribed_topics,
+            ratio: (subscribed_topics > 0)
+                .then(|| covered_deployments as f64 / subscribed_topics as f64),
+        })
+    }
+
+    /// Last message time of every subscribed topic and whether it went silent
+    async fn topic_activity(&self, ctx: &Context<'_>) -> Vec<TopicStatus> {
+        let config = &ctx.data_unchecked::<Arc<RadioContext>>().radio_config;
+        TOPIC_ACTIVITY.report(
+            &config.topics,
+            config.topic_silence_threshold,
+            &config.topic_silence_thresholds,
+            Utc::now().timestamp(),
+        )
+    }
+
+    /// Copies received per distinct message over the last network update interval, by
+    /// content topic, as a measure of gossip amplification
+    async fn duplication(&self) -> DuplicationReport {
+        DUPLICATION.last_report()
+    }
+
+    /// Content topics currently kept, every topic or only the configured ones
+    async fn topic_scope(&self) -> TopicScope {
+        topic_scope()
+    }
+
+    /// Share of the messages received in the last `minutes_ago` (default 1440) delivered by
+    /// each peer. Messages from sources that do not expose the peer are grouped under null
+    async fn peer_delivery_share(
+        &self,
+        ctx: &Context<'_>,
+        minutes_ago: Option<u64>,
+    ) -> Result<Vec<PeerShare>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let minutes_ago = minutes_ago.unwrap_or(1440);
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let shares = peer_delivery_share(pool, namespace, from_timestamp)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(shares)
+    }
+
+    /// Messages received in the last `minutes_ago` (default 1440) by Graphcast protocol
+    /// version, with the senders using payload fields this listener cannot decode. Such
+    /// senders run a newer SDK, and the listener should be upgraded to store their fields
+    async fn protocol_compatibility(
+        &self,
+        ctx: &Context<'_>,
+        minutes_ago: Option<u64>,
+    ) -> Result<Vec<ProtocolCompatibility>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let minutes_ago = minutes_ago.unwrap_or(1440);
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let report = protocol_compatibility(pool, namespace, from_timestamp)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(report)
+    }
+
+    /// Messages received in the last `minutes_ago` (default 1440) by message type
+    async fn message_type_stats(
+        &self,
+        ctx: &Context<'_>,
+        minutes_ago: Option<u64>,
+    ) -> Result<Vec<MessageTypeStats>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let minutes_ago = minutes_ago.unwrap_or(1440);
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let stats = message_type_stats(pool, namespace, from_timestamp)
+            .await
+            .map_err(anyhow::Error::from)?;
         Ok(stats)
     }
 
-    /// Grab a row from db by db entry id
-    async fn row(
+    /// Messages, senders and first and last receive times by content topic and deployment
+    /// identifier in the last `minutesAgo` (default 1440), busiest first. Counts messages
+    /// received up to now, unlike the hourly `topicStats`
+    async fn query_topic_stats(
+        &self,
+        ctx: &Context<'_>,
+        minutes_ago: Option<u64>,
+        content_topic: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<Vec<TopicIdentifierStats>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let minutes_ago = minutes_ago.unwrap_or(1440);
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+
+        let stats = topic_identifier_stats(
+            pool,
+            context.namespace(),
+            from_timestamp,
+            content_topic.as_deref(),
+            context.page_limit(limit, 100),
+        )
+        .await
+        .map_err(anyhow::Error::from)?;
+        Ok(stats)
+    }
+
+    /// Indexer stats of the last `minutesAgo` (default 1440) from the materialized stats
+    /// views, in hourly buckets. Cheaper than `queryIndexerStats` on large tables, but only
+    /// counts messages received before the last refresh of STATS_REFRESH_INTERVAL
+    async fn materialized_indexer_stats(
+        &self,
+        ctx: &Context<'_>,
+        indexers: Option<Vec<String>>,
+        minutes_ago: Option<u64>,
+    ) -> Result<Materialized<IndexerStats>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let minutes_ago = minutes_ago.unwrap_or(1440);
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let stats =
+            materialized_indexer_stats(pool, namespace, indexers.as_deref(), from_timestamp)
+                .await
+                .map_err(anyhow::Error::from)?;
+        Ok(stats)
+    }
+
+    /// Indexer activity series in `granularity` buckets (default hourly) from `fromTimestamp`
+    /// (default a day ago) up to `toTimestamp` (default now), from the materialized stats views
+    async fn activity_series(
+        &self,
+        ctx: &Context<'_>,
+        granularity: Option<ActivityGranularity>,
+        from_timestamp: Option<i64>,
+        to_timestamp: Option<i64>,
+    ) -> Result<Materialized<ActivityBucket>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let from_timestamp = from_timestamp.unwrap_or(Utc::now().timestamp() - 24 * 60 * 60);
+
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let series = materialized_activity(
+            pool,
+            namespace,
+            granularity.unwrap_or_default(),
+            from_timestamp,
+            to_timestamp,
+        )
+        .await
+        .map_err(anyhow::Error::from)?;
+        Ok(series)
+    }
+
+    /// Messages and senders by content topic in the last `minutesAgo` (default 1440), from
+    /// the materialized stats views
+    async fn topic_stats(
+        &self,
+        ctx: &Context<'_>,
+        minutes_ago: Option<u64>,
+    ) -> Result<Materialized<TopicStats>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let minutes_ago = minutes_ago.unwrap_or(1440);
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let stats = materialized_topic_stats(pool, namespace, from_timestamp)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(stats)
+    }
+
+    /// Consensus runs with their consensus and divergence counts, newest first, from the
+    /// materialized stats views
+    async fn consensus_summaries(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+    ) -> Result<Materialized<ConsensusSummary>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+
+        let summaries = materialized_consensus_summaries(
+            pool,
+            context.namespace(),
+            context.page_limit(limit, 20),
+        )
+        .await
+        .map_err(anyhow::Error::from)?;
+        Ok(summaries)
+    }
+
+    /// Latest version upgrade announcements of radios predating upgrade intents, optionally
+    /// about one deployment
+    async fn version_upgrades(
+        &self,
+        ctx: &Context<'_>,
+        identifier: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<Vec<VersionUpgradeMessage>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let identifier = identifier.map(|id| canonical_deployment(&id).unwrap_or(id));
+
+        let upgrades = list_payloads(
+            pool,
+            context.namespace(),
+            "version_upgrade",
+            identifier.as_deref(),
+            context.page_limit(limit, 100),
+        )
+        .await
+        .map_err(anyhow::Error::from)?;
+        Ok(upgrades)
+    }
+
+    /// Dead letters by category and creation time range (unix timestamps), newest first
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn dead_letters(
+        &self,
+        ctx: &Context<'_>,
+        category: Option<String>,
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<DeadLetter>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let filter = DeadLetterFilter {
+            ids: None,
+            category,
+            from,
+            to,
+        };
+
+        let limit = context.page_limit(limit, 100);
+        let dead_letters = list_dead_letters(pool, context.namespace(), &filter, limit)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(dead_letters)
+    }
+
+    /// Raw messages recorded by the debug capture, newest first
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn captured_messages(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+    ) -> Result<Vec<CapturedMessage>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+
+        let limit = context.page_limit(limit, 100);
+        let captured = list_captured_messages(pool, context.namespace(), limit)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(captured)
+    }
+
+    /// Messages of types this listener cannot decode, newest first, optionally of one
+    /// content topic
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn raw_messages(
+        &self,
+        ctx: &Context<'_>,
+        content_topic: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<Vec<RawMessage>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+
+        let limit = context.page_limit(limit, 100);
+        let raw = list_raw_messages(pool, context.namespace(), content_topic.as_deref(), limit)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(raw)
+    }
+
+    /// Message types this listener has no decoder for, inferred from the undecodable payloads
+    /// of the last `minutes_ago` (default 1440). Payloads are grouped by content topic and
+    /// protobuf field layout, reading at most `limit` (default 10000) of the newest ones
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn message_type_candidates(
+        &self,
+        ctx: &Context<'_>,
+        minutes_ago: Option<u64>,
+        samples: Option<usize>,
+        limit: Option<i64>,
+    ) -> Result<Vec<CandidateType>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let minutes_ago = minutes_ago.unwrap_or(1440);
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+
+        let payloads = list_undecoded_payloads(
+            pool,
+            context.namespace(),
+            from_timestamp,
+            context.page_limit(limit, 10000),
+        )
+        .await
+        .map_err(anyhow::Error::from)?;
+        Ok(infer_candidate_types(&payloads, samples.unwrap_or(3)))
+    }
+
+    /// Consensus POI of a deployment at a block from the latest POI of each sender, with the
+    /// senders diverging from it. `strategy` defaults to CONSENSUS_STRATEGY
+    async fn poi_consensus(
+        &self,
+        ctx: &Context<'_>,
+        deployment: String,
+        block_number: i64,
+        strategy: Option<ConsensusStrategy>,
+    ) -> Result<PoiConsensus, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let strategy = strategy.unwrap_or(context.radio_config.consensus_strategy);
+        let deployment = canonical_deployment(&deployment).unwrap_or(deployment);
+
+        let votes = list_block_votes(pool, context.namespace(), &deployment, block_number)
+            .await
+            .map_err(anyhow::Error::from)?;
+        let senders = votes.len() as i32;
+        let (consensus_poi, support, diverging) = match block_consensus(strategy, &votes) {
+            Some((result, diverging)) => (
+                Some(result.consensus_poi),
+                Some(result.support),
+                diverging.into_iter().map(|v| v.graph_account).collect(),
+            ),
+            None => (None, None, vec![]),
+        };
+        Ok(PoiConsensus {
+            deployment,
+            block_number,
+            methodology: strategy.methodology().to_string(),
+            consensus_poi,
+            support,
+            senders,
+            diverging,
+        })
+    }
+
+    /// Progress of a bulk deletion started by `deleteMessages`
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn message_deletion(
         &self,
         ctx: &Context<'_>,
         id: i64,
+    ) -> Result<Option<MessageDeletion>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let deletion = message_deletion(pool, namespace, id)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(deletion)
+    }
+
+    /// Consensus recomputations, newest first
+    async fn consensus_runs(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+    ) -> Result<Vec<ConsensusRun>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+
+        let runs = list_consensus_runs(pool, context.namespace(), context.page_limit(limit, 20))
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(runs)
+    }
+
+    /// Senders whose POI differed from the consensus computed by a run
+    async fn divergence_incidents(
+        &self,
+        ctx: &Context<'_>,
+        run_id: i64,
+        deployment: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<Vec<DivergenceIncident>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let deployment = deployment.map(|d| canonical_deployment(&d).unwrap_or(d));
+
+        let incidents = list_divergence_incidents(
+            pool,
+            context.namespace(),
+            run_id,
+            deployment.as_deref(),
+            context.page_limit(limit, 1000),
+        )
+        .await
+        .map_err(anyhow::Error::from)?;
+        Ok(incidents)
+    }
+
+    /// Retention holds of the namespace, oldest first
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn retention_holds(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<RetentionHold>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let holds = list_retention_holds(pool, namespace)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(holds)
+    }
+
+    /// API keys of the namespace, without their secrets
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn api_keys(&self, ctx: &Context<'_>) -> Result<Vec<ApiKey>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let keys = list_api_keys(pool, namespace)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(keys)
+    }
+
+    /// Requests, response bytes and last use of every API key, heaviest consumers first
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn api_key_usage(&self, ctx: &Context<'_>) -> Result<Vec<ApiKeyUsage>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let usage = list_api_key_usage(pool, namespace)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(usage)
+    }
+
+    /// Operations accepted in query allowlist mode
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn persisted_queries(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<PersistedQuery>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let queries = list_persisted_queries(pool, namespace)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(queries)
+    }
+
+    /// Notification templates set through the API, used instead of NOTIFICATION_TEMPLATES
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn notification_templates(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<NotificationTemplate>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let templates = list_notification_templates(pool, namespace)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(templates)
+    }
+
+    /// Most recent warnings and errors logged by the listener, newest first, kept in memory
+    /// up to RECENT_LOGS
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn recent_logs(
+        &self,
+        ctx: &Context<'_>,
+        errors_only: Option<bool>,
+        limit: Option<i64>,
+    ) -> Result<Vec<LogEvent>, HttpServiceError> {
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let limit = context.page_limit(limit, 100).max(0) as usize;
+        Ok(recent_logs(errors_only.unwrap_or_default(), limit))
+    }
+
+    /// Panics of listener threads and tasks with their backtrace, most recent first
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn crashes(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+    ) -> Result<Vec<Crash>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+
+        let crashes = list_crashes(pool, context.namespace(), context.page_limit(limit, 20))
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(crashes)
+    }
+
+    /// Directives filtering the logs, as set by RUST_LOG or setLogFilter
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn log_filter(&self) -> Option<String> {
+        log_filter()
+    }
+
+    /// Unix timestamp the running debug capture ends at, null when capture is off
+    async fn capture_until(&self) -> Option<i64> {
+        capture_until()
+    }
+
+    /// Grab a row from db by content hash, or by db entry id of this instance
+    async fn row(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<i64>,
+        content_hash: Option<String>,
     ) -> Result<GraphQLRow<GraphcastMessage<RadioPayloadMessage>>, HttpServiceError> {
         let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
 
         let row: GraphQLRow<GraphcastMessage<RadioPayloadMessage>> =
-            message_by_id(pool, id).await?.get_graphql_row();
+            MessageKey::new(id, content_hash)?
+                .find(pool, namespace)
+                .await?
+                .get_graphql_row();
         Ok(row)
     }
 
     // List messages but without filter options since msg fields are saved in jsonb
     // Later flatten the messages to have columns from graphcast message.
+    /// Page of stored messages in insertion order, paginated like `rows`
     async fn messages(
         &self,
         ctx: &Context<'_>,
-    ) -> Result<Vec<GraphcastMessage<RadioPayloadMessage>>, HttpServiceError> {
+        first: Option<i64>,
+        after: Option<String>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Connection<GraphcastMessage<RadioPayloadMessage>>, HttpServiceError> {
         let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let page = PageArgs::new(context, first.or(limit), after, offset)?;
 
-        let msgs: Vec<GraphcastMessage<RadioPayloadMessage>> = list_messages(pool)
-            .await?
-            .iter()
-            .map(|r| r.get_message())
-            .collect::<Vec<GraphcastMessage<RadioPayloadMessage>>>();
-        Ok(msgs)
+        let rows = list_messages::<GraphcastMessage<RadioPayloadMessage>>(
+            pool,
+            context.namespace(),
+            page.after,
+            page.offset,
+            Some(page.size + 1),
+        )
+        .await?;
+        let total_count = count_messages(pool, context.namespace())
+            .await
+            .map_err(anyhow::Error::from)?;
+        let rows = rows.iter().map(|r| (r.get_id(), r.get_message())).collect();
+        Ok(page.connection(rows, total_count))
+    }
+
+    /// Page of the stored messages matching every given condition, paginated like `rows`.
+    /// `messageType` is one of `public_poi`, `upgrade_intent`, `version_upgrade` or `simple`.
+    /// `senderValid` keeps the messages whose sender passed or failed ID_VALIDATION, messages
+    /// stored without the check match neither
+    #[allow(clippy::too_many_arguments)]
+    async fn messages_filtered(
+        &self,
+        ctx: &Context<'_>,
+        graph_account: Option<String>,
+        identifier: Option<String>,
+        network: Option<String>,
+        nonce_gte: Option<i64>,
+        nonce_lte: Option<i64>,
+        message_type: Option<String>,
+        content_topic: Option<String>,
+        sender_valid: Option<bool>,
+        first: Option<i64>,
+        after: Option<String>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Connection<GraphcastMessage<RadioPayloadMessage>>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let page = PageArgs::new(context, first.or(limit), after, offset)?;
+        let filter = MessageFilter {
+            graph_account,
+            identifier: identifier.map(|id| canonical_deployment(&id).unwrap_or(id)),
+            network,
+            nonce_gte,
+            nonce_lte,
+            message_type,
+            content_topic,
+            sender_valid,
+            ..Default::default()
+        };
+
+        let rows = list_filtered_messages::<GraphcastMessage<RadioPayloadMessage>>(
+            pool,
+            context.namespace(),
+            &filter,
+            page.after,
+            page.offset,
+            page.size + 1,
+        )
+        .await
+        .map_err(anyhow::Error::from)?;
+        let total_count = count_filtered_messages(pool, context.namespace(), &filter)
+            .await
+            .map_err(anyhow::Error::from)?;
+        let rows = rows.iter().map(|r| (r.get_id(), r.get_message())).collect();
+        Ok(page.connection(rows, total_count))
     }
 
+    /// Stored message by content hash or row id, like `row`
     async fn message(
         &self,
         ctx: &Context<'_>,
-        id: i64,
+        id: Option<i64>,
+        content_hash: Option<String>,
     ) -> Result<GraphcastMessage<RadioPayloadMessage>, HttpServiceError> {
         let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
 
-        let msg: GraphcastMessage<RadioPayloadMessage> =
-            message_by_id(pool, id).await?.get_message();
+        let msg: GraphcastMessage<RadioPayloadMessage> = MessageKey::new(id, content_hash)?
+            .find(pool, namespace)
+            .await?
+            .get_message();
         Ok(msg)
     }
+
+    /// List messages moved to the cold tier with nonces between `from` and `to` (unix timestamps)
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn cold_messages(
+        &self,
+        ctx: &Context<'_>,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<GraphQLRow<GraphcastMessage<RadioPayloadMessage>>>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let storage = context.cold_storage.as_ref().ok_or_else(|| {
+            HttpServiceError::MissingData("Cold storage is not configured".to_string())
+        })?;
+
+        // One row over the cap is enough to reject the range
+        let limit = context.max_rows().map(|max| max as usize + 1);
+        let rows = cold_messages(pool, context.namespace(), storage, from, to, limit).await?;
+        context.check_rows(rows)
+    }
+
+    /// Deployments the indexer is expected to gossip about under a subgraph-radio coverage
+    /// level, and the ones without a message from it in the last `minutes_ago` (default 1440).
+    /// Expectations come from the synced network allocations: minimal coverage only gossips
+    /// about locally configured topics, so nothing can be expected on-chain
+    async fn coverage_gaps(
+        &self,
+        ctx: &Context<'_>,
+        indexer: String,
+        coverage_level: Option<CoverageLevel>,
+        minutes_ago: Option<u64>,
+    ) -> Result<CoverageGaps, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let minutes_ago = minutes_ago.unwrap_or(1440);
+        let from_timestamp = (Utc::now() - Duration::from_secs(minutes_ago * 60)).timestamp();
+
+        let (expected, missing) = match coverage_level.unwrap_or_default() {
+            CoverageLevel::Minimal => (vec![], vec![]),
+            CoverageLevel::OnChain | CoverageLevel::Comprehensive => (
+                list_allocated_deployments(pool, &indexer)
+                    .await
+                    .map_err(anyhow::Error::from)?,
+                list_coverage_gaps(pool, namespace, &indexer, from_timestamp)
+                    .await
+                    .map_err(anyhow::Error::from)?,
+            ),
+        };
+        Ok(CoverageGaps {
+            indexer,
+            expected,
+            missing,
+        })
+    }
+
+    /// Side by side POIs of two indexers for a deployment at a block, the latest submission of
+    /// each is compared
+    async fn compare_pois(
+        &self,
+        ctx: &Context<'_>,
+        deployment: String,
+        block_number: i64,
+        left: String,
+        right: String,
+    ) -> Result<PoiComparison, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let deployment = canonical_deployment(&deployment).unwrap_or(deployment);
+
+        let left = poi_submission(pool, namespace, &deployment, block_number, &left)
+            .await
+            .map_err(anyhow::Error::from)?;
+        let right = poi_submission(pool, namespace, &deployment, block_number, &right)
+            .await
+            .map_err(anyhow::Error::from)?;
+        let matching = match (&left, &right) {
+            (Some(l), Some(r)) => Some(l.poi == r.poi),
+            _ => None,
+        };
+        Ok(PoiComparison {
+            deployment,
+            block_number,
+            left,
+            right,
+            matching,
+        })
+    }
+
+    /// Both representations of a deployment hash given as CIDv0 (`Qm...`) or bytes32 hex.
+    /// Stored messages use the CIDv0 form
+    async fn deployment_hash(
+        &self,
+        identifier: String,
+    ) -> Result<DeploymentHash, HttpServiceError> {
+        match (
+            canonical_deployment(&identifier),
+            deployment_hex(&identifier),
+        ) {
+            (Some(ipfs), Some(hex)) => Ok(DeploymentHash { ipfs, hex }),
+            _ => Err(HttpServiceError::MissingData(format!(
+                "{} is not a deployment hash",
+                identifier
+            ))),
+        }
+    }
 }
 
 // Unified query object for resolvers
@@ -135,45 +1118,724 @@ pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
+    /// Delete a stored message by content hash or row id, like `row`
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
     async fn delete_message(
         &self,
         ctx: &Context<'_>,
-        id: i64,
+        id: Option<i64>,
+        content_hash: Option<String>,
     ) -> Result<GraphcastMessage<RadioPayloadMessage>, HttpServiceError> {
         let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
 
-        let msg: GraphcastMessage<RadioPayloadMessage> =
-            delete_message_by_id(pool, id).await?.get_message();
+        let msg: GraphcastMessage<RadioPayloadMessage> = MessageKey::new(id, content_hash)?
+            .delete(pool, namespace)
+            .await?
+            .get_message();
+        PRUNED_MESSAGES.with_label_values(&["manual"]).inc();
         Ok(msg)
     }
 
+    /// Delete every stored message in the background, in batches. Returns the deletion, whose
+    /// id follows its progress through `messageDeletion` and is passed as `resume` to carry
+    /// on with a deletion interrupted by a restart. Messages stored after it started are kept
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
     async fn delete_messages(
         &self,
         ctx: &Context<'_>,
-    ) -> Result<Vec<GraphcastMessage<RadioPayloadMessage>>, HttpServiceError> {
+        resume: Option<i64>,
+    ) -> Result<MessageDeletion, HttpServiceError> {
         let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
 
-        let msgs: Vec<GraphcastMessage<RadioPayloadMessage>> = delete_message_all(pool)
-            .await?
-            .iter()
-            .map(|r| r.get_message())
-            .collect::<Vec<GraphcastMessage<RadioPayloadMessage>>>();
-        Ok(msgs)
+        let deletion = match resume {
+            Some(id) => message_deletion(pool, namespace, id)
+                .await
+                .map_err(anyhow::Error::from)?
+                .filter(|deletion| deletion.status == "running")
+                .ok_or_else(|| {
+                    HttpServiceError::MissingData(format!("No unfinished deletion {}", id))
+                })?,
+            None => create_message_deletion(pool, namespace)
+                .await
+                .map_err(anyhow::Error::from)?,
+        };
+        start_message_deletion(pool.clone(), namespace.to_string(), deletion.clone());
+        Ok(deletion)
+    }
+
+    /// Apply retention now instead of waiting for the next summary interval: delete the
+    /// messages received more than `olderThanMinutes` ago, then keep the `keepNewest` newest.
+    /// Held messages are kept, and pruned ones are archived first with ARCHIVE_BEFORE_PRUNE
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn prune_messages(
+        &self,
+        ctx: &Context<'_>,
+        older_than_minutes: Option<i32>,
+        keep_newest: Option<i64>,
+    ) -> Result<PruneOutcome, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let request = PruneRequest {
+            older_than_minutes,
+            keep_newest,
+        };
+        request.validate().map_err(HttpServiceError::MissingData)?;
+
+        let outcome = prune_now(
+            pool,
+            context.namespace(),
+            context.prune_archive(),
+            context.archive.as_ref(),
+            request,
+        )
+        .await?;
+        Ok(outcome)
+    }
+
+    /// Run dead letters through the ingest pipeline again, oldest first. Stored entries are
+    /// removed, entries failing again are kept with the new error
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn requeue_dead_letters(
+        &self,
+        ctx: &Context<'_>,
+        ids: Option<Vec<i64>>,
+        category: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<RequeueResult, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let namespace = context.namespace();
+        let filter = DeadLetterFilter {
+            ids,
+            category,
+            ..Default::default()
+        };
+        let pipeline = default_pipeline(&context.radio_config, pool.clone())?;
+
+        let mut result = RequeueResult::default();
+        let mut processed = vec![];
+        for (id, payload) in dead_letter_payloads(pool, namespace, &filter, limit.unwrap_or(100))
+            .await
+            .map_err(anyhow::Error::from)?
+        {
+            match pipeline.process_payload(&payload, None).await {
+                Ok(_) => {
+                    processed.push(id);
+                    result.requeued += 1;
+                }
+                Err(e) => {
+                    update_dead_letter(pool, id, e.kind(), &e.to_string())
+                        .await
+                        .map_err(anyhow::Error::from)?;
+                    result.failed += 1;
+                }
+            }
+        }
+        if !processed.is_empty() {
+            let processed = DeadLetterFilter {
+                ids: Some(processed),
+                ..Default::default()
+            };
+            delete_dead_letters(pool, namespace, &processed)
+                .await
+                .map_err(anyhow::Error::from)?;
+        }
+        Ok(result)
+    }
+
+    /// Delete dead letters by id, category or creation before a unix timestamp, at least one
+    /// filter is required. Returns the number of deleted entries
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn purge_dead_letters(
+        &self,
+        ctx: &Context<'_>,
+        ids: Option<Vec<i64>>,
+        category: Option<String>,
+        before: Option<i64>,
+    ) -> Result<i64, HttpServiceError> {
+        if ids.is_none() && category.is_none() && before.is_none() {
+            return Err(HttpServiceError::MissingData(
+                "Provide ids, category or before to purge dead letters".to_string(),
+            ));
+        }
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let filter = DeadLetterFilter {
+            ids,
+            category,
+            from: None,
+            to: before,
+        };
+
+        let purged = delete_dead_letters(pool, namespace, &filter)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(purged)
+    }
+
+    /// Recompute POI consensus and divergence incidents for messages sent between `from` and
+    /// `to` (unix timestamps, default the last 24 hours) in the background, with `strategy`
+    /// defaulting to CONSENSUS_STRATEGY. Results are stored as a new run next to earlier ones,
+    /// returns the run id
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn recompute_consensus(
+        &self,
+        ctx: &Context<'_>,
+        strategy: Option<ConsensusStrategy>,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<i64, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let context = ctx.data_unchecked::<Arc<RadioContext>>();
+        let namespace = context.namespace();
+        let strategy = strategy.unwrap_or(context.radio_config.consensus_strategy);
+        let to = to.unwrap_or_else(|| Utc::now().timestamp());
+        let from = from.unwrap_or(to - 86400);
+        if from >= to {
+            return Err(HttpServiceError::MissingData(
+                "from must be before to".to_string(),
+            ));
+        }
+
+        let run_id = start_consensus_run(pool, namespace, strategy, from, to)
+            .await
+            .map_err(anyhow::Error::from)?;
+        tokio::spawn(recompute_consensus(
+            pool.clone(),
+            namespace.to_string(),
+            run_id,
+            strategy,
+            from,
+            to,
+        ));
+        Ok(run_id)
+    }
+
+    /// Keep messages from pruning, max storage enforcement and cold tiering until the hold is
+    /// released: the messages with the given `ids` and every stored message matching the
+    /// conditions, filtered like `messagesFiltered`. Messages stored later are not held
+    #[allow(clippy::too_many_arguments)]
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn hold_messages(
+        &self,
+        ctx: &Context<'_>,
+        reason: String,
+        ids: Option<Vec<i64>>,
+        graph_account: Option<String>,
+        identifier: Option<String>,
+        network: Option<String>,
+        nonce_gte: Option<i64>,
+        nonce_lte: Option<i64>,
+        message_type: Option<String>,
+        content_topic: Option<String>,
+        sender_valid: Option<bool>,
+    ) -> Result<RetentionHold, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let ids = ids.unwrap_or_default();
+        let filter = MessageFilter {
+            graph_account,
+            identifier: identifier.map(|id| canonical_deployment(&id).unwrap_or(id)),
+            network,
+            nonce_gte,
+            nonce_lte,
+            message_type,
+            content_topic,
+            sender_valid,
+            ..Default::default()
+        };
+        let filter = (filter != MessageFilter::default()).then_some(filter);
+        if ids.is_empty() && filter.is_none() {
+            return Err(HttpServiceError::MissingData(
+                "Give message ids or conditions to hold".to_string(),
+            ));
+        }
+
+        let hold = hold_messages(pool, namespace, &reason, &ids, filter.as_ref())
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(hold)
+    }
+
+    /// Hold every POI message of the deployments and blocks a consensus run found diverging
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn hold_divergence_evidence(
+        &self,
+        ctx: &Context<'_>,
+        run_id: i64,
+        reason: String,
+    ) -> Result<RetentionHold, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let hold = hold_divergence_evidence(pool, namespace, run_id, &reason)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(hold)
+    }
+
+    /// Release a retention hold, returns whether it existed
+    #[graphql(guard = "RoleGuard::new(Role::Analyst)")]
+    async fn release_retention_hold(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+    ) -> Result<bool, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let released = release_retention_hold(pool, namespace, id)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(released)
+    }
+
+    /// Store every Waku message seen, decodable or not, for the next `minutes` (at most 60).
+    /// Returns the unix timestamp the capture ends at
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn start_capture(&self, minutes: u64) -> i64 {
+        start_capture(minutes)
+    }
+
+    /// Stop the debug capture, optionally deleting what was captured
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn stop_capture(
+        &self,
+        ctx: &Context<'_>,
+        clear: Option<bool>,
+    ) -> Result<i64, HttpServiceError> {
+        stop_capture();
+        if !clear.unwrap_or(false) {
+            return Ok(0);
+        }
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let deleted = delete_captured_messages(pool, namespace)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(deleted)
+    }
+
+    /// Switch between keeping every content topic and only the configured ones without a
+    /// restart, applied within a few seconds. The Waku protocol set at startup is unchanged
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn set_topic_scope(&self, scope: TopicScope) -> TopicScope {
+        set_topic_scope(scope);
+        scope
+    }
+
+    /// Toggle logging of `EXPLAIN ANALYZE` plans and timings for the stats queries
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn explain_queries(&self, enabled: bool) -> bool {
+        set_explain_queries(enabled);
+        enabled
+    }
+
+    /// Create an API key with `role`, valid until the `expiresAt` unix timestamp when set.
+    /// The secret is only returned here, send it as a bearer token
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn create_api_key(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        role: Role,
+        expires_at: Option<i64>,
+    ) -> Result<CreatedApiKey, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let (key, secret) = create_api_key(pool, namespace, &name, role.as_str(), expires_at)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(CreatedApiKey { key, secret })
+    }
+
+    /// Revoke an API key, returns whether an active key was revoked
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn revoke_api_key(&self, ctx: &Context<'_>, id: i64) -> Result<bool, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let revoked = revoke_api_key(pool, namespace, id)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(revoked)
+    }
+
+    /// Set the message template of an alert for one channel, or for every channel without a
+    /// template of its own when `channel` is null. Templates refer to alert values as
+    /// `{{variable}}`
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn set_notification_template(
+        &self,
+        ctx: &Context<'_>,
+        alert: AlertKind,
+        channel: Option<NotificationChannel>,
+        template: String,
+    ) -> Result<NotificationTemplate, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let channel = channel.map(|c| c.as_str());
+
+        set_notification_template(pool, namespace, alert.name(), channel, &template)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(NotificationTemplate {
+            alert: alert.name().to_string(),
+            channel: channel.map(str::to_string),
+            template,
+        })
+    }
+
+    /// Remove a notification template, returns whether it was set
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn delete_notification_template(
+        &self,
+        ctx: &Context<'_>,
+        alert: AlertKind,
+        channel: Option<NotificationChannel>,
+    ) -> Result<bool, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let deleted = delete_notification_template(
+            pool,
+            namespace,
+            alert.name(),
+            channel.map(|c| c.as_str()),
+        )
+        .await
+        .map_err(anyhow::Error::from)?;
+        Ok(deleted)
+    }
+
+    /// Change the log filter without restarting, with RUST_LOG directives for the global level
+    /// and per module, e.g. `info,listener_radio::operator=debug`. Returns the applied filter.
+    /// Restarts go back to RUST_LOG
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn set_log_filter(&self, filter: String) -> Result<String, HttpServiceError> {
+        let applied = set_log_filter(&filter).map_err(HttpServiceError::InvalidLogFilter)?;
+        info!(filter = applied, "Changed log filter");
+        Ok(applied)
+    }
+
+    /// Register an operation for query allowlist mode. Clients send it in full or by the
+    /// returned hash in the `persistedQuery` request extension
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn register_persisted_query(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        query: String,
+    ) -> Result<PersistedQuery, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let persisted = upsert_persisted_query(pool, namespace, &name, &query)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(persisted)
+    }
+
+    /// Remove a persisted query by hash, returns whether it was registered
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn remove_persisted_query(
+        &self,
+        ctx: &Context<'_>,
+        hash: String,
+    ) -> Result<bool, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+
+        let removed = delete_persisted_query(pool, namespace, &hash)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(removed)
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Messages as they are stored, optionally about one identifier or from one sender.
+    /// Subscribers reading slower than messages arrive skip the ones they fell behind on
+    async fn message_added(
+        &self,
+        identifier: Option<String>,
+        graph_account: Option<String>,
+    ) -> impl Stream<Item = LiveMessage> {
+        let identifier = identifier.map(|id| canonical_deployment(&id).unwrap_or(id));
+        BroadcastStream::new(live::subscribe()).filter_map(move |message| {
+            let message = message.ok()?;
+            let matches = identifier
+                .as_ref()
+                .is_none_or(|id| &message.identifier == id)
+                && graph_account.as_ref().is_none_or(|account| {
+                    message
+                        .graph_account
+                        .as_ref()
+                        .is_some_and(|sender| sender.eq_ignore_ascii_case(account))
+                });
+            matches.then_some(message)
+        })
+    }
+}
+
+/// Economic weight of the indexer from the synced network subgraph, null until the first
+/// sync or for accounts that are not indexers. Token amounts are GRT wei
+#[ComplexObject]
+impl IndexerStats {
+    async fn self_stake(&self, ctx: &Context<'_>) -> Result<Option<String>, HttpServiceError> {
+        Ok(self.network(ctx).await?.map(|i| i.staked_tokens))
+    }
+
+    async fn allocated_tokens(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Option<String>, HttpServiceError> {
+        Ok(self.network(ctx).await?.map(|i| i.allocated_tokens))
+    }
+
+    async fn allocation_count(&self, ctx: &Context<'_>) -> Result<Option<i32>, HttpServiceError> {
+        Ok(self.network(ctx).await?.map(|i| i.allocation_count))
+    }
+}
+
+impl IndexerStats {
+    async fn network(&self, ctx: &Context<'_>) -> Result<Option<NetworkIndexer>, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let indexer = network_indexer(pool, &self.graph_account)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(indexer)
+    }
+}
+
+#[ComplexObject]
+impl MessageDeletion {
+    /// Messages left to delete
+    async fn remaining(&self, ctx: &Context<'_>) -> Result<i64, HttpServiceError> {
+        let pool = ctx.data_unchecked::<Pool<Postgres>>();
+        let namespace = ctx.data_unchecked::<Arc<RadioContext>>().namespace();
+        let remaining = count_deletion_remaining(pool, namespace, self.max_id)
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(remaining)
     }
 }
 
 #[derive(Clone, Debug, SimpleObject)]
 pub struct GraphQLRow<T: Clone + Serialize + DeserializeOwned + OutputType> {
+    /// Row id of this instance, it differs between instances and after a re-import
     id: i64,
+    /// Hash of the message content, the identifier to keep when referring to a message
+    content_hash: String,
     message: T,
 }
 
 impl<T: Clone + Serialize + DeserializeOwned + OutputType> GraphQLRow<T> {
-    pub fn new(id: i64, message: T) -> Self {
-        GraphQLRow { id, message }
+    pub fn new(id: i64, content_hash: String, message: T) -> Self {
+        GraphQLRow {
+            id,
+            content_hash,
+            message,
+        }
+    }
+}
+
+/// Reference to a stored message in a lookup. The content hash stays valid across exports,
+/// re-imports and instances, the row id only on the instance that assigned it
+enum MessageKey {
+    Id(i64),
+    ContentHash(String),
+}
+
+impl MessageKey {
+    /// The content hash when given, the row id otherwise
+    fn new(id: Option<i64>, content_hash: Option<String>) -> Result<Self, HttpServiceError> {
+        match (content_hash, id) {
+            (Some(hash), _) => Ok(MessageKey::ContentHash(hash)),
+            (None, Some(id)) => Ok(MessageKey::Id(id)),
+            (None, None) => Err(HttpServiceError::MissingData(
+                "Pass the contentHash or the id of the message".to_string(),
+            )),
+        }
+    }
+
+    async fn find(
+        &self,
+        pool: &Pool<Postgres>,
+        namespace: &str,
+    ) -> Result<Row<GraphcastMessage<RadioPayloadMessage>>, anyhow::Error> {
+        match self {
+            MessageKey::Id(id) => message_by_id(pool, namespace, *id).await,
+            MessageKey::ContentHash(hash) => message_by_hash(pool, namespace, hash).await,
+        }
+    }
+
+    async fn delete(
+        &self,
+        pool: &Pool<Postgres>,
+        namespace: &str,
+    ) -> Result<Row<GraphcastMessage<RadioPayloadMessage>>, anyhow::Error> {
+        match self {
+            MessageKey::Id(id) => delete_message_by_id(pool, namespace, *id).await,
+            MessageKey::ContentHash(hash) => delete_message_by_hash(pool, namespace, hash).await,
+        }
+    }
+}
+
+/// Pagination arguments of the `messages` and `rows` queries
+struct PageArgs {
+    size: i64,
+    after: Option<i64>,
+    offset: i64,
+}
+
+impl PageArgs {
+    fn new(
+        context: &RadioContext,
+        size: Option<i64>,
+        after: Option<String>,
+        offset: Option<i64>,
+    ) -> Result<Self, HttpServiceError> {
+        let after = after
+            .map(|cursor| {
+                cursor
+                    .parse::<i64>()
+                    .map_err(|_| HttpServiceError::InvalidCursor(cursor))
+            })
+            .transpose()?;
+        Ok(PageArgs {
+            size: context.page_limit(size, 100).max(0),
+            after,
+            offset: offset.unwrap_or(0).max(0),
+        })
+    }
+
+    /// Connection over rows by id, fetched with one row more than the page size to tell
+    /// whether a next page exists
+    fn connection<T: OutputType>(&self, mut rows: Vec<(i64, T)>, total_count: i64) -> Connection<T>
+    where
+        Edge<T>: OutputType,
+    {
+        let has_next_page = rows.len() as i64 > self.size;
+        rows.truncate(self.size as usize);
+        let edges: Vec<Edge<T>> = rows
+            .into_iter()
+            .map(|(id, node)| Edge {
+                cursor: id.to_string(),
+                node,
+            })
+            .collect();
+        Connection {
+            total_count,
+            page_info: PageInfo {
+                has_next_page,
+                has_previous_page: self.after.is_some() || self.offset > 0,
+                start_cursor: edges.first().map(|edge| edge.cursor.clone()),
+                end_cursor: edges.last().map(|edge| edge.cursor.clone()),
+            },
+            edges,
+        }
     }
 }
 
+#[derive(Clone, Debug, SimpleObject)]
+pub struct PageInfo {
+    has_next_page: bool,
+    has_previous_page: bool,
+    start_cursor: Option<String>,
+    end_cursor: Option<String>,
+}
+
+/// Node types of the paginated queries, named for the concrete GraphQL types
+pub type PayloadMessage = GraphcastMessage<RadioPayloadMessage>;
+pub type PayloadRow = GraphQLRow<PayloadMessage>;
+
+#[derive(Clone, Debug, SimpleObject)]
+#[graphql(concrete(name = "MessageEdge", params(PayloadMessage)))]
+#[graphql(concrete(name = "RowEdge", params(PayloadRow)))]
+pub struct Edge<T: OutputType> {
+    /// Row id, pass it as `after` to fetch the following rows
+    cursor: String,
+    node: T,
+}
+
+/// Page of a list query with the total row count
+#[derive(Clone, Debug, SimpleObject)]
+#[graphql(concrete(name = "MessageConnection", params(PayloadMessage)))]
+#[graphql(concrete(name = "RowConnection", params(PayloadRow)))]
+pub struct Connection<T: OutputType>
+where
+    Edge<T>: OutputType,
+{
+    total_count: i64,
+    page_info: PageInfo,
+    edges: Vec<Edge<T>>,
+}
+
+#[derive(Clone, Debug, SimpleObject)]
+pub struct Coverage {
+    covered_deployments: i64,
+    subscribed_topics: i64,
+    /// Covered deployments per subscribed topic, null without subscribed topics
+    ratio: Option<f64>,
+}
+
+#[derive(Clone, Debug, SimpleObject)]
+pub struct PoiComparison {
+    deployment: String,
+    block_number: i64,
+    left: Option<PoiSubmission>,
+    right: Option<PoiSubmission>,
+    /// Whether both POIs are equal, null unless both indexers submitted one
+    matching: Option<bool>,
+}
+
+#[derive(Clone, Debug, SimpleObject)]
+pub struct CreatedApiKey {
+    key: ApiKey,
+    secret: String,
+}
+
+#[derive(Clone, Debug, SimpleObject)]
+pub struct PoiConsensus {
+    deployment: String,
+    block_number: i64,
+    methodology: String,
+    /// Null without votes, or without synced stake for the stake weighted strategy
+    consensus_poi: Option<String>,
+    /// Share of the vote weight behind the consensus POI
+    support: Option<f64>,
+    senders: i32,
+    /// Senders whose latest POI differs from the consensus
+    diverging: Vec<String>,
+}
+
+#[derive(Clone, Debug, SimpleObject)]
+pub struct CoverageGaps {
+    indexer: String,
+    expected: Vec<String>,
+    missing: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, SimpleObject)]
+pub struct RequeueResult {
+    /// Entries processed successfully, including messages that were already stored
+    requeued: i64,
+    failed: i64,
+}
+
+#[derive(Clone, Debug, SimpleObject)]
+pub struct DeploymentHash {
+    ipfs: String,
+    hex: String,
+}
+
 #[derive(Error, Debug)]
 pub enum HttpServiceError {
     #[error("Missing requested data: {0}")]
@@ -193,6 +1855,111 @@ pub enum HttpServiceError {
     InvalidUrl(String),
     #[error("HTTP client error: {0}")]
     HttpClientError(#[from] reqwest::Error),
+    #[error("Invalid log filter: {0}")]
+    InvalidLogFilter(String),
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+    #[error("Query matches more than {0} rows, the most this listener returns at once. Request smaller pages or narrow the query")]
+    TooManyRows(i64),
     #[error("{0}")]
     Others(#[from] anyhow::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_build_schema_persisted_queries(pool: Pool<Postgres>) {
+        let dir = std::env::temp_dir().join(format!("persisted-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("latest.graphql"), "{ rows { id } }").unwrap();
+        let config = Config {
+            persisted_queries_dir: Some(dir.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let ctx = Arc::new(RadioContext::init(config.clone(), pool.clone()).unwrap());
+        assert!(build_schema(ctx).await.is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // A missing directory fails the schema instead of panicking
+        let ctx = Arc::new(RadioContext::init(config, pool).unwrap());
+        assert!(build_schema(ctx).await.is_err());
+    }
+
+    async fn caller(config: Config, pool: &Pool<Postgres>, token: Option<&str>) -> Option<Role> {
+        let ctx = RadioContext::init(config, pool.clone()).unwrap();
+        ctx.authenticate(token).await.unwrap().map(|(role, _)| role)
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_authenticate(pool: Pool<Postgres>) {
+        // Without any token set up, anonymous requests only read
+        assert_eq!(
+            caller(Config::default(), &pool, None).await,
+            Some(Role::Viewer)
+        );
+        assert_eq!(
+            caller(Config::default(), &pool, Some("unknown")).await,
+            None
+        );
+        let open = Config {
+            api_open: true,
+            ..Default::default()
+        };
+        assert_eq!(caller(open.clone(), &pool, None).await, Some(Role::Admin));
+        let required = Config {
+            api_auth_required: true,
+            ..Default::default()
+        };
+        assert_eq!(caller(required, &pool, None).await, None);
+
+        let config = Config {
+            api_tokens: vec![("analyst-token".to_string(), Role::Analyst)],
+            ..Default::default()
+        };
+        assert_eq!(
+            caller(config.clone(), &pool, Some("analyst-token")).await,
+            Some(Role::Analyst)
+        );
+        assert_eq!(caller(config.clone(), &pool, Some("unknown")).await, None);
+        assert_eq!(caller(config.clone(), &pool, None).await, None);
+        let public = Config {
+            server_profile: ServerProfile::Public,
+            ..config
+        };
+        assert_eq!(
+            caller(public.clone(), &pool, None).await,
+            Some(Role::Viewer)
+        );
+        assert_eq!(caller(public, &pool, Some("unknown")).await, None);
+
+        // An API key closes the open API
+        let ctx = RadioContext::init(open.clone(), pool.clone()).unwrap();
+        let (_, secret) = create_api_key(&pool, ctx.namespace(), "ops", "admin", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            caller(open.clone(), &pool, Some(&secret)).await,
+            Some(Role::Admin)
+        );
+        assert_eq!(caller(open, &pool, None).await, None);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_anonymous_mutation_rejected(pool: Pool<Postgres>) {
+        let ctx = Arc::new(RadioContext::init(Config::default(), pool.clone()).unwrap());
+        let role = ctx.authenticate(None).await.unwrap().unwrap().0;
+        let schema = build_schema(ctx.clone()).await.unwrap();
+        let response = schema
+            .execute(
+                async_graphql::Request::new("mutation { deleteMessage(id: 1) { nonce } }")
+                    .data(ctx)
+                    .data(role)
+                    .data(AdminNetwork(true)),
+            )
+            .await;
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].message, "Requires the Admin role");
+    }
+}
@@ -0,0 +1,133 @@
+use async_graphql::{
+    extensions::{
+        Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextPrepareRequest,
+    },
+    parser::types::ExecutableDocument,
+    Request, ServerError, ServerResult, Value, Variables,
+};
+use sqlx::PgPool;
+use std::{fs, path::Path, sync::Arc};
+use tracing::{info, warn};
+
+use crate::{
+    db::resolver::{is_persisted_query, persisted_query, upsert_persisted_query},
+    server::auth::Role,
+};
+
+/// Register every `.graphql` document of `dir` under its file name, returns how many were found
+pub async fn register_persisted_queries(
+    pool: &PgPool,
+    namespace: &str,
+    dir: &Path,
+) -> anyhow::Result<usize> {
+    let mut registered = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("graphql") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let query = fs::read_to_string(&path)?;
+        upsert_persisted_query(pool, namespace, name, &query).await?;
+        registered += 1;
+    }
+    info!(
+        registered,
+        dir = tracing::field::debug(dir),
+        "Registered persisted queries"
+    );
+    Ok(registered)
+}
+
+/// Hash sent in the Apollo `persistedQuery` request extension
+fn persisted_query_hash(request: &Request) -> Option<String> {
+    match request.extensions.get("persistedQuery") {
+        Some(Value::Object(persisted)) => match persisted.get("sha256Hash") {
+            Some(Value::String(hash)) => Some(hash.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Only executes operations registered in `persisted_queries`, sent either in full or by hash.
+/// Requests with the admin role may run any operation
+pub struct QueryAllowlist {
+    db: PgPool,
+    namespace: String,
+}
+
+impl QueryAllowlist {
+    pub fn new(db: PgPool, namespace: String) -> Self {
+        QueryAllowlist { db, namespace }
+    }
+}
+
+impl ExtensionFactory for QueryAllowlist {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueryAllowlistExtension {
+            db: self.db.clone(),
+            namespace: self.namespace.clone(),
+        })
+    }
+}
+
+struct QueryAllowlistExtension {
+    db: PgPool,
+    namespace: String,
+}
+
+#[async_trait::async_trait]
+impl Extension for QueryAllowlistExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        mut request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        if request.query.is_empty() {
+            if let Some(hash) = persisted_query_hash(&request) {
+                request.query = persisted_query(&self.db, &self.namespace, &hash)
+                    .await
+                    .map_err(|e| {
+                        warn!(
+                            err = tracing::field::debug(&e),
+                            "Failed to load persisted query"
+                        );
+                        ServerError::new("Could not load the persisted query", None)
+                    })?
+                    .ok_or_else(|| ServerError::new("PersistedQueryNotFound", None))?;
+            }
+        }
+        next.run(ctx, request).await
+    }
+
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        if ctx.data_opt::<Role>() != Some(&Role::Admin) {
+            let registered = is_persisted_query(&self.db, &self.namespace, query)
+                .await
+                .map_err(|e| {
+                    warn!(
+                        err = tracing::field::debug(&e),
+                        "Failed to check persisted query"
+                    );
+                    ServerError::new("Could not check the query allowlist", None)
+                })?;
+            if !registered {
+                return Err(ServerError::new(
+                    "Only registered operations may be executed on this listener",
+                    None,
+                ));
+            }
+        }
+        next.run(ctx, query, variables).await
+    }
+}
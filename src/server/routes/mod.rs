@@ -1,45 +1,440 @@
-use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
-use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use async_graphql::{
+    http::{playground_source, GraphQLPlaygroundConfig, ALL_WEBSOCKET_PROTOCOLS},
+    parser::parse_query,
+    Data,
+};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
 use axum::{
-    extract::Extension,
-    http::StatusCode,
-    response::{Html, IntoResponse},
-    Json,
+    body::StreamBody,
+    extract::{ws::WebSocketUpgrade, ConnectInfo, Extension, Query},
+    headers::{authorization::Bearer, Authorization},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json, TypedHeader,
 };
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::io::{self, Write};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tracing::trace;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, trace, warn};
 
 use super::model::RadioContext;
-use crate::server::model::RadioSchema;
+use crate::{
+    db::{
+        resolver::{record_api_key_usage, ListenerNode, MessageFilter},
+        schema_version, DATA_SCHEMA_VERSION,
+    },
+    export::ipc::{export_messages, ExportFormat},
+    message_types::MESSAGE_SCHEMA_VERSION,
+    metrics::{API_REQUESTS, API_RESPONSE_BYTES},
+    operator::handoff,
+    operator::health::{check_health, HealthThresholds},
+    operator::node::local_node,
+    operator::prune::{prune_now, PruneRequest},
+    radio_name,
+    server::{
+        auth::{Credential, Role},
+        limits::{has_mutation, AdminNetwork},
+        links::GRAPHQL_WS_PATH,
+        model::RadioSchema,
+    },
+};
+
+/// Status of every component, 503 when any is down or degraded
+pub(crate) async fn health(Extension(context): Extension<Arc<RadioContext>>) -> impl IntoResponse {
+    let health = check_health(
+        &context.db,
+        HealthThresholds::from_config(&context.radio_config),
+    )
+    .await;
+    let status = if health.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(health))
+}
+
+/// Build and schema versions of the running listener
+#[derive(Serialize)]
+struct Info {
+    name: &'static str,
+    version: &'static str,
+    namespace: String,
+    /// Waku pubsub topic the listener joined
+    pubsub_topic: String,
+    /// Waku node of the listener, once it is recorded
+    node: Option<ListenerNode>,
+    data_schema_version: i32,
+    database_schema_version: Option<i32>,
+    message_schema_version: i32,
+}
+
+pub(crate) async fn info(Extension(context): Extension<Arc<RadioContext>>) -> impl IntoResponse {
+    let database_schema_version = match schema_version(&context.db).await {
+        Ok(version) => version,
+        Err(e) => {
+            warn!(
+                err = e.to_string(),
+                "Could not read database schema version"
+            );
+            None
+        }
+    };
+    let info = Info {
+        name: radio_name(),
+        version: env!("CARGO_PKG_VERSION"),
+        namespace: context.namespace().to_string(),
+        pubsub_topic: context.radio_config.pubsub_topic_name(),
+        node: local_node().cloned(),
+        data_schema_version: DATA_SCHEMA_VERSION,
+        database_schema_version,
+        message_schema_version: MESSAGE_SCHEMA_VERSION,
+    };
+
+    (StatusCode::OK, Json(info))
+}
 
 #[derive(Serialize)]
-struct Health {
-    healthy: bool,
+struct Drain {
+    draining: bool,
+}
+
+/// Error response unless the request comes from the admin networks with the Admin role
+async fn authorize_admin(
+    context: &RadioContext,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+) -> Result<(), Response> {
+    if context.admin_network(client.map(|ConnectInfo(addr)| addr.ip())) == AdminNetwork(false) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Admin operations are not allowed from this address",
+        )
+            .into_response());
+    }
+    let token = authorization
+        .as_ref()
+        .map(|TypedHeader(Authorization(bearer))| bearer.token());
+    match context.authenticate(token).await {
+        Ok(Some((Role::Admin, _))) => Ok(()),
+        Ok(_) => Err((StatusCode::UNAUTHORIZED, "Requires the Admin role").into_response()),
+        Err(e) => {
+            warn!(err = e.to_string(), "Could not authenticate request");
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// Stop ingesting and release the ingest lock to the instance replacing this one, admin only
+pub(crate) async fn drain(
+    Extension(context): Extension<Arc<RadioContext>>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+) -> Response {
+    if let Err(response) = authorize_admin(&context, authorization, client).await {
+        return response;
+    }
+
+    handoff::drain();
+    (StatusCode::OK, Json(Drain { draining: true })).into_response()
+}
+
+/// Prune stored messages now, by the `older_than_minutes` and `keep_newest` URL parameters
+/// like the `pruneMessages` mutation, admin only
+pub(crate) async fn prune(
+    Extension(context): Extension<Arc<RadioContext>>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    Query(request): Query<PruneRequest>,
+) -> Response {
+    if let Err(response) = authorize_admin(&context, authorization, client).await {
+        return response;
+    }
+    if let Err(e) = request.validate() {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    match prune_now(
+        &context.db,
+        context.namespace(),
+        context.prune_archive(),
+        context.archive.as_ref(),
+        request,
+    )
+    .await
+    {
+        Ok(outcome) => (StatusCode::OK, Json(outcome)).into_response(),
+        Err(e) => {
+            warn!(err = e.to_string(), "On demand pruning failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
 }
 
-pub(crate) async fn health() -> impl IntoResponse {
-    let health = Health { healthy: true };
+/// Export format and the conditions of [`MessageFilter`], as URL parameters
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct ExportParams {
+    format: ExportFormat,
+    graph_account: Option<String>,
+    identifier: Option<String>,
+    network: Option<String>,
+    nonce_gte: Option<i64>,
+    nonce_lte: Option<i64>,
+    message_type: Option<String>,
+    content_topic: Option<String>,
+    sender_valid: Option<bool>,
+    /// Received at or after, in unix seconds
+    from: Option<i64>,
+    /// Received before, in unix seconds
+    to: Option<i64>,
+}
+
+/// Stream the stored messages matching the parameters, for callers with the Analyst role
+pub(crate) async fn export(
+    Extension(context): Extension<Arc<RadioContext>>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let token = authorization
+        .as_ref()
+        .map(|TypedHeader(Authorization(bearer))| bearer.token());
+    let credential = match context.authenticate(token).await {
+        Ok(Some((role, credential))) if role >= Role::Analyst => credential,
+        Ok(_) => return (StatusCode::UNAUTHORIZED, "Requires the Analyst role").into_response(),
+        Err(e) => {
+            warn!(err = e.to_string(), "Could not authenticate request");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    API_REQUESTS
+        .with_label_values(&[credential.label().as_str()])
+        .inc();
+    let Ok(permit) = context.exports.clone().try_acquire_owned() else {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many exports running, retry later",
+        )
+            .into_response();
+    };
 
-    (StatusCode::OK, Json(health))
+    let filter = MessageFilter {
+        graph_account: params.graph_account,
+        identifier: params.identifier,
+        network: params.network,
+        nonce_gte: params.nonce_gte,
+        nonce_lte: params.nonce_lte,
+        message_type: params.message_type,
+        content_topic: params.content_topic,
+        sender_valid: params.sender_valid,
+        received_from: params.from,
+        received_to: params.to,
+    };
+    let body = StreamBody::new(export_messages(
+        context.db.clone(),
+        context.namespace().to_string(),
+        filter,
+        params.format,
+        (context.radio_config.export_idle_timeout > 0)
+            .then(|| Duration::from_secs(context.radio_config.export_idle_timeout)),
+        Some(permit),
+    ));
+    ([(header::CONTENT_TYPE, params.format.content_type())], body).into_response()
 }
 
 pub(crate) async fn graphql_playground() -> impl IntoResponse {
     Html(playground_source(
-        GraphQLPlaygroundConfig::new("/").subscription_endpoint("/ws"),
+        GraphQLPlaygroundConfig::new("/").subscription_endpoint(GRAPHQL_WS_PATH),
     ))
 }
 
+/// Queries sent as URL parameters are executed, as linked from alerts. Without them the
+/// playground is served. Mutations are only accepted through POST
+pub(crate) async fn graphql_get(
+    schema: Extension<RadioSchema>,
+    context: Extension<Arc<RadioContext>>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    req: Option<GraphQLRequest>,
+) -> Response {
+    let Some(req) = req else {
+        return graphql_playground().await.into_response();
+    };
+    if parse_query(&req.0.query).is_ok_and(|document| has_mutation(&document)) {
+        return (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Mutations must be sent with POST",
+        )
+            .into_response();
+    }
+    graphql_handler(schema, context, authorization, client, req).await
+}
+
 pub(crate) async fn graphql_handler(
-    req: GraphQLRequest,
     Extension(schema): Extension<RadioSchema>,
     Extension(context): Extension<Arc<RadioContext>>,
-) -> GraphQLResponse {
-    trace!("Processing GraphQL request");
-    let response = async move { schema.execute(req.into_inner().data(context)).await }.await;
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    req: GraphQLRequest,
+) -> Response {
+    let admin_network = context.admin_network(client.map(|ConnectInfo(addr)| addr.ip()));
+    let token = authorization
+        .as_ref()
+        .map(|TypedHeader(Authorization(bearer))| bearer.token());
+    let (role, credential) = match context.authenticate(token).await {
+        Ok(Some(caller)) => caller,
+        Ok(None) => {
+            return (StatusCode::UNAUTHORIZED, "Missing or unknown API token").into_response()
+        }
+        Err(e) => {
+            warn!(err = e.to_string(), "Could not authenticate request");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    trace!(
+        role = tracing::field::debug(role),
+        "Processing GraphQL request"
+    );
+    let db = context.db.clone();
+    let response = async move {
+        schema
+            .execute(
+                req.into_inner()
+                    .data(context)
+                    .data(role)
+                    .data(admin_network),
+            )
+            .await
+    }
+    .await;
 
     trace!("Processing GraphQL request finished");
 
-    response.into()
+    record_usage(db, credential, &response);
+    GraphQLResponse::from(response).into_response()
+}
+
+/// Bearer token of a websocket connection. Browsers cannot set headers on websockets, so
+/// clients send it in the `connection_init` payload as `{"Authorization": "Bearer <token>"}`
+fn connection_token(payload: &serde_json::Value) -> Option<&str> {
+    payload
+        .get("Authorization")
+        .or_else(|| payload.get("authorization"))
+        .and_then(serde_json::Value::as_str)
+        .map(|value| value.strip_prefix("Bearer ").unwrap_or(value))
+}
+
+/// GraphQL subscriptions over websockets, authenticated once when the connection is set up
+pub(crate) async fn graphql_ws(
+    Extension(schema): Extension<RadioSchema>,
+    Extension(context): Extension<Arc<RadioContext>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    protocol: GraphQLProtocol,
+    websocket: WebSocketUpgrade,
+) -> Response {
+    let admin_network = context.admin_network(client.map(|ConnectInfo(addr)| addr.ip()));
+    websocket
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            GraphQLWebSocket::new(stream, schema, protocol)
+                .on_connection_init(move |payload| async move {
+                    let (role, _) = context
+                        .authenticate(connection_token(&payload))
+                        .await
+                        .map_err(|e| async_graphql::Error::new(e.to_string()))?
+                        .ok_or_else(|| async_graphql::Error::new("Missing or unknown API token"))?;
+                    trace!(
+                        role = tracing::field::debug(role),
+                        "Opening GraphQL subscription connection"
+                    );
+                    let mut data = Data::default();
+                    data.insert(context);
+                    data.insert(role);
+                    data.insert(admin_network);
+                    Ok(data)
+                })
+                .serve()
+        })
+}
+
+/// Counts the bytes written without keeping them
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Count the request and the serialized size of its response towards the credential
+/// Returns the task recording the usage of an API key
+fn record_usage(
+    db: PgPool,
+    credential: Credential,
+    response: &async_graphql::Response,
+) -> Option<JoinHandle<()>> {
+    let mut counter = ByteCounter(0);
+    if let Err(e) = serde_json::to_writer(&mut counter, response) {
+        debug!(err = e.to_string(), "Could not measure response size");
+    }
+    let bytes = counter.0;
+
+    let label = credential.label();
+    API_REQUESTS.with_label_values(&[label.as_str()]).inc();
+    API_RESPONSE_BYTES
+        .with_label_values(&[label.as_str()])
+        .inc_by(bytes as u64);
+    let Credential::ApiKey(key_id) = credential else {
+        return None;
+    };
+    Some(tokio::spawn(async move {
+        if let Err(e) = record_api_key_usage(&db, key_id, bytes as i64).await {
+            warn!(
+                err = e.to_string(),
+                key_id, "Could not record API key usage"
+            );
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::resolver::{create_api_key, list_api_key_usage};
+
+    const TEST_NAMESPACE: &str = "default";
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_record_usage(pool: PgPool) {
+        let (key, _) = create_api_key(&pool, TEST_NAMESPACE, "ops", "viewer", None)
+            .await
+            .unwrap();
+        let response = async_graphql::Response::new(async_graphql::Value::from("0xpoi"));
+        let bytes = serde_json::to_vec(&response).unwrap().len() as i64;
+        let requests = API_REQUESTS.with_label_values(&[key.id.to_string().as_str()]);
+
+        for _ in 0..2 {
+            record_usage(pool.clone(), Credential::ApiKey(key.id), &response)
+                .unwrap()
+                .await
+                .unwrap();
+        }
+        assert!(record_usage(pool.clone(), Credential::Static, &response).is_none());
+
+        let usage = list_api_key_usage(&pool, TEST_NAMESPACE).await.unwrap();
+        assert_eq!((usage[0].requests, usage[0].bytes), (2, 2 * bytes));
+        assert_eq!(requests.get(), 2);
+    }
 }
@@ -0,0 +1,118 @@
+//! Graceful shutdown of the listener. A SIGINT or SIGTERM ends the operator loop, which stops
+//! the Waku node and then calls [`stop`]: message sources close once they handed over what was
+//! already received, the processing workers store the queue and their pending batches, and the
+//! HTTP and metrics servers finish their open requests. Redeploys then lose no received messages
+use once_cell::sync::Lazy;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::{signal, sync::Notify};
+use tracing::info;
+
+/// Shutdown state, one per process through [`stop`] and [`stopped`]
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    stopping: AtomicBool,
+    stopped: Notify,
+}
+
+impl Shutdown {
+    pub fn stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether this call started the shutdown
+    pub fn stop(&self) -> bool {
+        let started = !self.stopping.swap(true, Ordering::SeqCst);
+        self.stopped.notify_waiters();
+        started
+    }
+
+    pub async fn stopped(&self) {
+        let notified = self.stopped.notified();
+        tokio::pin!(notified);
+        // Register before checking the flag, so a stop in between is not missed
+        notified.as_mut().enable();
+        if self.stopping() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+static SHUTDOWN: Lazy<Shutdown> = Lazy::new(Shutdown::default);
+
+/// Whether the shutdown started
+pub fn stopping() -> bool {
+    SHUTDOWN.stopping()
+}
+
+/// Start the shutdown, draining the sources and stopping the servers
+pub fn stop() {
+    if SHUTDOWN.stop() {
+        info!("Shutting down, draining received messages");
+    }
+}
+
+/// Resolves once the shutdown started
+pub async fn stopped() {
+    SHUTDOWN.stopped().await
+}
+
+/// Wait for SIGINT or SIGTERM and clear `running_program`, which ends the operator loop
+pub async fn shutdown_signal(running_program: Arc<AtomicBool>) {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received SIGINT"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+
+    running_program.store(false, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_stopped() {
+        let shutdown = Arc::new(Shutdown::default());
+        let waiter = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.stopped().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        assert!(shutdown.stop());
+        assert!(!shutdown.stop(), "Only the first stop starts the shutdown");
+        assert!(shutdown.stopping());
+        timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("Waiters wake up on stop")
+            .unwrap();
+        // Later waiters resolve right away
+        timeout(Duration::from_secs(1), shutdown.stopped())
+            .await
+            .expect("Stopped after the shutdown started");
+    }
+}